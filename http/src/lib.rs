@@ -9,3 +9,6 @@ extern crate tracing;
 pub mod v0;
 
 pub mod config;
+
+#[cfg(test)]
+mod test_support;
@@ -1,8 +1,14 @@
 use std::num::NonZeroU16;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
 
-use ipfs::{Ipfs, IpfsOptions, IpfsTypes, UninitializedIpfs};
+use ipfs::repo::fs::{FlatfsBlockStore, FsBlockStore, FsDataStore};
+use ipfs::repo::mem::{MemBlockStore, MemDataStore};
+use ipfs::{
+    BlockEncryption, BlockStore, Compression, DataStore, DhtMode, EncryptionKey, Ipfs, IpfsOptions,
+    IpfsTypes, UninitializedIpfs,
+};
 use ipfs_http::{config, v0};
 use parity_multiaddr::{Multiaddr, Protocol};
 
@@ -25,7 +31,38 @@ enum Options {
         profile: Vec<config::Profile>,
     },
     /// Start the IPFS node in the foreground (not detaching from parent process).
-    Daemon,
+    Daemon {
+        /// Run the node without any networking; only the locally stored content is available.
+        #[structopt(long)]
+        offline: bool,
+        /// Maximum size of the stored blocks in bytes; least recently used unpinned blocks are
+        /// evicted after 90% of it has been used.
+        #[structopt(long)]
+        storage_max: Option<u64>,
+        /// Encrypt the stored blocks with the key in this file, which is created if it doesn't
+        /// exist. A relative path is relative to the repository.
+        #[structopt(long)]
+        encryption_key_file: Option<PathBuf>,
+        /// Compression of the blocks written from now on: `none`, `zstd` or `zstd:<level>` with
+        /// a level from 1 to 21. The existing blocks are readable regardless.
+        #[structopt(long, default_value = "none")]
+        block_compression: Compression,
+        /// The store of the blocks: `fs`, `flatfs` for the go-ipfs compatible layout under
+        /// `blocks/`, or `memory` to keep them only until the daemon exits.
+        #[structopt(long, default_value = "fs")]
+        block_store: BlockStoreKind,
+        /// The store of the pins and other metadata: `fs` or `memory`.
+        #[structopt(long, default_value = "fs")]
+        data_store: DataStoreKind,
+        /// The role of the node in the DHT: `client` only queries it, `server` also answers the
+        /// queries of other peers and `auto` becomes a server once found publicly reachable.
+        #[structopt(long, default_value = "server")]
+        dht_mode: DhtMode,
+        /// Resolve and publish IPNS names over pubsub in addition to the DHT, like the
+        /// `--enable-namesys-pubsub` of go-ipfs.
+        #[structopt(long)]
+        enable_namesys_pubsub: bool,
+    },
 }
 
 fn main() {
@@ -63,6 +100,39 @@ fn main() {
 
     let config_path = home.join("config");
 
+    let (offline, storage_max, block_encryption, block_compression, dht_mode, ipns_pubsub) =
+        match opts {
+            Options::Daemon {
+                offline,
+                storage_max,
+                ref encryption_key_file,
+                block_compression,
+                dht_mode,
+                enable_namesys_pubsub,
+                ..
+            } => (
+                offline,
+                storage_max,
+                encryption_key_file.clone().map(|path| BlockEncryption {
+                    key: EncryptionKey::KeyFile(path),
+                    hash_paths: false,
+                }),
+                block_compression,
+                dht_mode,
+                enable_namesys_pubsub,
+            ),
+            _ => (false, None, None, Compression::None, DhtMode::Server, false),
+        };
+
+    let (block_store, data_store) = match opts {
+        Options::Daemon {
+            block_store,
+            data_store,
+            ..
+        } => (block_store, data_store),
+        _ => (BlockStoreKind::Fs, DataStoreKind::Fs),
+    };
+
     let (keypair, listening_addrs, api_listening_addr) = match opts {
         Options::Init { bits, profile } => {
             println!("initializing IPFS node at {:?}", home);
@@ -116,7 +186,7 @@ fn main() {
                 }
             }
         }
-        Options::Daemon => {
+        Options::Daemon { .. } => {
             if !config_path.is_file() {
                 eprintln!("Error: no IPFS repo found in {:?}", home);
                 eprintln!("please run: 'ipfs init'");
@@ -145,14 +215,29 @@ fn main() {
             bootstrap: Vec::new(),
             mdns: false,
             kad_protocol: None,
+            dht_mode,
+            content_routers: Vec::new(),
+            peer_routers: Vec::new(),
+            ipns_pubsub,
             listening_addrs,
+            offline,
+            storage_max,
+            storage_high_water_mark: 90,
+            block_encryption,
+            block_cache: None,
+            block_compression,
+            migrations: Default::default(),
             span: None,
         };
 
-        let (ipfs, task): (Ipfs<ipfs::Types>, _) = UninitializedIpfs::new(opts)
-            .start()
-            .await
-            .expect("Initialization failed");
+        let block_store = block_store.create(&home);
+        let data_store = data_store.create(&home);
+
+        let (ipfs, task): (Ipfs<ipfs::DynTypes>, _) =
+            UninitializedIpfs::with_stores(opts, block_store, data_store)
+                .start()
+                .await
+                .expect("Initialization failed");
 
         tokio::spawn(task);
 
@@ -188,6 +273,74 @@ fn main() {
     info!("Shutdown complete");
 }
 
+/// The block stores selectable with `--block-store`.
+#[derive(Debug, Clone, Copy)]
+enum BlockStoreKind {
+    Fs,
+    Flatfs,
+    Memory,
+}
+
+impl BlockStoreKind {
+    /// Creates the store under the repository at `home`, in the same directory as the stores
+    /// created by default for the `fs` kind.
+    fn create(self, home: &Path) -> Box<dyn BlockStore> {
+        match self {
+            BlockStoreKind::Fs => Box::new(FsBlockStore::new(home.join("blockstore"))),
+            BlockStoreKind::Flatfs => Box::new(FlatfsBlockStore::new(home.join("blocks"))),
+            BlockStoreKind::Memory => Box::new(MemBlockStore::default()),
+        }
+    }
+}
+
+impl FromStr for BlockStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fs" => Ok(BlockStoreKind::Fs),
+            "flatfs" => Ok(BlockStoreKind::Flatfs),
+            "memory" => Ok(BlockStoreKind::Memory),
+            _ => Err(format!(
+                "invalid block store {:?}, expected one of fs, flatfs or memory",
+                s
+            )),
+        }
+    }
+}
+
+/// The data stores selectable with `--data-store`.
+#[derive(Debug, Clone, Copy)]
+enum DataStoreKind {
+    Fs,
+    Memory,
+}
+
+impl DataStoreKind {
+    /// Creates the store under the repository at `home`, see [`BlockStoreKind::create`].
+    fn create(self, home: &Path) -> Box<dyn DataStore> {
+        match self {
+            DataStoreKind::Fs => Box::new(FsDataStore::new(home.join("datastore"))),
+            DataStoreKind::Memory => Box::new(MemDataStore::default()),
+        }
+    }
+}
+
+impl FromStr for DataStoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fs" => Ok(DataStoreKind::Fs),
+            "memory" => Ok(DataStoreKind::Memory),
+            _ => Err(format!(
+                "invalid data store {:?}, expected one of fs or memory",
+                s
+            )),
+        }
+    }
+}
+
 fn serve<Types: IpfsTypes>(
     ipfs: &Ipfs<Types>,
     listening_addr: Multiaddr,
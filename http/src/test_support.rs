@@ -0,0 +1,19 @@
+//! Helpers shared by the tests of the crate.
+use ipfs::repo::mem::{MemBlockStore, MemDataStore};
+use ipfs::{DynTypes, Ipfs, IpfsOptions, UninitializedIpfs};
+
+/// Starts an in-memory node with its background task spawned on the current runtime.
+pub(crate) async fn tokio_ipfs() -> Ipfs<DynTypes> {
+    let options = IpfsOptions::inmemory_with_generated_keys();
+    let (ipfs, fut) = UninitializedIpfs::with_stores(
+        options,
+        Box::new(MemBlockStore::default()),
+        Box::new(MemDataStore::default()),
+    )
+    .start()
+    .await
+    .unwrap();
+
+    tokio::spawn(fut);
+    ipfs
+}
@@ -8,13 +8,16 @@ use warp::{query, Filter};
 pub mod bitswap;
 pub mod block;
 pub mod bootstrap;
+pub mod cid;
 pub mod dag;
 pub mod dht;
+pub mod filestore;
 pub mod id;
 pub mod ipns;
 pub mod pin;
 pub mod pubsub;
 pub mod refs;
+pub mod repo;
 pub mod root_files;
 pub mod swarm;
 pub mod version;
@@ -97,6 +100,8 @@ pub fn routes<T: IpfsTypes>(
         and_boxed!(warp::path!("get"), root_files::get(ipfs)),
         and_boxed!(warp::path!("refs" / "local"), refs::local(ipfs)),
         and_boxed!(warp::path!("refs"), refs::refs(ipfs)),
+        and_boxed!(warp::path!("repo" / "stat"), repo::stat(ipfs)),
+        and_boxed!(warp::path!("repo" / "verify"), repo::verify(ipfs)),
         and_boxed!(warp::path!("resolve"), ipns::resolve(ipfs)),
         warp::path!("version")
             .and(query::<version::Query>())
@@ -121,6 +126,13 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("rm"), bootstrap::bootstrap_rm(ipfs)),
             and_boxed!(warp::path!("rm" / "all"), bootstrap::bootstrap_clear(ipfs)),
         )),
+        warp::path("cid").and(combine!(
+            and_boxed!(warp::path!("base32"), cid::base32()),
+            and_boxed!(warp::path!("bases"), cid::bases()),
+            and_boxed!(warp::path!("codecs"), cid::codecs()),
+            and_boxed!(warp::path!("format"), cid::format()),
+            and_boxed!(warp::path!("hashes"), cid::hashes()),
+        )),
         warp::path("dag").and(combine!(
             and_boxed!(warp::path!("put"), dag::put(ipfs)),
             and_boxed!(warp::path!("resolve"), dag::resolve(ipfs)),
@@ -128,9 +140,22 @@ pub fn routes<T: IpfsTypes>(
         warp::path("dht").and(combine!(
             and_boxed!(warp::path!("findpeer"), dht::find_peer(ipfs)),
             and_boxed!(warp::path!("findprovs"), dht::find_providers(ipfs)),
+            and_boxed!(warp::path!("get"), dht::get_value(ipfs)),
             and_boxed!(warp::path!("provide"), dht::provide(ipfs)),
+            and_boxed!(warp::path!("put"), dht::put_value(ipfs)),
             and_boxed!(warp::path!("query"), dht::get_closest_peers(ipfs)),
         )),
+        warp::path("filestore").and(combine!(
+            and_boxed!(warp::path!("ls"), filestore::ls(ipfs)),
+            and_boxed!(warp::path!("verify"), filestore::verify(ipfs)),
+        )),
+        warp::path("name").and(combine!(
+            and_boxed!(warp::path!("publish"), ipns::publish(ipfs)),
+            and_boxed!(warp::path!("resolve"), ipns::resolve(ipfs)),
+            and_boxed!(warp::path!("pubsub" / "cancel"), ipns::pubsub_cancel(ipfs)),
+            and_boxed!(warp::path!("pubsub" / "state"), ipns::pubsub_state(ipfs)),
+            and_boxed!(warp::path!("pubsub" / "subs"), ipns::pubsub_subs(ipfs)),
+        )),
         warp::path("pubsub").and(combine!(
             and_boxed!(warp::path!("peers"), pubsub::peers(ipfs)),
             and_boxed!(warp::path!("ls"), pubsub::list_subscriptions(ipfs)),
@@ -151,16 +176,33 @@ pub fn routes<T: IpfsTypes>(
             and_boxed!(warp::path!("add"), pin::add(ipfs)),
             and_boxed!(warp::path!("ls"), pin::list(ipfs)),
             and_boxed!(warp::path!("rm"), pin::rm(ipfs)),
+            and_boxed!(warp::path!("update"), pin::update(ipfs)),
+            and_boxed!(warp::path!("verify"), pin::verify(ipfs)),
+            warp::path("remote").and(combine!(
+                and_boxed!(warp::path!("add"), pin::remote::add(ipfs)),
+                and_boxed!(warp::path!("ls"), pin::remote::list(ipfs)),
+                and_boxed!(warp::path!("rm"), pin::remote::rm(ipfs)),
+                and_boxed!(
+                    warp::path!("service" / "add"),
+                    pin::remote::service_add(ipfs)
+                ),
+                and_boxed!(
+                    warp::path!("service" / "ls"),
+                    pin::remote::service_list(ipfs)
+                ),
+                and_boxed!(warp::path!("service" / "rm"), pin::remote::service_rm(ipfs)),
+            )),
         )),
         combine_unify!(
             warp::path!("config" / ..),
-            warp::path!("dht" / "get"),
-            warp::path!("dht" / "put"),
             warp::path!("key" / ..),
             warp::path!("name" / ..),
             warp::path!("object" / ..),
             warp::path!("ping" / ..),
-            warp::path!("repo" / ..),
+            warp::path!("repo" / "fsck"),
+            warp::path!("repo" / "gc"),
+            warp::path!("repo" / "ls"),
+            warp::path!("repo" / "version"),
             warp::path!("stats" / ..),
         )
         .and_then(not_implemented),
@@ -184,16 +226,24 @@ async fn not_implemented() -> Result<(impl warp::Reply,), std::convert::Infallib
 
 #[cfg(test)]
 mod tests {
-    use ipfs::{Ipfs, TestTypes};
+    use ipfs::{DynTypes, Ipfs};
     /// Creates routes for tests, the ipfs will not work as no background task is being spawned.
     async fn testing_routes(
     ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         use super::routes;
+        use ipfs::repo::mem::{MemBlockStore, MemDataStore};
         use ipfs::{IpfsOptions, UninitializedIpfs};
 
         let options = IpfsOptions::inmemory_with_generated_keys();
-        let (ipfs, _): (Ipfs<TestTypes>, _) =
-            UninitializedIpfs::new(options).start().await.unwrap();
+        // same types as used by the binary to avoid having to build the routes twice
+        let (ipfs, _): (Ipfs<DynTypes>, _) = UninitializedIpfs::with_stores(
+            options,
+            Box::new(MemBlockStore::default()),
+            Box::new(MemDataStore::default()),
+        )
+        .start()
+        .await
+        .unwrap();
 
         let (shutdown_tx, _) = tokio::sync::mpsc::channel::<()>(1);
 
@@ -214,6 +264,93 @@ mod tests {
         assert_eq!(resp.body(), "404 page not found");
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn repo_stat_of_empty_repo() {
+        let routes = testing_routes().await;
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/repo/stat")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.body(),
+            r#"{"RepoSize":0,"StorageMax":18446744073709551615,"NumObjects":0,"LogicalSize":0}"#
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn repo_verify_of_empty_repo() {
+        let routes = testing_routes().await;
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/repo/verify?repair=quarantine")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.body(), "");
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/repo/verify?repair=fix")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 500);
+        assert_eq!(
+            resp.body(),
+            r#"{"Message":"invalid repair \"fix\", expected quarantine or delete","Code":0,"Type":"error"}"#
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn remote_pin_services_are_added_and_removed() {
+        let routes = testing_routes().await;
+        let resp = warp::test::request()
+            .method("POST")
+            .path(
+                "/api/v0/pin/remote/service/add?arg=mock&arg=https%3A%2F%2Fexample.com%2F&arg=key",
+            )
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/pin/remote/service/ls")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.body(),
+            r#"{"RemoteServices":[{"Service":"mock","ApiEndpoint":"https://example.com"}]}"#
+        );
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/pin/remote/service/rm?arg=mock")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let resp = warp::test::request()
+            .method("POST")
+            .path("/api/v0/pin/remote/ls?service=mock")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), 500);
+        assert_eq!(
+            resp.body(),
+            r#"{"Message":"service \"mock\" not found","Code":0,"Type":"error"}"#
+        );
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn invalid_peer_id_as_messageresponse() {
         let routes = testing_routes().await;
@@ -1,4 +1,4 @@
-use crate::v0::support::{with_ipfs, InvalidPeerId, StringError};
+use crate::v0::support::{cid_base, with_ipfs, CidBase, InvalidPeerId, StringError};
 use ipfs::{BitswapStats, Ipfs, IpfsTypes};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -18,6 +18,7 @@ pub struct WantlistResponse {
 async fn wantlist_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     query: WantlistQuery,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let peer_id = if let Some(peer_id) = query.peer {
         let peer_id = peer_id.parse().map_err(|_| InvalidPeerId)?;
@@ -31,7 +32,7 @@ async fn wantlist_query<T: IpfsTypes>(
         .map_err(StringError::from)?;
     let keys = cids
         .into_iter()
-        .map(|(cid, _)| json!({"/": cid.to_string()}))
+        .map(|(cid, _)| json!({ "/": cid_base.encode(&cid) }))
         .collect();
     let response = WantlistResponse { keys };
     Ok(reply::json(&response))
@@ -42,6 +43,7 @@ pub fn wantlist<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(query::<WantlistQuery>())
+        .and(cid_base())
         .and_then(wantlist_query)
 }
 
@@ -60,12 +62,12 @@ pub struct StatResponse {
     wantlist: Vec<Value>,
 }
 
-impl From<BitswapStats> for StatResponse {
-    fn from(stats: BitswapStats) -> Self {
+impl StatResponse {
+    fn new(stats: BitswapStats, cid_base: &CidBase) -> Self {
         let wantlist = stats
             .wantlist
             .into_iter()
-            .map(|(cid, _)| json!({"/": cid.to_string()}))
+            .map(|(cid, _)| json!({ "/": cid_base.encode(&cid) }))
             .collect();
         let peers = stats
             .peers
@@ -87,17 +89,16 @@ impl From<BitswapStats> for StatResponse {
     }
 }
 
-async fn stat_query<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
-    let stats: StatResponse = ipfs
-        .bitswap_stats()
-        .await
-        .map_err(StringError::from)?
-        .into();
-    Ok(reply::json(&stats))
+async fn stat_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    let stats = ipfs.bitswap_stats().await.map_err(StringError::from)?;
+    Ok(reply::json(&StatResponse::new(stats, &cid_base)))
 }
 
 pub fn stat<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    with_ipfs(ipfs).and_then(stat_query)
+    with_ipfs(ipfs).and(cid_base()).and_then(stat_query)
 }
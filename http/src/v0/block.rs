@@ -1,15 +1,14 @@
 use crate::v0::support::{
-    try_only_named_multipart, with_ipfs, HandledErr, MaybeTimeoutExt, StreamResponse, StringError,
-    StringSerialized,
+    cid_base, cid_options, try_only_named_multipart, with_ipfs, CidBase, HandledErr,
+    MaybeTimeoutExt, StreamResponse, StringError, StringSerialized,
 };
 use bytes::Buf;
-use cid::{Cid, Codec, Version};
+use cid::{Cid, Codec};
 use futures::stream::{FuturesOrdered, Stream, StreamExt};
 use ipfs::error::Error;
-use ipfs::{Ipfs, IpfsTypes};
+use ipfs::{CidOptions, Ipfs, IpfsTypes};
 use mime::Mime;
 
-use multihash::Multihash;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use warp::{http::Response, query, reply, Filter, Rejection, Reply};
@@ -53,7 +52,11 @@ pub fn get<T: IpfsTypes>(
 #[derive(Debug, Deserialize)]
 pub struct PutQuery {
     format: Option<String>,
+    #[serde(alias = "hash")]
     mhtype: Option<String>,
+    /// Truncates the multihash digest to this many bytes.
+    mhlen: Option<usize>,
+    #[serde(alias = "cid-version")]
     version: Option<u8>,
 }
 
@@ -68,20 +71,12 @@ impl PutQuery {
         })
     }
 
-    fn digest(&self) -> Result<fn(&'_ [u8]) -> Multihash, Rejection> {
-        Ok(match self.mhtype.as_deref().unwrap_or("sha2-256") {
-            "sha2-256" => multihash::Sha2_256::digest,
-            "sha2-512" => multihash::Sha2_512::digest,
-            _ => return Err(StringError::from("unknown hash").into()),
-        })
-    }
-
-    fn version(&self) -> Result<Version, Rejection> {
-        Ok(match self.version.unwrap_or(0) {
-            0 => Version::V0,
-            1 => Version::V1,
-            _ => return Err(StringError::from("invalid cid version").into()),
-        })
+    fn cid_options(&self) -> Result<CidOptions, Rejection> {
+        Ok(cid_options(
+            self.mhtype.as_deref(),
+            self.version,
+            self.mhlen,
+        )?)
     }
 }
 
@@ -92,6 +87,7 @@ pub fn put<T: IpfsTypes>(
         .and(query::<PutQuery>())
         .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
         .and(warp::body::stream())
+        .and(cid_base())
         .and_then(inner_put)
 }
 
@@ -100,39 +96,34 @@ async fn inner_put<T: IpfsTypes>(
     opts: PutQuery,
     mime: Mime,
     body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let boundary = mime
         .get_param("boundary")
         .map(|v| v.to_string())
         .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
 
+    // the options are validated before receiving the body; cidv0 is only created for dag-pb
+    // blocks hashed with the full length sha2-256, which are the defaults.
+    let format = opts.format()?;
+    let cid_options = opts.cid_options()?;
+
     let data = try_only_named_multipart(&["data", "file"], 1024 * 1024, boundary, body)
         .await
         .map_err(StringError::from)?;
 
     // FIXME: digest calculation should be done in line with the reception of new blocks, but
     // because of the old multihash version we use, we don't at least yet have access to that api.
-    let digest = opts.digest()?(&data);
-
-    // cid generation can fail if we try some other hash or format with cidv0 which only supports
-    // SHA2-256 and dag-pb, both are even implicit. could be that these parameters we use here are
-    // not supported by go-ipfs or something, as in, the `block/put` should mostly return CidV0.
-    // Haven't researched this deeply.
-    let cid = Cid::new(opts.version()?, opts.format()?, digest).map_err(StringError::from)?;
-
-    // bad thing about Box<[u8]>: converting to it forces a reallocation; do it now that we know
-    // the cid was generated successfully.
     let data = data.into_boxed_slice();
-
     let size = data.len();
-    let key = cid.to_string();
-
-    let block = ipfs::Block { cid, data };
 
-    ipfs.put_block(block).await.map_err(StringError::from)?;
+    let cid = ipfs
+        .put_block_data(data, format, &cid_options)
+        .await
+        .map_err(StringError::from)?;
 
     Ok(reply::json(&serde_json::json!({
-        "Key": key,
+        "Key": cid_base.encode(&cid),
         "Size": size,
     })))
 }
@@ -150,7 +141,10 @@ pub struct EmptyResponse;
 pub fn rm<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    with_ipfs(ipfs).and(rm_options()).and_then(rm_query)
+    with_ipfs(ipfs)
+        .and(rm_options())
+        .and(cid_base())
+        .and_then(rm_query)
 }
 
 fn rm_options() -> impl Filter<Extract = (RmOptions,), Error = Rejection> + Clone {
@@ -166,6 +160,7 @@ fn rm_options() -> impl Filter<Extract = (RmOptions,), Error = Rejection> + Clon
 async fn rm_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     options: RmOptions,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     use futures::future::TryFutureExt;
 
@@ -188,11 +183,11 @@ async fn rm_query<T: IpfsTypes>(
         .into_iter()
         .map(move |result| match result {
             Ok(cid) => RmResponse {
-                hash: cid.to_string(),
+                hash: cid_base.encode(&cid),
                 error: "".to_string(),
             },
             Err((cid, e)) => RmResponse {
-                hash: cid.to_string(),
+                hash: cid_base.encode(&cid),
                 error: if force { "".to_string() } else { e.to_string() },
             },
         })
@@ -221,12 +216,14 @@ pub fn stat<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(query::<GetStatOptions>())
+        .and(cid_base())
         .and_then(stat_query)
 }
 
 async fn stat_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     query: GetStatOptions,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let cid: Cid = query.arg.parse().map_err(StringError::from)?;
     let block = ipfs
@@ -237,7 +234,7 @@ async fn stat_query<T: IpfsTypes>(
         .map_err(StringError::from)?;
 
     Ok(reply::json(&serde_json::json!({
-        "Key": query.arg,
+        "Key": cid_base.base().map(|_| cid_base.encode(&cid)).unwrap_or(query.arg),
         "Size": block.data().len(),
     })))
 }
@@ -0,0 +1,335 @@
+//! `cid/*` as per https://docs.ipfs.io/reference/http/api/#api-v0-cid-base32 and the following,
+//! for inspecting and converting Cids. None of these need the node.
+use crate::v0::support::option_parsing::ParseError;
+use crate::v0::support::{HandledErr, StreamResponse, StringError};
+use cid::{Cid, Codec, Version};
+use multibase::Base;
+use serde::Serialize;
+use std::convert::TryFrom;
+use warp::{Filter, Rejection, Reply};
+
+/// Line of the `cid/format` and `cid/base32` responses.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CidResponse {
+    cid_str: String,
+    formatted: String,
+    error_msg: String,
+}
+
+impl CidResponse {
+    fn new(cid_str: String, res: Result<String, String>) -> Self {
+        let (formatted, error_msg) = match res {
+            Ok(formatted) => (formatted, String::new()),
+            Err(e) => (String::new(), e),
+        };
+
+        CidResponse {
+            cid_str,
+            formatted,
+            error_msg,
+        }
+    }
+}
+
+fn to_ndjson(responses: Vec<CidResponse>) -> impl Reply {
+    let lines = responses.into_iter().map(|response| {
+        serde_json::to_string(&response)
+            .map(|mut s| {
+                s.push('\n');
+                s
+            })
+            .map_err(|e| {
+                error!("cid response serialization failed: {}", e);
+                HandledErr
+            })
+    });
+
+    StreamResponse(futures::stream::iter(lines))
+}
+
+#[derive(Debug)]
+struct FormatRequest {
+    args: Vec<String>,
+    /// The printf-style template, defaults to `%s`
+    format: Option<String>,
+    version: Option<Version>,
+    codec: Option<Codec>,
+    base: Option<Base>,
+}
+
+impl<'a> TryFrom<&'a str> for FormatRequest {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::new();
+        let mut format = None;
+        let mut version = None;
+        let mut codec = None;
+        let mut base = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "arg" => {
+                    args.push(value.into_owned());
+                }
+                "f" => {
+                    if format.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    format = Some(value.into_owned());
+                }
+                "v" => {
+                    if version.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    version = Some(match &*value {
+                        "0" => Version::V0,
+                        "1" => Version::V1,
+                        _ => return Err(InvalidValue(key, value)),
+                    });
+                }
+                // the newer go-ipfs calls this `mc` but older accepted `codec`
+                "mc" | "codec" => {
+                    if codec.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    codec =
+                        Some(ipfs::cid::codec_from_name(&value).ok_or(InvalidValue(key, value))?);
+                }
+                "b" => {
+                    if base.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    base = Some(ipfs::cid::base_from_name(&value).ok_or(InvalidValue(key, value))?);
+                }
+                _ => {
+                    // ignore unknown
+                }
+            }
+        }
+
+        if args.is_empty() {
+            return Err(MissingArg);
+        }
+
+        Ok(FormatRequest {
+            args,
+            format,
+            version,
+            codec,
+            base,
+        })
+    }
+}
+
+impl FormatRequest {
+    fn format_one(&self, arg: &str) -> Result<String, String> {
+        let decoded = ipfs::cid::decode(arg).map_err(|e| e.to_string())?;
+        let mut cid = decoded.cid;
+
+        // changing the codec always creates a version 1 Cid
+        if let Some(codec) = self.codec.filter(|&codec| codec != cid.codec()) {
+            cid = Cid::new_v1(codec, cid.hash().to_owned());
+        }
+
+        // like in go-ipfs, giving only a base other than base58btc upgrades version 0 Cids
+        let version = match (self.version, self.base) {
+            (Some(version), _) => Some(version),
+            (None, Some(base)) if base != Base::Base58Btc => Some(Version::V1),
+            (None, _) => None,
+        };
+
+        if let Some(version) = version {
+            cid = ipfs::cid::convert(&cid, version).map_err(|e| e.to_string())?;
+        }
+
+        let base = match self.base {
+            Some(base) => base,
+            None if cid.version() == Version::V0 => Base::Base58Btc,
+            None => decoded.base,
+        };
+
+        ipfs::cid::format(&cid, self.format.as_deref().unwrap_or("%s"), base)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn format_options() -> impl Filter<Extract = (FormatRequest,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = FormatRequest::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn format_inner(req: FormatRequest) -> Result<impl Reply, Rejection> {
+    let responses = req
+        .args
+        .iter()
+        .map(|arg| CidResponse::new(arg.to_owned(), req.format_one(arg)))
+        .collect();
+
+    Ok(to_ndjson(responses))
+}
+
+/// `cid/format`, formatting the Cids with a printf-style template after optionally converting
+/// them to another version, codec or multibase.
+pub fn format() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    format_options().and_then(format_inner)
+}
+
+#[derive(Debug)]
+struct Base32Request {
+    args: Vec<String>,
+}
+
+impl<'a> TryFrom<&'a str> for Base32Request {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        let args = url::form_urlencoded::parse(q.as_bytes())
+            .filter(|(key, _)| key == "arg")
+            .map(|(_, value)| value.into_owned())
+            .collect::<Vec<_>>();
+
+        if args.is_empty() {
+            return Err(ParseError::MissingArg);
+        }
+
+        Ok(Base32Request { args })
+    }
+}
+
+fn base32_options() -> impl Filter<Extract = (Base32Request,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = Base32Request::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn base32_inner(req: Base32Request) -> Result<impl Reply, Rejection> {
+    let responses = req
+        .args
+        .into_iter()
+        .map(|arg| {
+            let res = Cid::try_from(arg.as_str())
+                .map(|cid| ipfs::cid::to_base32(&cid))
+                .map_err(|e| e.to_string());
+            CidResponse::new(arg, res)
+        })
+        .collect();
+
+    Ok(to_ndjson(responses))
+}
+
+/// `cid/base32`, converting the Cids to version 1 and encoding them in base32.
+pub fn base32() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    base32_options().and_then(base32_inner)
+}
+
+/// Item of the `cid/bases`, `cid/codecs` and `cid/hashes` responses.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CodeAndName {
+    code: u64,
+    name: &'static str,
+}
+
+fn code_and_names(list: Vec<ipfs::cid::CodeAndName>) -> impl Reply {
+    let list = list
+        .into_iter()
+        .map(|item| CodeAndName {
+            code: item.code,
+            name: item.name,
+        })
+        .collect::<Vec<_>>();
+
+    warp::reply::json(&list)
+}
+
+/// `cid/bases`, listing the supported multibases with their prefix characters as the codes.
+pub fn bases() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::any().map(|| code_and_names(ipfs::cid::bases()))
+}
+
+/// `cid/codecs`, listing the supported codecs.
+pub fn codecs() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::any().map(|| code_and_names(ipfs::cid::codecs()))
+}
+
+/// `cid/hashes`, listing the supported hash functions.
+pub fn hashes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::any().map(|| code_and_names(ipfs::cid::hashes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormatRequest;
+    use std::convert::TryFrom;
+
+    const CIDV0: &str = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+    const CIDV1: &str = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    fn format(query: &str, arg: &str) -> Result<String, String> {
+        FormatRequest::try_from(query).unwrap().format_one(arg)
+    }
+
+    #[test]
+    fn format_request_requires_arg() {
+        FormatRequest::try_from("f=%s").unwrap_err();
+        FormatRequest::try_from("arg=foo&v=2").unwrap_err();
+        FormatRequest::try_from("arg=foo&b=base31").unwrap_err();
+        FormatRequest::try_from("arg=foo&mc=foobar").unwrap_err();
+    }
+
+    #[test]
+    fn format_conversions() {
+        assert_eq!(format("arg=x", CIDV0).unwrap(), CIDV0);
+        assert_eq!(format("arg=x&v=1", CIDV0).unwrap(), CIDV1);
+        assert_eq!(format("arg=x&b=base32", CIDV0).unwrap(), CIDV1);
+        assert_eq!(format("arg=x&v=0", CIDV1).unwrap(), CIDV0);
+        assert_eq!(
+            format("arg=x&f=%25P", CIDV0).unwrap(),
+            "cidv0-dag-pb-sha2-256-32"
+        );
+        assert_eq!(
+            format("arg=x&mc=raw&f=%25v-%25c", CIDV0).unwrap(),
+            "cidv1-raw"
+        );
+
+        // a raw cid cannot be version 0
+        format("arg=x&mc=raw&v=0", CIDV0).unwrap_err();
+        format("arg=x", "foobar").unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn base32_lines() {
+        let response = warp::test::request()
+            .path(&format!("/cid/base32?arg={}&arg=foobar", CIDV0))
+            .reply(&super::base32())
+            .await;
+
+        assert_eq!(response.status(), 200);
+
+        let lines = std::str::from_utf8(response.body())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["CidStr"], CIDV0);
+        assert_eq!(lines[0]["Formatted"], CIDV1);
+        assert_eq!(lines[0]["ErrorMsg"], "");
+        assert_eq!(lines[1]["Formatted"], "");
+        assert_ne!(lines[1]["ErrorMsg"], "");
+    }
+}
@@ -1,8 +1,8 @@
 use crate::v0::support::{
-    try_only_named_multipart, with_ipfs, MaybeTimeoutExt, NotImplemented, StringError,
-    StringSerialized,
+    cid_base, cid_options, try_only_named_multipart, with_ipfs, CidBase, MaybeTimeoutExt,
+    NotImplemented, StringError, StringSerialized,
 };
-use cid::{Cid, Codec};
+use cid::Codec;
 use futures::stream::Stream;
 use ipfs::{Ipfs, IpfsTypes};
 use mime::Mime;
@@ -15,8 +15,15 @@ use warp::{query, reply, Buf, Filter, Rejection, Reply};
 pub struct PutQuery {
     format: Option<String>,
     hash: Option<String>,
+    #[serde(rename = "cid-version")]
+    cid_version: Option<u8>,
+    /// Truncates the multihash digest to this many bytes.
+    mhlen: Option<usize>,
     #[serde(rename = "input-enc", default)]
     encoding: InputEncoding,
+    /// Pin the block recursively. The block and the pin are committed together.
+    #[serde(default)]
+    pin: bool,
 }
 
 #[derive(PartialEq, Eq, Debug, Deserialize)]
@@ -40,6 +47,7 @@ pub fn put<T: IpfsTypes>(
         .and(query::<PutQuery>())
         .and(warp::header::<Mime>("content-type")) // TODO: rejects if missing
         .and(warp::body::stream())
+        .and(cid_base())
         .and_then(put_query)
 }
 
@@ -48,27 +56,22 @@ async fn put_query<T: IpfsTypes>(
     query: PutQuery,
     mime: Mime,
     body: impl Stream<Item = Result<impl Buf, warp::Error>> + Unpin,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
-    use multihash::{Multihash, Sha2_256, Sha2_512, Sha3_512};
-
     if query.encoding != InputEncoding::Raw {
         return Err(NotImplemented.into());
     }
 
-    let (format, v0_fmt) = match query.format.as_deref().unwrap_or("dag-cbor") {
-        "dag-cbor" => (Codec::DagCBOR, false),
-        "dag-pb" => (Codec::DagProtobuf, true),
-        "dag-json" => (Codec::DagJSON, false),
-        "raw" => (Codec::Raw, false),
+    let format = match query.format.as_deref().unwrap_or("dag-cbor") {
+        "dag-cbor" => Codec::DagCBOR,
+        "dag-pb" => Codec::DagProtobuf,
+        "dag-json" => Codec::DagJSON,
+        "raw" => Codec::Raw,
         _ => return Err(StringError::from("unknown codec").into()),
     };
 
-    let (hasher, v0_hash) = match query.hash.as_deref().unwrap_or("sha2-256") {
-        "sha2-256" => (Sha2_256::digest as fn(&[u8]) -> Multihash, true),
-        "sha2-512" => (Sha2_512::digest as fn(&[u8]) -> Multihash, false),
-        "sha3-512" => (Sha3_512::digest as fn(&[u8]) -> Multihash, false),
-        _ => return Err(StringError::from("unknown hash").into()),
-    };
+    // like in go-ipfs and js-ipfs, the default sha2-256 creates a v0 cid for dag-pb
+    let cid_options = cid_options(query.hash.as_deref(), query.cid_version, query.mhlen)?;
 
     let boundary = mime
         .get_param("boundary")
@@ -79,24 +82,25 @@ async fn put_query<T: IpfsTypes>(
         .await
         .map_err(StringError::from)?;
 
-    let digest = hasher(&data);
-
-    let cid = if v0_fmt && v0_hash {
-        // this is quite ugly way but apparently js-ipfs generates a v0 cid for this combination
-        // which is also created by go-ipfs
-        Cid::new_v0(digest).expect("cidv0 creation cannot fail for dag-pb and sha2-256")
-    } else {
-        Cid::new_v1(format, digest)
-    };
+    let cid = cid_options
+        .try_cid(format, &data)
+        .map_err(StringError::from)?;
 
     let reply = json!({
-        "Cid": { "/": cid.to_string() }
+        "Cid": { "/": cid_base.encode(&cid) }
     });
 
     // delay reallocation until cid has been generated
     let data = data.into_boxed_slice();
     let block = ipfs::Block { cid, data };
-    ipfs.put_block(block).await.map_err(StringError::from)?;
+
+    let mut batch = ipfs.batch();
+    let cid = batch.put_block(block).await.map_err(StringError::from)?;
+    if query.pin {
+        batch.insert_pin(&cid, true);
+    }
+    batch.commit().await.map_err(StringError::from)?;
+
     Ok(reply::json(&reply))
 }
 
@@ -108,6 +112,7 @@ pub fn resolve<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(query::<ResolveOptions>())
+        .and(cid_base())
         .and_then(inner_resolve)
 }
 
@@ -122,6 +127,7 @@ struct ResolveOptions {
 async fn inner_resolve<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     opts: ResolveOptions,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     use ipfs::IpfsPath;
     use std::convert::TryFrom;
@@ -143,7 +149,7 @@ async fn inner_resolve<T: IpfsTypes>(
     let current = resolved.source();
 
     Ok(reply::json(&json!({
-        "Cid": { "/": current.to_string() },
+        "Cid": { "/": cid_base.encode(current) },
         "RemPath": StringSerialized(remaining),
     })))
 }
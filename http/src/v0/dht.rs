@@ -1,8 +1,19 @@
-use crate::v0::support::{with_ipfs, MaybeTimeoutExt, StringError, StringSerialized};
-use ipfs::{Cid, Ipfs, IpfsTypes, PeerId};
+use crate::v0::support::option_parsing::ParseError;
+use crate::v0::support::{
+    cid_base, with_ipfs, CidBase, HandledErr, MaybeTimeoutExt, StreamResponse, StringError,
+    StringSerialized,
+};
+use futures::stream::{StreamExt, TryStreamExt};
+use ipfs::{Cid, Ipfs, IpfsTypes, PeerId, Quorum};
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use warp::{query, Filter, Rejection, Reply};
 
+/// The `routing.QueryEventType` of go-ipfs for the errors.
+const QUERY_ERROR: usize = 3;
+/// The `routing.QueryEventType` of go-ipfs for the found or put values.
+const VALUE: usize = 5;
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct Response {
@@ -95,19 +106,19 @@ async fn find_providers_query<T: IpfsTypes>(
         timeout,
     } = query;
     let cid = arg.into_inner();
+    // the lookups are stopped once enough providers have been found
     let providers = ipfs
-        .get_providers(cid)
+        .get_providers_stream(cid)
+        .take(if let Some(n) = num_providers { n } else { 20 })
+        .map_ok(|info| ResponsesMember {
+            addrs: info.addrs.iter().map(|addr| addr.to_string()).collect(),
+            id: info.peer_id.to_string(),
+        })
+        .try_collect::<Vec<_>>()
         .maybe_timeout(timeout.map(StringSerialized::into_inner))
         .await
         .map_err(StringError::from)?
-        .map_err(StringError::from)?
-        .into_iter()
-        .take(if let Some(n) = num_providers { n } else { 20 })
-        .map(|peer_id| ResponsesMember {
-            addrs: vec![],
-            id: peer_id.to_string(),
-        })
-        .collect();
+        .map_err(StringError::from)?;
 
     // FIXME: go-ipfs returns just a list of PeerIds
     let response = Response {
@@ -139,6 +150,7 @@ pub struct ProvideQuery {
 async fn provide_query<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     query: ProvideQuery,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let ProvideQuery {
         arg,
@@ -154,7 +166,7 @@ async fn provide_query<T: IpfsTypes>(
 
     let response = Response {
         extra: Default::default(),
-        id: cid.to_string(),
+        id: cid_base.encode(&cid),
         responses: vec![],
         r#type: 2,
     };
@@ -167,6 +179,7 @@ pub fn provide<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(query::<ProvideQuery>())
+        .and(cid_base())
         .and_then(provide_query)
 }
 
@@ -219,3 +232,277 @@ pub fn get_closest_peers<T: IpfsTypes>(
         .and(query::<GetClosestPeersQuery>())
         .and_then(get_closest_peers_query)
 }
+
+impl Response {
+    fn event(r#type: usize, extra: String) -> Self {
+        Response {
+            extra,
+            id: Default::default(),
+            responses: vec![],
+            r#type,
+        }
+    }
+}
+
+/// Streams the query events as newline delimited json like go-ipfs. The events are only known
+/// after the query has completed.
+fn query_events(events: Vec<Response>) -> impl Reply {
+    let lines = events.into_iter().map(|event| {
+        serde_json::to_string(&event)
+            .map(|mut s| {
+                s.push('\n');
+                s
+            })
+            .map_err(|e| {
+                error!("dht query event serialization failed: {}", e);
+                HandledErr
+            })
+    });
+
+    StreamResponse(futures::stream::iter(lines))
+}
+
+/// Converts the `/namespace/<peer id>` key to the `/namespace/<peer id bytes>` used in the DHT,
+/// like go-ipfs does.
+fn dht_key(key: &str) -> Result<Vec<u8>, StringError> {
+    let mut parts = key.split('/');
+
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some(namespace), Some(peer_id), None) if !namespace.is_empty() => {
+            let peer_id = peer_id
+                .parse::<PeerId>()
+                .map_err(|_| StringError::from("invalid key"))?;
+            let mut key = format!("/{}/", namespace).into_bytes();
+            key.extend_from_slice(peer_id.as_bytes());
+            Ok(key)
+        }
+        _ => Err(StringError::from("invalid key")),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetValueQuery {
+    arg: String,
+    // FIXME: in go-ipfs this returns a lot of logs
+    verbose: Option<bool>,
+    timeout: Option<StringSerialized<humantime::Duration>>,
+}
+
+async fn get_value_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: GetValueQuery,
+) -> Result<impl Reply, Rejection> {
+    let GetValueQuery {
+        arg,
+        verbose: _,
+        timeout,
+    } = query;
+    let key = dht_key(&arg)?;
+
+    let res = ipfs
+        .dht_get(key, Quorum::One)
+        .maybe_timeout(timeout.map(StringSerialized::into_inner))
+        .await
+        .map_err(StringError::from)?;
+
+    // the values come with the best one first
+    let event = match res {
+        Ok(values) if !values.is_empty() => {
+            Response::event(VALUE, multibase::Base::Base64Pad.encode(&values[0]))
+        }
+        Ok(_) => Response::event(QUERY_ERROR, "routing: not found".into()),
+        Err(e) => Response::event(QUERY_ERROR, e.to_string()),
+    };
+
+    Ok(query_events(vec![event]))
+}
+
+/// `dht/get`, returning the best value found for the key as base64 in the `Extra` of a value
+/// event.
+pub fn get_value<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<GetValueQuery>())
+        .and_then(get_value_query)
+}
+
+#[derive(Debug)]
+struct PutValueQuery {
+    key: String,
+    value: Vec<u8>,
+    timeout: Option<humantime::Duration>,
+}
+
+/// Decodes the `application/x-www-form-urlencoded` query value into bytes. Unlike
+/// `url::form_urlencoded::parse` this keeps the binary values, such as the signed records, intact.
+fn decode_bytes(value: &str) -> Vec<u8> {
+    let value = value.replace('+', " ");
+    percent_encoding::percent_decode_str(&value).collect()
+}
+
+impl<'a> TryFrom<&'a str> for PutValueQuery {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::with_capacity(2);
+        let mut timeout = None;
+
+        for pair in q.split('&').filter(|pair| !pair.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or_default();
+            let value = decode_bytes(parts.next().unwrap_or_default());
+
+            match key {
+                "arg" => args.push(value),
+                "timeout" => {
+                    if timeout.is_some() {
+                        return Err(DuplicateField(key.into()));
+                    }
+                    timeout = Some(
+                        String::from_utf8_lossy(&value)
+                            .parse()
+                            .map_err(|e| InvalidDuration("timeout".into(), e))?,
+                    );
+                }
+                _ => {
+                    // ignore unknown
+                }
+            }
+        }
+
+        let mut args = args.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            (Some(key), Some(value), None) => Ok(PutValueQuery {
+                key: String::from_utf8(key)
+                    .map_err(|e| InvalidValue("arg".into(), e.to_string().into()))?,
+                value,
+                timeout,
+            }),
+            (Some(_), None, _) => Err(MissingField("arg".into())),
+            (None, _, _) => Err(MissingArg),
+            (Some(_), Some(_), Some(_)) => Err(DuplicateField("arg".into())),
+        }
+    }
+}
+
+fn put_value_options() -> impl Filter<Extract = (PutValueQuery,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = PutValueQuery::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn put_value_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: PutValueQuery,
+) -> Result<impl Reply, Rejection> {
+    let PutValueQuery {
+        key,
+        value,
+        timeout,
+    } = query;
+    let key = dht_key(&key)?;
+
+    let res = ipfs
+        .dht_put(key, value.into_bytes(), Quorum::One)
+        .maybe_timeout(timeout)
+        .await
+        .map_err(StringError::from)?;
+
+    // the peers the record was stored at are not known, unlike in go-ipfs where there is an event
+    // for each of them
+    let event = match res {
+        Ok(()) => Response::event(VALUE, String::new()),
+        Err(e) => Response::event(QUERY_ERROR, e.to_string()),
+    };
+
+    Ok(query_events(vec![event]))
+}
+
+/// `dht/put`, storing the value given as the second argument for the key after validating it.
+pub fn put_value<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(put_value_options())
+        .and_then(put_value_query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dht_key, PutValueQuery};
+    use ipfs::PeerId;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn dht_keys() {
+        let peer_id = PeerId::random();
+
+        let mut expected = b"/pk/".to_vec();
+        expected.extend_from_slice(peer_id.as_bytes());
+        assert_eq!(dht_key(&format!("/pk/{}", peer_id)).unwrap(), expected);
+
+        dht_key("/pk/foobar").unwrap_err();
+        dht_key(&format!("pk/{}", peer_id)).unwrap_err();
+        dht_key(&format!("/pk/{}/foo", peer_id)).unwrap_err();
+    }
+
+    #[test]
+    fn put_requires_key_and_value() {
+        let query = PutValueQuery::try_from("arg=/pk/foo&arg=bar&timeout=10s").unwrap();
+        assert_eq!(query.key, "/pk/foo");
+        assert_eq!(query.value, b"bar");
+
+        PutValueQuery::try_from("arg=/pk/foo").unwrap_err();
+        PutValueQuery::try_from("arg=/pk/foo&arg=bar&arg=baz").unwrap_err();
+    }
+
+    #[test]
+    fn put_value_is_binary() {
+        let query = PutValueQuery::try_from("arg=%2Fpk%2Ffoo&arg=%00%FF+%C3").unwrap();
+        assert_eq!(query.key, "/pk/foo");
+        assert_eq!(query.value, b"\x00\xff \xc3");
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn put_signed_ipns_record() {
+        use ipfs::{IpfsOptions, IpfsPath, Quorum, TestTypes, UninitializedIpfs};
+        use std::time::Duration;
+
+        let options = IpfsOptions::inmemory_with_generated_keys();
+        let keypair = options.keypair.clone();
+        let peer_id = keypair.public().into_peer_id();
+        let (ipfs, fut) = UninitializedIpfs::<TestTypes>::new(options)
+            .start()
+            .await
+            .unwrap();
+        tokio::spawn(fut);
+
+        let path = "/ipfs/QmbWqxBEKC3P8tqsKc98xmWNzrzDtRLMiMPL8wBuTGsMnR"
+            .parse::<IpfsPath>()
+            .unwrap();
+        let record =
+            ipfs::ipns::create_record(&keypair, &path, 0, Duration::from_secs(60)).unwrap();
+        let value = url::form_urlencoded::byte_serialize(&record).collect::<String>();
+
+        let response = warp::test::request()
+            .path(&format!("/dht/put?arg=/ipns/{}&arg={}", peer_id, value))
+            .reply(&super::put_value(&ipfs))
+            .await;
+
+        assert_eq!(response.status(), 200);
+        let body = std::str::from_utf8(response.body()).unwrap();
+        assert!(!body.contains("invalid record"), "{}", body);
+
+        // there are no peers to put the record to, but it is stored locally
+        let mut key = b"/ipns/".to_vec();
+        key.extend_from_slice(peer_id.as_bytes());
+        assert_eq!(ipfs.dht_get(key, Quorum::One).await.unwrap(), vec![record]);
+    }
+}
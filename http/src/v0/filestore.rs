@@ -0,0 +1,100 @@
+use crate::v0::support::{cid_base, with_ipfs, CidBase, HandledErr, StringError};
+use futures::stream;
+use ipfs::{Cid, FileReference, FilestoreStatus, Ipfs, IpfsTypes};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use warp::hyper::Body;
+use warp::{Filter, Rejection, Reply};
+
+/// Response line of `filestore/ls` and `filestore/verify` as in go-ipfs.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListResponse {
+    status: u8,
+    error_msg: String,
+    key: Value,
+    file_path: PathBuf,
+    offset: u64,
+    size: u64,
+}
+
+impl ListResponse {
+    fn new(
+        cid: Cid,
+        reference: FileReference,
+        status: FilestoreStatus,
+        cid_base: &CidBase,
+    ) -> Self {
+        let error_msg = match status {
+            FilestoreStatus::Ok => String::new(),
+            ref other => other.to_string(),
+        };
+        ListResponse {
+            status: status.code(),
+            error_msg,
+            key: json!({ "/": cid_base.encode(&cid) }),
+            file_path: reference.path,
+            offset: reference.offset,
+            size: reference.length,
+        }
+    }
+}
+
+fn to_ndjson(responses: Vec<ListResponse>) -> warp::reply::Response {
+    let lines = responses.into_iter().map(|response| {
+        serde_json::to_string(&response)
+            .map(|mut s| {
+                s.push('\n');
+                s
+            })
+            .map_err(|e| {
+                error!("filestore response serialization failed: {}", e);
+                HandledErr
+            })
+    });
+
+    warp::reply::Response::new(Body::wrap_stream(stream::iter(lines)))
+}
+
+async fn ls_query<T: IpfsTypes>(ipfs: Ipfs<T>, cid_base: CidBase) -> Result<impl Reply, Rejection> {
+    let responses = ipfs
+        .filestore_ls()
+        .await
+        .map_err(StringError::from)?
+        .into_iter()
+        // listing does not verify the content, similar to go-ipfs
+        .map(|(cid, reference)| ListResponse::new(cid, reference, FilestoreStatus::Ok, &cid_base))
+        .collect();
+
+    Ok(to_ndjson(responses))
+}
+
+/// Lists the blocks stored as references to files.
+pub fn ls<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(cid_base()).and_then(ls_query)
+}
+
+async fn verify_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    let responses = ipfs
+        .filestore_verify()
+        .await
+        .map_err(StringError::from)?
+        .into_iter()
+        .map(|(cid, reference, status)| ListResponse::new(cid, reference, status, &cid_base))
+        .collect();
+
+    Ok(to_ndjson(responses))
+}
+
+/// Lists the blocks stored as references to files along with the status of the referenced files.
+pub fn verify<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(cid_base()).and_then(verify_query)
+}
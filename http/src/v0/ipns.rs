@@ -1,8 +1,12 @@
 use crate::v0::support::{with_ipfs, StringError, StringSerialized};
-use ipfs::{Ipfs, IpfsPath, IpfsTypes};
+use ipfs::{Ipfs, IpfsPath, IpfsTypes, PeerId};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use warp::{query, Filter, Rejection, Reply};
 
+/// The default lifetime of the published records, like in go-ipfs.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Debug, Deserialize)]
 pub struct ResolveQuery {
     // the name to resolve
@@ -88,3 +92,156 @@ async fn dns_query<T: IpfsTypes>(ipfs: Ipfs<T>, query: DnsQuery) -> Result<impl
 struct DnsResponse {
     path: String,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct PublishQuery {
+    // the path to publish
+    arg: StringSerialized<IpfsPath>,
+    lifetime: Option<StringSerialized<humantime::Duration>>,
+}
+
+/// `name/publish`, publishing the path under the peer id of the node.
+pub fn publish<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PublishQuery>())
+        .and_then(publish_query)
+}
+
+async fn publish_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: PublishQuery,
+) -> Result<impl Reply, Rejection> {
+    let PublishQuery { arg, lifetime } = query;
+    let value = arg.into_inner();
+    let lifetime = lifetime
+        .map(|lifetime| *lifetime.into_inner())
+        .unwrap_or(DEFAULT_LIFETIME);
+
+    let name = ipfs
+        .publish_ipns(&value, lifetime)
+        .await
+        .map_err(StringError::from)?;
+
+    // go-ipfs responds with the plain peer id
+    let name = name.to_string().trim_start_matches("/ipns/").to_owned();
+
+    let response = PublishResponse {
+        name,
+        value: value.to_string(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PublishResponse {
+    name: String,
+    value: String,
+}
+
+/// `name/pubsub/state`, telling if IPNS over pubsub is enabled.
+pub fn pubsub_state<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(pubsub_state_query)
+}
+
+async fn pubsub_state_query<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let response = PubsubStateResponse {
+        enabled: ipfs.ipns_pubsub_enabled(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PubsubStateResponse {
+    enabled: bool,
+}
+
+/// `name/pubsub/subs`, listing the names subscribed to over pubsub.
+pub fn pubsub_subs<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(pubsub_subs_query)
+}
+
+async fn pubsub_subs_query<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let strings = ipfs
+        .ipns_pubsub_subscriptions()
+        .map_err(StringError::from)?
+        .into_iter()
+        .map(|name| IpfsPath::from(name).to_string())
+        .collect();
+
+    let response = PubsubSubsResponse { strings };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PubsubSubsResponse {
+    strings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PubsubCancelQuery {
+    // the name, with or without the /ipns/ prefix
+    arg: String,
+}
+
+/// `name/pubsub/cancel`, canceling the pubsub subscription to a name.
+pub fn pubsub_cancel<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<PubsubCancelQuery>())
+        .and_then(pubsub_cancel_query)
+}
+
+fn parse_name(arg: &str) -> Result<PeerId, StringError> {
+    arg.trim_start_matches("/ipns/")
+        .parse::<PeerId>()
+        .map_err(|_| StringError::from(format!("invalid name {:?}", arg)))
+}
+
+async fn pubsub_cancel_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: PubsubCancelQuery,
+) -> Result<impl Reply, Rejection> {
+    let name = parse_name(&query.arg)?;
+
+    let canceled = ipfs.ipns_pubsub_cancel(&name).map_err(StringError::from)?;
+
+    let response = PubsubCancelResponse { canceled };
+
+    Ok(warp::reply::json(&response))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PubsubCancelResponse {
+    canceled: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_name;
+
+    #[test]
+    fn names_with_and_without_prefix() {
+        let id = "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN";
+
+        assert_eq!(parse_name(id).unwrap().to_string(), id);
+        assert_eq!(
+            parse_name(&format!("/ipns/{}", id)).unwrap().to_string(),
+            id
+        );
+        parse_name("/ipns/example.com").unwrap_err();
+    }
+}
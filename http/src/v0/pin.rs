@@ -1,6 +1,6 @@
 use crate::v0::support::option_parsing::ParseError;
-use crate::v0::support::{with_ipfs, StringError, StringSerialized};
-use ipfs::{Cid, Ipfs, IpfsTypes, PinKind, PinMode};
+use crate::v0::support::{cid_base, with_ipfs, CidBase, StringError, StringSerialized};
+use ipfs::{Cid, InterruptedPin, Ipfs, IpfsTypes, PinKind, PinMode, PinVerifyStatus};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -9,6 +9,7 @@ use std::str::FromStr;
 use warp::{Filter, Rejection, Reply};
 
 mod add;
+pub mod remote;
 
 /// `pin/add` per https://docs.ipfs.io/reference/http/api/#api-v0-pin-add or the
 /// interface-ipfs-http test suite.
@@ -17,6 +18,7 @@ pub fn add<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(add::add_request())
+        .and(cid_base())
         .and_then(add::add_inner)
 }
 
@@ -29,6 +31,10 @@ struct ListRequest {
     quiet: bool,
     // FIXME copypaste
     stream: bool,
+    /// Only the pins with this name are listed
+    name: Option<String>,
+    /// Include the names of the pins in the response
+    names: bool,
     timeout: Option<humantime::Duration>,
 }
 
@@ -45,6 +51,8 @@ impl<'a> TryFrom<&'a str> for ListRequest {
         let mut filter = None;
         let mut quiet = None;
         let mut stream = None;
+        let mut name = None;
+        let mut names = None;
         let mut timeout = None;
 
         for (key, value) in parse {
@@ -82,8 +90,17 @@ impl<'a> TryFrom<&'a str> for ListRequest {
                             return Err(DuplicateField(key));
                         }
                     }
+                    "name" => {
+                        if name.is_none() {
+                            name = Some(value.into_owned());
+                            continue;
+                        } else {
+                            return Err(DuplicateField(key));
+                        }
+                    }
                     "quiet" => &mut quiet,
                     "stream" => &mut stream,
+                    "names" => &mut names,
                     _ => {
                         // ignore unknown fields
                         continue;
@@ -108,6 +125,8 @@ impl<'a> TryFrom<&'a str> for ListRequest {
             quiet: quiet.unwrap_or(false),
             // this default was mentioned in the pin/ls api
             stream: quiet.unwrap_or(true),
+            name,
+            names: names.unwrap_or(false),
             timeout,
         })
     }
@@ -159,7 +178,10 @@ impl PinFilter {
 pub fn list<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    with_ipfs(ipfs).and(list_options()).and_then(list_inner)
+    with_ipfs(ipfs)
+        .and(list_options())
+        .and(cid_base())
+        .and_then(list_inner)
 }
 
 fn list_options() -> impl Filter<Extract = (ListRequest,), Error = Rejection> + Clone {
@@ -175,40 +197,61 @@ fn list_options() -> impl Filter<Extract = (ListRequest,), Error = Rejection> +
 async fn list_inner<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     req: ListRequest,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     use futures::stream::{StreamExt, TryStreamExt};
 
     #[derive(serde::Serialize)]
     struct Good {
         #[serde(rename = "Cid")]
-        cid: StringSerialized<Cid>,
+        cid: String,
+        #[serde(rename = "Name", skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
         #[serde(rename = "Type")]
         mode: Cow<'static, str>,
     }
 
-    impl From<(Cid, Cow<'static, str>)> for Good {
-        fn from((cid, mode): (Cid, Cow<'static, str>)) -> Good {
-            Good {
-                cid: StringSerialized(cid),
-                mode,
+    // looks up the names for the responses if they were asked for
+    let into_good = {
+        let ipfs = ipfs.clone();
+        let names = req.names;
+        move |(cid, mode): (Cid, Cow<'static, str>)| {
+            let ipfs = ipfs.clone();
+            async move {
+                let name = if names {
+                    let metadata = ipfs.pin_metadata(&cid).await?;
+                    Some(metadata.and_then(|m| m.name).unwrap_or_default())
+                } else {
+                    None
+                };
+                Ok::<_, ipfs::Error>(Good {
+                    cid: cid_base.encode(&cid),
+                    name,
+                    mode,
+                })
             }
         }
-    }
+    };
 
     if req.arg.is_empty() {
-        let st = ipfs.list_pins(req.filter.to_mode()).await;
+        let st = match req.name {
+            Some(name) => ipfs.list_named_pins(req.filter.to_mode(), name).await,
+            None => ipfs.list_pins(req.filter.to_mode()).await,
+        };
 
         if req.stream {
-            let st = st.map_ok(|(cid, mode)| {
-                Good::from((
-                    cid,
-                    Cow::Borrowed(match mode {
-                        PinMode::Direct => "direct",
-                        PinMode::Indirect => "indirect",
-                        PinMode::Recursive => "recursive",
-                    }),
-                ))
-            });
+            let st = st
+                .map_ok(|(cid, mode)| {
+                    (
+                        cid,
+                        Cow::Borrowed(match mode {
+                            PinMode::Direct => "direct",
+                            PinMode::Indirect => "indirect",
+                            PinMode::Recursive => "recursive",
+                        }),
+                    )
+                })
+                .and_then(into_good);
 
             Ok(format_json_newline(st))
         } else {
@@ -223,16 +266,17 @@ async fn list_inner<T: IpfsTypes>(
 
         let requirement = req.filter.to_mode();
 
-        let details = ipfs
-            .query_pins(req.arg, requirement)
-            .await
-            .map_err(StringError::from)?;
+        let details = match req.name {
+            Some(name) => ipfs.query_named_pins(req.arg, requirement, name).await,
+            None => ipfs.query_pins(req.arg, requirement).await,
+        }
+        .map_err(StringError::from)?;
 
         if req.stream {
             let st = futures::stream::iter(details)
-                .map(Ok::<_, std::convert::Infallible>) // only done trying to match the types
-                .map_ok(|(cid, kind)| {
-                    Good::from((
+                .map(Ok::<_, ipfs::Error>) // only done trying to match the types
+                .map_ok(move |(cid, kind)| {
+                    (
                         cid,
                         match kind {
                             PinKind::Recursive(_) | PinKind::RecursiveIntention => {
@@ -240,11 +284,12 @@ async fn list_inner<T: IpfsTypes>(
                             }
                             PinKind::Direct => "direct".into(),
                             PinKind::IndirectFrom(cid) => {
-                                format!("indirect through {}", cid).into()
+                                format!("indirect through {}", cid_base.encode(&cid)).into()
                             }
                         },
-                    ))
-                });
+                    )
+                })
+                .and_then(into_good);
 
             Ok(format_json_newline(st))
         } else {
@@ -318,24 +363,352 @@ pub fn rm<T: IpfsTypes>(
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     with_ipfs(ipfs)
         .and(warp::query::<RemoveRequest>())
+        .and(cid_base())
         .and_then(rm_inner)
 }
 
 async fn rm_inner<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     req: RemoveRequest,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     ipfs.remove_pin(req.arg.as_ref(), req.recursive.unwrap_or(true))
         .await
         .map_err(StringError::from)?;
 
     Ok(warp::reply::json(&RemoveResponse {
-        pins: vec![req.arg],
+        pins: vec![cid_base.encode(req.arg.as_ref())],
     }))
 }
 
 #[derive(Debug, Serialize)]
 struct RemoveResponse {
     #[serde(rename = "Pins")]
-    pins: Vec<StringSerialized<Cid>>,
+    pins: Vec<String>,
+}
+
+#[derive(Debug)]
+struct UpdateRequest {
+    from: Cid,
+    to: Cid,
+}
+
+impl<'a> TryFrom<&'a str> for UpdateRequest {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = Vec::new();
+        let mut unpin = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "arg" => {
+                    args.push(Cid::try_from(&*value).map_err(|e| InvalidCid("arg".into(), e))?);
+                }
+                "unpin" => {
+                    if unpin.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    match value.parse::<bool>() {
+                        Ok(value) => unpin = Some(value),
+                        Err(_) => return Err(InvalidBoolean(key, value)),
+                    }
+                }
+                _ => {
+                    // ignore unknown
+                }
+            }
+        }
+
+        if unpin == Some(false) {
+            // the old pin is always moved to the new root
+            return Err(InvalidValue(
+                "unpin".into(),
+                "false is not supported".into(),
+            ));
+        }
+
+        match args.len() {
+            0 | 1 => Err(MissingArg),
+            2 => {
+                let to = args.pop().unwrap();
+                let from = args.pop().unwrap();
+                Ok(UpdateRequest { from, to })
+            }
+            _ => Err(InvalidValue("arg".into(), "expected two paths".into())),
+        }
+    }
+}
+
+/// `pin/update` as per https://docs.ipfs.io/reference/http/api/#api-v0-pin-update, moving a
+/// recursive pin from the first arg to the second.
+pub fn update<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(update_options())
+        .and(cid_base())
+        .and_then(update_inner)
+}
+
+fn update_options() -> impl Filter<Extract = (UpdateRequest,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = UpdateRequest::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn update_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: UpdateRequest,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    ipfs.update_pin(&req.from, &req.to)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(warp::reply::json(&RemoveResponse {
+        pins: vec![cid_base.encode(&req.from), cid_base.encode(&req.to)],
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyRequest {
+    /// Report also the pins which are ok
+    verbose: Option<bool>,
+    /// Leave out the bad nodes of the broken pins
+    quiet: Option<bool>,
+    /// Refetch the missing blocks, not supported by go-ipfs
+    repair: Option<bool>,
+}
+
+/// Line of the `pin/verify` response as in go-ipfs, with the repairs done as an extension.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct VerifyResponse {
+    cid: String,
+    pin_status: PinStatus,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    refetched: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interrupted: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PinStatus {
+    ok: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bad_nodes: Vec<BadNode>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct BadNode {
+    cid: String,
+    err: String,
+}
+
+impl VerifyResponse {
+    fn new(status: PinVerifyStatus, quiet: bool, cid_base: &CidBase) -> Self {
+        let ok = status.is_ok();
+        let bad_nodes = if quiet {
+            Vec::new()
+        } else {
+            status
+                .bad_nodes
+                .into_iter()
+                .map(|node| BadNode {
+                    cid: cid_base.encode(&node.cid),
+                    err: node.error,
+                })
+                .collect()
+        };
+
+        VerifyResponse {
+            cid: cid_base.encode(&status.cid),
+            pin_status: PinStatus { ok, bad_nodes },
+            refetched: status
+                .refetched
+                .iter()
+                .map(|cid| cid_base.encode(cid))
+                .collect(),
+            interrupted: status.interrupted.map(|interrupted| match interrupted {
+                InterruptedPin::Found => "found",
+                InterruptedPin::Completed => "completed",
+                InterruptedPin::Removed => "removed",
+            }),
+        }
+    }
+}
+
+/// `pin/verify` as per https://docs.ipfs.io/reference/http/api/#api-v0-pin-verify, checking
+/// that the blocks of the recursive pins are available and intact.
+pub fn verify<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(warp::query::<VerifyRequest>())
+        .and(cid_base())
+        .and_then(verify_inner)
+}
+
+async fn verify_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: VerifyRequest,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    use futures::stream::StreamExt;
+
+    let verbose = req.verbose.unwrap_or(false);
+    let quiet = req.quiet.unwrap_or(false);
+    let repair = req.repair.unwrap_or(false);
+
+    let st = async_stream::stream! {
+        let statuses = ipfs.verify_pins(repair);
+        futures::pin_mut!(statuses);
+
+        while let Some(status) = statuses.next().await {
+            match status {
+                // only the broken or repaired pins are reported by default
+                Ok(status)
+                    if !verbose
+                        && status.is_ok()
+                        && status.refetched.is_empty()
+                        && status.interrupted.is_none() => {}
+                Ok(status) => yield Ok(VerifyResponse::new(status, quiet, &cid_base)),
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    };
+
+    Ok(format_json_newline(st))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::tokio_ipfs;
+    use ipfs::{Ipld, PinMode};
+
+    #[tokio::test(max_threads = 1)]
+    async fn named_pin_add_ls_and_update() {
+        let ipfs = tokio_ipfs().await;
+
+        let leaf = ipfs.put_dag(Ipld::String("leaf".into())).await.unwrap();
+        let old = ipfs
+            .put_dag(Ipld::List(vec![Ipld::Link(leaf.clone())]))
+            .await
+            .unwrap();
+        let new = ipfs
+            .put_dag(Ipld::List(vec![Ipld::Link(leaf), Ipld::Integer(1)]))
+            .await
+            .unwrap();
+
+        let response = warp::test::request()
+            .path(&format!("/pin/add?arg={}&recursive=true&name=dataset", old))
+            .reply(&super::add(&ipfs))
+            .await;
+        assert_eq!(response.status(), 200);
+
+        let ls = |query: String| {
+            let ipfs = ipfs.clone();
+            async move {
+                let response = warp::test::request()
+                    .path(&format!("/pin/ls?{}", query))
+                    .reply(&super::list(&ipfs))
+                    .await;
+                String::from_utf8(response.body().to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(
+            ls("name=dataset&names=true".into()).await,
+            format!(
+                "{{\"Cid\":\"{}\",\"Name\":\"dataset\",\"Type\":\"recursive\"}}\n",
+                old
+            )
+        );
+
+        let response = warp::test::request()
+            .path(&format!("/pin/update?arg={}&arg={}", old, new))
+            .reply(&super::update(&ipfs))
+            .await;
+        assert_eq!(
+            std::str::from_utf8(response.body()).unwrap(),
+            format!("{{\"Pins\":[\"{}\",\"{}\"]}}", old, new)
+        );
+
+        assert_eq!(
+            ls("name=dataset".into()).await,
+            format!("{{\"Cid\":\"{}\",\"Type\":\"recursive\"}}\n", new)
+        );
+        assert!(!ipfs.is_pinned(&old).await.unwrap());
+
+        let pins = ipfs
+            .query_pins(vec![new], Some(PinMode::Recursive))
+            .await
+            .unwrap();
+        assert_eq!(pins.len(), 1);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn verify_reports_broken_pins() {
+        use ipfs::{Block, Cid};
+        use multihash::Sha2_256;
+
+        let ipfs = tokio_ipfs().await;
+
+        // stored under the cid of other content
+        let corrupt = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"corrupt"));
+        ipfs.put_block(Block::new(b"rotten".to_vec().into(), corrupt.clone()))
+            .await
+            .unwrap();
+
+        let good = ipfs.put_dag(Ipld::String("good".into())).await.unwrap();
+        let broken = ipfs
+            .put_dag(Ipld::List(vec![Ipld::Link(corrupt.clone())]))
+            .await
+            .unwrap();
+
+        ipfs.insert_pin(&good, true).await.unwrap();
+        ipfs.insert_pin(&broken, true).await.unwrap();
+
+        let response = warp::test::request()
+            .path("/pin/verify")
+            .reply(&super::verify(&ipfs))
+            .await;
+        assert_eq!(
+            std::str::from_utf8(response.body()).unwrap(),
+            format!(
+                "{{\"Cid\":\"{}\",\"PinStatus\":{{\"Ok\":false,\"BadNodes\":[{{\"Cid\":\"{}\",\"Err\":\"hash mismatch\"}}]}}}}\n",
+                broken, corrupt
+            )
+        );
+
+        let response = warp::test::request()
+            .path("/pin/verify?verbose=true&quiet=true")
+            .reply(&super::verify(&ipfs))
+            .await;
+        let mut lines = std::str::from_utf8(response.body())
+            .unwrap()
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        lines.sort();
+
+        let mut expected = vec![
+            format!("{{\"Cid\":\"{}\",\"PinStatus\":{{\"Ok\":false}}}}", broken),
+            format!("{{\"Cid\":\"{}\",\"PinStatus\":{{\"Ok\":true}}}}", good),
+        ];
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
 }
@@ -1,7 +1,7 @@
 use crate::v0::support::option_parsing::ParseError;
-use crate::v0::support::{StringError, StringSerialized};
+use crate::v0::support::{CidBase, StringError};
 use futures::future::try_join_all;
-use ipfs::{Cid, Ipfs, IpfsTypes};
+use ipfs::{Cid, Ipfs, IpfsTypes, PinMetadata};
 use serde::Serialize;
 use std::convert::TryFrom;
 use warp::{reply, Filter, Rejection, Reply};
@@ -11,13 +11,15 @@ pub struct AddRequest {
     args: Vec<Cid>,
     recursive: bool,
     progress: bool,
+    /// Name given to all of the pins
+    name: Option<String>,
     // TODO: timeout, probably with rollback semantics?
 }
 
 #[derive(Serialize)]
 struct AddResponse {
     #[serde(rename = "Pins")]
-    pins: Vec<StringSerialized<Cid>>,
+    pins: Vec<String>,
     // FIXME: go-ipfs doesn't respond with this
     //progress: u8,
 }
@@ -25,6 +27,7 @@ struct AddResponse {
 pub async fn add_inner<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     request: AddRequest,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     if request.progress {
         // FIXME: there doesn't appear to be a test for this
@@ -34,11 +37,17 @@ pub async fn add_inner<T: IpfsTypes>(
     let cids: Vec<Cid> = request.args;
 
     let recursive = request.recursive;
+    let name = request.name;
 
     let dispatched_pins = cids.into_iter().map(|x| async {
-        ipfs.insert_pin(&x, recursive)
-            .await
-            .map(move |_| StringSerialized(x))
+        match &name {
+            Some(name) => {
+                ipfs.insert_pin_with(&x, recursive, PinMetadata::named(name.as_str()))
+                    .await
+            }
+            None => ipfs.insert_pin(&x, recursive).await,
+        }
+        .map(move |_| cid_base.encode(&x))
     });
 
     // could be unordered :)
@@ -61,6 +70,7 @@ impl<'a> TryFrom<&'a str> for AddRequest {
         let mut args = Vec::new();
         let mut recursive = None;
         let mut progress = None;
+        let mut name = None;
 
         for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
             let target = match &*key {
@@ -68,6 +78,13 @@ impl<'a> TryFrom<&'a str> for AddRequest {
                     args.push(Cid::try_from(&*value).map_err(|e| InvalidCid("arg".into(), e))?);
                     continue;
                 }
+                "name" => {
+                    if name.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    name = Some(value.into_owned());
+                    continue;
+                }
                 "recursive" => &mut recursive,
                 "progress" => &mut progress,
                 _ => {
@@ -94,6 +111,7 @@ impl<'a> TryFrom<&'a str> for AddRequest {
             args,
             recursive: recursive.unwrap_or(false),
             progress: progress.unwrap_or(false),
+            name,
         })
     }
 }
@@ -0,0 +1,472 @@
+//! `pin/remote/*` and `pin/remote/service/*` as per
+//! https://docs.ipfs.io/reference/http/api/#api-v0-pin-remote-add and the following, for using
+//! the remote pinning services.
+use super::format_json_newline;
+use crate::v0::support::option_parsing::ParseError;
+use crate::v0::support::{cid_base, with_ipfs, CidBase, StringError};
+use ipfs::{
+    Cid, Ipfs, IpfsTypes, RemotePinQuery, RemotePinService, RemotePinState, RemotePinStatus,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+use warp::{Filter, Rejection, Reply};
+
+/// How often the status of the pin is polled when waiting for the service to pin it.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PinResponse {
+    cid: String,
+    name: String,
+    status: String,
+}
+
+impl PinResponse {
+    fn new(status: RemotePinStatus, cid_base: &CidBase) -> Self {
+        PinResponse {
+            cid: cid_base.encode(&status.cid),
+            name: status.name.unwrap_or_default(),
+            status: status.status.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AddRequest {
+    arg: Cid,
+    service: String,
+    name: Option<String>,
+    /// Return as soon as the service has queued the pin, instead of waiting for it to be pinned
+    background: bool,
+}
+
+impl<'a> TryFrom<&'a str> for AddRequest {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut arg = None;
+        let mut service = None;
+        let mut name = None;
+        let mut background = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            let target = match &*key {
+                "arg" => {
+                    if arg.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    arg = Some(Cid::try_from(&*value).map_err(|e| InvalidCid("arg".into(), e))?);
+                    continue;
+                }
+                "service" => &mut service,
+                "name" => &mut name,
+                "background" => {
+                    if background.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    match value.parse::<bool>() {
+                        Ok(value) => background = Some(value),
+                        Err(_) => return Err(InvalidBoolean(key, value)),
+                    }
+                    continue;
+                }
+                _ => {
+                    // ignore unknown
+                    continue;
+                }
+            };
+
+            if target.is_none() {
+                *target = Some(value.into_owned());
+            } else {
+                return Err(DuplicateField(key));
+            }
+        }
+
+        Ok(AddRequest {
+            arg: arg.ok_or(MissingArg)?,
+            service: service.ok_or_else(|| MissingField("service".into()))?,
+            name,
+            background: background.unwrap_or(false),
+        })
+    }
+}
+
+/// `pin/remote/add`, which by default waits until the service has pinned the content.
+pub fn add<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(add_options())
+        .and(cid_base())
+        .and_then(add_inner)
+}
+
+fn add_options() -> impl Filter<Extract = (AddRequest,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = AddRequest::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn add_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: AddRequest,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    let remote = ipfs
+        .remote_pins(&req.service)
+        .await
+        .map_err(StringError::from)?;
+
+    let mut status = remote
+        .add(&req.arg, req.name.as_deref())
+        .await
+        .map_err(StringError::from)?;
+
+    while !req.background {
+        match status.status {
+            RemotePinState::Queued | RemotePinState::Pinning => {}
+            RemotePinState::Pinned => break,
+            RemotePinState::Failed => {
+                let msg = format!("remote service failed to pin {}", status.cid);
+                return Err(StringError::from(msg).into());
+            }
+        }
+
+        tokio::time::delay_for(POLL_INTERVAL).await;
+
+        status = remote
+            .status(&status.request_id)
+            .await
+            .map_err(StringError::from)?;
+    }
+
+    Ok(warp::reply::json(&PinResponse::new(status, &cid_base)))
+}
+
+/// The common options of `pin/remote/ls` and `pin/remote/rm`.
+#[derive(Debug)]
+struct QueryRequest {
+    service: String,
+    query: RemotePinQuery,
+    /// Allow removing more than a single pin
+    force: bool,
+}
+
+impl<'a> TryFrom<&'a str> for QueryRequest {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut service = None;
+        let mut query = RemotePinQuery::default();
+        let mut force = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            match &*key {
+                "service" => {
+                    if service.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    service = Some(value.into_owned());
+                }
+                "name" => {
+                    if query.name.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    query.name = Some(value.into_owned());
+                }
+                "cid" => {
+                    query
+                        .cids
+                        .push(Cid::try_from(&*value).map_err(|e| InvalidCid("cid".into(), e))?);
+                }
+                "status" => {
+                    let status = value
+                        .parse::<RemotePinState>()
+                        .map_err(|e| InvalidValue("status".into(), e.into()))?;
+                    query.status.push(status);
+                }
+                "force" => {
+                    if force.is_some() {
+                        return Err(DuplicateField(key));
+                    }
+                    match value.parse::<bool>() {
+                        Ok(value) => force = Some(value),
+                        Err(_) => return Err(InvalidBoolean(key, value)),
+                    }
+                }
+                _ => {
+                    // ignore unknown
+                }
+            }
+        }
+
+        Ok(QueryRequest {
+            service: service.ok_or_else(|| MissingField("service".into()))?,
+            query,
+            force: force.unwrap_or(false),
+        })
+    }
+}
+
+fn query_options() -> impl Filter<Extract = (QueryRequest,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = QueryRequest::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+/// `pin/remote/ls`, listing the pins matching the query, by default only the pinned ones.
+pub fn list<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query_options())
+        .and(cid_base())
+        .and_then(list_inner)
+}
+
+async fn list_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: QueryRequest,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    let remote = ipfs
+        .remote_pins(&req.service)
+        .await
+        .map_err(StringError::from)?;
+
+    let statuses = remote.list(req.query).await.map_err(StringError::from)?;
+
+    let st = futures::stream::iter(
+        statuses
+            .into_iter()
+            .map(move |status| Ok::<_, ipfs::Error>(PinResponse::new(status, &cid_base))),
+    );
+
+    Ok(format_json_newline(st))
+}
+
+/// `pin/remote/rm`, removing the pins matching the query. Removing more than a single pin
+/// requires `force`.
+pub fn rm<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and(query_options()).and_then(rm_inner)
+}
+
+async fn rm_inner<T: IpfsTypes>(ipfs: Ipfs<T>, req: QueryRequest) -> Result<impl Reply, Rejection> {
+    let remote = ipfs
+        .remote_pins(&req.service)
+        .await
+        .map_err(StringError::from)?;
+
+    let statuses = remote.list(req.query).await.map_err(StringError::from)?;
+
+    if statuses.len() > 1 && !req.force {
+        // same as go-ipfs
+        return Err(StringError::from(
+            "multiple remote pins are matching this query, add --force to confirm the bulk removal",
+        )
+        .into());
+    }
+
+    for status in statuses {
+        remote
+            .remove(&status.request_id)
+            .await
+            .map_err(StringError::from)?;
+    }
+
+    Ok(warp::reply::reply())
+}
+
+#[derive(Debug)]
+struct ServiceAddRequest {
+    name: String,
+    endpoint: String,
+    key: String,
+}
+
+impl<'a> TryFrom<&'a str> for ServiceAddRequest {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut args = url::form_urlencoded::parse(q.as_bytes())
+            .filter(|(key, _)| key == "arg")
+            .map(|(_, value)| value.into_owned())
+            .collect::<Vec<_>>();
+
+        match args.len() {
+            0..=2 => Err(MissingArg),
+            3 => {
+                let key = args.pop().unwrap();
+                let endpoint = args.pop().unwrap();
+                let name = args.pop().unwrap();
+                Ok(ServiceAddRequest {
+                    name,
+                    endpoint,
+                    key,
+                })
+            }
+            _ => Err(InvalidValue(
+                "arg".into(),
+                "expected the name, endpoint and key".into(),
+            )),
+        }
+    }
+}
+
+/// `pin/remote/service/add` registering a service with the name, endpoint and key as args.
+pub fn service_add<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(service_add_options())
+        .and_then(service_add_inner)
+}
+
+fn service_add_options() -> impl Filter<Extract = (ServiceAddRequest,), Error = Rejection> + Clone {
+    warp::filters::query::raw().and_then(|q: String| {
+        let res = ServiceAddRequest::try_from(q.as_str())
+            .map_err(StringError::from)
+            .map_err(warp::reject::custom);
+
+        futures::future::ready(res)
+    })
+}
+
+async fn service_add_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: ServiceAddRequest,
+) -> Result<impl Reply, Rejection> {
+    let service =
+        RemotePinService::new(req.name, req.endpoint, req.key).map_err(StringError::from)?;
+
+    ipfs.add_remote_pin_service(service)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(warp::reply::reply())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceListResponse {
+    remote_services: Vec<ServiceResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceResponse {
+    service: String,
+    api_endpoint: String,
+}
+
+/// `pin/remote/service/ls`, leaving out the keys of the services.
+pub fn service_list<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(service_list_inner)
+}
+
+async fn service_list_inner<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let services = ipfs
+        .remote_pin_services()
+        .await
+        .map_err(StringError::from)?;
+
+    let remote_services = services
+        .into_iter()
+        .map(|service| ServiceResponse {
+            service: service.name,
+            api_endpoint: service.endpoint,
+        })
+        .collect();
+
+    Ok(warp::reply::json(&ServiceListResponse { remote_services }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ServiceRemoveRequest {
+    arg: String,
+}
+
+/// `pin/remote/service/rm` removing the service with the name given as the arg.
+pub fn service_rm<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(warp::query::<ServiceRemoveRequest>())
+        .and_then(service_rm_inner)
+}
+
+async fn service_rm_inner<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    req: ServiceRemoveRequest,
+) -> Result<impl Reply, Rejection> {
+    ipfs.remove_remote_pin_service(&req.arg)
+        .await
+        .map_err(StringError::from)?;
+
+    Ok(warp::reply::reply())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AddRequest, QueryRequest, ServiceAddRequest};
+    use ipfs::RemotePinState;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn add_requires_service() {
+        let cid = "bafkreie7q3iidccmpvszul7kudcvvuavuo7u6gzlbobczuk5nqk3b4akba";
+
+        let req = AddRequest::try_from(&*format!("arg={}&service=mock&name=foo", cid)).unwrap();
+        assert_eq!(req.arg.to_string(), cid);
+        assert_eq!(req.service, "mock");
+        assert_eq!(req.name.as_deref(), Some("foo"));
+        assert!(!req.background);
+
+        AddRequest::try_from(&*format!("arg={}", cid)).unwrap_err();
+    }
+
+    #[test]
+    fn query_collects_cids_and_statuses() {
+        let req =
+            QueryRequest::try_from("service=mock&status=queued&status=pinning&force=true").unwrap();
+        assert_eq!(
+            req.query.status,
+            vec![RemotePinState::Queued, RemotePinState::Pinning]
+        );
+        assert!(req.force);
+
+        QueryRequest::try_from("service=mock&status=lost").unwrap_err();
+    }
+
+    #[test]
+    fn service_add_requires_three_args() {
+        let req =
+            ServiceAddRequest::try_from("arg=mock&arg=https%3A%2F%2Fexample.com&arg=key").unwrap();
+        assert_eq!(req.name, "mock");
+        assert_eq!(req.endpoint, "https://example.com");
+        assert_eq!(req.key, "key");
+
+        ServiceAddRequest::try_from("arg=mock&arg=https%3A%2F%2Fexample.com").unwrap_err();
+    }
+}
@@ -1,7 +1,7 @@
-use crate::v0::support::{with_ipfs, MaybeTimeoutExt, StringError};
+use crate::v0::support::{cid_base, with_ipfs, CidBase, MaybeTimeoutExt, StringError};
 use cid::{self, Cid};
 use futures::future::ready;
-use futures::stream::{self, FuturesOrdered, Stream, StreamExt, TryStreamExt};
+use futures::stream::{FuturesOrdered, Stream, StreamExt, TryStreamExt};
 use ipfs::ipld::{decode_ipld, Ipld};
 use ipfs::{Ipfs, IpfsTypes};
 use serde::{Deserialize, Serialize};
@@ -25,12 +25,16 @@ use crate::v0::support::{HandledErr, StreamResponse};
 pub fn refs<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    with_ipfs(ipfs).and(refs_options()).and_then(refs_inner)
+    with_ipfs(ipfs)
+        .and(refs_options())
+        .and(cid_base())
+        .and_then(refs_inner)
 }
 
 async fn refs_inner<T: IpfsTypes>(
     ipfs: Ipfs<T>,
     opts: RefsOptions,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let max_depth = opts.max_depth();
     let formatter = EdgeFormatter::from_options(opts.edges, opts.format.as_deref())
@@ -70,7 +74,7 @@ async fn refs_inner<T: IpfsTypes>(
                 destination,
                 name,
             }) => {
-                let ok = formatter.format(source, destination, name);
+                let ok = formatter.format(source, destination, name, &cid_base);
                 serde_json::to_string(&Edge {
                     ok: ok.into(),
                     err: "".into(),
@@ -172,23 +176,66 @@ async fn refs_paths<T: IpfsTypes>(
     Ok(ipfs::refs::iplds_refs(ipfs, iplds, max_depth, unique))
 }
 
+/// Query of `refs/local`. Neither of these are supported by go-ipfs.
+#[derive(Debug, Deserialize)]
+pub struct LocalQuery {
+    /// List only the blocks with this codec.
+    codec: Option<String>,
+    /// Resume the listing after this previously listed block.
+    after: Option<String>,
+}
+
+impl LocalQuery {
+    fn codec(&self) -> Result<Option<cid::Codec>, StringError> {
+        Ok(match self.codec.as_deref() {
+            Some("dag-cbor") => Some(cid::Codec::DagCBOR),
+            Some("dag-pb") => Some(cid::Codec::DagProtobuf),
+            Some("dag-json") => Some(cid::Codec::DagJSON),
+            Some("raw") => Some(cid::Codec::Raw),
+            Some(_) => return Err(StringError::from("unknown codec")),
+            None => None,
+        })
+    }
+
+    fn after(&self) -> Result<Option<Cid>, StringError> {
+        self.after
+            .as_deref()
+            .map(Cid::try_from)
+            .transpose()
+            .map_err(StringError::from)
+    }
+}
+
 /// Handling of https://docs-beta.ipfs.io/reference/http/api/#api-v0-refs-local
 pub fn local<T: IpfsTypes>(
     ipfs: &Ipfs<T>,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    with_ipfs(ipfs).and_then(inner_local)
+    with_ipfs(ipfs)
+        .and(warp::query::<LocalQuery>())
+        .and(cid_base())
+        .and_then(inner_local)
 }
 
-async fn inner_local<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+async fn inner_local<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: LocalQuery,
+    cid_base: CidBase,
+) -> Result<impl Reply, Rejection> {
+    let codec = query.codec()?;
+    let after = query.after()?;
+
     let refs = ipfs
-        .refs_local()
+        .refs_local(codec, after)
         .await
-        .map_err(StringError::from)?
-        .into_iter()
-        .map(|cid| cid.to_string())
-        .map(|refs| Edge {
-            ok: refs.into(),
-            err: "".into(),
+        .map(move |res| match res {
+            Ok(cid) => Edge {
+                ok: cid_base.encode(&cid).into(),
+                err: "".into(),
+            },
+            Err(e) => Edge {
+                ok: "".into(),
+                err: e.to_string().into(),
+            },
         })
         .map(|response| {
             serde_json::to_string(&response)
@@ -202,8 +249,7 @@ async fn inner_local<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejectio
                 })
         });
 
-    let stream = stream::iter(refs);
-    Ok(warp::reply::Response::new(Body::wrap_stream(stream)))
+    Ok(warp::reply::Response::new(Body::wrap_stream(refs)))
 }
 
 #[cfg(test)]
@@ -263,6 +309,47 @@ mod tests {
         assert!(diff.is_empty(), "{:?}", diff);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn local_by_codec_and_after_cursor() {
+        let filter = local(&*preloaded_testing_ipfs().await);
+
+        let list = |query: &str| {
+            let request = warp::test::request().path(&format!("/refs/local?{}", query));
+            let filter = filter.clone();
+            async move {
+                let response = request.reply(&filter).await;
+                assert_eq!(response.status(), 200);
+                response
+                    .body()
+                    .split(|&byte| byte == b'\n')
+                    .filter(|bytes| !bytes.is_empty())
+                    .map(|bytes| {
+                        let edge = serde_json::from_slice::<Edge>(bytes).unwrap();
+                        assert!(edge.err.is_empty(), "{:?}", edge.err);
+                        edge.ok.into_owned()
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let dag_cbor = list("codec=dag-cbor").await;
+        assert_eq!(dag_cbor.len(), 3);
+        assert!(dag_cbor.iter().all(|cid| cid.starts_with("bafyrei")));
+
+        let rest = list(&format!("codec=dag-cbor&after={}", dag_cbor[0])).await;
+        assert_eq!(rest, &dag_cbor[1..]);
+
+        let all = list("").await;
+        let rest = list(&format!("after={}", all[1])).await;
+        assert_eq!(rest, &all[2..]);
+
+        let response = warp::test::request()
+            .path("/refs/local?codec=foobar")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 500);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn refs_with_path() {
         let ipfs = preloaded_testing_ipfs().await;
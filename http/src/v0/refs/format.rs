@@ -1,3 +1,4 @@
+use crate::v0::support::CidBase;
 use cid::Cid;
 use std::fmt;
 
@@ -54,12 +55,23 @@ impl EdgeFormatter {
     }
 
     /// Produces a `String` for the `Ref` property of returned `Edge` values, according to the
-    /// configured formatting. `link_name` is always `None` except for `dag-pb` nodes.
-    pub fn format(&self, src: Cid, dst: Cid, link_name: Option<String>) -> String {
+    /// configured formatting. `link_name` is always `None` except for `dag-pb` nodes. The Cids
+    /// are encoded in the `cid_base`.
+    pub fn format(
+        &self,
+        src: Cid,
+        dst: Cid,
+        link_name: Option<String>,
+        cid_base: &CidBase,
+    ) -> String {
         match *self {
-            EdgeFormatter::Destination => dst.to_string(),
-            EdgeFormatter::Arrow => format!("{} -> {}", src, dst),
+            EdgeFormatter::Destination => cid_base.encode(&dst),
+            EdgeFormatter::Arrow => {
+                format!("{} -> {}", cid_base.encode(&src), cid_base.encode(&dst))
+            }
             EdgeFormatter::FormatString(ref parts) => {
+                let src = cid_base.encode(&src);
+                let dst = cid_base.encode(&dst);
                 let mut out = String::new();
                 for part in parts {
                     part.format(&mut out, &src, &dst, link_name.as_deref());
@@ -72,13 +84,12 @@ impl EdgeFormatter {
 }
 
 impl FormattedPart {
-    fn format(&self, out: &mut String, src: &Cid, dst: &Cid, linkname: Option<&str>) {
-        use fmt::Write;
+    fn format(&self, out: &mut String, src: &str, dst: &str, linkname: Option<&str>) {
         use FormattedPart::*;
         match *self {
             Static(ref s) => out.push_str(s),
-            Source => write!(out, "{}", src).expect("String writing shouldn't fail"),
-            Destination => write!(out, "{}", dst).expect("String writing shouldn't fail"),
+            Source => out.push_str(src),
+            Destination => out.push_str(dst),
             LinkName => {
                 if let Some(s) = linkname {
                     out.push_str(s)
@@ -0,0 +1,105 @@
+use crate::v0::support::{with_ipfs, HandledErr, StringError};
+use futures::stream::StreamExt;
+use ipfs::{Ipfs, IpfsTypes, RepoRepair};
+use serde::{Deserialize, Serialize};
+use warp::hyper::Body;
+use warp::{query, reply, Filter, Rejection, Reply};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatResponse {
+    repo_size: u64,
+    storage_max: u64,
+    num_objects: u64,
+    /// The size of the blocks before compression, not reported by go-ipfs.
+    logical_size: u64,
+}
+
+async fn stat_query<T: IpfsTypes>(ipfs: Ipfs<T>) -> Result<impl Reply, Rejection> {
+    let stat = ipfs.repo_stat().await.map_err(StringError::from)?;
+    let response = StatResponse {
+        repo_size: stat.size,
+        // go-ipfs uses the maximum value when there is no limit
+        storage_max: stat.storage_max.unwrap_or(u64::MAX),
+        num_objects: stat.blocks,
+        logical_size: stat.logical_size,
+    };
+    Ok(reply::json(&response))
+}
+
+pub fn stat<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs).and_then(stat_query)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyQuery {
+    /// Either `quarantine` or `delete` to repair the bad blocks as they are found.
+    repair: Option<String>,
+}
+
+/// Progress line of `repo/verify` as in go-ipfs. The message is empty for good blocks.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct VerifyProgress {
+    msg: String,
+    progress: u64,
+}
+
+async fn verify_query<T: IpfsTypes>(
+    ipfs: Ipfs<T>,
+    query: VerifyQuery,
+) -> Result<impl Reply, Rejection> {
+    let repair = match query.repair.as_deref() {
+        None => None,
+        Some("quarantine") => Some(RepoRepair::Quarantine),
+        Some("delete") => Some(RepoRepair::Delete),
+        Some(other) => {
+            return Err(StringError::from(format!(
+                "invalid repair {:?}, expected quarantine or delete",
+                other
+            ))
+            .into())
+        }
+    };
+
+    let st = async_stream::stream! {
+        let entries = ipfs.repo_verify(repair);
+        futures::pin_mut!(entries);
+
+        let mut progress = 0;
+
+        while let Some(entry) = entries.next().await {
+            progress += 1;
+
+            let msg = match entry {
+                Ok(entry) if entry.status.is_ok() => String::new(),
+                Ok(entry) if entry.repaired => format!("{}, repaired", entry.status),
+                Ok(entry) => entry.status.to_string(),
+                Err(e) => format!("verification failed: {}", e),
+            };
+
+            yield serde_json::to_string(&VerifyProgress { msg, progress })
+                .map(|mut s| {
+                    s.push('\n');
+                    s
+                })
+                .map_err(|e| {
+                    error!("repo verify progress serialization failed: {}", e);
+                    HandledErr
+                });
+        }
+    };
+
+    Ok(warp::reply::Response::new(Body::wrap_stream(st)))
+}
+
+/// Verifies the integrity of the stored blocks and pins, optionally repairing the bad blocks.
+pub fn verify<T: IpfsTypes>(
+    ipfs: &Ipfs<T>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    with_ipfs(ipfs)
+        .and(query::<VerifyQuery>())
+        .and_then(verify_query)
+}
@@ -1,12 +1,14 @@
 use crate::v0::support::{
-    with_ipfs, MaybeTimeoutExt, StreamResponse, StringError, StringSerialized,
+    cid_base, cid_options, with_ipfs, MaybeTimeoutExt, StreamResponse, StringError,
+    StringSerialized,
 };
 use async_stream::try_stream;
 use bytes::Bytes;
 use futures::stream::TryStream;
 use ipfs::unixfs::ll::walk::{self, ContinuedWalk, Walker};
+use ipfs::unixfs::ll::DEFAULT_INLINE_LIMIT;
 use ipfs::unixfs::{ll::file::FileReadFailed, TraversalFailed};
-use ipfs::{dag::ResolveError, Block, Ipfs, IpfsPath, IpfsTypes};
+use ipfs::{dag::ResolveError, Block, CidOptions, Ipfs, IpfsPath, IpfsTypes};
 use serde::Deserialize;
 use std::fmt;
 use std::path::Path;
@@ -28,6 +30,46 @@ pub struct AddArgs {
     /// When true, a new directory is created to hold more than 1 root level directories.
     #[serde(default, rename = "wrap-with-directory")]
     wrap_with_directory: bool,
+    /// When true, the file contents are stored as references to the files on the local
+    /// filesystem, named by the `Abspath` header of each part. Implies raw leaves.
+    #[serde(default)]
+    nocopy: bool,
+    /// Pin the added root recursively, defaults to true like in go-ipfs. The blocks and the pin
+    /// are committed together.
+    #[serde(default = "pin_by_default")]
+    pin: bool,
+    /// When true, blocks small enough are inlined into their Cids with the identity multihash.
+    #[serde(default)]
+    inline: bool,
+    /// The largest block size to inline when `inline` is used, defaults to 32 like in go-ipfs.
+    #[serde(rename = "inline-limit")]
+    inline_limit: Option<usize>,
+    /// The hash function of the blocks, defaults to sha2-256.
+    hash: Option<String>,
+    /// The Cid version, defaults to 0 unless other hash function is used. Version 1 implies raw
+    /// leaves like in go-ipfs.
+    #[serde(rename = "cid-version")]
+    cid_version: Option<u8>,
+    /// Truncates the multihash digests to this many bytes.
+    mhlen: Option<usize>,
+}
+
+fn pin_by_default() -> bool {
+    true
+}
+
+impl AddArgs {
+    fn inline_limit(&self) -> Option<usize> {
+        if self.inline {
+            Some(self.inline_limit.unwrap_or(DEFAULT_INLINE_LIMIT))
+        } else {
+            None
+        }
+    }
+
+    fn cid_options(&self) -> Result<CidOptions, StringError> {
+        cid_options(self.hash.as_deref(), self.cid_version, self.mhlen)
+    }
 }
 
 pub fn add<T: IpfsTypes>(
@@ -37,6 +79,7 @@ pub fn add<T: IpfsTypes>(
         .and(query::<AddArgs>())
         .and(warp::header::<mime::Mime>("content-type")) // TODO: rejects if missing
         .and(warp::body::stream())
+        .and(cid_base())
         .and_then(add::add_inner)
 }
 
@@ -1,10 +1,10 @@
 use super::AddArgs;
-use crate::v0::support::StringError;
+use crate::v0::support::{CidBase, StringError};
 use bytes::{
     buf::{BufExt, BufMutExt},
     Buf, BufMut, Bytes, BytesMut,
 };
-use cid::Cid;
+use cid::{Cid, Version};
 use futures::stream::{Stream, StreamExt, TryStreamExt};
 use ipfs::unixfs::ll::{
     dir::builder::{
@@ -12,12 +12,13 @@ use ipfs::unixfs::ll::{
     },
     file::adder::FileAdder,
 };
-use ipfs::{Block, Ipfs, IpfsTypes};
+use ipfs::{Batch, Block, CidOptions, FileReference, Ipfs, IpfsTypes};
 use mime::Mime;
 use mpart_async::server::{MultipartError, MultipartStream};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::fmt;
+use std::path::PathBuf;
 use warp::{Rejection, Reply};
 
 pub(super) async fn add_inner<T: IpfsTypes>(
@@ -25,15 +26,18 @@ pub(super) async fn add_inner<T: IpfsTypes>(
     opts: AddArgs,
     content_type: Mime,
     body: impl Stream<Item = Result<impl Buf, warp::Error>> + Send + Unpin + 'static,
+    cid_base: CidBase,
 ) -> Result<impl Reply, Rejection> {
     let boundary = content_type
         .get_param("boundary")
         .map(|v| v.to_string())
         .ok_or_else(|| StringError::from("missing 'boundary' on content-type"))?;
 
+    let cid_options = opts.cid_options()?;
+
     let st = MultipartStream::new(Bytes::from(boundary), body.map_ok(|mut buf| buf.to_bytes()));
 
-    let st = add_stream(ipfs, st, opts);
+    let st = add_stream(ipfs, st, opts, cid_options, cid_base);
 
     // map the errors into json objects; as we can't return them as trailers yet
 
@@ -68,6 +72,7 @@ enum AddError {
     InvalidFilename(std::str::Utf8Error),
     UnsupportedField(String),
     UnsupportedContentType(String),
+    MissingAbspath,
     ResponseSerialization(serde_json::Error),
     Persisting(ipfs::Error),
     TreeGathering(TreeBuildingFailed),
@@ -89,6 +94,7 @@ impl fmt::Display for AddError {
             InvalidFilename(e) => write!(fmt, "invalid multipart filename: {:?}", e),
             UnsupportedField(name) => write!(fmt, "unsupported field name: {:?}", name),
             UnsupportedContentType(t) => write!(fmt, "unsupported content-type: {:?} (supported: application/{{octet-stream,x-directory}})", t),
+            MissingAbspath => write!(fmt, "nocopy requires an absolute path in the Abspath header of each file"),
             ResponseSerialization(e) => write!(fmt, "progress serialization failed: {}", e),
             Persisting(e) => write!(fmt, "put_block failed: {}", e),
             TreeGathering(g) => write!(fmt, "invalid directory tree: {}", g),
@@ -103,6 +109,8 @@ fn add_stream<St, E>(
     ipfs: Ipfs<impl IpfsTypes>,
     mut fields: MultipartStream<St, E>,
     opts: AddArgs,
+    cid_options: CidOptions,
+    cid_base: CidBase,
 ) -> impl Stream<Item = Result<Bytes, AddError>> + Send + 'static
 where
     St: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
@@ -110,10 +118,19 @@ where
 {
     async_stream::try_stream! {
 
+        let pin = opts.pin;
+        let inline_limit = opts.inline_limit();
+        // the inline limit also limits the blocks hashed with the identity hash
+        let cid_options = cid_options.with_inline_limit(inline_limit);
+        let raw_leaves = opts.nocopy || cid_options.version() == Version::V1;
+        let mut batch = ipfs.batch();
+
         let mut tree_opts = TreeOptions::default();
         if opts.wrap_with_directory {
             tree_opts.wrap_with_directory();
         }
+        tree_opts.inline_limit(inline_limit);
+        tree_opts.cid_options(cid_options);
 
         let mut tree = BufferingTreeBuilder::new(tree_opts);
         let mut buffer = BytesMut::new();
@@ -152,7 +169,24 @@ where
                         Ok(())
                     }?;
 
-                    let mut adder = FileAdder::default();
+                    let mut nocopy = if opts.nocopy {
+                        let path = field
+                            .headers()
+                            .get("abspath")
+                            .and_then(|value| value.to_str().ok())
+                            .map(PathBuf::from)
+                            .filter(|path| path.is_absolute())
+                            .ok_or(AddError::MissingAbspath)?;
+                        Some(NoCopy { path, offset: 0 })
+                    } else {
+                        None
+                    };
+
+                    let mut adder = FileAdder::builder()
+                        .with_raw_leaves(raw_leaves)
+                        .with_inline_limit(inline_limit)
+                        .with_cid_options(cid_options)
+                        .build();
                     // how many bytes we have stored as blocks
                     let mut total_written = 0u64;
                     // how many bytes of input we have read
@@ -166,7 +200,7 @@ where
 
                         match next {
                             Some(next) => {
-                                let (read, saved_any, written) = push_all(&ipfs, &mut adder, next).await?;
+                                let (read, saved_any, written) = push_all(&mut batch, &mut adder, next, &cid_options, &mut nocopy).await?;
                                 total_written += written;
                                 total_read += read;
 
@@ -201,7 +235,7 @@ where
                         // response in as well
                     }
 
-                    let (root, subtotal) = import_all(&ipfs, adder.finish())
+                    let (root, subtotal) = import_all(&mut batch, adder.finish(), &cid_options, &mut nocopy)
                         .await
                         .map_err(AddError::Persisting)?
                         // there was a bug in ipfs-unixfs however in general the "push" operation
@@ -217,19 +251,23 @@ where
                     tree.put_link(&filename, root.clone(), total_written)
                         .map_err(AddError::TreeGathering)?;
 
+                    if pin && is_root(&filename, opts.wrap_with_directory) {
+                        batch.insert_pin(&root, true);
+                    }
+
                     let filename: Cow<'_, str> = if filename.is_empty() {
                         // cid needs to be repeated if no filename was given; in which case there
                         // should not be anything to build as tree either. note that intentionally
                         // no such Cid repeating happens when building the tree and a new wrapping
                         // root will have empty filename in the progress report.
-                        Cow::Owned(root.to_string())
+                        Cow::Owned(cid_base.encode(&root))
                     } else {
                         Cow::Owned(filename)
                     };
 
                     serde_json::to_writer((&mut buffer).writer(), &Response::Added {
                         name: filename,
-                        hash: Quoted(&root),
+                        hash: cid_base.encode(&root),
                         size: Quoted(total_written),
                     }).map_err(AddError::ResponseSerialization)?;
 
@@ -261,7 +299,9 @@ where
                 }
             }?;
 
-            yield next;
+            if !next.is_empty() {
+                yield next;
+            }
         }
 
         let mut iter = tree.build();
@@ -269,26 +309,53 @@ where
         while let Some(res) = iter.next_borrowed() {
             let TreeNode { path, cid, total_size, block } = res.map_err(AddError::TreeBuilding)?;
 
+            cid_options.check_len(block.len()).map_err(|e| AddError::Persisting(e.into()))?;
+
             // shame we need to allocate once again here..
-            ipfs.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
+            batch.put_block(Block { cid: cid.to_owned(), data: block.into() }).await.map_err(AddError::Persisting)?;
+
+            if pin && is_root(path, opts.wrap_with_directory) {
+                batch.insert_pin(cid, true);
+            }
 
             serde_json::to_writer((&mut buffer).writer(), &Response::Added {
                 name: Cow::Borrowed(path),
-                hash: Quoted(cid),
+                hash: cid_base.encode(cid),
                 size: Quoted(total_size),
             }).map_err(AddError::ResponseSerialization)?;
 
             buffer.put(&b"\r\n"[..]);
-
             yield buffer.split().freeze();
         }
+
+        // the progress has been streamed as the content was added; a failure to commit is sent as
+        // the final error line, with nothing of the request stored or pinned
+        batch.commit().await.map_err(AddError::Persisting)?;
     }
 }
 
+/// Returns true if the added file or directory at `path` is the root of what was added, which is
+/// the wrapping directory when `wrap_with_directory` is used.
+fn is_root(path: &str, wrap_with_directory: bool) -> bool {
+    if wrap_with_directory {
+        path.is_empty()
+    } else {
+        !path.contains('/')
+    }
+}
+
+/// The file being added with `nocopy`, and the offset of the next leaf block in it.
+struct NoCopy {
+    path: PathBuf,
+    offset: u64,
+}
+
 async fn push_all(
-    ipfs: &Ipfs<impl IpfsTypes>,
+    batch: &mut Batch<'_, impl IpfsTypes>,
     adder: &mut FileAdder,
     next: Bytes,
+    cid_options: &CidOptions,
+    nocopy: &mut Option<NoCopy>,
 ) -> Result<(u64, bool, u64), AddError> {
     let mut read = 0usize;
     let mut saved_any = false;
@@ -298,7 +365,7 @@ async fn push_all(
         let (iter, used) = adder.push(&next.slice(read..));
         read += used;
 
-        let maybe_tuple = import_all(&ipfs, iter)
+        let maybe_tuple = import_all(batch, iter, cid_options, nocopy)
             .await
             .map_err(AddError::Persisting)?;
 
@@ -313,21 +380,36 @@ async fn push_all(
 }
 
 async fn import_all(
-    ipfs: &Ipfs<impl IpfsTypes>,
+    batch: &mut Batch<'_, impl IpfsTypes>,
     iter: impl Iterator<Item = (Cid, Vec<u8>)>,
+    cid_options: &CidOptions,
+    nocopy: &mut Option<NoCopy>,
 ) -> Result<Option<(Cid, u64)>, ipfs::Error> {
     // TODO: use FuturesUnordered
     let mut last: Option<Cid> = None;
     let mut total = 0u64;
 
     for (cid, data) in iter {
+        cid_options.check_len(data.len())?;
         total += data.len() as u64;
         let block = Block {
             cid,
             data: data.into_boxed_slice(),
         };
 
-        let cid = ipfs.put_block(block).await?;
+        let cid = match nocopy {
+            // the leaves are produced in the order of the file content
+            Some(NoCopy { path, offset }) if block.cid.codec() == cid::Codec::Raw => {
+                let reference = FileReference {
+                    path: path.clone(),
+                    offset: *offset,
+                    length: block.data.len() as u64,
+                };
+                *offset += reference.length;
+                batch.put_block_reference(block, reference).await?
+            }
+            _ => batch.put_block(block).await?,
+        };
 
         last = Some(cid);
     }
@@ -353,7 +435,7 @@ enum Response<'a> {
     #[serde(rename_all = "PascalCase")]
     Added {
         /// The resulting Cid as a string.
-        hash: Quoted<&'a Cid>,
+        hash: String,
         /// Name of the file added from filename or the resulting Cid.
         name: Cow<'a, str>,
         /// Stringified version of the total cumulative size in bytes.
@@ -375,6 +457,7 @@ impl<D: fmt::Display> serde::Serialize for Quoted<D> {
 
 #[cfg(test)]
 mod tests {
+    use crate::test_support::tokio_ipfs;
     use crate::v0::root_files::add;
 
     #[tokio::test(max_threads = 1)]
@@ -383,36 +466,90 @@ mod tests {
 
         // this is from interface-ipfs-core, pretty much simplest add a buffer test case
         // but the body content is from the pubsub test case I copied this from
+        let request = |path: &str| {
+            warp::test::request()
+                .path(path)
+                .header(
+                    "content-type",
+                    "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
+                )
+                .body(
+                    &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                        Content-Disposition: form-data; name=\"file\"; filename=\"testfile.txt\"\r\n\
+                        Content-Type: application/octet-stream\r\n\
+                        \r\n\
+                        Plz add me!\n\
+                        \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
+                )
+        };
+
+        let response = request("/add").reply(&add(&ipfs)).await;
+
+        let body = std::str::from_utf8(response.body()).unwrap();
+
+        assert_eq!(
+            body,
+            "{\"Hash\":\"Qma4hjFTnCasJ8PVp3mZbZK5g2vGDT4LByLJ7m8ciyRFZP\",\"Name\":\"testfile.txt\",\"Size\":\"20\"}\r\n"
+        );
+
+        // pinned by default like in go-ipfs
+        let root = "Qma4hjFTnCasJ8PVp3mZbZK5g2vGDT4LByLJ7m8ciyRFZP"
+            .parse()
+            .unwrap();
+        assert!(ipfs.is_pinned(&root).await.unwrap());
+
+        ipfs.remove_pin(&root, true).await.unwrap();
+        let response = request("/add?pin=false").reply(&add(&ipfs)).await;
+        assert_eq!(response.status(), 200);
+        assert!(!ipfs.is_pinned(&root).await.unwrap());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn add_nocopy_file() {
+        let ipfs = tokio_ipfs().await;
+
+        let path = std::env::temp_dir().join("add_nocopy_file.txt");
+        std::fs::write(&path, b"Plz add me!\n").unwrap();
+
+        let body = format!(
+            "-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
+                Content-Disposition: form-data; name=\"file\"; filename=\"testfile.txt\"\r\n\
+                Content-Type: application/octet-stream\r\n\
+                Abspath: {}\r\n\
+                \r\n\
+                Plz add me!\n\
+                \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n",
+            path.display()
+        );
+
         let response = warp::test::request()
-            .path("/add")
+            .path("/add?nocopy=true")
             .header(
                 "content-type",
                 "multipart/form-data; boundary=-----------------------------Z0oYi6XyTm7_x2L4ty8JL",
             )
-            .body(
-                &b"-------------------------------Z0oYi6XyTm7_x2L4ty8JL\r\n\
-                    Content-Disposition: form-data; name=\"file\"; filename=\"testfile.txt\"\r\n\
-                    Content-Type: application/octet-stream\r\n\
-                    \r\n\
-                    Plz add me!\n\
-                    \r\n-------------------------------Z0oYi6XyTm7_x2L4ty8JL--\r\n"[..],
-            )
+            .body(body)
             .reply(&add(&ipfs))
             .await;
 
         let body = std::str::from_utf8(response.body()).unwrap();
 
+        // a single raw leaf as with go-ipfs add --nocopy, which implies --raw-leaves
         assert_eq!(
             body,
-            "{\"Hash\":\"Qma4hjFTnCasJ8PVp3mZbZK5g2vGDT4LByLJ7m8ciyRFZP\",\"Name\":\"testfile.txt\",\"Size\":\"20\"}\r\n"
+            "{\"Hash\":\"bafkreidffqfydlguosmmyebv5rp72m45tbpbq6segnkosa45kjfnduix6u\",\"Name\":\"testfile.txt\",\"Size\":\"12\"}\r\n"
         );
-    }
 
-    async fn tokio_ipfs() -> ipfs::Ipfs<ipfs::TestTypes> {
-        let options = ipfs::IpfsOptions::inmemory_with_generated_keys();
-        let (ipfs, fut) = ipfs::UninitializedIpfs::new(options).start().await.unwrap();
+        let references = ipfs.filestore_ls().await.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].1.path, path);
+        assert_eq!(references[0].1.length, 12);
+
+        std::fs::write(&path, b"Plz don't!\n").unwrap();
+
+        let verified = ipfs.filestore_verify().await.unwrap();
+        assert_eq!(verified[0].2, ipfs::FilestoreStatus::FileChanged);
 
-        tokio::spawn(fut);
-        ipfs
+        std::fs::remove_file(&path).unwrap();
     }
 }
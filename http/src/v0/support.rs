@@ -1,4 +1,4 @@
-use ipfs::{Ipfs, IpfsTypes};
+use ipfs::{CidOptions, Ipfs, IpfsTypes};
 use serde::Serialize;
 use std::borrow::Cow;
 use std::error::Error as StdError;
@@ -18,6 +18,9 @@ pub use timeout::MaybeTimeoutExt;
 mod serdesupport;
 pub use serdesupport::StringSerialized;
 
+mod cid_base;
+pub use cid_base::{cid_base, CidBase};
+
 /// The common responses apparently returned by the go-ipfs HTTP api on errors.
 /// See also: https://github.com/ferristseng/rust-ipfs-api/blob/master/ipfs-api/src/response/error.rs
 #[derive(Debug, Serialize)]
@@ -144,6 +147,35 @@ impl StringError {
     }
 }
 
+/// Creates the options for hashing the blocks from the `hash`, `cid-version` and `mhlen` query
+/// options. Like in go-ipfs, other hash functions than sha2-256 and truncated digests imply Cid
+/// version 1 unless the version is given.
+pub(crate) fn cid_options(
+    hash: Option<&str>,
+    cid_version: Option<u8>,
+    mhlen: Option<usize>,
+) -> Result<CidOptions, StringError> {
+    use cid::Version;
+    use ipfs::cid::hash_from_name;
+    use multihash::Code;
+
+    let hash = match hash {
+        Some(name) => hash_from_name(name)
+            .ok_or_else(|| StringError::from(format!("unknown hash: {:?}", name)))?,
+        None => Code::Sha2_256,
+    };
+
+    let version = match cid_version {
+        Some(0) => Version::V0,
+        Some(1) => Version::V1,
+        Some(_) => return Err(StringError::from("invalid cid version")),
+        None if hash == Code::Sha2_256 && mhlen.map(|len| len == 32).unwrap_or(true) => Version::V0,
+        None => Version::V1,
+    };
+
+    CidOptions::new(hash, version, mhlen).map_err(StringError::from)
+}
+
 /// Common rejection handling strategy for ipfs http api compatible error responses
 pub async fn recover_as_message_response(
     err: warp::reject::Rejection,
@@ -0,0 +1,95 @@
+//! Support for the global `cid-base` option, which selects the multibase of the Cids in the
+//! responses of all of the endpoints.
+
+use super::option_parsing::ParseError;
+use super::StringError;
+use cid::Cid;
+use multibase::Base;
+use std::convert::TryFrom;
+use warp::{Filter, Rejection};
+
+/// The multibase of the Cids in the responses. Without the `cid-base` option the Cids are output
+/// as they are. Like in go-ipfs, version 0 Cids are upgraded to version 1 for other bases than
+/// base58btc.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CidBase(Option<Base>);
+
+impl CidBase {
+    /// Returns the base given with the option, if any.
+    pub fn base(&self) -> Option<Base> {
+        self.0
+    }
+
+    /// Returns the string representation of the Cid in the selected base.
+    pub fn encode(&self, cid: &Cid) -> String {
+        match self.0 {
+            Some(base) => ipfs::cid::encode(cid, base),
+            None => cid.to_string(),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for CidBase {
+    type Error = ParseError<'a>;
+
+    fn try_from(q: &'a str) -> Result<Self, Self::Error> {
+        use ParseError::*;
+
+        let mut base = None;
+
+        for (key, value) in url::form_urlencoded::parse(q.as_bytes()) {
+            if key != "cid-base" {
+                continue;
+            }
+
+            if base.is_some() {
+                return Err(DuplicateField(key));
+            }
+
+            base = Some(ipfs::cid::base_from_name(&value).ok_or(InvalidValue(key, value))?);
+        }
+
+        Ok(CidBase(base))
+    }
+}
+
+/// Parses the `cid-base` option, accepting any other options and requests without any options.
+pub fn cid_base() -> impl Filter<Extract = (CidBase,), Error = Rejection> + Clone {
+    warp::filters::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|q: String| {
+            let res = CidBase::try_from(q.as_str())
+                .map_err(StringError::from)
+                .map_err(warp::reject::custom);
+
+            futures::future::ready(res)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CidBase;
+    use multibase::Base;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn parse_and_encode() {
+        let cid: cid::Cid = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n"
+            .parse()
+            .unwrap();
+
+        let base = CidBase::try_from("arg=foo&cid-base=base32").unwrap();
+        assert_eq!(base.base(), Some(Base::Base32Lower));
+        assert_eq!(
+            base.encode(&cid),
+            "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+
+        let base = CidBase::try_from("arg=foo").unwrap();
+        assert_eq!(base.encode(&cid), cid.to_string());
+
+        CidBase::try_from("cid-base=base31").unwrap_err();
+        CidBase::try_from("cid-base=base32&cid-base=base32").unwrap_err();
+    }
+}
@@ -0,0 +1,391 @@
+//! Inspection and conversion of Cids between versions and multibases, like `ipfs cid` does.
+//!
+//! The multicodec names of the codecs, hash functions and multibases supported by the crate can
+//! be listed with [`codecs`], [`hashes`] and [`bases`].
+
+use cid::{Cid, Codec, Version};
+use multibase::Base;
+use multihash::Code;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use thiserror::Error;
+
+/// The multicodec names of the supported codecs.
+const CODEC_NAMES: &[(&str, Codec)] = &[
+    ("raw", Codec::Raw),
+    ("dag-pb", Codec::DagProtobuf),
+    ("dag-cbor", Codec::DagCBOR),
+    ("dag-json", Codec::DagJSON),
+    ("git-raw", Codec::GitRaw),
+    ("eth-block", Codec::EthereumBlock),
+    ("eth-block-list", Codec::EthereumBlockList),
+    ("eth-tx-trie", Codec::EthereumTxTrie),
+    ("eth-tx", Codec::EthereumTx),
+    ("eth-tx-receipt-trie", Codec::EthereumTxReceiptTrie),
+    ("eth-tx-receipt", Codec::EthereumTxReceipt),
+    ("eth-state-trie", Codec::EthereumStateTrie),
+    ("eth-account-snapshot", Codec::EthereumAccountSnapshot),
+    ("eth-storage-trie", Codec::EthereumStorageTrie),
+    ("bitcoin-block", Codec::BitcoinBlock),
+    ("bitcoin-tx", Codec::BitcoinTx),
+    ("zcash-block", Codec::ZcashBlock),
+    ("zcash-tx", Codec::ZcashTx),
+];
+
+/// The multicodec names of the supported hash functions.
+const HASH_NAMES: &[(&str, Code)] = &[
+    ("identity", Code::Identity),
+    ("sha1", Code::Sha1),
+    ("sha2-256", Code::Sha2_256),
+    ("sha2-512", Code::Sha2_512),
+    ("sha3-224", Code::Sha3_224),
+    ("sha3-256", Code::Sha3_256),
+    ("sha3-384", Code::Sha3_384),
+    ("sha3-512", Code::Sha3_512),
+    ("keccak-224", Code::Keccak224),
+    ("keccak-256", Code::Keccak256),
+    ("keccak-384", Code::Keccak384),
+    ("keccak-512", Code::Keccak512),
+    ("blake2b-256", Code::Blake2b256),
+    ("blake2b-512", Code::Blake2b512),
+    ("blake2s-128", Code::Blake2s128),
+    ("blake2s-256", Code::Blake2s256),
+];
+
+/// The names of the supported multibases.
+const BASE_NAMES: &[(&str, Base)] = &[
+    ("identity", Base::Identity),
+    ("base2", Base::Base2),
+    ("base8", Base::Base8),
+    ("base10", Base::Base10),
+    ("base16", Base::Base16Lower),
+    ("base16upper", Base::Base16Upper),
+    ("base32", Base::Base32Lower),
+    ("base32upper", Base::Base32Upper),
+    ("base32pad", Base::Base32PadLower),
+    ("base32padupper", Base::Base32PadUpper),
+    ("base32hex", Base::Base32HexLower),
+    ("base32hexupper", Base::Base32HexUpper),
+    ("base32hexpad", Base::Base32HexPadLower),
+    ("base32hexpadupper", Base::Base32HexPadUpper),
+    ("base32z", Base::Base32Z),
+    ("base58flickr", Base::Base58Flickr),
+    ("base58btc", Base::Base58Btc),
+    ("base64", Base::Base64),
+    ("base64pad", Base::Base64Pad),
+    ("base64url", Base::Base64Url),
+    ("base64urlpad", Base::Base64UrlPad),
+];
+
+fn find_by_name<T: Copy>(table: &[(&'static str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+}
+
+fn find_name<T: PartialEq>(table: &[(&'static str, T)], value: &T) -> Option<&'static str> {
+    table.iter().find(|(_, t)| t == value).map(|(n, _)| *n)
+}
+
+/// Returns the codec for the given multicodec name, like `dag-pb`.
+pub fn codec_from_name(name: &str) -> Option<Codec> {
+    find_by_name(CODEC_NAMES, name)
+}
+
+/// Returns the multicodec name of the codec.
+pub fn codec_name(codec: Codec) -> &'static str {
+    find_name(CODEC_NAMES, &codec).expect("all codecs are named")
+}
+
+/// Returns the hash function for the given multicodec name, like `sha2-256` or `blake2b-256`.
+pub fn hash_from_name(name: &str) -> Option<Code> {
+    find_by_name(HASH_NAMES, name)
+}
+
+/// Returns the multicodec name of the hash function.
+pub fn hash_name(code: Code) -> Option<&'static str> {
+    find_name(HASH_NAMES, &code)
+}
+
+/// Returns the multibase for the given name, like `base32` or `base58btc`.
+pub fn base_from_name(name: &str) -> Option<Base> {
+    find_by_name(BASE_NAMES, name)
+}
+
+/// Returns the name of the multibase.
+pub fn base_name(base: Base) -> &'static str {
+    find_name(BASE_NAMES, &base).expect("all bases are named")
+}
+
+/// The numeric code and the name of a codec, a hash function or a multibase. The code of a
+/// multibase is its prefix character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeAndName {
+    pub code: u64,
+    pub name: &'static str,
+}
+
+/// Lists the supported codecs.
+pub fn codecs() -> Vec<CodeAndName> {
+    CODEC_NAMES
+        .iter()
+        .map(|&(name, codec)| CodeAndName {
+            code: codec.into(),
+            name,
+        })
+        .collect()
+}
+
+/// Lists the supported hash functions.
+pub fn hashes() -> Vec<CodeAndName> {
+    HASH_NAMES
+        .iter()
+        .map(|&(name, code)| CodeAndName {
+            code: code.into(),
+            name,
+        })
+        .collect()
+}
+
+/// Lists the supported multibases.
+pub fn bases() -> Vec<CodeAndName> {
+    BASE_NAMES
+        .iter()
+        .map(|&(name, base)| CodeAndName {
+            code: base.code() as u64,
+            name,
+        })
+        .collect()
+}
+
+/// A Cid decoded from its string representation into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedCid {
+    pub cid: Cid,
+    /// The multibase of the string, always base58btc for version 0.
+    pub base: Base,
+    pub version: Version,
+    pub codec: Codec,
+    pub hash: Code,
+    pub digest: Vec<u8>,
+}
+
+/// Decodes the string representation of a Cid into its parts.
+pub fn decode(s: &str) -> Result<DecodedCid, cid::Error> {
+    let cid = Cid::try_from(s)?;
+
+    let base = match cid.version() {
+        Version::V0 => Base::Base58Btc,
+        Version::V1 => s
+            .chars()
+            .next()
+            .and_then(|c| Base::from_code(c).ok())
+            .ok_or(cid::Error::ParsingError)?,
+    };
+
+    let hash = cid.hash();
+
+    Ok(DecodedCid {
+        base,
+        version: cid.version(),
+        codec: cid.codec(),
+        hash: hash.algorithm(),
+        digest: hash.digest().to_vec(),
+        cid,
+    })
+}
+
+/// Converts the Cid to the given version. Only `dag-pb` Cids with the full length sha2-256
+/// multihash can be converted to version 0.
+pub fn convert(cid: &Cid, version: Version) -> Result<Cid, cid::Error> {
+    if cid.version() == version {
+        return Ok(cid.to_owned());
+    }
+
+    let hash = cid.hash().to_owned();
+    match version {
+        Version::V0 if cid.codec() != Codec::DagProtobuf => Err(cid::Error::InvalidCidV0Codec),
+        Version::V0 => Cid::new_v0(hash),
+        Version::V1 => Ok(Cid::new_v1(cid.codec(), hash)),
+    }
+}
+
+/// Encodes the Cid in the given multibase. Version 0 Cids are upgraded to version 1 unless the
+/// base is base58btc, as version 0 Cids can only be encoded in base58btc.
+pub fn encode(cid: &Cid, base: Base) -> String {
+    let cid = match cid.version() {
+        Version::V0 if base != Base::Base58Btc => Cid::new_v1(cid.codec(), cid.hash().to_owned()),
+        _ => cid.to_owned(),
+    };
+
+    cid.to_string_of_base(base)
+        .expect("version 0 was upgraded for other bases")
+}
+
+/// Converts the Cid to version 1 and encodes it in base32, which is the case insensitive
+/// representation used for example in the subdomains of gateways.
+pub fn to_base32(cid: &Cid) -> String {
+    encode(cid, Base::Base32Lower)
+}
+
+/// Failure to [`format`] a Cid.
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("unknown format specifier %{0}")]
+    UnknownSpecifier(char),
+    #[error("the template ends in an incomplete format specifier")]
+    IncompleteSpecifier,
+    #[error("version 0 cid cannot be encoded in {0}, convert it to version 1 first")]
+    UnsupportedBase(&'static str),
+}
+
+/// Formats the Cid with a printf-style template using the same specifiers as `ipfs cid format`:
+///
+/// * `%%`: literal `%`
+/// * `%b`: name of the multibase, `%B`: the prefix character of the multibase
+/// * `%v`: version as `cidv0` or `cidv1`, `%V`: version number
+/// * `%c`: codec name, `%C`: codec code
+/// * `%h`: hash function name, `%H`: hash function code, `%L`: digest length
+/// * `%m`: multihash in the multibase, `%M`: the same without the multibase prefix
+/// * `%d`: digest in the multibase, `%D`: the same without the multibase prefix
+/// * `%s`: Cid in the multibase, `%S`: the same without the multibase prefix
+/// * `%P`: the Cid prefix, as `%v-%c-%h-%L`
+///
+/// Version 0 Cids can only be formatted in base58btc.
+pub fn format(cid: &Cid, template: &str, base: Base) -> Result<String, FormatError> {
+    if cid.version() == Version::V0 && base != Base::Base58Btc {
+        return Err(FormatError::UnsupportedBase(base_name(base)));
+    }
+
+    let hash = cid.hash();
+    let hash_label = || match hash_name(hash.algorithm()) {
+        Some(name) => name.to_owned(),
+        None => format!("hash-{}", u64::from(hash.algorithm())),
+    };
+    let without_prefix = |s: String| s.chars().skip(1).collect::<String>();
+
+    let mut out = String::with_capacity(template.len());
+    let mut chars = template.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            out.push(ch);
+            continue;
+        }
+
+        let spec = chars.next().ok_or(FormatError::IncompleteSpecifier)?;
+
+        // writing to a String cannot fail
+        match spec {
+            '%' => out.push('%'),
+            'b' => out.push_str(base_name(base)),
+            'B' => out.push(base.code()),
+            'v' => write!(out, "cidv{}", version_number(cid)).unwrap(),
+            'V' => write!(out, "{}", version_number(cid)).unwrap(),
+            'c' => out.push_str(codec_name(cid.codec())),
+            'C' => write!(out, "{}", u64::from(cid.codec())).unwrap(),
+            'h' => out.push_str(&hash_label()),
+            'H' => write!(out, "{}", u64::from(hash.algorithm())).unwrap(),
+            'L' => write!(out, "{}", hash.digest().len()).unwrap(),
+            'm' => out.push_str(&multibase::encode(base, hash.as_bytes())),
+            'M' => out.push_str(&base.encode(hash.as_bytes())),
+            'd' => out.push_str(&multibase::encode(base, hash.digest())),
+            'D' => out.push_str(&base.encode(hash.digest())),
+            's' => out.push_str(&encode(cid, base)),
+            // version 0 cids have no multibase prefix
+            'S' if cid.version() == Version::V0 => out.push_str(&encode(cid, base)),
+            'S' => out.push_str(&without_prefix(encode(cid, base))),
+            'P' => write!(
+                out,
+                "cidv{}-{}-{}-{}",
+                version_number(cid),
+                codec_name(cid.codec()),
+                hash_label(),
+                hash.digest().len()
+            )
+            .unwrap(),
+            other => return Err(FormatError::UnknownSpecifier(other)),
+        }
+    }
+
+    Ok(out)
+}
+
+fn version_number(cid: &Cid) -> u8 {
+    match cid.version() {
+        Version::V0 => 0,
+        Version::V1 => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    const V0: &str = "QmdfTbBqBPQ7VNxZEYEj14VmRuZBkqFbiwReogJgS1zR1n";
+    const V1: &str = "bafybeihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    #[test]
+    fn converts_between_versions() {
+        let v0 = Cid::try_from(V0).unwrap();
+        let v1 = convert(&v0, Version::V1).unwrap();
+        assert_eq!(v1.to_string(), V1);
+        assert_eq!(convert(&v1, Version::V0).unwrap(), v0);
+        assert_eq!(to_base32(&v0), V1);
+
+        let raw = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(b"foobar"));
+        assert!(convert(&raw, Version::V0).is_err());
+    }
+
+    #[test]
+    fn decodes_parts() {
+        let decoded = decode(&encode(&Cid::try_from(V0).unwrap(), Base::Base16Lower)).unwrap();
+        assert_eq!(decoded.base, Base::Base16Lower);
+        assert_eq!(decoded.version, Version::V1);
+        assert_eq!(decoded.codec, Codec::DagProtobuf);
+        assert_eq!(decoded.hash, Code::Sha2_256);
+        assert_eq!(decoded.digest.len(), 32);
+
+        let decoded = decode(V0).unwrap();
+        assert_eq!(decoded.base, Base::Base58Btc);
+        assert_eq!(decoded.version, Version::V0);
+    }
+
+    #[test]
+    fn formats_with_template() {
+        let v0 = Cid::try_from(V0).unwrap();
+        assert_eq!(
+            format(&v0, "%P %b %s %%", Base::Base58Btc).unwrap(),
+            format!("cidv0-dag-pb-sha2-256-32 base58btc {} %", V0)
+        );
+        assert!(format(&v0, "%s", Base::Base32Lower).is_err());
+
+        let v1 = Cid::try_from(V1).unwrap();
+        assert_eq!(format(&v1, "%s", Base::Base32Lower).unwrap(), V1);
+        assert_eq!(format(&v1, "%S", Base::Base32Lower).unwrap(), &V1[1..]);
+        assert_eq!(
+            format(&v1, "%V %C %H %L", Base::Base32Lower).unwrap(),
+            "1 112 18 32"
+        );
+        assert!(matches!(
+            format(&v1, "%x", Base::Base32Lower),
+            Err(FormatError::UnknownSpecifier('x'))
+        ));
+        assert!(matches!(
+            format(&v1, "%", Base::Base32Lower),
+            Err(FormatError::IncompleteSpecifier)
+        ));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for CodeAndName { name, .. } in codecs() {
+            assert_eq!(codec_name(codec_from_name(name).unwrap()), name);
+        }
+        for CodeAndName { name, .. } in hashes() {
+            assert_eq!(hash_name(hash_from_name(name).unwrap()), Some(name));
+        }
+        for CodeAndName { name, .. } in bases() {
+            assert_eq!(base_name(base_from_name(name).unwrap()), name);
+        }
+    }
+}
@@ -5,11 +5,11 @@ use crate::ipld::{decode_ipld, encode_ipld, Ipld};
 use crate::path::{IpfsPath, SlashedPath};
 use crate::repo::RepoTypes;
 use crate::{Block, Ipfs};
-use cid::{Cid, Codec, Version};
+use cid::{Cid, Codec};
 use ipfs_unixfs::{
     dagpb::{wrap_node_data, NodeData},
     dir::{Cache, ShardedLookup},
-    resolve, MaybeResolved,
+    resolve, CidOptions, MaybeResolved,
 };
 use std::convert::TryFrom;
 use std::error::Error as StdError;
@@ -169,15 +169,23 @@ impl<Types: RepoTypes> IpldDag<Types> {
         IpldDag { ipfs }
     }
 
+    /// Puts the document into the repo encoded with the given codec, using the default sha2-256
+    /// hash. The Cid is version 0 for `dag-pb` and version 1 for other codecs.
     pub async fn put(&self, data: Ipld, codec: Codec) -> Result<Cid, Error> {
+        self.put_with_options(data, codec, CidOptions::default())
+            .await
+    }
+
+    /// Puts the document into the repo encoded with the given codec, creating the Cid with the
+    /// hash function and the Cid version of the given options.
+    pub async fn put_with_options(
+        &self,
+        data: Ipld,
+        codec: Codec,
+        cid_options: CidOptions,
+    ) -> Result<Cid, Error> {
         let bytes = encode_ipld(&data, codec)?;
-        let hash = multihash::Sha2_256::digest(&bytes);
-        let version = if codec == Codec::DagProtobuf {
-            Version::V0
-        } else {
-            Version::V1
-        };
-        let cid = Cid::new(version, codec, hash)?;
+        let cid = cid_options.try_cid(codec, &bytes)?;
         let block = Block::new(bytes, cid);
         let (cid, _) = self.ipfs.repo.put_block(block).await?;
         Ok(cid)
@@ -362,10 +370,12 @@ impl ResolvedNode {
         }
     }
 
-    /// Unwraps the dagpb block variant and turns others into UnexpectedResolved.
+    /// Unwraps the dagpb block variant and turns others into UnexpectedResolved. A `raw` block is
+    /// accepted as well, as it is a file of single block in unixfs.
     /// This is useful wherever unixfs operations are continued after resolving an IpfsPath.
     pub fn into_unixfs_block(self) -> Result<Block, UnexpectedResolved> {
-        if self.source().codec() != cid::Codec::DagProtobuf {
+        let codec = self.source().codec();
+        if codec != cid::Codec::DagProtobuf && codec != cid::Codec::Raw {
             Err(UnexpectedResolved::UnexpectedCodec(
                 cid::Codec::DagProtobuf,
                 self,
@@ -605,6 +615,22 @@ mod tests {
         assert_eq!(res, data);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn test_put_with_options() {
+        let Node { ipfs, .. } = Node::new("test_node").await;
+        let dag = IpldDag::new(ipfs);
+        let data = make_ipld!([1, 2, 3]);
+        let opts = CidOptions::new(multihash::Code::Sha3_256, cid::Version::V1, Some(20)).unwrap();
+        let cid = dag
+            .put_with_options(data.clone(), Codec::DagCBOR, opts)
+            .await
+            .unwrap();
+        assert_eq!(cid.hash().algorithm(), multihash::Code::Sha3_256);
+        assert_eq!(cid.hash().digest().len(), 20);
+        let res = dag.get(IpfsPath::from(cid)).await.unwrap();
+        assert_eq!(res, data);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_resolve_array_elem() {
         let Node { ipfs, .. } = Node::new("test_node").await;
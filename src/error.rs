@@ -15,3 +15,20 @@ pub use anyhow::Error;
 ///
 /// Very likely to change in the future.
 pub struct TryError;
+
+/// The operation requires networking but the node was started with
+/// [`crate::IpfsOptions::offline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("this action must be run in online mode")]
+pub struct Offline;
+
+/// The operation requires [`crate::IpfsOptions::ipns_pubsub`] which was not enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("ipns over pubsub is not enabled")]
+pub struct IpnsPubsubDisabled;
+
+/// The block was not found in the local repository and it could not be fetched from the network
+/// because the node was started with [`crate::IpfsOptions::offline`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("block was not found locally (offline): {0}")]
+pub struct BlockNotFound(pub cid::Cid);
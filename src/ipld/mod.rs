@@ -257,8 +257,8 @@ pub fn validate(cid: &Cid, data: &[u8]) -> Result<(), BlockError> {
     if data.len() > MAX_BLOCK_SIZE {
         return Err(BlockError::BlockTooLarge(data.len()));
     }
-    let hash = cid.hash().algorithm().digest(&data);
-    if hash.as_ref() != cid.hash() {
+    if !crate::repo::digest_matches(cid, data) {
+        let hash = cid.hash().algorithm().digest(&data);
         return Err(BlockError::InvalidHash(hash));
     }
     Ok(())
@@ -0,0 +1,179 @@
+//! Signed IPNS records, stored in the DHT under `/ipns/<peer id bytes>`.
+
+use libp2p::core::{identity::PublicKey, PeerId};
+use multihash::{Code, MultihashRef};
+use prost::Message;
+use std::time::SystemTime;
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/ipns_pb.rs"));
+}
+
+pub(crate) use pb::IpnsEntry;
+
+/// The only validity type, end of life.
+const VALIDITY_EOL: i32 = pb::ipns_entry::ValidityType::Eol as i32;
+
+/// Failure to validate an IPNS record.
+#[derive(Debug, thiserror::Error)]
+pub enum IpnsError {
+    #[error("failed to decode the record: {0}")]
+    Decoding(#[from] prost::DecodeError),
+    #[error("invalid public key in the record")]
+    InvalidPublicKey,
+    #[error("the public key does not match the peer id")]
+    PublicKeyMismatch,
+    #[error("the public key cannot be extracted from the peer id")]
+    MissingPublicKey,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("unsupported validity type {0}")]
+    UnsupportedValidityType(i32),
+    #[error("invalid validity: {0}")]
+    InvalidValidity(String),
+    #[error("the record has expired")]
+    Expired,
+    #[error("signing failed: {0}")]
+    Signing(#[from] libp2p::core::identity::error::SigningError),
+}
+
+/// The DHT key of the records of the peer.
+pub(crate) fn key(peer_id: &PeerId) -> Vec<u8> {
+    let mut key = b"/ipns/".to_vec();
+    key.extend_from_slice(peer_id.as_bytes());
+    key
+}
+
+/// Creates a record of `value` signed with the keypair of the peer, valid until `eol`.
+pub(crate) fn create(
+    keypair: &libp2p::core::identity::Keypair,
+    value: Vec<u8>,
+    sequence: u64,
+    eol: SystemTime,
+    ttl_nanos: u64,
+) -> Result<Vec<u8>, IpnsError> {
+    let mut entry = IpnsEntry {
+        value,
+        signature: Vec::new(),
+        validity_type: VALIDITY_EOL,
+        validity: humantime::format_rfc3339_nanos(eol)
+            .to_string()
+            .into_bytes(),
+        sequence,
+        ttl: ttl_nanos,
+        pub_key: Vec::new(),
+    };
+
+    entry.signature = keypair.sign(&data_for_signature(&entry))?;
+
+    let public = keypair.public();
+    if public_key_from_peer_id(&public.clone().into_peer_id()).is_none() {
+        // the keys not inlined in the peer id need to be sent along
+        entry.pub_key = public.into_protobuf_encoding();
+    }
+
+    let mut bytes = Vec::with_capacity(entry.encoded_len());
+    entry
+        .encode(&mut bytes)
+        .expect("there is no upper limit on the Vec");
+    Ok(bytes)
+}
+
+/// Decodes the record and checks that it has been signed by the given peer and has not expired.
+pub(crate) fn verify(peer_id: &PeerId, bytes: &[u8]) -> Result<IpnsEntry, IpnsError> {
+    let entry = IpnsEntry::decode(bytes)?;
+
+    let public = if entry.pub_key.is_empty() {
+        public_key_from_peer_id(peer_id).ok_or(IpnsError::MissingPublicKey)?
+    } else {
+        let public = PublicKey::from_protobuf_encoding(&entry.pub_key)
+            .map_err(|_| IpnsError::InvalidPublicKey)?;
+        if &public.clone().into_peer_id() != peer_id {
+            return Err(IpnsError::PublicKeyMismatch);
+        }
+        public
+    };
+
+    if !public.verify(&data_for_signature(&entry), &entry.signature) {
+        return Err(IpnsError::InvalidSignature);
+    }
+
+    if eol(&entry)? < SystemTime::now() {
+        return Err(IpnsError::Expired);
+    }
+
+    Ok(entry)
+}
+
+/// The end of life of the record.
+pub(crate) fn eol(entry: &IpnsEntry) -> Result<SystemTime, IpnsError> {
+    if entry.validity_type != VALIDITY_EOL {
+        return Err(IpnsError::UnsupportedValidityType(entry.validity_type));
+    }
+
+    let validity = std::str::from_utf8(&entry.validity)
+        .map_err(|e| IpnsError::InvalidValidity(e.to_string()))?;
+
+    humantime::parse_rfc3339(validity).map_err(|e| IpnsError::InvalidValidity(e.to_string()))
+}
+
+/// The signed data is the concatenation of the value, validity and the name of the validity type
+/// (`"EOL"`) like in go-ipfs.
+fn data_for_signature(entry: &IpnsEntry) -> Vec<u8> {
+    let mut data = Vec::with_capacity(entry.value.len() + entry.validity.len() + 3);
+    data.extend_from_slice(&entry.value);
+    data.extend_from_slice(&entry.validity);
+    data.extend_from_slice(b"EOL");
+    data
+}
+
+/// Small keys such as ed25519 are inlined in the peer id with the identity hash.
+pub(crate) fn public_key_from_peer_id(peer_id: &PeerId) -> Option<PublicKey> {
+    let mh = MultihashRef::from_slice(peer_id.as_bytes()).ok()?;
+    if mh.algorithm() != Code::Identity {
+        return None;
+    }
+    PublicKey::from_protobuf_encoding(mh.digest()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create, verify, IpnsError};
+    use libp2p::core::identity::Keypair;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn signed_record_roundtrip() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().into_peer_id();
+        let eol = SystemTime::now() + Duration::from_secs(60);
+
+        let bytes = create(&keypair, b"/ipfs/foo".to_vec(), 3, eol, 0).unwrap();
+        let entry = verify(&peer_id, &bytes).unwrap();
+
+        assert_eq!(entry.value, b"/ipfs/foo");
+        assert_eq!(entry.sequence, 3);
+        // ed25519 keys are extracted from the peer id
+        assert!(entry.pub_key.is_empty());
+    }
+
+    #[test]
+    fn invalid_records() {
+        let keypair = Keypair::generate_ed25519();
+        let peer_id = keypair.public().into_peer_id();
+        let other = Keypair::generate_ed25519().public().into_peer_id();
+
+        let eol = SystemTime::now() + Duration::from_secs(60);
+        let bytes = create(&keypair, b"/ipfs/foo".to_vec(), 0, eol, 0).unwrap();
+
+        assert!(matches!(
+            verify(&other, &bytes),
+            Err(IpnsError::InvalidSignature)
+        ));
+
+        let eol = SystemTime::now() - Duration::from_secs(60);
+        let bytes = create(&keypair, b"/ipfs/foo".to_vec(), 0, eol, 0).unwrap();
+
+        assert!(matches!(verify(&peer_id, &bytes), Err(IpnsError::Expired)));
+    }
+}
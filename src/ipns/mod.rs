@@ -4,8 +4,41 @@ use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot};
 use crate::repo::RepoTypes;
 use crate::Ipfs;
+use anyhow::anyhow;
+use futures::future::{join, select_ok, FutureExt};
+use libp2p::{kad::Quorum, PeerId};
+use prost::Message;
+use std::time::{Duration, SystemTime};
 
 mod dnslink;
+pub(crate) mod entry;
+mod pubsub;
+
+pub use entry::IpnsError;
+pub(crate) use pubsub::IpnsPubsub;
+
+/// How long the resolvers cache the published records, like in go-ipfs.
+const RECORD_TTL: Duration = Duration::from_secs(60);
+
+/// How long a record is waited for over pubsub, in case the DHT lookup fails.
+const PUBSUB_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Creates a record of the path with the given sequence number, signed with the keypair and
+/// valid for the `lifetime`.
+pub fn create_record(
+    keypair: &libp2p::identity::Keypair,
+    path: &IpfsPath,
+    sequence: u64,
+    lifetime: Duration,
+) -> Result<Vec<u8>, IpnsError> {
+    entry::create(
+        keypair,
+        path.to_string().into_bytes(),
+        sequence,
+        SystemTime::now() + lifetime,
+        RECORD_TTL.as_nanos() as u64,
+    )
+}
 
 /// IPNS facade around [`Ipns`].
 #[derive(Clone, Debug)]
@@ -23,8 +56,84 @@ impl<Types: RepoTypes> Ipns<Types> {
         let path = path.to_owned();
         match path.root() {
             PathRoot::Ipld(_) => Ok(path),
-            PathRoot::Ipns(_) => Err(anyhow::anyhow!("unimplemented")),
+            PathRoot::Ipns(peer_id) => {
+                let resolved = self.resolve_record(peer_id).await?;
+                path.iter()
+                    .try_fold(resolved, |resolved, segment| resolved.sub_path(segment))
+            }
             PathRoot::Dns(domain) => Ok(dnslink::resolve(domain).await?),
         }
     }
+
+    /// Looks the newest record of the name up from the DHT, racing it against the subscribers of
+    /// the name over pubsub when [`crate::IpfsOptions::ipns_pubsub`] is enabled. Only the valid
+    /// records take part in the race.
+    async fn resolve_record(&self, peer_id: &PeerId) -> Result<IpfsPath, Error> {
+        let dht = async move {
+            // the records are validated and the best one is returned first, but the one stored
+            // locally might have expired since
+            self.ipfs
+                .dht_get(entry::key(peer_id), Quorum::One)
+                .await?
+                .iter()
+                .find_map(|record| entry::verify(peer_id, record).ok())
+                .ok_or_else(|| anyhow!("no valid ipns records found for {}", peer_id))
+        };
+
+        let entry = match self.ipfs.ipns_pubsub.as_ref() {
+            Some(ipns_pubsub) => {
+                // the pubsub records are verified, and the expired ones evicted, while resolving
+                let pubsub = async move {
+                    tokio::time::timeout(PUBSUB_TIMEOUT, ipns_pubsub.resolve(&self.ipfs, peer_id))
+                        .await
+                        .map_err(|_| anyhow!("no ipns records received for {}", peer_id))?
+                };
+
+                select_ok(vec![dht.boxed(), pubsub.boxed()]).await?.0
+            }
+            None => dht.await?,
+        };
+
+        let value = String::from_utf8(entry.value)?;
+        value.parse()
+    }
+
+    /// Publishes a record of the path under the name of the node, valid for the given lifetime.
+    /// The record is put to the DHT and broadcast to the subscribers of the name when
+    /// [`crate::IpfsOptions::ipns_pubsub`] is enabled, in which case failing to put the record to
+    /// the DHT is not an error.
+    pub async fn publish(&self, path: &IpfsPath, lifetime: Duration) -> Result<IpfsPath, Error> {
+        let keypair = self.ipfs.keys.get_ref();
+        let peer_id = keypair.public().into_peer_id();
+
+        let sequence = match self.ipfs.repo.get_ipns_record(&peer_id).await? {
+            Some(previous) => entry::IpnsEntry::decode(&previous[..])?.sequence + 1,
+            None => 0,
+        };
+
+        let record = create_record(keypair, path, sequence, lifetime)?;
+
+        self.ipfs.repo.put_ipns_record(&peer_id, &record).await?;
+
+        let dht = self
+            .ipfs
+            .dht_put(entry::key(&peer_id), record.clone(), Quorum::One);
+
+        match self.ipfs.ipns_pubsub.as_ref() {
+            Some(ipns_pubsub) => {
+                let pubsub = ipns_pubsub.publish(&self.ipfs, &peer_id, record);
+                let (dht, pubsub) = join(dht, pubsub).await;
+                pubsub?;
+                if let Err(e) = dht {
+                    debug!(
+                        "failed to put the ipns record of {} to the dht: {}",
+                        peer_id, e
+                    );
+                }
+            }
+            None => dht.await?,
+        }
+
+        Ok(IpfsPath::from(peer_id))
+    }
 }
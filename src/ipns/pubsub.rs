@@ -0,0 +1,321 @@
+//! IPNS over pubsub: the records of a name are broadcast on the `/record/<base64url key>` topic,
+//! where the key is the DHT key `/ipns/<peer id bytes>`.
+//!
+//! The nodes subscribed to a name keep the newest valid record they have seen, and rebroadcast it
+//! whenever new peers join the topic and periodically, so that newly subscribed nodes get the
+//! record without waiting for the next publish.
+use super::entry;
+use crate::error::Error;
+use crate::{Ipfs, IpfsTypes, PubsubMessage, SubscriptionStream};
+use anyhow::anyhow;
+use futures::future::{self, abortable, AbortHandle};
+use futures::stream::{self, StreamExt};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How often the newest record is rebroadcast to all of the peers.
+const REBROADCAST_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The pubsub topic of the name, like in go-ipfs.
+pub(crate) fn topic(peer_id: &PeerId) -> String {
+    format!(
+        "/record/{}",
+        multibase::Base::Base64Url.encode(entry::key(peer_id))
+    )
+}
+
+type Record = Option<Vec<u8>>;
+
+struct Name {
+    newest: Arc<watch::Sender<Record>>,
+    updates: watch::Receiver<Record>,
+    abort: AbortHandle,
+}
+
+impl Drop for Name {
+    fn drop(&mut self) {
+        // dropping the subscription stream in the task unsubscribes from the topic
+        self.abort.abort();
+    }
+}
+
+/// The names subscribed to over pubsub.
+pub(crate) struct IpnsPubsub {
+    names: Mutex<HashMap<PeerId, Name>>,
+    // held while subscribing so that concurrent resolves and publishes of a name do not both
+    // try to subscribe to the topic, which only one of them could do
+    subscribing: tokio::sync::Mutex<()>,
+}
+
+impl Default for IpnsPubsub {
+    fn default() -> Self {
+        IpnsPubsub {
+            names: Default::default(),
+            subscribing: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for IpnsPubsub {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self.names.lock().unwrap();
+        fmt.debug_set().entries(names.keys()).finish()
+    }
+}
+
+impl IpnsPubsub {
+    /// The subscribed names.
+    pub(crate) fn names(&self) -> Vec<PeerId> {
+        self.names.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Cancels the subscription to the name, returning false if it was not subscribed to.
+    pub(crate) fn cancel(&self, peer_id: &PeerId) -> bool {
+        self.names.lock().unwrap().remove(peer_id).is_some()
+    }
+
+    fn existing(
+        &self,
+        peer_id: &PeerId,
+    ) -> Option<(Arc<watch::Sender<Record>>, watch::Receiver<Record>)> {
+        self.names
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .map(|name| (Arc::clone(&name.newest), name.updates.clone()))
+    }
+
+    /// Subscribes to the name if it has not been subscribed to already, returning the newest
+    /// record and the receiver of the updates.
+    async fn subscribe<Types: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<Types>,
+        peer_id: &PeerId,
+    ) -> Result<(Arc<watch::Sender<Record>>, watch::Receiver<Record>), Error> {
+        if let Some(existing) = self.existing(peer_id) {
+            return Ok(existing);
+        }
+
+        let _guard = self.subscribing.lock().await;
+
+        // subscribed to while waiting for the guard
+        if let Some(existing) = self.existing(peer_id) {
+            return Ok(existing);
+        }
+
+        let topic = topic(peer_id);
+        // fails if the topic has been subscribed to directly with `Ipfs::pubsub_subscribe`
+        let stream = ipfs
+            .pubsub_subscribe(topic.clone())
+            .await
+            .map_err(|e| anyhow!("cannot subscribe to the ipns records of {}: {}", peer_id, e))?;
+        let joins = ipfs.pubsub_peer_joins(topic.clone()).await?;
+
+        let (tx, rx) = watch::channel(None);
+        let newest = Arc::new(tx);
+
+        let (task, abort) = abortable(keep_fresh(
+            ipfs.clone(),
+            peer_id.to_owned(),
+            topic,
+            stream,
+            joins,
+            Arc::clone(&newest),
+            rx.clone(),
+        ));
+
+        tokio::spawn(task);
+        self.names.lock().unwrap().insert(
+            peer_id.to_owned(),
+            Name {
+                newest: Arc::clone(&newest),
+                updates: rx.clone(),
+                abort,
+            },
+        );
+
+        Ok((newest, rx))
+    }
+
+    /// Waits for a valid record of the name from the peers, subscribing to it first if needed.
+    /// Returns immediately if a record has already been received, unless it has expired.
+    pub(crate) async fn resolve<Types: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<Types>,
+        peer_id: &PeerId,
+    ) -> Result<entry::IpnsEntry, Error> {
+        let (newest, mut updates) = self.subscribe(ipfs, peer_id).await?;
+
+        loop {
+            if let Some(entry) = current(peer_id, &newest, &updates) {
+                return Ok(entry);
+            }
+
+            if updates.recv().await.is_none() {
+                return Err(anyhow!(
+                    "the pubsub subscription to {} was canceled",
+                    peer_id
+                ));
+            }
+        }
+    }
+
+    /// Keeps the record as the newest one of the name and broadcasts it to the subscribers.
+    pub(crate) async fn publish<Types: IpfsTypes>(
+        &self,
+        ipfs: &Ipfs<Types>,
+        peer_id: &PeerId,
+        record: Vec<u8>,
+    ) -> Result<(), Error> {
+        let (newest, updates) = self.subscribe(ipfs, peer_id).await?;
+
+        if !offer(peer_id, &newest, &updates, &record) {
+            return Err(anyhow!("a newer record has already been published"));
+        }
+
+        ipfs.pubsub_publish(topic(peer_id), record).await
+    }
+}
+
+/// Returns the newest record if it is still valid, evicting it if it has expired.
+fn current(
+    peer_id: &PeerId,
+    newest: &watch::Sender<Record>,
+    updates: &watch::Receiver<Record>,
+) -> Option<entry::IpnsEntry> {
+    let record = updates.borrow().clone()?;
+
+    match entry::verify(peer_id, &record) {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            debug!("evicting the ipns record of {}: {}", peer_id, e);
+            // unless a newer one was received meanwhile
+            if updates.borrow().as_ref() == Some(&record) {
+                let _ = newest.broadcast(None);
+            }
+            None
+        }
+    }
+}
+
+/// Replaces the newest record with the given one if it is valid and newer. Returns true if the
+/// record was accepted or is the same as the newest.
+fn offer(
+    peer_id: &PeerId,
+    newest: &watch::Sender<Record>,
+    updates: &watch::Receiver<Record>,
+    record: &[u8],
+) -> bool {
+    let entry = match entry::verify(peer_id, record) {
+        Ok(entry) => entry,
+        Err(e) => {
+            debug!("ignoring an invalid ipns record of {}: {}", peer_id, e);
+            return false;
+        }
+    };
+
+    let newer = match updates.borrow().as_ref() {
+        Some(current) if current == record => return true,
+        Some(current) => match entry::verify(peer_id, current) {
+            // the newest might have expired since it was received
+            Ok(current) => {
+                (entry.sequence, entry::eol(&entry).ok())
+                    > (current.sequence, entry::eol(&current).ok())
+            }
+            Err(_) => true,
+        },
+        None => true,
+    };
+
+    if newer {
+        let _ = newest.broadcast(Some(record.to_vec()));
+    }
+    newer
+}
+
+/// The events of a [`keep_fresh`] task.
+enum Event {
+    Received(Arc<PubsubMessage>),
+    Unsubscribed,
+    PeerJoined(PeerId),
+    Rebroadcast,
+}
+
+/// Receives the records of the name, and rebroadcasts the newest one to the peers joining the
+/// topic and periodically. Ends when the subscription stream ends, which happens when the node is
+/// shut down.
+async fn keep_fresh<Types: IpfsTypes>(
+    ipfs: Ipfs<Types>,
+    peer_id: PeerId,
+    topic: String,
+    stream: SubscriptionStream,
+    joins: impl futures::Stream<Item = PeerId> + Unpin,
+    newest: Arc<watch::Sender<Record>>,
+    updates: watch::Receiver<Record>,
+) {
+    let received = stream
+        .map(Event::Received)
+        .chain(stream::once(future::ready(Event::Unsubscribed)));
+    let joins = joins.map(Event::PeerJoined);
+    let rebroadcasts = tokio::time::interval(REBROADCAST_INTERVAL).map(|_| Event::Rebroadcast);
+
+    let mut events = stream::select(received, stream::select(joins, rebroadcasts));
+
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Received(msg) => {
+                if offer(&peer_id, &newest, &updates, &msg.data) {
+                    trace!("received an ipns record of {} from {}", peer_id, msg.source);
+                }
+                continue;
+            }
+            Event::Unsubscribed => break,
+            Event::PeerJoined(peer) => {
+                trace!("{} subscribed to the ipns records of {}", peer, peer_id);
+            }
+            Event::Rebroadcast => {}
+        }
+
+        // the expired records are not spread any further
+        if current(&peer_id, &newest, &updates).is_none() {
+            continue;
+        }
+
+        let record = updates.borrow().clone();
+        if let Some(record) = record {
+            if let Err(e) = ipfs.pubsub_publish(topic.clone(), record).await {
+                debug!(
+                    "failed to rebroadcast the ipns record of {}: {}",
+                    peer_id, e
+                );
+                break;
+            }
+        }
+    }
+
+    debug!("stopped keeping the ipns record of {} fresh", peer_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::topic;
+    use libp2p::PeerId;
+
+    #[test]
+    fn topic_is_base64url_of_the_key() {
+        let peer_id = "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN"
+            .parse::<PeerId>()
+            .unwrap();
+
+        let topic = topic(&peer_id);
+        assert!(topic.starts_with("/record/"), "{}", topic);
+        let encoded = &topic["/record/".len()..];
+
+        // "/ipns/" in unpadded base64url
+        assert!(encoded.starts_with("L2lwbnMv"), "{}", topic);
+        assert!(!encoded.contains(&['=', '+', '/'][..]), "{}", topic);
+    }
+}
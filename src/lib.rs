@@ -22,6 +22,7 @@
 // the docs better.
 //#![allow(private_intra_doc_links)]
 
+pub mod cid;
 pub mod config;
 pub mod dag;
 pub mod error;
@@ -30,24 +31,28 @@ pub mod ipld;
 pub mod ipns;
 pub mod p2p;
 pub mod path;
+mod pin;
 pub mod refs;
 pub mod repo;
+pub mod routing;
 mod subscription;
+#[cfg(test)]
+mod test_support;
 pub mod unixfs;
 
 #[macro_use]
 extern crate tracing;
 
+use ::cid::Codec;
 use anyhow::{anyhow, format_err};
-use cid::Codec;
 use either::Either;
 use futures::{
     channel::{
-        mpsc::{channel, Receiver, Sender},
+        mpsc::{channel, Receiver, Sender, UnboundedReceiver},
         oneshot::{channel as oneshot_channel, Sender as OneshotSender},
     },
     sink::SinkExt,
-    stream::{Fuse, Stream},
+    stream::{BoxStream, Fuse, Stream},
 };
 use libp2p::swarm::NetworkBehaviour;
 use tracing::Span;
@@ -63,16 +68,18 @@ use std::{
     pin::Pin,
     sync::{atomic::Ordering, Arc},
     task::{Context, Poll},
+    time::Duration,
 };
 
 use self::{
     dag::IpldDag,
-    ipns::Ipns,
+    ipns::{Ipns, IpnsPubsub},
     p2p::{
         addr::{could_be_bound_from_ephemeral, starts_unspecified},
         create_swarm, SwarmOptions, TSwarm,
     },
-    repo::{create_repo, Repo, RepoEvent, RepoOptions},
+    repo::{create_repo, migrations::Migrations, Repo, RepoEvent, RepoOptions},
+    routing::{CompositeRouter, ContentRouting, KademliaRouter, PeerInfo, PeerRouting},
     subscription::SubscriptionFuture,
 };
 
@@ -81,13 +88,25 @@ pub use self::{
     ipld::Ipld,
     p2p::{
         pubsub::{PubsubMessage, SubscriptionStream},
-        Connection, KadResult, MultiaddrWithPeerId, MultiaddrWithoutPeerId,
+        Connection, DhtMode, InvalidDhtMode, KadResult, MultiaddrWithPeerId,
+        MultiaddrWithoutPeerId,
     },
     path::IpfsPath,
-    repo::{PinKind, PinMode, RepoTypes},
+    pin::{
+        BadNode, InterruptedPin, PinVerifyStatus, RemotePinQuery, RemotePinService, RemotePinState,
+        RemotePinStatus, RemotePins,
+    },
+    repo::{
+        Batch, BlockCache, BlockCacheStats, BlockCompression, BlockEncryption, BlockFiles,
+        BlockStore, BlockStoreExtensions, CachedBlockStore, Compression, DataStore,
+        EncryptedBlockStore, EncryptionKey, FileReference, FileReferences, FilestoreStatus,
+        PinKind, PinMetadata, PinMode, RepoRepair, RepoStat, RepoTypes, RepoVerifyEntry,
+        RepoVerifyStatus,
+    },
 };
-pub use cid::Cid;
+pub use ::cid::Cid;
 pub use ipfs_bitswap::Block;
+pub use ipfs_unixfs::CidOptions;
 pub use libp2p::{
     core::{connection::ListenerId, multiaddr::Protocol, Multiaddr, PeerId, PublicKey},
     identity::Keypair,
@@ -114,6 +133,19 @@ impl RepoTypes for TestTypes {
     type TDataStore = repo::mem::MemDataStore;
 }
 
+/// Configuration where the block store and data store are selected at runtime, see
+/// [`UninitializedIpfs::with_stores`]. Allows using custom store implementations without making
+/// the rest of the application generic over the [`IpfsTypes`].
+///
+/// Using [`UninitializedIpfs::new`] with this configuration creates the same persistent stores as
+/// [`Types`].
+#[derive(Debug)]
+pub struct DynTypes;
+impl RepoTypes for DynTypes {
+    type TBlockStore = Box<dyn repo::BlockStore>;
+    type TDataStore = Box<dyn repo::DataStore>;
+}
+
 /// Ipfs node options used to configure the node to be created with [`UninitializedIpfs`].
 #[derive(Clone)]
 pub struct IpfsOptions {
@@ -145,9 +177,60 @@ pub struct IpfsOptions {
     /// [`libp2p_kad::KademliaConfig::set_protocol_name`]: https://docs.rs/libp2p-kad/*/libp2p_kad/struct.KademliaConfig.html##method.set_protocol_name
     pub kad_protocol: Option<String>,
 
+    /// The role of the node in the DHT. In [`DhtMode::Client`] the node can query the DHT but
+    /// does not advertise the Kademlia protocol nor answer the queries of other peers, which is
+    /// suitable for short lived nodes and nodes behind NAT. [`DhtMode::Auto`] starts as a client
+    /// and switches to the server mode once connected peers have succeeded in dialing the node
+    /// back on its listening addresses.
+    ///
+    /// Switching the mode only affects the connections established after the switch. The
+    /// reachability is checked again periodically, so the node can also switch back.
+    pub dht_mode: DhtMode,
+
+    /// Routers queried for the providers of content in parallel with the DHT, for example the
+    /// [`routing::DelegatedRouter`].
+    pub content_routers: Vec<Arc<dyn ContentRouting>>,
+
+    /// Routers queried for the addresses of peers in parallel with the DHT.
+    pub peer_routers: Vec<Arc<dyn PeerRouting>>,
+
+    /// Enables IPNS over pubsub when true: the published records are also broadcast over pubsub,
+    /// and the names are resolved over both the DHT and pubsub, after which the node keeps
+    /// receiving the newer records of the name and sharing them with new subscribers.
+    pub ipns_pubsub: bool,
+
     /// Bound listening addresses; by default the node will not listen on any address.
     pub listening_addrs: Vec<Multiaddr>,
 
+    /// Runs the node without any networking when true: no swarm is created and no addresses are
+    /// listened on. Blocks missing from the local repository cannot be fetched, and any network
+    /// related operation fails with [`error::Offline`].
+    pub offline: bool,
+
+    /// The maximum size of the blocks stored in the repository in bytes. When the size grows over
+    /// the [`IpfsOptions::storage_high_water_mark`], the least recently used unpinned blocks are
+    /// evicted. `None` means the size is not limited.
+    pub storage_max: Option<u64>,
+
+    /// The percentage of [`IpfsOptions::storage_max`] after which blocks are evicted. Values over
+    /// 100 are treated as 100.
+    pub storage_high_water_mark: u8,
+
+    /// Encrypts the blocks stored in the repository when set, see [`EncryptedBlockStore`].
+    pub block_encryption: Option<BlockEncryption>,
+
+    /// Caches this many bytes of the block data in memory when set, along with a bloom filter of
+    /// the stored blocks, see [`CachedBlockStore`].
+    pub block_cache: Option<u64>,
+
+    /// The compression of the blocks written to the repository, see [`Compression`]. Only
+    /// supported by the filesystem block store; the existing blocks are readable regardless.
+    pub block_compression: Compression,
+
+    /// The migrations run on the repository directory when the node is started with persistent
+    /// stores. The default upgrades the repositories of the earlier versions to the current one.
+    pub migrations: Migrations,
+
     /// The span for tracing purposes, `None` value is converted to `tracing::trace_span!("ipfs")`.
     ///
     /// All futures returned by `Ipfs`, background task actions and swarm actions are instrumented
@@ -166,7 +249,18 @@ impl fmt::Debug for IpfsOptions {
             .field("keypair", &DebuggableKeypair(&self.keypair))
             .field("mdns", &self.mdns)
             .field("kad_protocol", &self.kad_protocol)
+            .field("dht_mode", &self.dht_mode)
+            .field("content_routers", &self.content_routers)
+            .field("peer_routers", &self.peer_routers)
+            .field("ipns_pubsub", &self.ipns_pubsub)
             .field("listening_addrs", &self.listening_addrs)
+            .field("offline", &self.offline)
+            .field("storage_max", &self.storage_max)
+            .field("storage_high_water_mark", &self.storage_high_water_mark)
+            .field("block_encryption", &self.block_encryption)
+            .field("block_cache", &self.block_cache)
+            .field("block_compression", &self.block_compression)
+            .field("migrations", &self.migrations)
             .field("span", &self.span)
             .finish()
     }
@@ -184,7 +278,18 @@ impl IpfsOptions {
             bootstrap: Default::default(),
             // default to lan kad for go-ipfs use in tests
             kad_protocol: Some("/ipfs/lan/kad/1.0.0".to_owned()),
+            dht_mode: DhtMode::Server,
+            content_routers: Vec::new(),
+            peer_routers: Vec::new(),
+            ipns_pubsub: false,
             listening_addrs: vec!["/ip4/127.0.0.1/tcp/0".parse().unwrap()],
+            offline: false,
+            storage_max: None,
+            storage_high_water_mark: 90,
+            block_encryption: None,
+            block_cache: None,
+            block_compression: Compression::None,
+            migrations: Migrations::default(),
             span: None,
         }
     }
@@ -226,6 +331,8 @@ pub struct Ipfs<Types: IpfsTypes> {
     repo: Arc<Repo<Types>>,
     keys: DebuggableKeypair<Keypair>,
     to_task: Sender<IpfsEvent>,
+    routing: CompositeRouter,
+    ipns_pubsub: Option<Arc<IpnsPubsub>>,
 }
 
 impl<Types: IpfsTypes> Clone for Ipfs<Types> {
@@ -235,6 +342,8 @@ impl<Types: IpfsTypes> Clone for Ipfs<Types> {
             repo: Arc::clone(&self.repo),
             keys: self.keys.clone(),
             to_task: self.to_task.clone(),
+            routing: self.routing.clone(),
+            ipns_pubsub: self.ipns_pubsub.clone(),
         }
     }
 }
@@ -264,6 +373,7 @@ enum IpfsEvent {
     PubsubUnsubscribe(String, OneshotSender<bool>),
     PubsubPublish(String, Vec<u8>, OneshotSender<()>),
     PubsubPeers(Option<String>, OneshotSender<Vec<PeerId>>),
+    PubsubPeerJoins(String, OneshotSender<UnboundedReceiver<PeerId>>),
     PubsubSubscribed(OneshotSender<Vec<String>>),
     WantList(
         Option<PeerId>,
@@ -320,6 +430,14 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
     pub fn new(options: IpfsOptions) -> Self {
         let repo_options = RepoOptions::from(&options);
         let (repo, repo_events) = create_repo(repo_options);
+        Self::with_repo(options, repo, repo_events)
+    }
+
+    fn with_repo(
+        options: IpfsOptions,
+        repo: Repo<Types>,
+        repo_events: Receiver<RepoEvent>,
+    ) -> Self {
         let keys = options.keypair.clone();
 
         UninitializedIpfs {
@@ -353,13 +471,43 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
 
         let swarm_span = tracing::trace_span!(parent: facade_span.clone(), "swarm");
 
+        let mut routing = CompositeRouter::default();
+        if !options.offline {
+            let kad = Arc::new(KademliaRouter::new(facade_span.clone(), to_task.clone()));
+            routing.add_content_router(kad.clone());
+            routing.add_peer_router(kad);
+        }
+        for router in options.content_routers.drain(..) {
+            routing.add_content_router(router);
+        }
+        for router in options.peer_routers.drain(..) {
+            routing.add_peer_router(router);
+        }
+
+        let ipns_pubsub = if options.ipns_pubsub {
+            Some(Arc::new(IpnsPubsub::default()))
+        } else {
+            None
+        };
+
         let ipfs = Ipfs {
             span: facade_span,
             repo: repo.clone(),
             keys: DebuggableKeypair(keys),
             to_task,
+            routing,
+            ipns_pubsub,
         };
 
+        if options.offline {
+            let fut = OfflineFuture {
+                repo_events: repo_events.fuse(),
+                from_facade: receiver.fuse(),
+            };
+
+            return Ok((ipfs, futures::future::Either::Left(fut)));
+        }
+
         let swarm_options = SwarmOptions::from(&options);
         let swarm = create_swarm(swarm_options, swarm_span, repo).await?;
 
@@ -378,7 +526,22 @@ impl<Types: IpfsTypes> UninitializedIpfs<Types> {
             fut.start_add_listener_address(addr, None);
         }
 
-        Ok((ipfs, fut))
+        Ok((ipfs, futures::future::Either::Right(fut)))
+    }
+}
+
+impl UninitializedIpfs<DynTypes> {
+    /// Configures a new UninitializedIpfs using the given, already created stores instead of
+    /// creating them under the [`IpfsOptions::ipfs_path`]. The stores can be any implementations
+    /// of [`BlockStore`] and [`DataStore`], including ones defined outside of this crate.
+    pub fn with_stores(
+        options: IpfsOptions,
+        block_store: Box<dyn BlockStore>,
+        data_store: Box<dyn DataStore>,
+    ) -> Self {
+        let repo_options = RepoOptions::from(&options);
+        let (repo, repo_events) = Repo::with_stores(repo_options, block_store, data_store);
+        Self::with_repo(options, repo, repo_events)
     }
 }
 
@@ -392,6 +555,15 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         Ipns::new(self.clone())
     }
 
+    /// Fails with [`error::Offline`] if the node was started with [`IpfsOptions::offline`].
+    fn ensure_online(&self) -> Result<(), Error> {
+        if self.repo.is_offline() {
+            Err(error::Offline.into())
+        } else {
+            Ok(())
+        }
+    }
+
     /// Puts a block into the ipfs repo.
     ///
     /// # Forget safety
@@ -406,6 +578,39 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .map(|(cid, _put_status)| cid)
     }
 
+    /// Creates a block of the given codec from the data, hashing it with the given options, and
+    /// puts it into the local blockstore.
+    pub async fn put_block_data(
+        &self,
+        data: Box<[u8]>,
+        codec: Codec,
+        cid_options: &CidOptions,
+    ) -> Result<Cid, Error> {
+        let cid = cid_options.try_cid(codec, &data)?;
+        self.put_block(Block::new(data, cid)).await
+    }
+
+    /// Puts a `raw` block into the local blockstore as a reference to the file containing the
+    /// block content, instead of a copy of the content. The referenced content is verified
+    /// whenever the block is read; see [`Ipfs::filestore_verify`].
+    pub async fn put_block_reference(
+        &self,
+        block: Block,
+        reference: FileReference,
+    ) -> Result<Cid, Error> {
+        self.repo
+            .put_block_reference(block, reference)
+            .instrument(self.span.clone())
+            .await
+            .map(|(cid, _put_status)| cid)
+    }
+
+    /// Starts a batch of block puts and pin insertions which are committed together, see
+    /// [`Batch`].
+    pub fn batch(&self) -> Batch<'_, Types> {
+        self.repo.batch()
+    }
+
     /// Retrieves a block from the local blockstore, or starts fetching from the network or join an
     /// already started fetch.
     pub async fn get_block(&self, cid: &Cid) -> Result<Block, Error> {
@@ -437,7 +642,7 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     ///
     /// If a recursive `insert_pin` operation is interrupted because of a crash or the crash
     /// prevents from synchronizing the data store to disk, this will leave the system in an inconsistent
-    /// state. The remedy is to re-pin recursive pins.
+    /// state. The remedy is to re-pin recursive pins, or to use [`Ipfs::verify_pins`].
     pub async fn insert_pin(&self, cid: &Cid, recursive: bool) -> Result<(), Error> {
         use futures::stream::{StreamExt, TryStreamExt};
         let span = debug_span!(parent: &self.span, "insert_pin", cid = %cid, recursive);
@@ -467,6 +672,24 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Pins a given Cid like [`Ipfs::insert_pin`], and replaces the name and metadata of the pin
+    /// with the given `metadata`. Pinning with [`Ipfs::insert_pin`] keeps the previous metadata.
+    pub async fn insert_pin_with(
+        &self,
+        cid: &Cid,
+        recursive: bool,
+        metadata: PinMetadata,
+    ) -> Result<(), Error> {
+        self.insert_pin(cid, recursive).await?;
+
+        let span =
+            debug_span!(parent: &self.span, "insert_pin_with", cid = %cid, name = ?metadata.name);
+        self.repo
+            .put_pin_metadata(cid, &metadata)
+            .instrument(span)
+            .await
+    }
+
     /// Unpins a given Cid recursively or only directly.
     ///
     /// Recursively unpinning a previously only directly pinned Cid will remove the direct pin.
@@ -502,7 +725,60 @@ impl<Types: IpfsTypes> Ipfs<Types> {
                     .boxed();
 
                 self.repo.remove_recursive_pin(cid, st).await
+            }?;
+
+            // there can only be one of the direct or recursive pin, so the metadata goes with it
+            self.repo.remove_pin_metadata(cid).await
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Moves a recursive pin from `old` to `new`, along with the name and metadata of the pin,
+    /// fetching the missing blocks of `new`.
+    ///
+    /// Only the blocks of the DAG of `new` which differ from the DAG of `old` are loaded or
+    /// fetched; the common subtrees are found through the links into the DAG of `old`. The new
+    /// pin is inserted before the old one is removed, so the common blocks stay pinned all the
+    /// time. If `new` is already pinned recursively, only the pin of `old` is removed.
+    pub async fn update_pin(&self, old: &Cid, new: &Cid) -> Result<(), Error> {
+        use futures::stream::{self, StreamExt};
+        let span = debug_span!(parent: &self.span, "update_pin", old = %old, new = %new);
+
+        async move {
+            if old == new {
+                return Ok(());
             }
+
+            self.repo
+                .query_pins(vec![old.to_owned()], Some(PinMode::Recursive))
+                .await?;
+
+            let diff = crate::refs::dag_diff(self, old, new).await?;
+
+            let already_pinned = self
+                .repo
+                .query_pins(vec![new.to_owned()], Some(PinMode::Recursive))
+                .await
+                .is_ok();
+
+            if !already_pinned {
+                let refs = diff.new_refs().cloned().map(Ok).collect::<Vec<_>>();
+                self.repo
+                    .insert_recursive_pin(new, stream::iter(refs).boxed())
+                    .await?;
+            }
+
+            if let Some(metadata) = self.repo.get_pin_metadata(old).await? {
+                self.repo.put_pin_metadata(new, &metadata).await?;
+            }
+
+            let refs = diff.old_refs(old).cloned().map(Ok).collect::<Vec<_>>();
+            self.repo
+                .remove_recursive_pin(old, stream::iter(refs).boxed())
+                .await?;
+
+            self.repo.remove_pin_metadata(old).await
         }
         .instrument(span)
         .await
@@ -518,15 +794,39 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Cannot currently detect partially written recursive pins. Those can happen if
     /// `Ipfs::insert_pin(cid, true)` is interrupted by a crash for example.
     ///
-    /// Works correctly only under no-crash situations. Workaround for hitting a crash is to re-pin
-    /// any existing recursive pins.
-    ///
-    // TODO: This operation could be provided as a `Ipfs::fix_pins()`.
+    /// Works correctly only under no-crash situations. Workaround for hitting a crash is to
+    /// complete or remove the interrupted recursive pins with [`Ipfs::verify_pins`].
     pub async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
         let span = debug_span!(parent: &self.span, "is_pinned", cid = %cid);
         self.repo.is_pinned(cid).instrument(span).await
     }
 
+    /// Walks the DAG of every recursive pin, streaming the missing and corrupt blocks found under
+    /// each pin. The recursive pins whose insertion had been interrupted are reported as well. With
+    /// `repair`, the missing blocks are refetched from the network and the interrupted pins are
+    /// completed, if all of their blocks are available, or removed.
+    pub fn verify_pins(
+        &self,
+        repair: bool,
+    ) -> impl Stream<Item = Result<PinVerifyStatus, Error>> + Send + '_ {
+        let span = debug_span!(parent: &self.span, "verify_pins", repair);
+
+        let st = async_stream::try_stream! {
+            for cid in pin::recursive_roots(self).await? {
+                let status = pin::verify_pin(self, cid, repair).await?;
+                yield status;
+            }
+        };
+
+        st.instrument(span)
+    }
+
+    /// Returns the name and metadata of the direct or recursive pin, if it has any.
+    pub async fn pin_metadata(&self, cid: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let span = debug_span!(parent: &self.span, "pin_metadata", cid = %cid);
+        self.repo.get_pin_metadata(cid).instrument(span).await
+    }
+
     /// Lists all pins, or the specific kind thereof.
     ///
     /// # Crash unsafety
@@ -540,6 +840,40 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         self.repo.list_pins(filter).instrument(span).await
     }
 
+    /// Lists the direct and recursive pins with the given name, or the specific kind thereof.
+    ///
+    /// # Crash unsafety
+    ///
+    /// Does not currently recover from partial recursive pin insertions.
+    pub async fn list_named_pins(
+        &self,
+        filter: Option<PinMode>,
+        name: String,
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
+        use futures::stream::{StreamExt, TryStreamExt};
+        let span = debug_span!(parent: &self.span, "list_named_pins", ?filter, %name);
+        let st = self.repo.list_pins(filter).instrument(span).await;
+
+        let repo = Arc::clone(&self.repo);
+        st.try_filter_map(move |(cid, mode)| {
+            let repo = Arc::clone(&repo);
+            let name = name.clone();
+            async move {
+                if mode == PinMode::Indirect {
+                    return Ok(None);
+                }
+
+                let metadata = repo.get_pin_metadata(&cid).await?;
+                if metadata.and_then(|m| m.name).as_ref() == Some(&name) {
+                    Ok(Some((cid, mode)))
+                } else {
+                    Ok(None)
+                }
+            }
+        })
+        .boxed()
+    }
+
     /// Read specific pins. When `requirement` is `Some`, all pins are required to be of the given
     /// [`PinMode`].
     ///
@@ -558,6 +892,107 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Read specific pins like [`Ipfs::query_pins`], returning only the direct and recursive pins
+    /// with the given name.
+    ///
+    /// # Crash unsafety
+    ///
+    /// Does not currently recover from partial recursive pin insertions.
+    pub async fn query_named_pins(
+        &self,
+        cids: Vec<Cid>,
+        requirement: Option<PinMode>,
+        name: String,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        let span = debug_span!(
+            parent: &self.span,
+            "query_named_pins",
+            ids = cids.len(),
+            ?requirement,
+            %name
+        );
+
+        async move {
+            let pins = self.repo.query_pins(cids, requirement).await?;
+
+            let mut named = Vec::with_capacity(pins.len());
+            for (cid, kind) in pins {
+                if let PinKind::IndirectFrom(_) = kind {
+                    continue;
+                }
+
+                let metadata = self.repo.get_pin_metadata(&cid).await?;
+                if metadata.and_then(|m| m.name).as_ref() == Some(&name) {
+                    named.push((cid, kind));
+                }
+            }
+            Ok(named)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Registers a remote pinning service to be used with [`Ipfs::remote_pins`]. Fails if a
+    /// service with the same name has already been registered.
+    pub async fn add_remote_pin_service(&self, service: RemotePinService) -> Result<(), Error> {
+        let span = debug_span!(parent: &self.span, "add_remote_pin_service", name = %service.name);
+
+        async move {
+            let mut services = self.repo.get_remote_pin_services().await?;
+
+            if services.iter().any(|s| s.name == service.name) {
+                return Err(anyhow!("service {:?} already exists", service.name));
+            }
+
+            services.push(service);
+            self.repo.put_remote_pin_services(&services).await
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Lists the registered remote pinning services.
+    pub async fn remote_pin_services(&self) -> Result<Vec<RemotePinService>, Error> {
+        self.repo
+            .get_remote_pin_services()
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Unregisters the remote pinning service. The pins on the service are left as is.
+    pub async fn remove_remote_pin_service(&self, name: &str) -> Result<(), Error> {
+        let span = debug_span!(parent: &self.span, "remove_remote_pin_service", name);
+
+        async move {
+            let mut services = self.repo.get_remote_pin_services().await?;
+            let before = services.len();
+            services.retain(|s| s.name != name);
+
+            if services.len() == before {
+                return Err(anyhow!("service {:?} not found", name));
+            }
+
+            self.repo.put_remote_pin_services(&services).await
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Returns the pins of a registered remote pinning service, through which pins can be added,
+    /// listed and removed on the service.
+    pub async fn remote_pins(&self, service: &str) -> Result<RemotePins<Types>, Error> {
+        let service = self
+            .repo
+            .get_remote_pin_services()
+            .instrument(self.span.clone())
+            .await?
+            .into_iter()
+            .find(|s| s.name == service)
+            .ok_or_else(|| anyhow!("service {:?} not found", service))?;
+
+        Ok(RemotePins::new(self.clone(), service))
+    }
+
     /// Puts an ipld node into the ipfs repo using `dag-cbor` codec and Sha2_256 hash.
     ///
     /// Returns Cid version 1 for the document
@@ -568,6 +1003,21 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
+    /// Puts an ipld node into the ipfs repo using the given codec, hash function and Cid version.
+    ///
+    /// See [`IpldDag::put_with_options`] for more information.
+    pub async fn put_dag_with_options(
+        &self,
+        ipld: Ipld,
+        codec: Codec,
+        cid_options: CidOptions,
+    ) -> Result<Cid, Error> {
+        self.dag()
+            .put_with_options(ipld, codec, cid_options)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Gets an ipld node from the ipfs, fetching the block if necessary.
     ///
     /// See [`IpldDag::get`] for more information.
@@ -599,7 +1049,9 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             .await
     }
 
-    /// Resolves a ipns path to an ipld path; currently only supports dnslink resolution.
+    /// Resolves a ipns path to an ipld path. Peer ids are resolved through the IPNS records in the
+    /// DHT and over pubsub when [`IpfsOptions::ipns_pubsub`] is enabled, and domain names through
+    /// dnslink.
     pub async fn resolve_ipns(&self, path: &IpfsPath, recursive: bool) -> Result<IpfsPath, Error> {
         async move {
             let ipns = self.ipns();
@@ -623,6 +1075,41 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Publishes an IPNS record of the path under the peer id of the node, valid for the given
+    /// lifetime, returning the published name. The sequence number of the record follows the
+    /// previously published one.
+    pub async fn publish_ipns(
+        &self,
+        path: &IpfsPath,
+        lifetime: Duration,
+    ) -> Result<IpfsPath, Error> {
+        self.ensure_online()?;
+
+        self.ipns()
+            .publish(path, lifetime)
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Returns true if IPNS over pubsub was enabled with [`IpfsOptions::ipns_pubsub`].
+    pub fn ipns_pubsub_enabled(&self) -> bool {
+        self.ipns_pubsub.is_some()
+    }
+
+    /// Returns the names subscribed to over pubsub by resolving or publishing them. Fails with
+    /// [`error::IpnsPubsubDisabled`] if IPNS over pubsub is not enabled.
+    pub fn ipns_pubsub_subscriptions(&self) -> Result<Vec<PeerId>, Error> {
+        let ipns_pubsub = self.ipns_pubsub.as_ref().ok_or(error::IpnsPubsubDisabled)?;
+        Ok(ipns_pubsub.names())
+    }
+
+    /// Cancels the pubsub subscription to the name, returning false if it was not subscribed to.
+    /// Fails with [`error::IpnsPubsubDisabled`] if IPNS over pubsub is not enabled.
+    pub fn ipns_pubsub_cancel(&self, name: &PeerId) -> Result<bool, Error> {
+        let ipns_pubsub = self.ipns_pubsub.as_ref().ok_or(error::IpnsPubsubDisabled)?;
+        Ok(ipns_pubsub.cancel(name))
+    }
+
     /// Connects to the peer at the given Multiaddress.
     ///
     /// Accepts only multiaddresses with the PeerId to authenticate the connection.
@@ -630,6 +1117,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Returns a future which will complete when the connection has been successfully made or
     /// failed for whatever reason.
     pub async fn connect(&self, target: MultiaddrWithPeerId) -> Result<(), Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
@@ -650,6 +1139,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Returns known peer addresses
     pub async fn addrs(&self) -> Result<Vec<(PeerId, Vec<Multiaddr>)>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task.clone().send(IpfsEvent::Addresses(tx)).await?;
@@ -661,6 +1152,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Returns local listening addresses
     pub async fn addrs_local(&self) -> Result<Vec<Multiaddr>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task.clone().send(IpfsEvent::Listeners(tx)).await?;
@@ -672,6 +1165,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Returns the connected peers
     pub async fn peers(&self) -> Result<Vec<Connection>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
@@ -689,6 +1184,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// At the moment the peer is disconnected by temporarily banning the peer and unbanning it
     /// right after. This should always disconnect all connections to the peer.
     pub async fn disconnect(&self, target: MultiaddrWithPeerId) -> Result<(), Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
             self.to_task
@@ -731,6 +1228,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// The subscription can be unsubscribed by dropping the stream or calling
     /// [`Ipfs::pubsub_unsubscribe`].
     pub async fn pubsub_subscribe(&self, topic: String) -> Result<SubscriptionStream, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -748,6 +1247,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Publishes to the topic which may have been subscribed to earlier
     pub async fn pubsub_publish(&self, topic: String, data: Vec<u8>) -> Result<(), Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -767,6 +1268,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     ///
     /// Returns true if unsubscription was successful
     pub async fn pubsub_unsubscribe(&self, topic: &str) -> Result<bool, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -783,6 +1286,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Returns all known pubsub peers with the optional topic filter
     pub async fn pubsub_peers(&self, topic: Option<String>) -> Result<Vec<PeerId>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -797,8 +1302,31 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
+    /// Returns a stream of the peers subscribing to the topic from now on.
+    pub(crate) async fn pubsub_peer_joins(
+        &self,
+        topic: String,
+    ) -> Result<UnboundedReceiver<PeerId>, Error> {
+        self.ensure_online()?;
+
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PubsubPeerJoins(topic, tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns all currently subscribed topics
     pub async fn pubsub_subscribed(&self) -> Result<Vec<String>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -818,6 +1346,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         &self,
         peer: Option<PeerId>,
     ) -> Result<Vec<(Cid, ipfs_bitswap::Priority)>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -832,16 +1362,68 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
-    /// Returns a list of local blocks
-    ///
-    /// This implementation is subject to change into a stream, which might only include the pinned
-    /// blocks.
-    pub async fn refs_local(&self) -> Result<Vec<Cid>, Error> {
-        self.repo.list_blocks().instrument(self.span.clone()).await
+    /// Lists the local blocks lazily, optionally only the ones with the given `codec`. The listing
+    /// can be resumed by giving the last listed block as the `cursor`.
+    pub async fn refs_local(
+        &self,
+        codec: Option<Codec>,
+        cursor: Option<Cid>,
+    ) -> BoxStream<'static, Result<Cid, Error>> {
+        use futures::stream::StreamExt;
+
+        self.repo
+            .list_blocks(codec, cursor)
+            .instrument(self.span.clone())
+            .await
+            .instrument(self.span.clone())
+            .boxed()
+    }
+
+    /// Returns the size and the number of the locally stored blocks along with the configured
+    /// [`IpfsOptions::storage_max`].
+    pub async fn repo_stat(&self) -> Result<RepoStat, Error> {
+        self.repo.stat().instrument(self.span.clone()).await
+    }
+
+    /// Returns the hit and miss counters of the block cache, if the block store is a
+    /// [`CachedBlockStore`].
+    pub fn block_cache_stats(&self) -> Option<BlockCacheStats> {
+        self.repo.block_cache_stats()
+    }
+
+    /// Verifies the integrity of the local blockstore and pins, streaming the status of each block,
+    /// orphaned temporary file, and missing pinned block. Bad blocks and orphaned files are
+    /// quarantined or deleted as they are found, if `repair` is given.
+    pub fn repo_verify(
+        &self,
+        repair: Option<RepoRepair>,
+    ) -> impl Stream<Item = Result<RepoVerifyEntry, Error>> + Send + '_ {
+        self.repo.verify(repair).instrument(self.span.clone())
+    }
+
+    /// Lists the blocks stored as references to files with [`Ipfs::put_block_reference`].
+    pub async fn filestore_ls(&self) -> Result<Vec<(Cid, FileReference)>, Error> {
+        self.repo
+            .list_references()
+            .instrument(self.span.clone())
+            .await
+    }
+
+    /// Lists the blocks stored as references to files along with the status of the referenced
+    /// content, which could have been moved or modified since.
+    pub async fn filestore_verify(
+        &self,
+    ) -> Result<Vec<(Cid, FileReference, FilestoreStatus)>, Error> {
+        self.repo
+            .verify_references()
+            .instrument(self.span.clone())
+            .await
     }
 
     /// Returns the accumulated bitswap stats
     pub async fn bitswap_stats(&self) -> Result<BitswapStats, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -870,6 +1452,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Returns the bound multiaddress, which in the case of original containing an ephemeral port
     /// has now been changed.
     pub async fn add_listening_address(&self, addr: Multiaddr) -> Result<Multiaddr, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -889,6 +1473,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     ///
     /// The removal of all listening addresses added through unspecified addresses is not supported.
     pub async fn remove_listening_address(&self, addr: Multiaddr) -> Result<(), Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -903,73 +1489,75 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         .await
     }
 
-    /// Obtain the addresses associated with the given `PeerId`; they are first searched for locally
-    /// and the DHT is used as a fallback: a `Kademlia::get_closest_peers(peer_id)` query is run and
-    /// when it's finished, the newly added DHT records are checked for the existence of the desired
-    /// `peer_id` and if it's there, the list of its known addresses is returned.
+    /// Obtain the addresses associated with the given `PeerId` from the first of the routers to
+    /// find them. The DHT router first searches for them locally and uses the DHT as a fallback:
+    /// a `Kademlia::get_closest_peers(peer_id)` query is run and when it's finished, the newly
+    /// added DHT records are checked for the existence of the desired `peer_id` and if it's there,
+    /// the list of its known addresses is returned.
     pub async fn find_peer(&self, peer_id: PeerId) -> Result<Vec<Multiaddr>, Error> {
-        async move {
-            let (tx, rx) = oneshot_channel();
+        self.ensure_online()?;
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::FindPeer(peer_id.clone(), false, tx))
-                .await?;
+        self.routing
+            .find_peer(&peer_id)
+            .instrument(self.span.clone())
+            .await
+    }
 
-            match rx.await? {
-                Either::Left(addrs) if !addrs.is_empty() => return Ok(addrs),
-                Either::Left(_) => unreachable!(),
-                Either::Right(future) => {
-                    future.await?;
+    /// Performs a DHT lookup for providers of a value to the given key, in parallel with the
+    /// lookups of the other content routers.
+    ///
+    /// Returns a list of peers found providing the Cid.
+    pub async fn get_providers(&self, cid: Cid) -> Result<Vec<PeerId>, Error> {
+        let providers = self.find_providers_with_addrs(cid).await?;
 
-                    let (tx, rx) = oneshot_channel();
+        Ok(providers.into_iter().map(|info| info.peer_id).collect())
+    }
 
-                    self.to_task
-                        .clone()
-                        .send(IpfsEvent::FindPeer(peer_id.clone(), true, tx))
-                        .await?;
+    /// Looks up the providers of the Cid like [`Ipfs::get_providers`], returning the addresses
+    /// the content routers know for them along with the peers.
+    pub async fn find_providers_with_addrs(&self, cid: Cid) -> Result<Vec<PeerInfo>, Error> {
+        self.ensure_online()?;
 
-                    match rx.await? {
-                        Either::Left(addrs) if !addrs.is_empty() => return Ok(addrs),
-                        _ => return Err(anyhow!("couldn't find peer {}", peer_id)),
-                    }
-                }
-            }
-        }
-        .instrument(self.span.clone())
-        .await
+        self.routing
+            .find_providers(&cid)
+            .instrument(self.span.clone())
+            .await
     }
 
-    /// Performs a DHT lookup for providers of a value to the given key.
-    ///
-    /// Returns a list of peers found providing the Cid.
-    pub async fn get_providers(&self, cid: Cid) -> Result<Vec<PeerId>, Error> {
-        let kad_result = async move {
-            let (tx, rx) = oneshot_channel();
+    /// Streams the providers of the Cid like [`Ipfs::find_providers_with_addrs`], but as soon as any of the
+    /// content routers finds them. Every provider is yielded once, with the addresses known by
+    /// the first router to find it.
+    pub fn get_providers_stream(
+        &self,
+        cid: Cid,
+    ) -> impl Stream<Item = Result<PeerInfo, Error>> + Send + 'static {
+        use futures::stream::StreamExt;
 
-            self.to_task
-                .clone()
-                .send(IpfsEvent::GetProviders(cid, tx))
-                .await?;
+        let online = self.ensure_online();
+        let mut found = self.routing.providers(&cid);
 
-            Ok(rx.await?).map_err(|e: String| anyhow!(e))
-        }
-        .instrument(self.span.clone())
-        .await?
-        .await;
+        let st = async_stream::try_stream! {
+            online?;
 
-        match kad_result {
-            Ok(KadResult::Peers(providers)) => Ok(providers),
-            Ok(_) => unreachable!(),
-            Err(e) => Err(anyhow!(e)),
-        }
+            let mut seen = HashSet::new();
+            while let Some(info) = found.next().await {
+                let info = info?;
+                if seen.insert(info.peer_id.clone()) {
+                    yield info;
+                }
+            }
+        };
+
+        st.instrument(self.span.clone())
     }
 
     /// Establishes the node as a provider of a block with the given Cid: it publishes a provider
     /// record with the given key (Cid) and the node's PeerId to the peers closest to the key. The
     /// publication of provider records is periodically repeated as per the interval specified in
-    /// `libp2p`'s  `KademliaConfig`.
+    /// `libp2p`'s  `KademliaConfig`. Succeeds if any of the content routers succeeds.
     pub async fn provide(&self, cid: Cid) -> Result<(), Error> {
+        self.ensure_online()?;
+
         // don't provide things we don't actually have
         if self.repo.get_block_now(&cid).await?.is_none() {
             return Err(anyhow!(
@@ -978,31 +1566,18 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             ));
         }
 
-        let kad_result = async move {
-            let (tx, rx) = oneshot_channel();
-
-            self.to_task
-                .clone()
-                .send(IpfsEvent::Provide(cid, tx))
-                .await?;
-
-            rx.await?
-        }
-        .instrument(self.span.clone())
-        .await?
-        .await;
-
-        match kad_result {
-            Ok(KadResult::Complete) => Ok(()),
-            Ok(_) => unreachable!(),
-            Err(e) => Err(anyhow!(e)),
-        }
+        self.routing
+            .provide(&cid)
+            .instrument(self.span.clone())
+            .await
     }
 
     /// Returns a list of peers closest to the given `PeerId`, as suggested by the DHT. The
     /// node must have at least one known peer in its routing table in order for the query
     /// to return any values.
     pub async fn get_closest_peers(&self, peer_id: PeerId) -> Result<Vec<PeerId>, Error> {
+        self.ensure_online()?;
+
         let kad_result = async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1025,12 +1600,15 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     }
 
     /// Attempts to look a key up in the DHT and returns the values found in the records
-    /// containing that key.
+    /// containing that key. The records of the `/pk` and `/ipns` namespaces are validated and the
+    /// best value is returned first.
     pub async fn dht_get<T: Into<Key>>(
         &self,
         key: T,
         quorum: Quorum,
     ) -> Result<Vec<Vec<u8>>, Error> {
+        self.ensure_online()?;
+
         let kad_result = async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1058,12 +1636,19 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Stores the given key + value record locally and replicates it in the DHT. It doesn't
     /// expire locally and is periodically replicated in the DHT, as per the `KademliaConfig`
     /// setup.
+    ///
+    /// Records of the `/pk` namespace need to contain the public key of the peer id in the key,
+    /// and records of the `/ipns` namespace need to be IPNS records signed by the peer id in the
+    /// key and not older than the record already stored locally. The records of other namespaces
+    /// are rejected.
     pub async fn dht_put<T: Into<Key>>(
         &self,
         key: T,
         value: Vec<u8>,
         quorum: Quorum,
     ) -> Result<(), Error> {
+        self.ensure_online()?;
+
         let kad_result = async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1103,6 +1688,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Obtain the list of addresses of bootstrapper nodes that are currently used.
     pub async fn get_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1121,6 +1708,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Return value cannot be used to determine if the `addr` was a new bootstrapper, subject to
     /// change.
     pub async fn add_bootstrapper(&self, addr: MultiaddrWithPeerId) -> Result<Multiaddr, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1139,6 +1728,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Return value cannot be used to determine if the `addr` was an actual bootstrapper, subject to
     /// change.
     pub async fn remove_bootstrapper(&self, addr: MultiaddrWithPeerId) -> Result<Multiaddr, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1155,6 +1746,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 
     /// Clear the currently used list of bootstrapper nodes, returning the removed addresses.
     pub async fn clear_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1172,6 +1765,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
     /// Restore the originally configured bootstrapper node list by adding them to the list of the
     /// currently used bootstrapper node address list; returns the restored addresses.
     pub async fn restore_bootstrappers(&self) -> Result<Vec<Multiaddr>, Error> {
+        self.ensure_online()?;
+
         async move {
             let (tx, rx) = oneshot_channel();
 
@@ -1206,6 +1801,44 @@ struct IpfsFuture<Types: IpfsTypes> {
     listening_addresses: HashMap<Multiaddr, (ListenerId, Option<Channel<Multiaddr>>)>,
 }
 
+/// Background task of `Ipfs` created when calling `UninitializedIpfs::start` with
+/// [`IpfsOptions::offline`]. As there is no swarm, the only responsibilities are answering the
+/// few events which make sense without networking and exiting when requested.
+struct OfflineFuture {
+    repo_events: Fuse<Receiver<RepoEvent>>,
+    from_facade: Fuse<Receiver<IpfsEvent>>,
+}
+
+impl Future for OfflineFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Self::Output> {
+        // the offline repo does not send any events, but drain them anyway so that nothing gets
+        // stuck waiting on the channel capacity.
+        while let Poll::Ready(Some(evt)) = Pin::new(&mut self.repo_events).poll_next(ctx) {
+            trace!("ignoring {:?} while offline", evt);
+        }
+
+        loop {
+            let inner = match Pin::new(&mut self.from_facade).poll_next(ctx) {
+                Poll::Ready(Some(evt)) => evt,
+                Poll::Ready(None) => IpfsEvent::Exit,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match inner {
+                IpfsEvent::GetAddresses(ret) => {
+                    // nothing is listened on
+                    let _ = ret.send(Vec::new());
+                }
+                IpfsEvent::Exit => return Poll::Ready(()),
+                // the facade guards against these, dropping the channel will be an error
+                _ => trace!("ignoring an event while offline"),
+            }
+        }
+    }
+}
+
 impl<TRepoTypes: RepoTypes> IpfsFuture<TRepoTypes> {
     /// Completes the adding of listening address by matching the new listening address `addr` to
     /// the `self.listening_addresses` so that we can detect even the multiaddresses with ephemeral
@@ -1420,6 +2053,9 @@ impl<TRepoTypes: RepoTypes> Future for IpfsFuture<TRepoTypes> {
                     IpfsEvent::PubsubPeers(None, ret) => {
                         let _ = ret.send(self.swarm.pubsub().known_peers());
                     }
+                    IpfsEvent::PubsubPeerJoins(topic, ret) => {
+                        let _ = ret.send(self.swarm.pubsub().watch_joins(topic));
+                    }
                     IpfsEvent::PubsubSubscribed(ret) => {
                         let _ = ret.send(self.swarm.pubsub().subscribed_topics());
                     }
@@ -1745,6 +2381,140 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn test_put_and_get_block_with_dyn_stores() {
+        let opts = IpfsOptions::inmemory_with_generated_keys();
+        let (ipfs, fut): (Ipfs<DynTypes>, _) = UninitializedIpfs::with_stores(
+            opts,
+            Box::new(repo::mem::MemBlockStore::default()),
+            Box::new(repo::mem::MemDataStore::default()),
+        )
+        .start()
+        .await
+        .unwrap();
+        tokio::task::spawn(fut);
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let block = Block::new(data, cid);
+
+        let cid: Cid = ipfs.put_block(block.clone()).await.unwrap();
+        let new_block = ipfs.get_block(&cid).await.unwrap();
+        assert_eq!(block, new_block);
+
+        ipfs.exit_daemon().await;
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn offline_node_serves_only_local_blocks() {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.offline = true;
+        let ipfs = Node::with_options(opts).await;
+
+        let data = b"hello block\n".to_vec().into_boxed_slice();
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let block = Block::new(data, cid);
+
+        let cid: Cid = ipfs.put_block(block.clone()).await.unwrap();
+        assert_eq!(ipfs.get_block(&cid).await.unwrap(), block);
+
+        let missing = Cid::new_v1(Codec::Raw, Sha2_256::digest(b"missing"));
+        let e = ipfs.get_block(&missing).await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<error::BlockNotFound>(),
+            Some(&error::BlockNotFound(missing))
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn offline_node_refuses_network_operations() {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.offline = true;
+        let ipfs = Node::with_options(opts).await;
+
+        let e = ipfs.peers().await.unwrap_err();
+        assert_eq!(e.downcast_ref::<error::Offline>(), Some(&error::Offline));
+
+        let e = ipfs.pubsub_subscribe("topic".into()).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<error::Offline>(), Some(&error::Offline));
+
+        let (public_key, addrs) = ipfs.identity().await.unwrap();
+        assert_eq!(public_key, ipfs.keys.get_ref().public());
+        assert!(addrs.is_empty());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn least_recently_used_unpinned_blocks_are_evicted() {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.offline = true;
+        opts.storage_max = Some(10);
+        opts.storage_high_water_mark = 100;
+        let ipfs = Node::with_options(opts).await;
+
+        let mut cids = Vec::new();
+        for data in &[b"0001", b"0002", b"0003"] {
+            let data = data.to_vec().into_boxed_slice();
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            cids.push(ipfs.put_block(Block::new(data, cid)).await.unwrap());
+
+            if cids.len() == 1 {
+                ipfs.insert_pin(&cids[0], false).await.unwrap();
+            }
+        }
+
+        // the first one is pinned, so the second one is evicted
+        ipfs.get_block(&cids[0]).await.unwrap();
+        ipfs.get_block(&cids[1]).await.unwrap_err();
+        ipfs.get_block(&cids[2]).await.unwrap();
+
+        let stat = ipfs.repo_stat().await.unwrap();
+        assert_eq!(
+            stat,
+            RepoStat {
+                size: 8,
+                logical_size: 8,
+                blocks: 2,
+                storage_max: Some(10),
+            }
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn pinned_block_is_not_removed_through_other_codec() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"pinned as dag-pb".to_vec().into_boxed_slice();
+        let pinned = Cid::new_v0(Sha2_256::digest(&data)).unwrap();
+        ipfs.put_block(Block::new(data, pinned.clone()))
+            .await
+            .unwrap();
+        ipfs.insert_pin(&pinned, false).await.unwrap();
+
+        // the same content, stored only once in the multihash keyed block store
+        let alias = Cid::new_v1(Codec::Raw, pinned.hash().to_owned());
+        ipfs.remove_block(alias).await.unwrap_err();
+
+        assert!(ipfs.repo.get_block_now(&pinned).await.unwrap().is_some());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn block_cache_is_enabled_through_options() {
+        let ipfs = Node::new("test_node").await;
+        assert_eq!(ipfs.block_cache_stats(), None);
+
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.offline = true;
+        opts.block_cache = Some(1024);
+        let ipfs = Node::with_options(opts).await;
+
+        let cid = ipfs.put_dag(make_ipld!("cached")).await.unwrap();
+        ipfs.get_dag(cid.into()).await.unwrap();
+
+        let stats = ipfs.block_cache_stats().unwrap();
+        assert_eq!(stats.capacity, 1024);
+        assert_eq!(stats.cached_blocks, 1);
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
@@ -1767,4 +2537,67 @@ mod tests {
         ipfs.remove_pin(&cid, false).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test(max_threads = 1)]
+    async fn named_pin_is_updated() {
+        use futures::stream::TryStreamExt;
+
+        let ipfs = Node::new("test_node").await;
+
+        let a = ipfs.put_dag(make_ipld!("a")).await.unwrap();
+        let b = ipfs.put_dag(make_ipld!("b")).await.unwrap();
+        let c = ipfs.put_dag(make_ipld!("c")).await.unwrap();
+        let old = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(a.clone()),
+                Ipld::Link(b.clone()),
+            ]))
+            .await
+            .unwrap();
+        let new = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(b.clone()),
+                Ipld::Link(c.clone()),
+            ]))
+            .await
+            .unwrap();
+
+        ipfs.insert_pin(&a, false).await.unwrap();
+        ipfs.insert_pin_with(&old, true, PinMetadata::named("dataset"))
+            .await
+            .unwrap();
+
+        async fn named<T: IpfsTypes>(ipfs: &Ipfs<T>) -> Vec<(Cid, PinMode)> {
+            ipfs.list_named_pins(None, "dataset".into())
+                .await
+                .try_collect()
+                .await
+                .unwrap()
+        }
+
+        assert_eq!(named(&ipfs).await, vec![(old.clone(), PinMode::Recursive)]);
+
+        ipfs.update_pin(&old, &new).await.unwrap();
+
+        assert_eq!(named(&ipfs).await, vec![(new.clone(), PinMode::Recursive)]);
+        assert_eq!(ipfs.pin_metadata(&old).await.unwrap(), None);
+        assert!(!ipfs.is_pinned(&old).await.unwrap());
+        assert!(ipfs.is_pinned(&b).await.unwrap());
+        assert!(ipfs.is_pinned(&c).await.unwrap());
+
+        // the direct pin stays
+        let pins = ipfs.query_pins(vec![a], None).await.unwrap();
+        assert_eq!(pins.len(), 1);
+        assert_eq!(pins[0].1, PinMode::Direct);
+
+        // indirect pins have no names
+        let pins = ipfs
+            .query_named_pins(vec![new, b], None, "dataset".into())
+            .await
+            .unwrap();
+        assert_eq!(pins.len(), 1);
+
+        ipfs.remove_pin(&pins[0].0, true).await.unwrap();
+        assert!(named(&ipfs).await.is_empty());
+    }
 }
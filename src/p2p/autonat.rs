@@ -0,0 +1,607 @@
+//! AutoNAT-style reachability checks: the node asks the peers it connects to to dial back its
+//! listening addresses on a new connection. A single successful dial back means the node is
+//! publicly reachable, while repeated failures mean it is most likely behind a NAT or a firewall.
+//! The connected peers are asked again periodically, as the reachability can change over time.
+//!
+//! This is not the libp2p AutoNAT protocol: the messages are simpler and the peers only dial back
+//! the addresses with the same IP address as the connection the request came in.
+use futures::future::BoxFuture;
+use futures::io::{AsyncRead, AsyncWrite};
+use libp2p::core::{
+    connection::{ConnectedPoint, ConnectionId},
+    multiaddr::Protocol,
+    upgrade, InboundUpgrade, Multiaddr, OutboundUpgrade, PeerId, UpgradeInfo,
+};
+use libp2p::swarm::{
+    NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, OneShotHandler, PollParameters,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use std::{io, iter};
+use tokio::time::{interval_at, Instant, Interval};
+
+const PROTOCOL_NAME: &[u8] = b"/rust-ipfs/autonat/0.1.0";
+
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+/// The maximum number of addresses dialed back for a single request.
+const MAX_DIAL_BACKS: usize = 8;
+
+/// The number of failed dial backs after which the node is considered private.
+const PRIVATE_THRESHOLD: usize = 3;
+
+/// How often the connected peers are asked again to dial back.
+const REPROBE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// The reachability of the node as found out by the dial backs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    /// Not enough peers have answered yet.
+    Unknown,
+    /// A peer was able to dial back one of the listening addresses.
+    Public,
+    /// The dial backs of multiple peers failed.
+    Private,
+}
+
+/// Events of the [`AutoNat`] behaviour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoNatEvent {
+    /// The reachability has been found out or it has changed.
+    ReachabilityChanged(Reachability),
+}
+
+/// The messages of the protocol. Each message is sent on a new substream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutoNatMessage {
+    /// Asks the remote to dial back any of the addresses.
+    DialBack(Vec<Multiaddr>),
+    /// The address which was successfully dialed back, if any.
+    DialBackResponse(Option<Multiaddr>),
+}
+
+impl AutoNatMessage {
+    fn into_bytes(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            AutoNatMessage::DialBack(addrs) => {
+                out.push(0);
+                for addr in addrs {
+                    let bytes = addr.to_vec();
+                    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+                    out.extend_from_slice(&bytes);
+                }
+            }
+            AutoNatMessage::DialBackResponse(addr) => {
+                out.push(1);
+                if let Some(addr) = addr {
+                    out.extend_from_slice(&addr.to_vec());
+                }
+            }
+        }
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let parse_addr = |bytes: &[u8]| {
+            Multiaddr::try_from(bytes.to_vec()).map_err(|_| invalid("invalid multiaddr"))
+        };
+
+        match bytes.split_first() {
+            Some((&0, rest)) => {
+                let mut rest = rest;
+                let mut addrs = Vec::new();
+                while !rest.is_empty() {
+                    if rest.len() < 2 || addrs.len() >= MAX_DIAL_BACKS {
+                        return Err(invalid("invalid dial back request"));
+                    }
+                    let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    if rest.len() < 2 + len {
+                        return Err(invalid("truncated multiaddr"));
+                    }
+                    addrs.push(parse_addr(&rest[2..2 + len])?);
+                    rest = &rest[2 + len..];
+                }
+                Ok(AutoNatMessage::DialBack(addrs))
+            }
+            Some((&1, rest)) if rest.is_empty() => Ok(AutoNatMessage::DialBackResponse(None)),
+            Some((&1, rest)) => Ok(AutoNatMessage::DialBackResponse(Some(parse_addr(rest)?))),
+            _ => Err(invalid("unknown message")),
+        }
+    }
+}
+
+/// The inbound side of the protocol.
+#[derive(Debug, Clone, Default)]
+pub struct AutoNatProtocol;
+
+impl UpgradeInfo for AutoNatProtocol {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL_NAME)
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for AutoNatProtocol
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = AutoNatMessage;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_inbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let packet = upgrade::read_one(&mut socket, MAX_MESSAGE_SIZE)
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            AutoNatMessage::from_bytes(&packet)
+        })
+    }
+}
+
+impl UpgradeInfo for AutoNatMessage {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(PROTOCOL_NAME)
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for AutoNatMessage
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Output, Self::Error>>;
+
+    fn upgrade_outbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move { upgrade::write_one(&mut socket, self.into_bytes()).await })
+    }
+}
+
+/// Transmission between the `OneShotHandler` and the `AutoNat` behaviour.
+#[derive(Debug)]
+pub enum InnerMessage {
+    Rx(AutoNatMessage),
+    Sent,
+}
+
+impl From<AutoNatMessage> for InnerMessage {
+    fn from(msg: AutoNatMessage) -> Self {
+        InnerMessage::Rx(msg)
+    }
+}
+
+impl From<()> for InnerMessage {
+    fn from(_: ()) -> Self {
+        InnerMessage::Sent
+    }
+}
+
+/// Answers the dial back requests of the other peers, and when probing, asks the newly connected
+/// peers to dial back until the reachability is known. The reachability is then checked again
+/// with all of the connected peers on every [`REPROBE_INTERVAL`].
+pub struct AutoNat {
+    probe: bool,
+    /// True until the reachability has been found out in the current round of dial backs.
+    probing: bool,
+    reprobe_interval: Duration,
+    /// Created on the first poll, as it needs the timer of the runtime.
+    reprobe: Option<Interval>,
+    reachability: Reachability,
+    /// The results of the current round of dial backs.
+    successes: usize,
+    failures: usize,
+    listen_addrs: Vec<Multiaddr>,
+    /// The peers asked to dial back, which have not yet answered.
+    asked: HashSet<PeerId>,
+    /// The remote addresses of the connected peers.
+    remote_addrs: HashMap<PeerId, Multiaddr>,
+    /// The addresses being dialed back for the requesting peers.
+    dialing: HashMap<PeerId, HashSet<Multiaddr>>,
+    events: VecDeque<NetworkBehaviourAction<AutoNatMessage, AutoNatEvent>>,
+}
+
+impl AutoNat {
+    /// Creates the behaviour, asking for the dial backs only if `probe` is true.
+    pub fn new(probe: bool) -> Self {
+        AutoNat {
+            probe,
+            probing: probe,
+            reprobe_interval: REPROBE_INTERVAL,
+            reprobe: None,
+            reachability: Reachability::Unknown,
+            successes: 0,
+            failures: 0,
+            listen_addrs: Vec::new(),
+            asked: HashSet::new(),
+            remote_addrs: HashMap::new(),
+            dialing: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Overrides the interval of asking the connected peers again.
+    pub fn with_reprobe_interval(mut self, interval: Duration) -> Self {
+        self.reprobe_interval = interval;
+        self
+    }
+
+    /// The reachability found out so far.
+    pub fn reachability(&self) -> Reachability {
+        self.reachability
+    }
+
+    fn ask(&mut self, peer_id: PeerId) {
+        if self.listen_addrs.is_empty() || !self.asked.insert(peer_id.clone()) {
+            return;
+        }
+        self.events
+            .push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::Any,
+                event: AutoNatMessage::DialBack(self.listen_addrs.clone()),
+            });
+    }
+
+    /// Starts a new round of dial backs with all of the connected peers. The reachability is kept
+    /// until the new round has found it out again.
+    fn reprobe(&mut self) {
+        debug!("autonat: checking the reachability again");
+        self.probing = true;
+        self.successes = 0;
+        self.failures = 0;
+        self.asked.clear();
+        let peers = self.remote_addrs.keys().cloned().collect::<Vec<_>>();
+        for peer_id in peers {
+            self.ask(peer_id);
+        }
+    }
+
+    fn respond(&mut self, peer_id: PeerId, addr: Option<Multiaddr>) {
+        self.events
+            .push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::Any,
+                event: AutoNatMessage::DialBackResponse(addr),
+            });
+    }
+
+    fn on_dial_back_request(&mut self, peer_id: PeerId, addrs: Vec<Multiaddr>) {
+        if self.dialing.contains_key(&peer_id) {
+            // the previous request is still in progress
+            return;
+        }
+
+        let observed = self.remote_addrs.get(&peer_id).and_then(ip_of);
+
+        // only the addresses of the requesting peer are dialed, so that the requests cannot be
+        // used to make this node dial arbitrary hosts
+        let addrs = addrs
+            .into_iter()
+            .filter(|addr| observed.is_some() && ip_of(addr) == observed)
+            .take(MAX_DIAL_BACKS)
+            .collect::<HashSet<_>>();
+
+        if addrs.is_empty() {
+            self.respond(peer_id, None);
+            return;
+        }
+
+        for address in &addrs {
+            self.events.push_back(NetworkBehaviourAction::DialAddress {
+                address: address.to_owned(),
+            });
+        }
+
+        self.dialing.insert(peer_id, addrs);
+    }
+
+    fn on_dial_back_response(&mut self, peer_id: &PeerId, addr: Option<Multiaddr>) {
+        if !self.asked.remove(peer_id) {
+            return;
+        }
+
+        let reachability = match addr {
+            Some(addr) => {
+                debug!("autonat: {} dialed back {}", peer_id, addr);
+                self.successes += 1;
+                self.failures = 0;
+                self.probing = false;
+                Reachability::Public
+            }
+            None => {
+                debug!("autonat: {} could not dial back", peer_id);
+                self.failures += 1;
+                if self.failures >= PRIVATE_THRESHOLD && self.successes == 0 {
+                    self.probing = false;
+                    Reachability::Private
+                } else {
+                    self.reachability
+                }
+            }
+        };
+
+        if reachability != self.reachability {
+            info!("autonat: reachability changed to {:?}", reachability);
+            self.reachability = reachability;
+            self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                AutoNatEvent::ReachabilityChanged(reachability),
+            ));
+        }
+    }
+
+    /// Marks the dialed address as done, responding to the requesting peer if the address was
+    /// successfully dialed or if it was the last one remaining.
+    fn on_dial_back_done(&mut self, addr: &Multiaddr, success: bool) {
+        let peer_id = self
+            .dialing
+            .iter()
+            .find(|(_, addrs)| addrs.contains(addr))
+            .map(|(peer_id, _)| peer_id.to_owned());
+
+        let peer_id = match peer_id {
+            Some(peer_id) => peer_id,
+            None => return,
+        };
+
+        let addrs = self.dialing.get_mut(&peer_id).expect("just found");
+        addrs.remove(addr);
+
+        if success {
+            self.dialing.remove(&peer_id);
+            self.respond(peer_id, Some(addr.to_owned()));
+        } else if addrs.is_empty() {
+            self.dialing.remove(&peer_id);
+            self.respond(peer_id, None);
+        }
+    }
+}
+
+/// Returns the IP address part of the multiaddr, if any.
+fn ip_of(addr: &Multiaddr) -> Option<Protocol<'static>> {
+    match addr.iter().next() {
+        Some(ip @ Protocol::Ip4(_)) | Some(ip @ Protocol::Ip6(_)) => Some(ip.acquire()),
+        _ => None,
+    }
+}
+
+impl NetworkBehaviour for AutoNat {
+    type ProtocolsHandler = OneShotHandler<AutoNatProtocol, AutoNatMessage, InnerMessage>;
+    type OutEvent = AutoNatEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _peer_id: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, _peer_id: &PeerId) {}
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.remote_addrs.remove(peer_id);
+        self.asked.remove(peer_id);
+        self.dialing.remove(peer_id);
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        _connection_id: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.remote_addrs
+            .insert(peer_id.to_owned(), endpoint.get_remote_address().to_owned());
+
+        if let ConnectedPoint::Dialer { address } = endpoint {
+            if self
+                .dialing
+                .get(peer_id)
+                .map(|addrs| addrs.contains(address))
+                .unwrap_or(false)
+            {
+                self.on_dial_back_done(address, true);
+                return;
+            } else {
+                // the dial back reached another peer
+                self.on_dial_back_done(address, false);
+            }
+        }
+
+        if self.probing {
+            self.ask(peer_id.to_owned());
+        }
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, _connection: ConnectionId, event: InnerMessage) {
+        match event {
+            InnerMessage::Rx(AutoNatMessage::DialBack(addrs)) => {
+                self.on_dial_back_request(peer_id, addrs)
+            }
+            InnerMessage::Rx(AutoNatMessage::DialBackResponse(addr)) => {
+                self.on_dial_back_response(&peer_id, addr)
+            }
+            InnerMessage::Sent => {}
+        }
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        _error: &dyn std::error::Error,
+    ) {
+        if peer_id.is_none() {
+            self.on_dial_back_done(addr, false);
+        }
+    }
+
+    fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
+        if !self.listen_addrs.contains(addr) {
+            self.listen_addrs.push(addr.to_owned());
+        }
+    }
+
+    fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
+        self.listen_addrs.retain(|a| a != addr);
+    }
+
+    fn poll(
+        &mut self,
+        ctx: &mut Context,
+        _poll: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<AutoNatMessage, AutoNatEvent>> {
+        if self.probe {
+            let period = self.reprobe_interval;
+            let reprobe = self
+                .reprobe
+                .get_or_insert_with(|| interval_at(Instant::now() + period, period));
+
+            // polled until pending so that the next tick wakes the task up
+            if let Poll::Ready(_) = reprobe.poll_tick(ctx) {
+                while reprobe.poll_tick(ctx).is_ready() {}
+                self.reprobe();
+            }
+        }
+
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoNat, AutoNatEvent, AutoNatMessage, Reachability, PRIVATE_THRESHOLD};
+    use crate::p2p::transport::build_transport;
+    use futures::future::{select, Either};
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::{NetworkBehaviourAction, Swarm, SwarmEvent};
+    use libp2p::{Multiaddr, PeerId};
+
+    #[test]
+    fn message_roundtrip() {
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+            "/ip6/::1/tcp/4001".parse().unwrap(),
+        ];
+
+        for msg in vec![
+            AutoNatMessage::DialBack(addrs.clone()),
+            AutoNatMessage::DialBack(Vec::new()),
+            AutoNatMessage::DialBackResponse(Some(addrs[0].clone())),
+            AutoNatMessage::DialBackResponse(None),
+        ] {
+            let bytes = msg.clone().into_bytes();
+            assert_eq!(AutoNatMessage::from_bytes(&bytes).unwrap(), msg);
+        }
+
+        AutoNatMessage::from_bytes(&[2]).unwrap_err();
+        AutoNatMessage::from_bytes(&[0, 0, 5, 1]).unwrap_err();
+    }
+
+    fn mk_swarm(probe: bool) -> Swarm<AutoNat> {
+        let key = Keypair::generate_ed25519();
+        let peer_id = key.public().into_peer_id();
+        let transport = build_transport(key).unwrap();
+        Swarm::new(transport, AutoNat::new(probe), peer_id)
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn listening_node_is_public() {
+        let mut server = mk_swarm(false);
+        let mut client = mk_swarm(true);
+
+        Swarm::listen_on(&mut server, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        Swarm::listen_on(&mut client, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+        let server_addr = loop {
+            if let SwarmEvent::NewListenAddr(addr) = server.next_event().await {
+                break addr;
+            }
+        };
+
+        loop {
+            if let SwarmEvent::NewListenAddr(_) = client.next_event().await {
+                break;
+            }
+        }
+
+        Swarm::dial_addr(&mut client, server_addr).unwrap();
+
+        loop {
+            match select(Box::pin(client.next()), Box::pin(server.next_event())).await {
+                Either::Left((AutoNatEvent::ReachabilityChanged(reachability), _)) => {
+                    assert_eq!(reachability, Reachability::Public);
+                    break;
+                }
+                Either::Right(_) => {}
+            }
+        }
+
+        assert_eq!(client.reachability(), Reachability::Public);
+    }
+
+    fn changes(autonat: &mut AutoNat) -> Vec<Reachability> {
+        autonat
+            .events
+            .drain(..)
+            .filter_map(|event| match event {
+                NetworkBehaviourAction::GenerateEvent(AutoNatEvent::ReachabilityChanged(r)) => {
+                    Some(r)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_dial_backs_make_the_node_private() {
+        let addr: Multiaddr = "/ip4/192.168.1.2/tcp/4001".parse().unwrap();
+        let mut autonat = AutoNat::new(true);
+        autonat.listen_addrs.push(addr.clone());
+
+        let peers = (0..PRIVATE_THRESHOLD)
+            .map(|_| PeerId::random())
+            .collect::<Vec<_>>();
+
+        for peer_id in &peers {
+            autonat.remote_addrs.insert(peer_id.clone(), addr.clone());
+            autonat.ask(peer_id.clone());
+        }
+
+        for peer_id in &peers[1..] {
+            autonat.on_dial_back_response(peer_id, None);
+        }
+        assert_eq!(autonat.reachability(), Reachability::Unknown);
+
+        // the answers of the peers which were not asked are ignored
+        autonat.on_dial_back_response(&peers[1], None);
+        assert_eq!(autonat.reachability(), Reachability::Unknown);
+
+        autonat.on_dial_back_response(&peers[0], None);
+        assert_eq!(autonat.reachability(), Reachability::Private);
+        assert_eq!(changes(&mut autonat), vec![Reachability::Private]);
+
+        // the newly connected peers are not asked until the next round
+        assert!(!autonat.probing);
+
+        // a single successful dial back in the next round makes the node public again
+        autonat.reprobe();
+        assert_eq!(autonat.asked.len(), PRIVATE_THRESHOLD);
+        autonat.on_dial_back_response(&peers[2], Some(addr));
+        assert_eq!(autonat.reachability(), Reachability::Public);
+        assert_eq!(changes(&mut autonat), vec![Reachability::Public]);
+    }
+}
@@ -1,3 +1,6 @@
+use super::autonat::{AutoNat, AutoNatEvent, Reachability};
+use super::dht::{Dht, DhtMode};
+use super::identify::Identify;
 use super::pubsub::Pubsub;
 use super::swarm::{Connection, Disconnector, SwarmApi};
 use crate::config::BOOTSTRAP_NODES;
@@ -9,7 +12,7 @@ use anyhow::anyhow;
 use cid::Cid;
 use ipfs_bitswap::{Bitswap, BitswapEvent};
 use libp2p::core::{Multiaddr, PeerId};
-use libp2p::identify::{Identify, IdentifyEvent};
+use libp2p::identify::IdentifyEvent;
 use libp2p::kad::record::{store::MemoryStore, Key, Record};
 use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent, Quorum};
 use libp2p::mdns::{MdnsEvent, TokioMdns};
@@ -26,12 +29,13 @@ pub struct Behaviour<Types: IpfsTypes> {
     #[behaviour(ignore)]
     repo: Arc<Repo<Types>>,
     mdns: Toggle<TokioMdns>,
-    kademlia: Kademlia<MemoryStore>,
+    kademlia: Dht,
     #[behaviour(ignore)]
     kad_subscriptions: SubscriptionRegistry<KadResult, String>,
     bitswap: Bitswap,
     ping: Ping,
     identify: Identify,
+    autonat: AutoNat,
     pubsub: Pubsub,
     pub swarm: SwarmApi,
 }
@@ -182,7 +186,11 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<KademliaEvent> for Behaviour
                     }
                     GetRecord(Ok(GetRecordOk { records })) => {
                         if self.kademlia.query(&id).is_none() {
-                            let records = records.into_iter().map(|rec| rec.record).collect();
+                            let mut records = records
+                                .into_iter()
+                                .map(|rec| rec.record)
+                                .collect::<Vec<_>>();
+                            self.kademlia.best_first(&mut records);
                             self.kad_subscriptions
                                 .finish_subscription(id.into(), Ok(KadResult::Records(records)));
                         }
@@ -410,6 +418,23 @@ impl<Types: IpfsTypes> NetworkBehaviourEventProcess<IdentifyEvent> for Behaviour
     }
 }
 
+impl<Types: IpfsTypes> NetworkBehaviourEventProcess<AutoNatEvent> for Behaviour<Types> {
+    fn inject_event(&mut self, event: AutoNatEvent) {
+        match event {
+            AutoNatEvent::ReachabilityChanged(reachability) => {
+                let server = reachability == Reachability::Public;
+                if self.kademlia.set_server(server) {
+                    self.identify.set_advertise_kad(server);
+                    info!(
+                        "kad: switched to the {} mode",
+                        if server { "server" } else { "client" }
+                    );
+                }
+            }
+        }
+    }
+}
+
 impl<Types: IpfsTypes> Behaviour<Types> {
     /// Create a Kademlia behaviour with the IPFS bootstrap nodes.
     pub async fn new(options: SwarmOptions, repo: Arc<Repo<Types>>) -> Self {
@@ -422,15 +447,15 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         }
         .into();
 
-        let store = MemoryStore::new(options.peer_id.to_owned());
-
         let mut kad_config = KademliaConfig::default();
         kad_config.disjoint_query_paths(true);
         kad_config.set_query_timeout(std::time::Duration::from_secs(300));
-        if let Some(protocol) = options.kad_protocol {
-            kad_config.set_protocol_name(protocol.into_bytes());
-        }
-        let mut kademlia = Kademlia::with_config(options.peer_id.to_owned(), store, kad_config);
+        let mut kademlia = Dht::new(
+            options.peer_id.to_owned(),
+            kad_config,
+            options.kad_protocol,
+            options.dht_mode,
+        );
 
         for (addr, peer_id) in &options.bootstrap {
             kademlia.add_address(peer_id, addr.to_owned());
@@ -439,10 +464,16 @@ impl<Types: IpfsTypes> Behaviour<Types> {
         let bitswap = Bitswap::default();
         let ping = Ping::default();
         let identify = Identify::new(
-            "/ipfs/0.1.0".into(),
-            "rust-ipfs".into(),
-            options.keypair.public(),
+            libp2p::identify::Identify::new(
+                "/ipfs/0.1.0".into(),
+                "rust-ipfs".into(),
+                options.keypair.public(),
+            ),
+            kademlia.protocol_name().to_vec(),
+            kademlia.is_server(),
         );
+        // all nodes answer the dial back requests but only the auto mode asks for them
+        let autonat = AutoNat::new(options.dht_mode == DhtMode::Auto);
         let pubsub = Pubsub::new(options.peer_id);
         let mut swarm = SwarmApi::default();
 
@@ -460,6 +491,7 @@ impl<Types: IpfsTypes> Behaviour<Types> {
             bitswap,
             ping,
             identify,
+            autonat,
             pubsub,
             swarm,
         }
@@ -584,6 +616,9 @@ impl<Types: IpfsTypes> Behaviour<Types> {
             publisher: None,
             expires: None,
         };
+        if let Err(e) = self.kademlia.validate(&record) {
+            return Err(anyhow!("kad: invalid record: {}", e));
+        }
         match self.kademlia.put_record(record, quorum) {
             Ok(id) => Ok(self.kad_subscriptions.create_subscription(id.into(), None)),
             Err(e) => {
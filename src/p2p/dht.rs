@@ -0,0 +1,503 @@
+//! Kademlia in either the client or the server mode. In the client mode the kad protocol is not
+//! advertised and the inbound kad streams are denied, so the node can query the DHT without being
+//! added to the routing tables of the other peers.
+//!
+//! The records put by other peers and the records found in the queries are checked with the
+//! [`Validators`]; the invalid incoming put requests are answered by resetting the stream.
+use super::validator::{RecordError, Validators};
+use libp2p::core::{
+    connection::{ConnectedPoint, ConnectionId, ListenerId},
+    Multiaddr, PeerId,
+};
+use libp2p::kad::handler::{
+    KademliaHandler, KademliaHandlerConfig, KademliaHandlerEvent, KademliaHandlerIn,
+};
+use libp2p::kad::protocol::KademliaProtocolConfig;
+use libp2p::kad::record::store::{MemoryStore, RecordStore};
+use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent, QueryId, Record};
+use libp2p::swarm::{
+    NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters, ProtocolsHandler,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// How long an idle connection is kept open for the kad protocol, same as the libp2p default.
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The role of the node in the DHT, see [`crate::IpfsOptions::dht_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhtMode {
+    /// Only query the DHT, without answering the queries of other peers. Suitable for short lived
+    /// nodes and the nodes behind NAT.
+    Client,
+    /// Query the DHT and answer the queries of other peers.
+    Server,
+    /// Start as a client and switch to the server mode once the node has been found to be
+    /// publicly reachable by the other peers dialing it back.
+    Auto,
+}
+
+impl Default for DhtMode {
+    fn default() -> Self {
+        DhtMode::Server
+    }
+}
+
+impl fmt::Display for DhtMode {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DhtMode::Client => "client",
+            DhtMode::Server => "server",
+            DhtMode::Auto => "auto",
+        };
+        fmt.write_str(s)
+    }
+}
+
+impl FromStr for DhtMode {
+    type Err = InvalidDhtMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(DhtMode::Client),
+            "server" => Ok(DhtMode::Server),
+            "auto" => Ok(DhtMode::Auto),
+            _ => Err(InvalidDhtMode(s.to_owned())),
+        }
+    }
+}
+
+/// The string was not one of `client`, `server` or `auto`.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid dht mode {0:?}, expected one of client, server or auto")]
+pub struct InvalidDhtMode(String);
+
+/// Wrapper for `Kademlia` which creates the connection handlers according to the current mode.
+/// Switching the mode only affects the connections established after the switch.
+pub struct Dht {
+    kademlia: Kademlia<MemoryStore>,
+    mode: DhtMode,
+    server: bool,
+    protocol_config: KademliaProtocolConfig,
+    validators: Validators,
+    /// Resets of the streams of the invalid incoming put requests.
+    resets: VecDeque<(PeerId, ConnectionId, KademliaHandlerIn<QueryId>)>,
+}
+
+impl Dht {
+    /// Creates the Kademlia behaviour with the given config; the protocol name needs to be given
+    /// separately as it cannot be read back from the [`KademliaConfig`].
+    pub fn new(
+        peer_id: PeerId,
+        mut config: KademliaConfig,
+        protocol_name: Option<String>,
+        mode: DhtMode,
+    ) -> Self {
+        let mut protocol_config = KademliaProtocolConfig::default();
+        if let Some(protocol) = protocol_name {
+            protocol_config.set_protocol_name(protocol.clone().into_bytes());
+            config.set_protocol_name(protocol.into_bytes());
+        }
+        config.set_connection_idle_timeout(CONNECTION_IDLE_TIMEOUT);
+
+        let store = MemoryStore::new(peer_id.clone());
+        let kademlia = Kademlia::with_config(peer_id, store, config);
+
+        Dht {
+            kademlia,
+            mode,
+            server: mode == DhtMode::Server,
+            protocol_config,
+            validators: Validators::default(),
+            resets: VecDeque::new(),
+        }
+    }
+
+    /// Validates the record and checks that it is not worse than the one stored locally for the
+    /// same key, if any.
+    pub fn validate(&mut self, record: &Record) -> Result<(), RecordError> {
+        self.validators
+            .validate(record.key.as_ref(), &record.value)?;
+
+        if let Some(existing) = self.kademlia.store_mut().get(&record.key) {
+            if existing.value != record.value
+                && self
+                    .validators
+                    .validate(existing.key.as_ref(), &existing.value)
+                    .is_ok()
+            {
+                let values = [&record.value[..], &existing.value[..]];
+                if self.validators.select(record.key.as_ref(), &values) != 0 {
+                    return Err(RecordError::Outdated);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the best of the records, which all have the same key, to the front.
+    pub fn best_first(&self, records: &mut [Record]) {
+        if let Some(first) = records.first() {
+            let values = records.iter().map(|rec| &rec.value[..]).collect::<Vec<_>>();
+            let best = self.validators.select(first.key.as_ref(), &values);
+            records.swap(0, best);
+        }
+    }
+
+    /// The configured mode.
+    pub fn mode(&self) -> DhtMode {
+        self.mode
+    }
+
+    /// The name of the Kademlia protocol.
+    pub fn protocol_name(&self) -> &[u8] {
+        self.protocol_config.protocol_name()
+    }
+
+    /// Returns true if the inbound kad streams are accepted.
+    pub fn is_server(&self) -> bool {
+        self.server
+    }
+
+    /// Switches between the client and the server mode when the mode is [`DhtMode::Auto`].
+    /// Returns true if the mode was changed.
+    pub fn set_server(&mut self, server: bool) -> bool {
+        if self.mode != DhtMode::Auto || self.server == server {
+            return false;
+        }
+        self.server = server;
+        true
+    }
+}
+
+impl Deref for Dht {
+    type Target = Kademlia<MemoryStore>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.kademlia
+    }
+}
+
+impl DerefMut for Dht {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.kademlia
+    }
+}
+
+impl NetworkBehaviour for Dht {
+    type ProtocolsHandler = KademliaHandler<QueryId>;
+    type OutEvent = KademliaEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        KademliaHandler::new(KademliaHandlerConfig {
+            protocol_config: self.protocol_config.clone(),
+            allow_listening: self.server,
+            idle_timeout: CONNECTION_IDLE_TIMEOUT,
+        })
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.kademlia.addresses_of_peer(peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.kademlia.inject_connected(peer_id)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.kademlia.inject_disconnected(peer_id)
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        connected_point: &ConnectedPoint,
+    ) {
+        self.kademlia
+            .inject_connection_established(peer_id, connection_id, connected_point)
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        connected_point: &ConnectedPoint,
+    ) {
+        self.kademlia
+            .inject_connection_closed(peer_id, connection_id, connected_point)
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.kademlia
+            .inject_address_change(peer_id, connection_id, old, new)
+    }
+
+    fn inject_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+    ) {
+        let event = match event {
+            KademliaHandlerEvent::PutRecord { record, request_id } => {
+                if let Err(e) = self.validate(&record) {
+                    let key = multibase::encode(multibase::Base::Base32Lower, &record.key);
+                    debug!("kad: rejected record {} from {}: {}", key, peer_id, e);
+                    self.resets.push_back((
+                        peer_id,
+                        connection,
+                        KademliaHandlerIn::Reset(request_id),
+                    ));
+                    return;
+                }
+                KademliaHandlerEvent::PutRecord { record, request_id }
+            }
+            KademliaHandlerEvent::GetRecordRes {
+                record,
+                closer_peers,
+                user_data,
+            } => {
+                let record = record.filter(|record| {
+                    let res = self.validators.validate(record.key.as_ref(), &record.value);
+                    if let Err(ref e) = res {
+                        let key = multibase::encode(multibase::Base::Base32Lower, &record.key);
+                        debug!(
+                            "kad: ignored invalid record {} from {}: {}",
+                            key, peer_id, e
+                        );
+                    }
+                    res.is_ok()
+                });
+                KademliaHandlerEvent::GetRecordRes {
+                    record,
+                    closer_peers,
+                    user_data,
+                }
+            }
+            other => other,
+        };
+
+        self.kademlia.inject_event(peer_id, connection, event)
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn std::error::Error,
+    ) {
+        self.kademlia
+            .inject_addr_reach_failure(peer_id, addr, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.kademlia.inject_dial_failure(peer_id)
+    }
+
+    fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
+        self.kademlia.inject_new_listen_addr(addr)
+    }
+
+    fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
+        self.kademlia.inject_expired_listen_addr(addr)
+    }
+
+    fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
+        self.kademlia.inject_new_external_addr(addr)
+    }
+
+    fn inject_listener_error(&mut self, id: ListenerId, err: &(dyn std::error::Error + 'static)) {
+        self.kademlia.inject_listener_error(id, err)
+    }
+
+    fn poll(
+        &mut self,
+        ctx: &mut Context,
+        poll: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            KademliaEvent,
+        >,
+    > {
+        if let Some((peer_id, connection, event)) = self.resets.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event,
+            });
+        }
+
+        self.kademlia.poll(ctx, poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dht, DhtMode};
+    use crate::p2p::autonat::{AutoNat, AutoNatEvent, Reachability};
+    use crate::p2p::transport::build_transport;
+    use libp2p::identity::Keypair;
+    use libp2p::kad::{KademliaConfig, KademliaEvent, QueryResult};
+    use libp2p::swarm::{
+        NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters, Swarm, SwarmEvent,
+    };
+    use libp2p::{Multiaddr, PeerId};
+    use std::collections::VecDeque;
+    use std::task::{Context, Poll};
+
+    /// The DHT switching the mode on the reachability changes like the node behaviour does.
+    #[derive(libp2p::NetworkBehaviour)]
+    #[behaviour(out_event = "Event", poll_method = "poll_events")]
+    struct TestBehaviour {
+        dht: Dht,
+        autonat: AutoNat,
+        #[behaviour(ignore)]
+        events: VecDeque<Event>,
+    }
+
+    enum Event {
+        Kad(KademliaEvent),
+        Reachability(Reachability),
+    }
+
+    impl NetworkBehaviourEventProcess<KademliaEvent> for TestBehaviour {
+        fn inject_event(&mut self, event: KademliaEvent) {
+            self.events.push_back(Event::Kad(event));
+        }
+    }
+
+    impl NetworkBehaviourEventProcess<AutoNatEvent> for TestBehaviour {
+        fn inject_event(&mut self, event: AutoNatEvent) {
+            let AutoNatEvent::ReachabilityChanged(reachability) = event;
+            self.dht.set_server(reachability == Reachability::Public);
+            self.events.push_back(Event::Reachability(reachability));
+        }
+    }
+
+    impl TestBehaviour {
+        fn poll_events<T>(
+            &mut self,
+            _ctx: &mut Context,
+            _params: &mut impl PollParameters,
+        ) -> Poll<NetworkBehaviourAction<T, Event>> {
+            match self.events.pop_front() {
+                Some(event) => Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn mk_swarm(mode: DhtMode) -> Swarm<TestBehaviour> {
+        let key = Keypair::generate_ed25519();
+        let peer_id = key.public().into_peer_id();
+        let transport = build_transport(key).unwrap();
+        let behaviour = TestBehaviour {
+            dht: Dht::new(peer_id.clone(), KademliaConfig::default(), None, mode),
+            autonat: AutoNat::new(mode == DhtMode::Auto),
+            events: VecDeque::new(),
+        };
+        Swarm::new(transport, behaviour, peer_id)
+    }
+
+    async fn listen(swarm: &mut Swarm<TestBehaviour>) -> Multiaddr {
+        Swarm::listen_on(swarm, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        loop {
+            if let SwarmEvent::NewListenAddr(addr) = swarm.next_event().await {
+                return addr;
+            }
+        }
+    }
+
+    fn spawn(mut swarm: Swarm<TestBehaviour>) {
+        tokio::spawn(async move {
+            loop {
+                swarm.next_event().await;
+            }
+        });
+    }
+
+    /// Queries the closest peers through the given peer only, returning the peers which answered.
+    async fn closest_peers(
+        querier: &mut Swarm<TestBehaviour>,
+        peer_id: &PeerId,
+        addr: Multiaddr,
+    ) -> Vec<PeerId> {
+        querier.dht.add_address(peer_id, addr);
+        querier.dht.get_closest_peers(PeerId::random());
+
+        loop {
+            if let SwarmEvent::Behaviour(Event::Kad(KademliaEvent::QueryResult {
+                result: QueryResult::GetClosestPeers(result),
+                ..
+            })) = querier.next_event().await
+            {
+                return result.expect("the query should not time out").peers;
+            }
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn client_refuses_inbound_kad_streams() {
+        for &(mode, answers) in &[(DhtMode::Client, false), (DhtMode::Server, true)] {
+            let mut target = mk_swarm(mode);
+            let addr = listen(&mut target).await;
+            let peer_id = Swarm::local_peer_id(&target).to_owned();
+            spawn(target);
+
+            let mut querier = mk_swarm(DhtMode::Server);
+            let peers = closest_peers(&mut querier, &peer_id, addr).await;
+            assert_eq!(peers.contains(&peer_id), answers, "{}", mode);
+        }
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn auto_mode_switches_to_server_when_dialed_back() {
+        let mut peer = mk_swarm(DhtMode::Server);
+        let peer_addr = listen(&mut peer).await;
+        spawn(peer);
+
+        let mut node = mk_swarm(DhtMode::Auto);
+        let node_addr = listen(&mut node).await;
+        let node_id = Swarm::local_peer_id(&node).to_owned();
+        assert!(!node.dht.is_server());
+
+        Swarm::dial_addr(&mut node, peer_addr).unwrap();
+
+        loop {
+            if let SwarmEvent::Behaviour(Event::Reachability(reachability)) =
+                node.next_event().await
+            {
+                assert_eq!(reachability, Reachability::Public);
+                break;
+            }
+        }
+
+        assert!(node.dht.is_server());
+        spawn(node);
+
+        // the connections established after the switch accept the kad streams
+        let mut querier = mk_swarm(DhtMode::Server);
+        let peers = closest_peers(&mut querier, &node_id, node_addr).await;
+        assert!(peers.contains(&node_id));
+    }
+
+    #[test]
+    fn dht_mode_roundtrip() {
+        for mode in &[DhtMode::Client, DhtMode::Server, DhtMode::Auto] {
+            assert_eq!(mode.to_string().parse::<DhtMode>().unwrap(), *mode);
+        }
+        "dhtclient".parse::<DhtMode>().unwrap_err();
+    }
+}
@@ -0,0 +1,190 @@
+//! Wrapper for `Identify` which keeps the advertised Kademlia protocol in sync with the current
+//! [`super::DhtMode`]. The swarm computes the supported protocols only once when it is created, so
+//! without the wrapper a node switching to the server mode would never advertise the protocol.
+use libp2p::core::{
+    connection::{ConnectedPoint, ConnectionId},
+    Multiaddr, PeerId,
+};
+use libp2p::identify::{Identify as Inner, IdentifyEvent};
+use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters, ProtocolsHandler};
+use std::task::{Context, Poll};
+
+pub struct Identify {
+    inner: Inner,
+    kad_protocol: Vec<u8>,
+    advertise_kad: bool,
+}
+
+impl Identify {
+    /// Wraps the behaviour, advertising the given Kademlia protocol only if `advertise_kad` is
+    /// true.
+    pub fn new(inner: Inner, kad_protocol: Vec<u8>, advertise_kad: bool) -> Self {
+        Identify {
+            inner,
+            kad_protocol,
+            advertise_kad,
+        }
+    }
+
+    /// Starts or stops advertising the Kademlia protocol to the peers identifying the node.
+    pub fn set_advertise_kad(&mut self, advertise_kad: bool) {
+        self.advertise_kad = advertise_kad;
+    }
+}
+
+/// The parameters of the swarm with the Kademlia protocol added or removed.
+struct Advertised<'a, P> {
+    params: &'a P,
+    kad_protocol: &'a [u8],
+    advertise_kad: bool,
+}
+
+impl<'a, P: PollParameters> PollParameters for Advertised<'a, P> {
+    type SupportedProtocolsIter = std::vec::IntoIter<Vec<u8>>;
+    type ListenedAddressesIter = P::ListenedAddressesIter;
+    type ExternalAddressesIter = P::ExternalAddressesIter;
+
+    fn supported_protocols(&self) -> Self::SupportedProtocolsIter {
+        let mut protocols = self
+            .params
+            .supported_protocols()
+            .filter(|p| p.as_slice() != self.kad_protocol)
+            .collect::<Vec<_>>();
+        if self.advertise_kad {
+            protocols.push(self.kad_protocol.to_vec());
+        }
+        protocols.into_iter()
+    }
+
+    fn listened_addresses(&self) -> Self::ListenedAddressesIter {
+        self.params.listened_addresses()
+    }
+
+    fn external_addresses(&self) -> Self::ExternalAddressesIter {
+        self.params.external_addresses()
+    }
+
+    fn local_peer_id(&self) -> &PeerId {
+        self.params.local_peer_id()
+    }
+}
+
+impl NetworkBehaviour for Identify {
+    type ProtocolsHandler = <Inner as NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = IdentifyEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.inner.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.inner.addresses_of_peer(peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.inner.inject_connected(peer_id)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.inner.inject_disconnected(peer_id)
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.inner
+            .inject_connection_established(peer_id, connection_id, endpoint)
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.inner
+            .inject_connection_closed(peer_id, connection_id, endpoint)
+    }
+
+    fn inject_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
+    ) {
+        self.inner.inject_event(peer_id, connection, event)
+    }
+
+    fn poll(
+        &mut self,
+        ctx: &mut Context,
+        params: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            IdentifyEvent,
+        >,
+    > {
+        let mut params = Advertised {
+            params: &*params,
+            kad_protocol: &self.kad_protocol,
+            advertise_kad: self.advertise_kad,
+        };
+        self.inner.poll(ctx, &mut params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Advertised;
+    use libp2p::core::{Multiaddr, PeerId};
+    use libp2p::swarm::PollParameters;
+
+    struct Params(PeerId);
+
+    impl PollParameters for Params {
+        type SupportedProtocolsIter = std::vec::IntoIter<Vec<u8>>;
+        type ListenedAddressesIter = std::vec::IntoIter<Multiaddr>;
+        type ExternalAddressesIter = std::vec::IntoIter<Multiaddr>;
+
+        fn supported_protocols(&self) -> Self::SupportedProtocolsIter {
+            vec![b"/ipfs/id/1.0.0".to_vec(), b"/ipfs/kad/1.0.0".to_vec()].into_iter()
+        }
+
+        fn listened_addresses(&self) -> Self::ListenedAddressesIter {
+            Vec::new().into_iter()
+        }
+
+        fn external_addresses(&self) -> Self::ExternalAddressesIter {
+            Vec::new().into_iter()
+        }
+
+        fn local_peer_id(&self) -> &PeerId {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn kad_protocol_follows_the_mode() {
+        let params = Params(PeerId::random());
+
+        for &advertise_kad in &[false, true] {
+            let advertised = Advertised {
+                params: &params,
+                kad_protocol: b"/ipfs/kad/1.0.0",
+                advertise_kad,
+            };
+
+            let protocols = advertised.supported_protocols().collect::<Vec<_>>();
+            let expected = if advertise_kad { 2 } else { 1 };
+            assert_eq!(protocols.len(), expected, "{:?}", protocols);
+            assert_eq!(
+                protocols.contains(&b"/ipfs/kad/1.0.0".to_vec()),
+                advertise_kad
+            );
+        }
+    }
+}
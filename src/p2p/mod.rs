@@ -9,12 +9,17 @@ use std::sync::Arc;
 use tracing::Span;
 
 pub(crate) mod addr;
+mod autonat;
 mod behaviour;
+mod dht;
+mod identify;
 pub(crate) mod pubsub;
 mod swarm;
 mod transport;
+mod validator;
 
 pub use addr::{MultiaddrWithPeerId, MultiaddrWithoutPeerId};
+pub use dht::{DhtMode, InvalidDhtMode};
 pub use {behaviour::KadResult, swarm::Connection};
 
 /// Type alias for [`libp2p::Swarm`] running the [`behaviour::Behaviour`] with the given [`IpfsTypes`].
@@ -32,6 +37,8 @@ pub struct SwarmOptions {
     pub mdns: bool,
     /// Custom Kademlia protocol name, see [`IpfsOptions::kad_protocol`].
     pub kad_protocol: Option<String>,
+    /// The role of the node in the DHT, see [`IpfsOptions::dht_mode`].
+    pub dht_mode: DhtMode,
 }
 
 impl From<&IpfsOptions> for SwarmOptions {
@@ -41,6 +48,7 @@ impl From<&IpfsOptions> for SwarmOptions {
         let bootstrap = options.bootstrap.clone();
         let mdns = options.mdns;
         let kad_protocol = options.kad_protocol.clone();
+        let dht_mode = options.dht_mode;
 
         SwarmOptions {
             keypair,
@@ -48,6 +56,7 @@ impl From<&IpfsOptions> for SwarmOptions {
             bootstrap,
             mdns,
             kad_protocol,
+            dht_mode,
         }
     }
 }
@@ -21,6 +21,8 @@ use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters, Pr
 pub struct Pubsub {
     streams: HashMap<Topic, channel::UnboundedSender<Arc<PubsubMessage>>>,
     peers: HashMap<PeerId, Vec<Topic>>,
+    // the senders of the peers subscribing to the topics, see `Pubsub::watch_joins`
+    joins: HashMap<Topic, Vec<channel::UnboundedSender<PeerId>>>,
     floodsub: Floodsub,
     // the subscription streams implement Drop and will send out their topic name through the
     // sender cloned from here if they are dropped before the stream has ended.
@@ -133,6 +135,7 @@ impl Pubsub {
         Pubsub {
             streams: HashMap::new(),
             peers: HashMap::new(),
+            joins: HashMap::new(),
             floodsub: Floodsub::from_config(config),
             unsubscriptions: (tx, rx),
         }
@@ -210,6 +213,17 @@ impl Pubsub {
             .collect()
     }
 
+    /// Returns a receiver of the peers subscribing to the topic from now on. The receiver can be
+    /// dropped to stop watching.
+    pub fn watch_joins(&mut self, topic: impl Into<String>) -> channel::UnboundedReceiver<PeerId> {
+        let (tx, rx) = channel::unbounded();
+        self.joins
+            .entry(Topic::new(topic))
+            .or_insert_with(Vec::new)
+            .push(tx);
+        rx
+    }
+
     /// Returns the list of currently subscribed topics. This can contain topics for which stream
     /// has been dropped but no messages have yet been received on the topics after the drop.
     pub fn subscribed_topics(&self) -> Vec<String> {
@@ -379,14 +393,26 @@ impl NetworkBehaviour for Pubsub {
                 }) => {
                     let topics = self.peers.entry(peer_id.clone()).or_insert_with(Vec::new);
                     let appeared = topics.is_empty();
-                    if topics.iter().find(|&t| t == &topic).is_none() {
-                        topics.push(topic);
+                    let joined = topics.iter().find(|&t| t == &topic).is_none();
+                    if joined {
+                        topics.push(topic.clone());
                     }
 
                     if appeared {
                         debug!("peer appeared as pubsub subscriber: {}", peer_id);
                     }
 
+                    if joined {
+                        if let Entry::Occupied(mut oe) = self.joins.entry(topic) {
+                            // the closed receivers are forgotten
+                            oe.get_mut()
+                                .retain(|tx| tx.unbounded_send(peer_id.clone()).is_ok());
+                            if oe.get().is_empty() {
+                                oe.remove();
+                            }
+                        }
+                    }
+
                     continue;
                 }
                 NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Unsubscribed {
@@ -0,0 +1,216 @@
+//! Validation of the DHT records by the namespace of the key, like in go-ipfs. The key of a
+//! record is `/<namespace>/<rest>` where the rest is usually the bytes of a peer id.
+//!
+//! Records with keys outside of the known namespaces are rejected, like in go-ipfs.
+use crate::ipns::{entry, IpnsError};
+use libp2p::core::{identity::PublicKey, PeerId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Failure to validate a record.
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    #[error("invalid key for the namespace {0:?}")]
+    InvalidKey(&'static str),
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    #[error("the public key does not match the peer id in the key")]
+    PublicKeyMismatch,
+    #[error("invalid ipns record: {0}")]
+    Ipns(#[from] IpnsError),
+    #[error("a better record already exists for the key")]
+    Outdated,
+    #[error("unknown namespace")]
+    UnknownNamespace,
+}
+
+/// Validator for the records of a single namespace.
+pub trait Validator: Send + Sync {
+    /// Checks that the value is valid for the key, which includes the namespace.
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), RecordError>;
+
+    /// Returns the index of the best of the already validated values. Ties go to the earlier
+    /// values.
+    fn select(&self, _key: &[u8], _values: &[&[u8]]) -> usize {
+        0
+    }
+}
+
+/// The validators keyed by the namespace.
+pub struct Validators {
+    namespaces: HashMap<&'static str, Box<dyn Validator>>,
+}
+
+impl Default for Validators {
+    /// Creates the validators for the `/pk` and `/ipns` namespaces.
+    fn default() -> Self {
+        let mut namespaces = HashMap::new();
+        namespaces.insert("pk", Box::new(PublicKeyValidator) as Box<dyn Validator>);
+        namespaces.insert("ipns", Box::new(IpnsValidator));
+        Validators { namespaces }
+    }
+}
+
+impl fmt::Debug for Validators {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_set().entries(self.namespaces.keys()).finish()
+    }
+}
+
+impl Validators {
+    fn validator(&self, key: &[u8]) -> Option<&dyn Validator> {
+        let (namespace, _) = split_key(key)?;
+        let namespace = std::str::from_utf8(namespace).ok()?;
+        self.namespaces.get(namespace).map(|v| &**v)
+    }
+
+    /// Validates the value with the validator of the namespace of the key.
+    pub fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), RecordError> {
+        match self.validator(key) {
+            Some(validator) => validator.validate(key, value),
+            None => Err(RecordError::UnknownNamespace),
+        }
+    }
+
+    /// Returns the index of the best of the already validated values.
+    pub fn select(&self, key: &[u8], values: &[&[u8]]) -> usize {
+        match self.validator(key) {
+            Some(validator) => validator.select(key, values),
+            None => 0,
+        }
+    }
+}
+
+/// Splits `/namespace/rest` into the namespace and the rest.
+fn split_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    if key.first() != Some(&b'/') {
+        return None;
+    }
+    let key = &key[1..];
+    let pos = key.iter().position(|&b| b == b'/')?;
+    Some((&key[..pos], &key[pos + 1..]))
+}
+
+fn peer_id_of_key(namespace: &'static str, key: &[u8]) -> Result<PeerId, RecordError> {
+    split_key(key)
+        .and_then(|(_, rest)| PeerId::from_bytes(rest.to_vec()).ok())
+        .ok_or(RecordError::InvalidKey(namespace))
+}
+
+/// Records of the `/pk` namespace are the protobuf encoded public keys of the peer ids in the
+/// keys.
+struct PublicKeyValidator;
+
+impl Validator for PublicKeyValidator {
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), RecordError> {
+        let peer_id = peer_id_of_key("pk", key)?;
+        let public =
+            PublicKey::from_protobuf_encoding(value).map_err(|_| RecordError::InvalidPublicKey)?;
+
+        if public.into_peer_id() != peer_id {
+            return Err(RecordError::PublicKeyMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Records of the `/ipns` namespace are the IPNS records signed by the peer ids in the keys. The
+/// record with the highest sequence number and then the latest end of life is the best one.
+struct IpnsValidator;
+
+impl Validator for IpnsValidator {
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), RecordError> {
+        let peer_id = peer_id_of_key("ipns", key)?;
+        entry::verify(&peer_id, value)?;
+        Ok(())
+    }
+
+    fn select(&self, _key: &[u8], values: &[&[u8]]) -> usize {
+        use prost::Message;
+
+        let mut best = None;
+
+        for (i, value) in values.iter().enumerate() {
+            let entry = match entry::IpnsEntry::decode(*value) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let order = (entry.sequence, entry::eol(&entry).ok());
+
+            match best {
+                Some((_, ref best_order)) if *best_order >= order => {}
+                _ => best = Some((i, order)),
+            }
+        }
+
+        best.map(|(i, _)| i).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecordError, Validators};
+    use crate::ipns::entry;
+    use libp2p::core::identity::Keypair;
+    use std::time::{Duration, SystemTime};
+
+    fn key(namespace: &str, keypair: &Keypair) -> Vec<u8> {
+        let mut key = format!("/{}/", namespace).into_bytes();
+        key.extend_from_slice(keypair.public().into_peer_id().as_bytes());
+        key
+    }
+
+    #[test]
+    fn public_key_records() {
+        let validators = Validators::default();
+        let keypair = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let key = key("pk", &keypair);
+
+        validators
+            .validate(&key, &keypair.public().into_protobuf_encoding())
+            .unwrap();
+
+        assert!(matches!(
+            validators.validate(&key, &other.public().into_protobuf_encoding()),
+            Err(RecordError::PublicKeyMismatch)
+        ));
+        assert!(matches!(
+            validators.validate(b"/pk/foobar", &keypair.public().into_protobuf_encoding()),
+            Err(RecordError::InvalidKey("pk"))
+        ));
+    }
+
+    #[test]
+    fn ipns_records() {
+        let validators = Validators::default();
+        let keypair = Keypair::generate_ed25519();
+        let key = key("ipns", &keypair);
+        let eol = SystemTime::now() + Duration::from_secs(60);
+
+        let older = entry::create(&keypair, b"/ipfs/foo".to_vec(), 1, eol, 0).unwrap();
+        let newer = entry::create(&keypair, b"/ipfs/bar".to_vec(), 2, eol, 0).unwrap();
+
+        validators.validate(&key, &older).unwrap();
+        validators.validate(&key, &newer).unwrap();
+        validators.validate(&key, b"foobar").unwrap_err();
+
+        assert_eq!(validators.select(&key, &[&older, &newer]), 1);
+        assert_eq!(validators.select(&key, &[&newer, &older]), 0);
+        // ties go to the first
+        assert_eq!(validators.select(&key, &[&newer, &newer]), 0);
+    }
+
+    #[test]
+    fn unknown_namespaces_are_rejected() {
+        let validators = Validators::default();
+        assert!(matches!(
+            validators.validate(b"key", b"value"),
+            Err(RecordError::UnknownNamespace)
+        ));
+        assert!(matches!(
+            validators.validate(b"/foo/bar", b"value"),
+            Err(RecordError::UnknownNamespace)
+        ));
+    }
+}
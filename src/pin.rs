@@ -0,0 +1,387 @@
+//! Verification and repair of the recursive pins, see [`Ipfs::verify_pins`], and the remote
+//! pinning services in [`remote`].
+use crate::error::Error;
+use crate::ipld::decode_ipld;
+use crate::refs::{Edge, IpldRefs, IpldRefsError};
+use crate::repo::{hash_matches, PinKind, PinMode};
+use crate::{Ipfs, IpfsTypes};
+use cid::Cid;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::fmt;
+use std::time::Duration;
+
+mod remote;
+
+pub use remote::{RemotePinQuery, RemotePinService, RemotePinState, RemotePinStatus, RemotePins};
+
+/// How long a missing block is searched for from the network when repairing the pins.
+const REFETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A block under a recursive pin which is missing or corrupt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadNode {
+    pub cid: Cid,
+    pub error: String,
+}
+
+/// What was done to a recursive pin whose insertion had been interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptedPin {
+    /// The pin was left as is, as the pins are only changed when repairing.
+    Found,
+    /// All of the blocks were available, and the pin was completed.
+    Completed,
+    /// Some of the blocks were missing or corrupt, and the pin was removed.
+    Removed,
+}
+
+/// The status of a single recursive pin checked by [`Ipfs::verify_pins`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinVerifyStatus {
+    /// The root of the recursive pin.
+    pub cid: Cid,
+    /// The missing or corrupt blocks of the pinned DAG, including the root.
+    pub bad_nodes: Vec<BadNode>,
+    /// The blocks which were missing but were fetched from the network while repairing.
+    pub refetched: Vec<Cid>,
+    /// Set if the insertion of the pin had been interrupted.
+    pub interrupted: Option<InterruptedPin>,
+}
+
+impl PinVerifyStatus {
+    pub fn is_ok(&self) -> bool {
+        self.bad_nodes.is_empty()
+    }
+}
+
+impl fmt::Display for PinVerifyStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_ok() {
+            write!(fmt, "{} ok", self.cid)?;
+        } else {
+            write!(
+                fmt,
+                "{} broken, {} bad nodes",
+                self.cid,
+                self.bad_nodes.len()
+            )?;
+        }
+
+        if !self.refetched.is_empty() {
+            write!(fmt, ", {} refetched", self.refetched.len())?;
+        }
+
+        match self.interrupted {
+            Some(InterruptedPin::Found) => write!(fmt, ", interrupted pin"),
+            Some(InterruptedPin::Completed) => write!(fmt, ", interrupted pin completed"),
+            Some(InterruptedPin::Removed) => write!(fmt, ", interrupted pin removed"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The result of walking the DAG of a pin with the locally available blocks.
+#[derive(Default)]
+struct Walk {
+    /// The unique references of the root, excluding the missing and corrupt blocks.
+    refs: Vec<Cid>,
+    bad_nodes: Vec<BadNode>,
+    /// The subset of `bad_nodes` which were not found at all.
+    missing: Vec<Cid>,
+}
+
+impl Walk {
+    fn missing(&mut self, cid: Cid) {
+        self.bad_nodes.push(BadNode {
+            cid: cid.clone(),
+            error: "block not found locally".into(),
+        });
+        self.missing.push(cid);
+    }
+
+    fn corrupt(&mut self, cid: Cid, error: String) {
+        self.bad_nodes.push(BadNode { cid, error });
+    }
+}
+
+async fn walk<Types: IpfsTypes>(ipfs: &Ipfs<Types>, root: &Cid) -> Result<Walk, Error> {
+    let mut walk = Walk::default();
+
+    let block = match ipfs.repo.get_block_now(root).await? {
+        Some(block) if hash_matches(&block) => block,
+        Some(_) => {
+            walk.corrupt(root.to_owned(), "hash mismatch".into());
+            return Ok(walk);
+        }
+        None => {
+            walk.missing(root.to_owned());
+            return Ok(walk);
+        }
+    };
+
+    let ipld = match decode_ipld(root, block.data()) {
+        Ok(ipld) => ipld,
+        Err(e) => {
+            walk.corrupt(root.to_owned(), e.to_string());
+            return Ok(walk);
+        }
+    };
+
+    let refs = IpldRefs::default()
+        .with_only_unique()
+        .with_verified_blocks()
+        .refs_of_resolved(ipfs, vec![(root.to_owned(), ipld)]);
+
+    futures::pin_mut!(refs);
+
+    while let Some(edge) = refs.next().await {
+        match edge {
+            Ok(Edge { destination, .. }) => walk.refs.push(destination),
+            Err(IpldRefsError::BlockNotFound(cid)) => walk.missing(cid),
+            Err(IpldRefsError::Corrupt(cid, e)) => walk.corrupt(cid, e),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(walk)
+}
+
+/// Checks the DAG of a single recursive pin, see [`Ipfs::verify_pins`].
+pub(crate) async fn verify_pin<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    cid: Cid,
+    repair: bool,
+) -> Result<PinVerifyStatus, Error> {
+    let mut refetched = Vec::new();
+
+    let walk = loop {
+        let walk = walk(ipfs, &cid).await?;
+
+        if !repair || walk.missing.is_empty() {
+            break walk;
+        }
+
+        let mut found_any = false;
+
+        for missing in &walk.missing {
+            match tokio::time::timeout(REFETCH_TIMEOUT, ipfs.get_block(missing)).await {
+                Ok(Ok(_)) => {
+                    refetched.push(missing.to_owned());
+                    found_any = true;
+                }
+                Ok(Err(e)) => warn!("failed to refetch {} pinned by {}: {}", missing, cid, e),
+                Err(_) => warn!("timed out refetching {} pinned by {}", missing, cid),
+            }
+        }
+
+        // the refetched blocks can link to further missing blocks
+        if !found_any {
+            break walk;
+        }
+    };
+
+    let kind = ipfs
+        .repo
+        .query_pins(vec![cid.clone()], Some(PinMode::Recursive))
+        .await?
+        .pop()
+        .map(|(_, kind)| kind);
+
+    let interrupted = if kind != Some(PinKind::RecursiveIntention) {
+        None
+    } else if !repair {
+        Some(InterruptedPin::Found)
+    } else {
+        let refs = stream::iter(walk.refs.into_iter().map(Ok)).boxed();
+
+        if walk.bad_nodes.is_empty() {
+            ipfs.repo.insert_recursive_pin(&cid, refs).await?;
+            Some(InterruptedPin::Completed)
+        } else {
+            ipfs.repo.remove_recursive_pin(&cid, refs).await?;
+            Some(InterruptedPin::Removed)
+        }
+    };
+
+    Ok(PinVerifyStatus {
+        cid,
+        bad_nodes: walk.bad_nodes,
+        refetched,
+        interrupted,
+    })
+}
+
+/// Lists the roots of the recursive pins to be verified.
+pub(crate) async fn recursive_roots<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+) -> Result<Vec<Cid>, Error> {
+    ipfs.repo
+        .list_pins(Some(PinMode::Recursive))
+        .await
+        .map_ok(|(cid, _)| cid)
+        .try_collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{fs::FsDataStore, mem::MemBlockStore, DataStore};
+    use crate::test_support::raw;
+    use crate::{make_ipld, Block, DynTypes, IpfsOptions, Ipld, Node, UninitializedIpfs};
+
+    async fn verify<T: IpfsTypes>(ipfs: &Ipfs<T>, repair: bool) -> Vec<PinVerifyStatus> {
+        let mut statuses: Vec<_> = ipfs.verify_pins(repair).try_collect().await.unwrap();
+        statuses.sort_by_key(|status| status.cid.to_string());
+        statuses
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn missing_and_corrupt_blocks_are_found_and_refetched() {
+        let ipfs = Node::new("verifier").await;
+        let other = Node::new("source").await;
+
+        let present = raw(b"present");
+        let missing = raw(b"missing");
+        let corrupt = Block::new(b"rotten".to_vec().into(), raw(b"corrupt").cid().to_owned());
+
+        ipfs.put_block(present.clone()).await.unwrap();
+        ipfs.put_block(corrupt.clone()).await.unwrap();
+        other.put_block(missing.clone()).await.unwrap();
+
+        let root = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(present.cid().to_owned()),
+                Ipld::Link(missing.cid().to_owned()),
+                Ipld::Link(corrupt.cid().to_owned()),
+            ]))
+            .await
+            .unwrap();
+
+        // pinned through the repo as the missing block would be fetched by Ipfs::insert_pin
+        let refs = vec![present.cid(), missing.cid(), corrupt.cid()]
+            .into_iter()
+            .map(|cid| Ok(cid.to_owned()))
+            .collect::<Vec<_>>();
+        ipfs.repo
+            .insert_recursive_pin(&root, stream::iter(refs).boxed())
+            .await
+            .unwrap();
+
+        let statuses = verify(&ipfs, false).await;
+        assert_eq!(statuses.len(), 1);
+        assert!(!statuses[0].is_ok());
+        assert_eq!(
+            statuses[0]
+                .bad_nodes
+                .iter()
+                .map(|node| &node.cid)
+                .collect::<Vec<_>>(),
+            vec![missing.cid(), corrupt.cid()]
+        );
+        assert!(statuses[0].refetched.is_empty());
+
+        ipfs.connect(other.addrs[0].clone()).await.unwrap();
+
+        let statuses = verify(&ipfs, true).await;
+        assert_eq!(statuses[0].refetched, vec![missing.cid().to_owned()]);
+        assert_eq!(statuses[0].bad_nodes.len(), 1);
+        assert_eq!(&statuses[0].bad_nodes[0].cid, corrupt.cid());
+
+        assert_eq!(
+            ipfs.repo.get_block_now(missing.cid()).await.unwrap(),
+            Some(missing)
+        );
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn interrupted_pins_are_completed_or_removed() {
+        let ipfs = Node::new("test_node").await;
+        interrupted_pins_scenario(&ipfs).await;
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn interrupted_fs_pins_are_completed_or_removed() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.ipfs_path = tmp.path().to_owned();
+
+        let (ipfs, fut): (Ipfs<DynTypes>, _) = UninitializedIpfs::with_stores(
+            opts,
+            Box::new(MemBlockStore::default()),
+            Box::new(FsDataStore::new(tmp.path().join("datastore"))),
+        )
+        .start()
+        .await
+        .unwrap();
+        tokio::task::spawn(fut);
+
+        interrupted_pins_scenario(&ipfs).await;
+
+        ipfs.exit_daemon().await;
+    }
+
+    async fn interrupted_pins_scenario<T: IpfsTypes>(ipfs: &Ipfs<T>) {
+        let leaf = ipfs.put_dag(make_ipld!("leaf")).await.unwrap();
+        // corrupt instead of missing so that repairing does not wait for it from the network
+        let corrupt = Block::new(b"rotten".to_vec().into(), raw(b"corrupt").cid().to_owned());
+        ipfs.put_block(corrupt.clone()).await.unwrap();
+
+        let complete = ipfs
+            .put_dag(Ipld::List(vec![Ipld::Link(leaf.clone())]))
+            .await
+            .unwrap();
+        let broken = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(leaf.clone()),
+                Ipld::Link(corrupt.cid().to_owned()),
+            ]))
+            .await
+            .unwrap();
+
+        // interrupted after recording the first reference
+        for root in &[&complete, &broken] {
+            let refs = vec![
+                Ok(leaf.clone()),
+                Err(IpldRefsError::BlockNotFound(leaf.clone())),
+            ];
+            ipfs.repo
+                .insert_recursive_pin(root, stream::iter(refs).boxed())
+                .await
+                .unwrap_err();
+        }
+
+        let outcome = |statuses: &[PinVerifyStatus], cid: &Cid| {
+            statuses
+                .iter()
+                .find(|status| &status.cid == cid)
+                .unwrap()
+                .interrupted
+        };
+
+        // only reported without repairing
+        let statuses = verify(ipfs, false).await;
+        assert_eq!(outcome(&statuses, &complete), Some(InterruptedPin::Found));
+        assert_eq!(outcome(&statuses, &broken), Some(InterruptedPin::Found));
+
+        let pins = ipfs.query_pins(vec![broken.clone()], None).await.unwrap();
+        assert_eq!(pins[0].1, PinKind::RecursiveIntention);
+
+        let statuses = verify(ipfs, true).await;
+        assert_eq!(
+            outcome(&statuses, &complete),
+            Some(InterruptedPin::Completed)
+        );
+        assert_eq!(outcome(&statuses, &broken), Some(InterruptedPin::Removed));
+
+        let pins = ipfs.query_pins(vec![complete.clone()], None).await.unwrap();
+        assert_eq!(pins[0].1, PinKind::Recursive(1));
+        assert!(!ipfs.is_pinned(&broken).await.unwrap());
+
+        // the leaf stays pinned only through the completed pin
+        ipfs.remove_pin(&complete, true).await.unwrap();
+        assert!(!ipfs.is_pinned(&leaf).await.unwrap());
+
+        assert!(verify(ipfs, true).await.is_empty());
+    }
+}
@@ -0,0 +1,650 @@
+//! Client of the [IPFS Pinning Service API], see [`Ipfs::remote_pins`].
+//!
+//! The services are registered with a name, an API endpoint and an access token, and are stored
+//! in the data store. The pins added to a service are fetched by the delegate peers of the
+//! service over bitswap; the delegates are connected to whenever they are seen in a response.
+//!
+//! [IPFS Pinning Service API]: https://ipfs.github.io/pinning-services-api-spec/
+use crate::error::Error;
+use crate::p2p::MultiaddrWithPeerId;
+use crate::{Ipfs, IpfsTypes};
+use anyhow::anyhow;
+use cid::Cid;
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use libp2p::Multiaddr;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// The largest page of pins the services are required to return.
+const PAGE_LIMIT: usize = 1000;
+
+/// A remote pinning service registered with [`Ipfs::add_remote_pin_service`].
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemotePinService {
+    pub name: String,
+    /// The API endpoint, for example `https://pinning.example.com/api/v1`.
+    pub endpoint: String,
+    /// The access token sent with every request.
+    pub key: String,
+}
+
+impl RemotePinService {
+    /// Fails if the name is empty or the endpoint is not an http or https url.
+    pub fn new<N, E, K>(name: N, endpoint: E, key: K) -> Result<Self, Error>
+    where
+        N: Into<String>,
+        E: Into<String>,
+        K: Into<String>,
+    {
+        let name = name.into();
+        let endpoint = endpoint.into();
+
+        if name.is_empty() {
+            return Err(anyhow!("the name of the service cannot be empty"));
+        }
+
+        let uri = endpoint
+            .parse::<Uri>()
+            .map_err(|e| anyhow!("invalid endpoint {:?}: {}", endpoint, e))?;
+
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => return Err(anyhow!("endpoint {:?} is not an http(s) url", endpoint)),
+        }
+
+        Ok(RemotePinService {
+            name,
+            // the paths of the api are appended to this
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            key: key.into(),
+        })
+    }
+}
+
+impl fmt::Debug for RemotePinService {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the key is left out on purpose
+        fmt.debug_struct("RemotePinService")
+            .field("name", &self.name)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// The status of a pin on the remote pinning service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemotePinState {
+    /// The pin is waiting to be processed by the service.
+    Queued,
+    /// The service is fetching the content.
+    Pinning,
+    /// The content has been pinned by the service.
+    Pinned,
+    /// The service could not pin the content.
+    Failed,
+}
+
+impl RemotePinState {
+    fn as_str(&self) -> &'static str {
+        use RemotePinState::*;
+        match self {
+            Queued => "queued",
+            Pinning => "pinning",
+            Pinned => "pinned",
+            Failed => "failed",
+        }
+    }
+}
+
+impl fmt::Display for RemotePinState {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RemotePinState {
+    // the not understood input
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use RemotePinState::*;
+        Ok(match s {
+            "queued" => Queued,
+            "pinning" => Pinning,
+            "pinned" => Pinned,
+            "failed" => Failed,
+            other => return Err(other.to_owned()),
+        })
+    }
+}
+
+/// A pin request on the remote pinning service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemotePinStatus {
+    /// The identifier of the pin request given by the service.
+    pub request_id: String,
+    pub status: RemotePinState,
+    /// The time the pin request was received by the service, as an RFC 3339 timestamp.
+    pub created: String,
+    pub cid: Cid,
+    pub name: Option<String>,
+    /// The peers of the service which will fetch the content.
+    pub delegates: Vec<Multiaddr>,
+}
+
+/// Filters the pins listed with [`RemotePins::list`]. The default query matches all pins with
+/// the status `pinned`, as the service defaults to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemotePinQuery {
+    /// Only the pins of these Cids are listed, if any are given.
+    pub cids: Vec<Cid>,
+    /// Only the pins with exactly this name are listed.
+    pub name: Option<String>,
+    /// Only the pins in these states are listed, if any are given.
+    pub status: Vec<RemotePinState>,
+}
+
+#[derive(Debug, Serialize)]
+struct PinRequest<'a> {
+    cid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    origins: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PinStatusResponse {
+    requestid: String,
+    status: RemotePinState,
+    created: String,
+    pin: PinResponse,
+    #[serde(default)]
+    delegates: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PinResponse {
+    cid: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PinResults {
+    count: usize,
+    results: Vec<PinStatusResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Failure {
+    error: FailureReason,
+}
+
+#[derive(Debug, Deserialize)]
+struct FailureReason {
+    reason: String,
+    #[serde(default)]
+    details: Option<String>,
+}
+
+impl TryFrom<PinStatusResponse> for RemotePinStatus {
+    type Error = Error;
+
+    fn try_from(resp: PinStatusResponse) -> Result<Self, Self::Error> {
+        let cid = Cid::try_from(resp.pin.cid.as_str())
+            .map_err(|e| anyhow!("invalid cid {:?} in response: {}", resp.pin.cid, e))?;
+
+        let delegates = resp
+            .delegates
+            .into_iter()
+            .filter_map(|addr| match addr.parse::<Multiaddr>() {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    warn!("ignoring invalid delegate {:?}: {}", addr, e);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(RemotePinStatus {
+            request_id: resp.requestid,
+            status: resp.status,
+            created: resp.created,
+            cid,
+            name: resp.pin.name,
+            delegates,
+        })
+    }
+}
+
+/// The pins on a single remote pinning service, see [`Ipfs::remote_pins`].
+pub struct RemotePins<Types: IpfsTypes> {
+    ipfs: Ipfs<Types>,
+    service: RemotePinService,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    page_limit: usize,
+}
+
+impl<Types: IpfsTypes> RemotePins<Types> {
+    pub(crate) fn new(ipfs: Ipfs<Types>, service: RemotePinService) -> Self {
+        let client = hyper::Client::builder().build(HttpsConnector::with_webpki_roots());
+
+        RemotePins {
+            ipfs,
+            service,
+            client,
+            page_limit: PAGE_LIMIT,
+        }
+    }
+
+    /// The name of the service.
+    pub fn service(&self) -> &str {
+        &self.service.name
+    }
+
+    /// Asks the service to pin the `cid`, returning once the request has been queued. The
+    /// addresses of this node are given to the service as the origins of the content.
+    pub async fn add(&self, cid: &Cid, name: Option<&str>) -> Result<RemotePinStatus, Error> {
+        let (_, origins) = self.ipfs.identity().await?;
+
+        let body = serde_json::to_vec(&PinRequest {
+            cid: cid.to_string(),
+            name,
+            origins: origins.iter().map(|addr| addr.to_string()).collect(),
+        })?;
+
+        let bytes = self.send(Method::POST, "/pins", Some(body)).await?;
+        let status = parse_status(&bytes)?;
+
+        self.connect_delegates(&status);
+        Ok(status)
+    }
+
+    /// Lists all of the pins matching the query.
+    pub async fn list(&self, query: RemotePinQuery) -> Result<Vec<RemotePinStatus>, Error> {
+        let mut statuses = Vec::new();
+        let mut seen = HashSet::new();
+        let mut before = None;
+
+        loop {
+            let mut params = url::form_urlencoded::Serializer::new(String::new());
+
+            if !query.cids.is_empty() {
+                let cids = query
+                    .cids
+                    .iter()
+                    .map(|cid| cid.to_string())
+                    .collect::<Vec<_>>();
+                params.append_pair("cid", &cids.join(","));
+            }
+
+            if let Some(name) = query.name.as_ref() {
+                params.append_pair("name", name);
+            }
+
+            if !query.status.is_empty() {
+                let status = query
+                    .status
+                    .iter()
+                    .map(|status| status.as_str())
+                    .collect::<Vec<_>>();
+                params.append_pair("status", &status.join(","));
+            }
+
+            if let Some(before) = before.as_ref() {
+                params.append_pair("before", before);
+            }
+
+            params.append_pair("limit", &self.page_limit.to_string());
+
+            let path = format!("/pins?{}", params.finish());
+            let bytes = self.send(Method::GET, &path, None).await?;
+            // the count is of the pins before the `before`, so it cannot tell when to stop
+            let PinResults { results, .. } = serde_json::from_slice(&bytes)?;
+
+            // the results are ordered by the creation time, newest first, so the next page is
+            // found by asking for the ones created before the last one.
+            before = results.last().map(|status| status.created.clone());
+            let fetched = results.len();
+            let mut new = 0;
+
+            for status in results {
+                // the services might return the pins created at the same time as the last one
+                // again on the next page
+                if seen.insert(status.requestid.clone()) {
+                    statuses.push(RemotePinStatus::try_from(status)?);
+                    new += 1;
+                }
+            }
+
+            if fetched < self.page_limit || new == 0 {
+                return Ok(statuses);
+            }
+        }
+    }
+
+    /// Returns the current status of the pin request.
+    pub async fn status(&self, request_id: &str) -> Result<RemotePinStatus, Error> {
+        let bytes = self.send(Method::GET, &pin_path(request_id), None).await?;
+        let status = parse_status(&bytes)?;
+
+        self.connect_delegates(&status);
+        Ok(status)
+    }
+
+    /// Removes the pin request, which unpins the content from the service.
+    pub async fn remove(&self, request_id: &str) -> Result<(), Error> {
+        self.send(Method::DELETE, &pin_path(request_id), None)
+            .await?;
+        Ok(())
+    }
+
+    /// Connects the delegates in the background so that they can fetch the content over bitswap.
+    fn connect_delegates(&self, status: &RemotePinStatus) {
+        if self.ipfs.repo.is_offline() {
+            return;
+        }
+
+        for addr in &status.delegates {
+            let addr = match MultiaddrWithPeerId::try_from(addr.to_owned()) {
+                Ok(addr) => addr,
+                Err(e) => {
+                    debug!("not connecting to delegate {}: {}", addr, e);
+                    continue;
+                }
+            };
+
+            let ipfs = self.ipfs.clone();
+            tokio::spawn(async move {
+                if let Err(e) = ipfs.connect(addr.clone()).await {
+                    debug!("failed to connect to delegate {}: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Sends a request to the service, returning the body of a successful response.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<hyper::body::Bytes, Error> {
+        let builder = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.service.endpoint, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.service.key));
+
+        let req = match body {
+            Some(body) => builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))?,
+            None => builder.body(Body::empty())?,
+        };
+
+        let resp = self.client.request(req).await?;
+        let status = resp.status();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+
+        if status.is_success() {
+            return Ok(bytes);
+        }
+
+        Err(match serde_json::from_slice::<Failure>(&bytes) {
+            Ok(Failure { error }) => match error.details {
+                Some(details) => anyhow!("{} ({}): {}", error.reason, status, details),
+                None => anyhow!("{} ({})", error.reason, status),
+            },
+            Err(_) if status == StatusCode::UNAUTHORIZED => {
+                anyhow!("access token was rejected by {}", self.service.name)
+            }
+            Err(_) => anyhow!("{} responded with {}", self.service.name, status),
+        })
+    }
+}
+
+/// The path of a single pin request, as the request ids are chosen by the service.
+fn pin_path(request_id: &str) -> String {
+    format!(
+        "/pins/{}",
+        utf8_percent_encode(request_id, NON_ALPHANUMERIC)
+    )
+}
+
+fn parse_status(bytes: &[u8]) -> Result<RemotePinStatus, Error> {
+    RemotePinStatus::try_from(serde_json::from_slice::<PinStatusResponse>(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::http_server;
+    use crate::Node;
+    use hyper::Response;
+    use multihash::Sha2_256;
+    use std::sync::{Arc, Mutex};
+
+    const TOKEN: &str = "secret";
+
+    /// The pins of the mock service, in the order of creation.
+    type Pins = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    fn reply(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn has_path(pin: &serde_json::Value, path: &str) -> bool {
+        pin_path(pin["requestid"].as_str().unwrap()) == path
+    }
+
+    async fn handle(pins: Pins, req: Request<Body>) -> Response<Body> {
+        let authorized = req
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|value| value == &format!("Bearer {}", TOKEN)[..])
+            .unwrap_or(false);
+
+        if !authorized {
+            let failure = serde_json::json!({ "error": { "reason": "UNAUTHORIZED" } });
+            return reply(StatusCode::UNAUTHORIZED, failure);
+        }
+
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        let query = req.uri().query().unwrap_or_default().to_owned();
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+
+        let mut pins = pins.lock().unwrap();
+
+        match (method, path.as_str()) {
+            (Method::POST, "/pins") => {
+                let pin: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let status = serde_json::json!({
+                    "requestid": format!("req-{}", pins.len()),
+                    "status": "queued",
+                    "created": format!("2020-10-{:02}T00:00:00Z", pins.len() + 1),
+                    "pin": pin,
+                    "delegates": ["/ip4/127.0.0.1/tcp/1"],
+                });
+                pins.push(status.clone());
+                reply(StatusCode::ACCEPTED, status)
+            }
+            (Method::GET, "/pins") => {
+                let params = url::form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect::<std::collections::HashMap<_, _>>();
+                let status = params.get("status").map(String::as_str).unwrap_or("pinned");
+
+                let results = pins
+                    .iter()
+                    .rev()
+                    .filter(|pin| status.split(',').any(|s| pin["status"] == s))
+                    .filter(|pin| {
+                        params
+                            .get("before")
+                            .map(|before| pin["created"].as_str().unwrap() < before.as_str())
+                            .unwrap_or(true)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                let limit = params
+                    .get("limit")
+                    .map(|limit| limit.parse().unwrap())
+                    .unwrap_or(10);
+
+                let count = results.len();
+                let page = results.into_iter().take(limit).collect::<Vec<_>>();
+                reply(
+                    StatusCode::OK,
+                    serde_json::json!({ "count": count, "results": page }),
+                )
+            }
+            (Method::GET, path) => match pins.iter().find(|pin| has_path(pin, path)) {
+                Some(pin) => reply(StatusCode::OK, pin.clone()),
+                None => reply(
+                    StatusCode::NOT_FOUND,
+                    serde_json::json!({ "error": { "reason": "NOT_FOUND" } }),
+                ),
+            },
+            (Method::DELETE, path) => {
+                pins.retain(|pin| !has_path(pin, path));
+                Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            _ => reply(StatusCode::BAD_REQUEST, serde_json::json!({})),
+        }
+    }
+
+    /// Starts the mock service, returning the endpoint.
+    fn mock_service(pins: Pins) -> String {
+        http_server(move |req| handle(Arc::clone(&pins), req))
+    }
+
+    fn cid(data: &[u8]) -> Cid {
+        Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(data))
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn services_are_registered() {
+        let ipfs = Node::new("test_node").await;
+
+        let service = RemotePinService::new("mock", "http://127.0.0.1:1/api/", TOKEN).unwrap();
+        assert_eq!(service.endpoint, "http://127.0.0.1:1/api");
+
+        ipfs.add_remote_pin_service(service.clone()).await.unwrap();
+        ipfs.add_remote_pin_service(service.clone())
+            .await
+            .unwrap_err();
+
+        assert_eq!(ipfs.remote_pin_services().await.unwrap(), vec![service]);
+
+        ipfs.remove_remote_pin_service("mock").await.unwrap();
+        ipfs.remove_remote_pin_service("mock").await.unwrap_err();
+        assert!(ipfs.remote_pins("mock").await.is_err());
+
+        RemotePinService::new("", "http://127.0.0.1:1", TOKEN).unwrap_err();
+        RemotePinService::new("mock", "ftp://127.0.0.1:1", TOKEN).unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn pins_are_added_listed_and_removed() {
+        let ipfs = Node::new("test_node").await;
+        let pins = Pins::default();
+        let endpoint = mock_service(Arc::clone(&pins));
+
+        let service = RemotePinService::new("mock", endpoint, TOKEN).unwrap();
+        ipfs.add_remote_pin_service(service).await.unwrap();
+        let remote = ipfs.remote_pins("mock").await.unwrap();
+
+        let first = remote.add(&cid(b"first"), Some("first")).await.unwrap();
+        assert_eq!(first.status, RemotePinState::Queued);
+        assert_eq!(first.cid, cid(b"first"));
+        assert_eq!(first.name.as_deref(), Some("first"));
+        assert_eq!(first.delegates.len(), 1);
+
+        // the addresses of the node were given as the origins
+        assert_eq!(
+            pins.lock().unwrap()[0]["pin"]["origins"],
+            serde_json::json!(ipfs
+                .addrs
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>())
+        );
+
+        let second = remote.add(&cid(b"second"), None).await.unwrap();
+        assert_eq!(remote.status(&second.request_id).await.unwrap(), second);
+
+        // nothing has been pinned yet
+        assert!(remote
+            .list(RemotePinQuery::default())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let query = RemotePinQuery {
+            status: vec![RemotePinState::Queued, RemotePinState::Pinning],
+            ..Default::default()
+        };
+        let listed = remote.list(query.clone()).await.unwrap();
+        assert_eq!(listed, vec![second.clone(), first.clone()]);
+
+        remote.remove(&first.request_id).await.unwrap();
+        assert_eq!(remote.list(query).await.unwrap(), vec![second]);
+
+        remote.status(&first.request_id).await.unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn listing_fetches_all_of_the_pages() {
+        let ipfs = Node::new("test_node").await;
+        let endpoint = mock_service(Pins::default());
+
+        let service = RemotePinService::new("mock", endpoint, TOKEN).unwrap();
+        ipfs.add_remote_pin_service(service).await.unwrap();
+        let mut remote = ipfs.remote_pins("mock").await.unwrap();
+        // small pages to need more than a few of them
+        remote.page_limit = 2;
+
+        let mut added = Vec::new();
+        for i in 0..7u8 {
+            added.push(remote.add(&cid(&[i]), None).await.unwrap());
+        }
+        added.reverse();
+
+        let query = RemotePinQuery {
+            status: vec![RemotePinState::Queued],
+            ..Default::default()
+        };
+        assert_eq!(remote.list(query).await.unwrap(), added);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn rejected_token_is_an_error() {
+        let ipfs = Node::new("test_node").await;
+        let endpoint = mock_service(Pins::default());
+
+        let service = RemotePinService::new("mock", endpoint, "wrong").unwrap();
+        ipfs.add_remote_pin_service(service).await.unwrap();
+        let remote = ipfs.remote_pins("mock").await.unwrap();
+
+        let e = remote.add(&cid(b"first"), None).await.unwrap_err();
+        assert!(e.to_string().starts_with("UNAUTHORIZED"), "{}", e);
+    }
+}
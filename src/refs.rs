@@ -6,8 +6,7 @@ use async_stream::stream;
 use cid::{self, Cid};
 use futures::stream::Stream;
 use std::borrow::Borrow;
-use std::collections::HashSet;
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 
 /// Represents a single link in an IPLD tree encountered during a `refs` walk.
@@ -39,12 +38,15 @@ pub enum IpldRefsError {
     Loading(#[from] crate::Error),
     #[error("block not found locally: {}", .0)]
     BlockNotFound(Cid),
+    #[error("block {} is corrupt: {}", .0, .1)]
+    Corrupt(Cid, String),
 }
 
 pub(crate) struct IpldRefs {
     max_depth: Option<u64>,
     unique: bool,
     download_blocks: bool,
+    verify_blocks: bool,
 }
 
 impl Default for IpldRefs {
@@ -53,6 +55,7 @@ impl Default for IpldRefs {
             max_depth: None, // unlimited
             unique: false,
             download_blocks: true,
+            verify_blocks: false,
         }
     }
 }
@@ -81,6 +84,16 @@ impl IpldRefs {
         self
     }
 
+    /// Like [`IpldRefs::with_existing_blocks`] but instead of stopping on the first block which is
+    /// not found locally, yields [`IpldRefsError::BlockNotFound`] for the missing blocks and
+    /// [`IpldRefsError::Corrupt`] for the blocks not matching their Cid or failing to parse, and
+    /// continues the walk past them.
+    pub fn with_verified_blocks(mut self) -> IpldRefs {
+        self.download_blocks = false;
+        self.verify_blocks = true;
+        self
+    }
+
     pub fn refs_of_resolved<'a, Types, MaybeOwned, Iter>(
         self,
         ipfs: MaybeOwned,
@@ -128,6 +141,7 @@ where
         max_depth,
         unique,
         download_blocks: true,
+        verify_blocks: false,
     };
     iplds_refs_inner(ipfs, iplds, opts).map_err(|e| match e {
         IpldRefsError::Block(e) => e,
@@ -155,6 +169,7 @@ where
         max_depth,
         unique,
         download_blocks,
+        verify_blocks,
     } = opts;
 
     let empty_stream = max_depth.map(|n| n == 0).unwrap_or(false);
@@ -209,11 +224,23 @@ where
                 }
             } else {
                 match borrowed.repo.get_block_now(&cid).await {
+                    Ok(Some(block)) if verify_blocks && !crate::repo::hash_matches(&block) => {
+                        yield Err(IpldRefsError::Corrupt(cid, "hash mismatch".into()));
+                        continue;
+                    }
                     Ok(Some(Block { data, .. })) => data,
+                    Ok(None) if verify_blocks => {
+                        yield Err(IpldRefsError::BlockNotFound(cid));
+                        continue;
+                    }
                     Ok(None) => {
                         yield Err(IpldRefsError::BlockNotFound(cid.to_owned()));
                         return;
                     }
+                    Err(e) if verify_blocks => {
+                        yield Err(IpldRefsError::Corrupt(cid, e.to_string()));
+                        continue;
+                    }
                     Err(e) => {
                         yield Err(IpldRefsError::from(e));
                         return;
@@ -225,6 +252,10 @@ where
 
             let ipld = match decode_ipld(&cid, &data) {
                 Ok(ipld) => ipld,
+                Err(e) if verify_blocks => {
+                    yield Err(IpldRefsError::Corrupt(cid, e.to_string()));
+                    continue;
+                }
                 Err(e) => {
                     warn!(cid = %cid, source = %cid, "failed to parse: {}", e);
                     // go-ipfs on raw Qm hash:
@@ -250,7 +281,120 @@ where
     }
 }
 
-fn ipld_links(
+/// The difference between the references of two DAGs, see [`dag_diff`]. None of the lists
+/// contain either of the roots, except `kept` which contains the old root if the new DAG links to
+/// it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct DagDiff {
+    /// References of the new DAG which are not in the old DAG.
+    pub added: Vec<Cid>,
+    /// References of both of the DAGs.
+    pub kept: Vec<Cid>,
+    /// References of the old DAG which are not in the new DAG.
+    pub removed: Vec<Cid>,
+}
+
+impl DagDiff {
+    /// All of the references of the new DAG.
+    pub fn new_refs(&self) -> impl Iterator<Item = &Cid> {
+        self.added.iter().chain(self.kept.iter())
+    }
+
+    /// All of the references of the old DAG, not including the old root.
+    pub fn old_refs<'a>(&'a self, old: &'a Cid) -> impl Iterator<Item = &'a Cid> {
+        self.kept
+            .iter()
+            .filter(move |cid| *cid != old)
+            .chain(self.removed.iter())
+    }
+}
+
+/// Compares the DAG of `new` to the DAG of `old`, which needs to be available locally. Both of
+/// the DAGs are walked together, a block at a time from each, and neither walk descends into the
+/// blocks already reached by the other one, as the subtrees are the same by their Cid. Every block
+/// is loaded at most once; the blocks which are only in the new DAG are fetched if needed.
+pub(crate) async fn dag_diff<Types: IpfsTypes>(
+    ipfs: &Ipfs<Types>,
+    old: &Cid,
+    new: &Cid,
+) -> Result<DagDiff, crate::Error> {
+    // the links of the blocks loaded by either of the walks
+    let mut links = HashMap::new();
+    let mut old_seen = HashSet::new();
+    let mut new_seen = HashSet::new();
+    let mut old_work = VecDeque::new();
+    let mut new_work = VecDeque::new();
+    // the blocks reached by both of the walks; everything under them is in both of the DAGs
+    let mut shared = Vec::new();
+
+    old_work.push_back(old.to_owned());
+    new_work.push_back(new.to_owned());
+
+    while !old_work.is_empty() || !new_work.is_empty() {
+        if let Some(cid) = old_work.pop_front() {
+            if old_seen.insert(cid.clone()) {
+                if new_seen.contains(&cid) {
+                    shared.push(cid);
+                } else {
+                    let Block { data, .. } = ipfs
+                        .repo
+                        .get_block_now(&cid)
+                        .await?
+                        .ok_or_else(|| IpldRefsError::BlockNotFound(cid.clone()))?;
+
+                    let next = ipld_links(&cid, decode_ipld(&cid, &data)?)
+                        .map(|(_, link)| link)
+                        .collect::<Vec<_>>();
+                    old_work.extend(next.iter().cloned());
+                    links.insert(cid, next);
+                }
+            }
+        }
+
+        if let Some(cid) = new_work.pop_front() {
+            if new_seen.insert(cid.clone()) {
+                if old_seen.contains(&cid) {
+                    shared.push(cid);
+                } else {
+                    let Block { data, .. } = ipfs.get_block(&cid).await?;
+
+                    let next = ipld_links(&cid, decode_ipld(&cid, &data)?)
+                        .map(|(_, link)| link)
+                        .collect::<Vec<_>>();
+                    new_work.extend(next.iter().cloned());
+                    links.insert(cid, next);
+                }
+            }
+        }
+    }
+
+    // every block was loaded by the walk which reached it first, so the links of the shared
+    // subtrees are all known
+    let mut kept = HashSet::new();
+    while let Some(cid) = shared.pop() {
+        if kept.insert(cid.clone()) {
+            shared.extend(links[&cid].iter().cloned());
+        }
+    }
+
+    let added = new_seen
+        .into_iter()
+        .filter(|cid| cid != new && !kept.contains(cid))
+        .collect();
+    let removed = old_seen
+        .into_iter()
+        .filter(|cid| cid != old && !kept.contains(cid))
+        .collect();
+    let kept = kept.into_iter().filter(|cid| cid != new).collect();
+
+    Ok(DagDiff {
+        added,
+        kept,
+        removed,
+    })
+}
+
+pub(crate) fn ipld_links(
     cid: &Cid,
     ipld: Ipld,
 ) -> impl Iterator<Item = (Option<String>, Cid)> + Send + 'static {
@@ -518,4 +662,50 @@ mod tests {
 
         ipfs
     }
+
+    #[tokio::test(max_threads = 1)]
+    async fn dag_diff_of_updated_dag() {
+        use super::{dag_diff, DagDiff};
+        use crate::make_ipld;
+        use crate::Ipld;
+
+        let ipfs = Node::new("test_node").await;
+
+        let a = ipfs.put_dag(make_ipld!("a")).await.unwrap();
+        let b = ipfs.put_dag(make_ipld!("b")).await.unwrap();
+        let c = ipfs.put_dag(make_ipld!("c")).await.unwrap();
+        let shared = ipfs
+            .put_dag(Ipld::List(vec![Ipld::Link(b.clone())]))
+            .await
+            .unwrap();
+        let old = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(a.clone()),
+                Ipld::Link(shared.clone()),
+            ]))
+            .await
+            .unwrap();
+        let new = ipfs
+            .put_dag(Ipld::List(vec![
+                Ipld::Link(shared.clone()),
+                Ipld::Link(c.clone()),
+            ]))
+            .await
+            .unwrap();
+
+        let mut diff = dag_diff(&ipfs, &old, &new).await.unwrap();
+        diff.kept.sort_by_key(|cid| cid.to_string());
+
+        let mut kept = vec![b, shared];
+        kept.sort_by_key(|cid| cid.to_string());
+
+        assert_eq!(
+            diff,
+            DagDiff {
+                added: vec![c],
+                kept,
+                removed: vec![a],
+            }
+        );
+    }
 }
@@ -0,0 +1,430 @@
+//! Atomic batches of block puts and pin insertions.
+use super::{BlockPut, BlockRm, BlockStore, FileReference, PinMode, Repo, RepoTypes};
+use crate::error::Error;
+use crate::ipld::decode_ipld;
+use crate::refs::ipld_links;
+use crate::Block;
+use cid::Cid;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashSet, VecDeque};
+
+/// Once the buffered blocks grow over this many bytes, they are written to the block store ahead
+/// of the commit.
+const FLUSH_BYTES: usize = 8 * 1024 * 1024;
+
+/// Buffers block puts and pin insertions so that they are committed together, created with
+/// [`Repo::batch`].
+///
+/// The buffered blocks are written with a single pass of [`super::BlockStore::put_batch`] before
+/// the pins are inserted, so that a crash cannot leave a partially written DAG pinned. Large
+/// batches are flushed to the block store in parts ahead of the commit. If the commit fails, or
+/// [`Batch::rollback`] is called, the blocks added by the batch are removed and the pins inserted
+/// by it are removed again. A batch dropped without either leaves the flushed blocks unpinned.
+///
+/// The stored blocks are protected from the eviction of the storage quota until the batch is
+/// committed or dropped.
+#[derive(Debug)]
+pub struct Batch<'a, T: RepoTypes> {
+    repo: &'a Repo<T>,
+    buffered: Vec<Block>,
+    buffered_bytes: usize,
+    /// The blocks which did not exist before they were stored by this batch.
+    stored: Vec<Cid>,
+    pins: Vec<(Cid, bool)>,
+    /// The blocks protected from eviction by this batch.
+    held: Vec<Cid>,
+}
+
+impl<T: RepoTypes> Repo<T> {
+    /// Starts a new batch of block puts and pin insertions, see [`Batch`].
+    pub fn batch(&self) -> Batch<'_, T> {
+        Batch {
+            repo: self,
+            buffered: Vec::new(),
+            buffered_bytes: 0,
+            stored: Vec::new(),
+            pins: Vec::new(),
+            held: Vec::new(),
+        }
+    }
+}
+
+/// A pin inserted by [`Batch::commit`], removed again on failure.
+enum InsertedPin {
+    Direct(Cid),
+    Recursive(Cid, Vec<Cid>),
+}
+
+impl<'a, T: RepoTypes> Batch<'a, T> {
+    /// Buffers the block to be stored on commit.
+    pub async fn put_block(&mut self, block: Block) -> Result<Cid, Error> {
+        let cid = block.cid.clone();
+
+        if super::is_inline(&cid) {
+            // nothing to store, see Repo::put_block
+            return Ok(cid);
+        }
+
+        self.buffered_bytes += block.data.len();
+        self.buffered.push(block);
+
+        if self.buffered_bytes > FLUSH_BYTES {
+            self.flush().await?;
+        }
+        Ok(cid)
+    }
+
+    /// Stores the block as a reference to a file right away, see [`Repo::put_block_reference`].
+    /// The reference is removed on rollback if it was added by this batch.
+    pub async fn put_block_reference(
+        &mut self,
+        block: Block,
+        reference: FileReference,
+    ) -> Result<Cid, Error> {
+        let (cid, res) = self.repo.put_block_reference(block, reference).await?;
+        self.hold(&cid);
+        if let BlockPut::NewBlock = res {
+            self.stored.push(cid.clone());
+        }
+        Ok(cid)
+    }
+
+    /// Pins the block on commit. A recursive pin requires the whole DAG to be available locally by
+    /// the time of the commit, either stored before or as a part of this batch.
+    pub fn insert_pin(&mut self, cid: &Cid, recursive: bool) {
+        self.pins.push((cid.to_owned(), recursive));
+    }
+
+    /// Writes out the buffered blocks with a single pass over the block store.
+    async fn flush(&mut self) -> Result<(), Error> {
+        let blocks = std::mem::take(&mut self.buffered);
+        self.buffered_bytes = 0;
+
+        if blocks.is_empty() {
+            return Ok(());
+        }
+
+        for block in &blocks {
+            self.hold(&block.cid);
+        }
+
+        let results = self.repo.block_store.put_batch(blocks.clone()).await?;
+
+        for (block, (cid, res)) in blocks.into_iter().zip(results) {
+            if let BlockPut::NewBlock = res {
+                self.stored.push(cid);
+            }
+            // the eviction is delayed until the commit so that the blocks of this batch cannot
+            // be evicted before they are pinned
+            self.repo.announce_stored(block, res).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores the buffered blocks and then inserts the pins. On failure everything done by the
+    /// batch is rolled back.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        let mut inserted = Vec::new();
+
+        match self.try_commit(&mut inserted).await {
+            Ok(()) => {
+                self.release();
+                self.repo.evict_if_needed(None).await?;
+                Ok(())
+            }
+            Err(e) => {
+                debug!("rolling back a batch after a failed commit: {}", e);
+                self.undo(inserted).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn try_commit(&mut self, inserted: &mut Vec<InsertedPin>) -> Result<(), Error> {
+        self.flush().await?;
+
+        for (cid, recursive) in std::mem::take(&mut self.pins) {
+            let mode = if recursive {
+                PinMode::Recursive
+            } else {
+                PinMode::Direct
+            };
+
+            if self
+                .repo
+                .query_pins(vec![cid.clone()], Some(mode))
+                .await
+                .is_ok()
+            {
+                // already pinned before the batch; must not be removed on rollback
+                continue;
+            }
+
+            if recursive {
+                let refs = self.local_refs(&cid).await?;
+                let st = stream::iter(refs.clone().into_iter().map(Ok)).boxed();
+                self.repo.insert_recursive_pin(&cid, st).await?;
+                inserted.push(InsertedPin::Recursive(cid, refs));
+            } else {
+                self.repo.insert_direct_pin(&cid).await?;
+                inserted.push(InsertedPin::Direct(cid));
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the buffered blocks and pins, and removes the blocks already added by the batch.
+    pub async fn rollback(self) {
+        self.undo(Vec::new()).await
+    }
+
+    /// Removes the `inserted` pins and the blocks added by the batch, on a best effort basis.
+    async fn undo(self, inserted: Vec<InsertedPin>) {
+        for pin in inserted.into_iter().rev() {
+            let res = match pin {
+                InsertedPin::Direct(cid) => self.repo.remove_direct_pin(&cid).await,
+                InsertedPin::Recursive(cid, refs) => {
+                    let st = stream::iter(refs.into_iter().map(Ok)).boxed();
+                    self.repo.remove_recursive_pin(&cid, st).await
+                }
+            };
+            if let Err(e) = res {
+                warn!("failed to remove a pin while rolling back a batch: {}", e);
+            }
+        }
+
+        for cid in &self.stored {
+            // pins from elsewhere are respected
+            if let Ok(true) = self.repo.is_pinned(cid).await {
+                continue;
+            }
+
+            match self.repo.block_store.remove(cid).await {
+                Ok(Ok(BlockRm::Removed(_))) => self.repo.block_removed(cid).await,
+                Ok(Err(_)) => {}
+                Err(e) => warn!("failed to remove {} while rolling back a batch: {}", cid, e),
+            }
+        }
+    }
+
+    /// Protects the block from eviction until the batch is committed or dropped.
+    fn hold(&mut self, cid: &Cid) {
+        if let Some(quota) = self.repo.quota.as_ref() {
+            quota.hold(cid);
+            self.held.push(cid.to_owned());
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some(quota) = self.repo.quota.as_ref() {
+            for cid in self.held.drain(..) {
+                quota.release(&cid);
+            }
+        }
+    }
+
+    /// Walks the DAG rooted at `root` through the locally stored blocks, failing if any of the
+    /// blocks are missing.
+    async fn local_refs(&self, root: &Cid) -> Result<Vec<Cid>, Error> {
+        let mut refs = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(root.to_owned());
+
+        while let Some(cid) = queue.pop_front() {
+            let block = self.repo.get_block_now(&cid).await?.ok_or_else(|| {
+                anyhow::anyhow!("cannot pin {} recursively, {} is missing", root, cid)
+            })?;
+
+            let ipld = decode_ipld(&cid, block.data())?;
+            for (_, link) in ipld_links(&cid, ipld) {
+                if seen.insert(link.clone()) {
+                    refs.push(link.clone());
+                    queue.push_back(link);
+                }
+            }
+        }
+
+        Ok(refs)
+    }
+}
+
+impl<'a, T: RepoTypes> Drop for Batch<'a, T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::{encode_ipld, Ipld};
+    use crate::test_support::{offline_repo, offline_repo_with_quota, raw};
+    use crate::DynTypes;
+    use cid::Codec;
+    use multihash::Sha2_256;
+    use std::env::temp_dir;
+
+    async fn is_stored(repo: &Repo<DynTypes>, cid: &Cid) -> bool {
+        repo.get_block_now(cid).await.unwrap().is_some()
+    }
+
+    /// A dag-cbor block linking to the given cids.
+    fn parent(links: &[&Cid]) -> Block {
+        let links = links
+            .iter()
+            .map(|&cid| Ipld::Link(cid.to_owned()))
+            .collect::<Vec<_>>();
+        let data = encode_ipld(&Ipld::List(links), Codec::DagCBOR).unwrap();
+        let cid = Cid::new_v1(Codec::DagCBOR, Sha2_256::digest(&data));
+        Block::new(data, cid)
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn commit_stores_blocks_and_pins() {
+        let tmp = temp_dir().join("repo_batch_commit");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let child = raw(b"child");
+        let root = parent(&[child.cid()]);
+
+        let mut batch = repo.batch();
+        batch.put_block(child.clone()).await.unwrap();
+        let root_cid = batch.put_block(root).await.unwrap();
+        batch.insert_pin(&root_cid, true);
+
+        assert!(!is_stored(&repo, child.cid()).await);
+
+        batch.commit().await.unwrap();
+
+        assert!(is_stored(&repo, child.cid()).await);
+        assert!(repo.is_pinned(&root_cid).await.unwrap());
+        assert!(repo.is_pinned(child.cid()).await.unwrap());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn blocks_of_unfinished_batches_are_not_evicted() {
+        let tmp = temp_dir().join("repo_batch_quota");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo_with_quota(tmp.clone(), Some(100));
+        repo.init().await.unwrap();
+
+        let held = raw(&[1; 60]);
+        let other = raw(&[2; 60]);
+
+        let mut batch = repo.batch();
+        batch.put_block(held.clone()).await.unwrap();
+        batch.flush().await.unwrap();
+
+        // over the quota, but the block of the batch is not yet pinned
+        repo.put_block(other.clone()).await.unwrap();
+        assert!(is_stored(&repo, held.cid()).await);
+
+        batch.insert_pin(held.cid(), false);
+        batch.commit().await.unwrap();
+
+        assert!(is_stored(&repo, held.cid()).await);
+        assert!(!is_stored(&repo, other.cid()).await);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn inline_blocks_are_not_stored() {
+        let tmp = temp_dir().join("repo_batch_inline");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let data = b"inline";
+        let cid = Cid::new_v1(Codec::Raw, multihash::Identity::digest(data));
+        let inline = Block::new(data.to_vec().into_boxed_slice(), cid);
+        let root = parent(&[inline.cid()]);
+
+        let mut batch = repo.batch();
+        batch.put_block(inline.clone()).await.unwrap();
+        let root_cid = batch.put_block(root).await.unwrap();
+        batch.insert_pin(&root_cid, true);
+        batch.commit().await.unwrap();
+
+        assert!(!repo.block_store.contains(inline.cid()).await.unwrap());
+        assert!(repo.contains(inline.cid()).await.unwrap());
+        assert_eq!(
+            repo.get_block_now(inline.cid()).await.unwrap(),
+            Some(inline)
+        );
+        assert!(repo.is_pinned(&root_cid).await.unwrap());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn failed_commit_is_rolled_back() {
+        let tmp = temp_dir().join("repo_batch_failed");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let existing = raw(b"existing");
+        repo.put_block(existing.clone()).await.unwrap();
+
+        let missing = raw(b"missing");
+        let root = parent(&[existing.cid(), missing.cid()]);
+        let stored = raw(b"stored");
+
+        let mut batch = repo.batch();
+        batch.put_block(stored.clone()).await.unwrap();
+        batch.put_block(existing.clone()).await.unwrap();
+        let root_cid = batch.put_block(root).await.unwrap();
+        batch.insert_pin(stored.cid(), false);
+        batch.insert_pin(&root_cid, true);
+
+        batch.commit().await.unwrap_err();
+
+        assert!(!is_stored(&repo, &root_cid).await);
+        assert!(!is_stored(&repo, stored.cid()).await);
+        assert!(!repo.is_pinned(stored.cid()).await.unwrap());
+        // blocks stored before the batch are kept
+        assert!(is_stored(&repo, existing.cid()).await);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn rollback_removes_flushed_blocks() {
+        let tmp = temp_dir().join("repo_batch_rollback");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let pinned = raw(b"pinned");
+        repo.put_block(pinned.clone()).await.unwrap();
+        repo.insert_direct_pin(pinned.cid()).await.unwrap();
+
+        let added = raw(b"added");
+
+        let mut batch = repo.batch();
+        batch.put_block(added.clone()).await.unwrap();
+        batch.put_block(pinned.clone()).await.unwrap();
+        batch.flush().await.unwrap();
+
+        assert!(is_stored(&repo, added.cid()).await);
+
+        batch.rollback().await;
+
+        assert!(!is_stored(&repo, added.cid()).await);
+        assert!(is_stored(&repo, pinned.cid()).await);
+        assert!(repo.is_pinned(pinned.cid()).await.unwrap());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -0,0 +1,179 @@
+//! Implementations of the store traits for boxed trait objects, which allow selecting the stores
+//! at runtime through [`crate::DynTypes`].
+use crate::error::Error;
+use crate::repo::{
+    BlockPut, BlockRm, BlockRmError, BlockStore, BlockStoreExtensions, Column, DataStore, PinKind,
+    PinMode, PinStore, References,
+};
+use crate::Block;
+use async_trait::async_trait;
+use cid::Cid;
+use futures::stream::BoxStream;
+use multihash::Multihash;
+use std::path::PathBuf;
+
+#[async_trait]
+impl BlockStore for Box<dyn BlockStore> {
+    /// Defaults to the persistent [`super::fs::FsBlockStore`].
+    fn new(path: PathBuf) -> Self {
+        Box::new(super::fs::FsBlockStore::new(path))
+    }
+
+    fn is_persistent(&self) -> bool {
+        (**self).is_persistent()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        (**self).init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        (**self).open().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        (**self).contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        (**self).get(cid).await
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        (**self).put(block).await
+    }
+
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        (**self).put_batch(blocks).await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        (**self).remove(cid).await
+    }
+
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        (**self).list().await
+    }
+
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        (**self).list_after(cursor).await
+    }
+
+    async fn size(&self) -> Result<u64, Error> {
+        (**self).size().await
+    }
+
+    async fn list_multihashes(&self) -> Result<Vec<Multihash>, Error> {
+        (**self).list_multihashes().await
+    }
+
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        (**self).extensions()
+    }
+
+    async fn wipe(&self) {
+        (**self).wipe().await
+    }
+}
+
+#[async_trait]
+impl DataStore for Box<dyn DataStore> {
+    /// Defaults to the persistent [`super::fs::FsDataStore`].
+    fn new(path: PathBuf) -> Self {
+        Box::new(super::fs::FsDataStore::new(path))
+    }
+
+    fn is_persistent(&self) -> bool {
+        (**self).is_persistent()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        (**self).init().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        (**self).open().await
+    }
+
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        (**self).contains(col, key).await
+    }
+
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        (**self).get(col, key).await
+    }
+
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        (**self).put(col, key, value).await
+    }
+
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        (**self).remove(col, key).await
+    }
+
+    async fn wipe(&self) {
+        (**self).wipe().await
+    }
+}
+
+#[async_trait]
+impl PinStore for Box<dyn DataStore> {
+    async fn is_pinned(&self, block: &Cid) -> Result<bool, Error> {
+        (**self).is_pinned(block).await
+    }
+
+    async fn insert_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        (**self).insert_direct_pin(target).await
+    }
+
+    async fn insert_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        (**self).insert_recursive_pin(target, referenced).await
+    }
+
+    async fn remove_direct_pin(&self, target: &Cid) -> Result<(), Error> {
+        (**self).remove_direct_pin(target).await
+    }
+
+    async fn remove_recursive_pin(
+        &self,
+        target: &Cid,
+        referenced: References<'_>,
+    ) -> Result<(), Error> {
+        (**self).remove_recursive_pin(target, referenced).await
+    }
+
+    async fn list(
+        &self,
+        mode: Option<PinMode>,
+    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
+        PinStore::list(&**self, mode).await
+    }
+
+    async fn query(
+        &self,
+        ids: Vec<Cid>,
+        requirement: Option<PinMode>,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        (**self).query(ids, requirement).await
+    }
+}
+
+#[cfg(test)]
+crate::pinstore_interface_tests!(
+    common_tests,
+    |path: std::path::PathBuf| -> Box<dyn crate::repo::DataStore> {
+        Box::new(crate::repo::mem::MemDataStore::new(path))
+    }
+);
+
+#[cfg(test)]
+crate::column_interface_tests!(
+    column_common_tests,
+    |path: std::path::PathBuf| -> Box<dyn crate::repo::DataStore> {
+        Box::new(crate::repo::fs::FsDataStore::new(path))
+    }
+);
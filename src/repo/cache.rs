@@ -0,0 +1,745 @@
+//! In-memory block cache and bloom filter in front of any [`BlockStore`].
+use super::{
+    BlockCache, BlockFiles, BlockPut, BlockRm, BlockRmError, BlockStore, BlockStoreExtensions,
+    FileReference, FileReferences, RepoCid,
+};
+use crate::error::Error;
+use crate::Block;
+use async_trait::async_trait;
+use cid::Cid;
+use futures::stream::{BoxStream, TryStreamExt};
+use multihash::Multihash;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The default size of the cached block data in bytes, used with [`BlockStore::new`].
+pub const DEFAULT_CACHE_CAPACITY: u64 = 64 * 1024 * 1024;
+
+/// The smallest number of items the bloom filter is sized for.
+const MIN_FILTER_ITEMS: usize = 1024;
+
+/// Counters of the [`CachedBlockStore`], returned by [`BlockCache::cache_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    /// Lookups answered from the cached block data.
+    pub hits: u64,
+    /// Lookups which had to go to the wrapped block store.
+    pub misses: u64,
+    /// Lookups of missing blocks answered by the bloom filter alone.
+    pub filtered: u64,
+    /// The size of the cached block data in bytes.
+    pub cached_bytes: u64,
+    /// The number of cached blocks.
+    pub cached_blocks: u64,
+    /// The maximum size of the cached block data in bytes.
+    pub capacity: u64,
+}
+
+/// Wraps a [`BlockStore`] with a size-bounded least recently used cache of the block data and a
+/// bloom filter answering the lookups of missing blocks without going to the wrapped store.
+///
+/// The bloom filter is built from [`BlockStore::list`] when the store is initialized or opened,
+/// and rebuilt whenever enough blocks have been added since. Until then all lookups go through
+/// the cache to the wrapped store.
+#[derive(Debug)]
+pub struct CachedBlockStore<S> {
+    inner: S,
+    cache: Mutex<Lru>,
+    filter: Mutex<FilterState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    filtered: AtomicU64,
+}
+
+impl<S: BlockStore> CachedBlockStore<S> {
+    /// Wraps the `inner` store, caching at most `capacity` bytes of block data.
+    pub fn with_capacity(inner: S, capacity: u64) -> Self {
+        CachedBlockStore {
+            inner,
+            cache: Mutex::new(Lru::new(capacity)),
+            filter: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            filtered: Default::default(),
+        }
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The file references of the wrapped store, only reported when it has them.
+    fn inner_references(&self) -> &dyn FileReferences {
+        self.inner
+            .extensions()
+            .references
+            .expect("reported only when the wrapped store supports file references")
+    }
+
+    /// The block files of the wrapped store, only reported when it has them.
+    fn inner_files(&self) -> &dyn BlockFiles {
+        self.inner
+            .extensions()
+            .files
+            .expect("reported only when the wrapped store has block files")
+    }
+
+    /// Returns true if the bloom filter says the block is certainly not stored.
+    fn filtered_out(&self, cid: &Cid) -> bool {
+        let filtered = match self.filter.lock().unwrap().bloom.as_ref() {
+            Some(bloom) => !bloom.may_contain(cid.hash().as_bytes()),
+            None => false,
+        };
+        if filtered {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        filtered
+    }
+
+    /// Adds the block to the bloom filter, returning true if the filter is full and should be
+    /// rebuilt. This is done before the block is stored so that the filter never misses a block.
+    fn add_to_filter(&self, hash: &[u8]) -> bool {
+        let mut state = self.filter.lock().unwrap();
+        if let Some(added) = state.added_while_building.as_mut() {
+            added.push(hash.to_vec());
+        }
+        match state.bloom.as_mut() {
+            Some(bloom) => {
+                bloom.insert(hash);
+                bloom.is_full() && state.added_while_building.is_none()
+            }
+            None => false,
+        }
+    }
+
+    /// Rebuilds the bloom filter from the listing of the wrapped store, inserting the blocks as
+    /// they are listed. The filter is sized for the number of items in the previous filter and
+    /// grows if more are listed. The blocks put while listing are recorded and added to the new
+    /// filter.
+    async fn build_filter(&self) -> Result<(), Error> {
+        let expected = {
+            let mut state = self.filter.lock().unwrap();
+            if state.added_while_building.is_some() {
+                // another build is already underway
+                return Ok(());
+            }
+            state.added_while_building = Some(Vec::new());
+            state.bloom.as_ref().map(|bloom| bloom.items).unwrap_or(0)
+        };
+
+        let mut bloom = Bloom::new(expected);
+        let listed = {
+            let mut listing = self.inner.list().await;
+            loop {
+                match listing.try_next().await {
+                    Ok(Some(cid)) => bloom.insert(cid.hash().as_bytes()),
+                    Ok(None) => break Ok(bloom.items),
+                    Err(e) => break Err(e),
+                }
+            }
+        };
+
+        let mut state = self.filter.lock().unwrap();
+        let added = state.added_while_building.take().unwrap_or_default();
+
+        let listed = listed?;
+        for hash in &added {
+            bloom.insert(hash);
+        }
+        state.bloom = Some(bloom);
+
+        trace!(blocks = listed, "built the bloom filter");
+        Ok(())
+    }
+
+    fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl<S: BlockStore> BlockStore for CachedBlockStore<S> {
+    /// Wraps the store created at `path`, with the cache of [`DEFAULT_CACHE_CAPACITY`] bytes.
+    fn new(path: PathBuf) -> Self {
+        Self::with_capacity(S::new(path), DEFAULT_CACHE_CAPACITY)
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.inner.is_persistent()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        self.inner.init().await?;
+        self.build_filter().await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.inner.open().await?;
+        self.build_filter().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        if self.filtered_out(cid) {
+            return Ok(false);
+        }
+
+        if self.cache.lock().unwrap().contains(cid) {
+            self.hit();
+            return Ok(true);
+        }
+
+        self.miss();
+        self.inner.contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        if self.filtered_out(cid) {
+            return Ok(None);
+        }
+
+        if let Some(block) = self.cache.lock().unwrap().get(cid) {
+            self.hit();
+            return Ok(Some(block));
+        }
+
+        self.miss();
+        let generation = self.cache.lock().unwrap().generation;
+        let block = self.inner.get(cid).await?;
+        if let Some(block) = block.as_ref() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert_unless_removed(block.clone(), generation);
+        }
+        Ok(block)
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let rebuild = self.add_to_filter(block.cid().hash().as_bytes());
+
+        let res = self.inner.put(block.clone()).await?;
+        self.cache.lock().unwrap().insert(block);
+
+        if rebuild {
+            self.build_filter().await?;
+        }
+        Ok(res)
+    }
+
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let mut rebuild = false;
+        for block in &blocks {
+            rebuild |= self.add_to_filter(block.cid().hash().as_bytes());
+        }
+
+        let res = self.inner.put_batch(blocks.clone()).await?;
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for block in blocks {
+                cache.insert(block);
+            }
+        }
+
+        if rebuild {
+            self.build_filter().await?;
+        }
+        Ok(res)
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        // the bloom filter cannot forget blocks, they remain as false positives until rebuilt
+        self.cache.lock().unwrap().invalidate(cid);
+        let res = self.inner.remove(cid).await;
+        // the reads which started before the removal completed must not cache the block again
+        self.cache.lock().unwrap().invalidate(cid);
+        res
+    }
+
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        self.inner.list().await
+    }
+
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        self.inner.list_after(cursor).await
+    }
+
+    async fn size(&self) -> Result<u64, Error> {
+        self.inner.size().await
+    }
+
+    async fn list_multihashes(&self) -> Result<Vec<Multihash>, Error> {
+        self.inner.list_multihashes().await
+    }
+
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        let inner = self.inner.extensions();
+        BlockStoreExtensions {
+            references: inner.references.map(|_| self as &dyn FileReferences),
+            files: inner.files.map(|_| self as &dyn BlockFiles),
+            compression: inner.compression,
+            cache: Some(self),
+        }
+    }
+
+    async fn wipe(&self) {
+        self.inner.wipe().await;
+        self.cache.lock().unwrap().clear();
+        let mut state = self.filter.lock().unwrap();
+        if state.bloom.is_some() {
+            state.bloom = Some(Bloom::new(0));
+        }
+    }
+}
+
+#[async_trait]
+impl<S: BlockStore> FileReferences for CachedBlockStore<S> {
+    async fn put_reference(
+        &self,
+        cid: Cid,
+        reference: FileReference,
+    ) -> Result<(Cid, BlockPut), Error> {
+        let rebuild = self.add_to_filter(cid.hash().as_bytes());
+
+        let res = self
+            .inner_references()
+            .put_reference(cid, reference)
+            .await?;
+
+        if rebuild {
+            self.build_filter().await?;
+        }
+        Ok(res)
+    }
+
+    async fn list_references(&self) -> Result<Vec<(Cid, FileReference)>, Error> {
+        self.inner_references().list_references().await
+    }
+}
+
+#[async_trait]
+impl<S: BlockStore> BlockFiles for CachedBlockStore<S> {
+    async fn quarantine(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.cache.lock().unwrap().invalidate(cid);
+        let res = self.inner_files().quarantine(cid).await;
+        self.cache.lock().unwrap().invalidate(cid);
+        res
+    }
+
+    async fn list_orphans(&self) -> Result<Vec<PathBuf>, Error> {
+        self.inner_files().list_orphans().await
+    }
+
+    async fn remove_orphan(&self, path: &Path, quarantine: bool) -> Result<(), Error> {
+        self.inner_files().remove_orphan(path, quarantine).await
+    }
+}
+
+impl<S: BlockStore> BlockCache for CachedBlockStore<S> {
+    fn cache_stats(&self) -> BlockCacheStats {
+        let cache = self.cache.lock().unwrap();
+        BlockCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            cached_bytes: cache.size,
+            cached_blocks: cache.entries.len() as u64,
+            capacity: cache.capacity,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FilterState {
+    /// `None` until built on init or open.
+    bloom: Option<Bloom>,
+    /// The blocks put while the filter is being built, `None` when not building.
+    added_while_building: Option<Vec<Vec<u8>>>,
+}
+
+/// Bloom filter over the multihash bytes of the blocks, sized for about one percent false positives.
+///
+/// The filter grows by adding layers twice the size of the previous one, so that it can be built
+/// while listing the blocks without knowing their number up front. Each layer adds to the false
+/// positives, so a filter with more items than it was sized for should be rebuilt.
+#[derive(Debug)]
+struct Bloom {
+    /// The bits of each layer; the items are inserted into the last one.
+    layers: Vec<Vec<u64>>,
+    /// The number of items the first layer was sized for.
+    capacity: usize,
+    /// The number of items the layers have room for.
+    room: usize,
+    items: usize,
+}
+
+impl Bloom {
+    const BITS_PER_ITEM: usize = 10;
+    const HASHES: u64 = 7;
+
+    /// Creates a filter with room for twice the number of `expected` items.
+    fn new(expected: usize) -> Self {
+        let capacity = (expected * 2).max(MIN_FILTER_ITEMS);
+        Bloom {
+            layers: vec![Self::layer(capacity)],
+            capacity,
+            room: capacity,
+            items: 0,
+        }
+    }
+
+    fn layer(capacity: usize) -> Vec<u64> {
+        vec![0; (capacity * Self::BITS_PER_ITEM + 63) / 64]
+    }
+
+    fn insert(&mut self, hash: &[u8]) {
+        if self.items >= self.room {
+            let capacity = self.capacity << self.layers.len();
+            self.layers.push(Self::layer(capacity));
+            self.room += capacity;
+        }
+
+        let layer = self.layers.last_mut().expect("there is always a layer");
+        for bit in Self::bit_indices(layer.len(), hash) {
+            layer[bit / 64] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    fn may_contain(&self, hash: &[u8]) -> bool {
+        self.layers.iter().any(|layer| {
+            Self::bit_indices(layer.len(), hash).all(|bit| layer[bit / 64] & (1 << (bit % 64)) != 0)
+        })
+    }
+
+    /// Returns true when more items have been added than the filter was sized for.
+    fn is_full(&self) -> bool {
+        self.items > self.capacity
+    }
+
+    /// The bit indices of the `hash` in a layer of `words` with the double hashing scheme.
+    fn bit_indices(words: usize, hash: &[u8]) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        hash.hash(&mut hasher);
+        let h1 = hasher.finish();
+        // the odd second hash keeps the indices distinct
+        h1.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+
+        let len = (words * 64) as u64;
+        (0..Self::HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+/// Least recently used cache of blocks bounded by the total size of the block data.
+#[derive(Debug)]
+struct Lru {
+    capacity: u64,
+    size: u64,
+    /// Incremented on every use, the "time" of the last use of the entries.
+    clock: u64,
+    /// Keyed by the multihash like the wrapped stores, so that all of the Cids of a block are
+    /// found and invalidated together.
+    entries: HashMap<RepoCid, (Block, u64)>,
+    by_age: BTreeMap<u64, Cid>,
+    /// Incremented on every removal from the wrapped store, so that the blocks read from the
+    /// wrapped store concurrently with a removal are not cached.
+    generation: u64,
+}
+
+impl Lru {
+    fn new(capacity: u64) -> Self {
+        Lru {
+            capacity,
+            size: 0,
+            clock: 0,
+            entries: Default::default(),
+            by_age: Default::default(),
+            generation: 0,
+        }
+    }
+
+    fn contains(&self, cid: &Cid) -> bool {
+        self.entries.contains_key(&RepoCid(cid.to_owned()))
+    }
+
+    /// Returns the cached block with the same multihash, under the requested Cid.
+    fn get(&mut self, cid: &Cid) -> Option<Block> {
+        self.clock += 1;
+        let now = self.clock;
+        let (block, last_used) = self.entries.get_mut(&RepoCid(cid.to_owned()))?;
+        self.by_age.remove(last_used);
+        self.by_age.insert(now, block.cid().to_owned());
+        *last_used = now;
+        if block.cid() == cid {
+            Some(block.clone())
+        } else {
+            Some(Block::new(block.data().into(), cid.to_owned()))
+        }
+    }
+
+    /// Caches the block, evicting the least recently used blocks as needed. Blocks larger than
+    /// the capacity are not cached.
+    fn insert(&mut self, block: Block) {
+        let len = block.data().len() as u64;
+        if len > self.capacity {
+            return;
+        }
+
+        self.remove(block.cid());
+
+        while self.size + len > self.capacity {
+            let oldest = match self.by_age.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            let cid = self.by_age.remove(&oldest).expect("key was just found");
+            self.remove(&cid);
+        }
+
+        self.clock += 1;
+        self.by_age.insert(self.clock, block.cid().to_owned());
+        self.size += len;
+        self.entries
+            .insert(RepoCid(block.cid().to_owned()), (block, self.clock));
+    }
+
+    /// Caches the block read from the wrapped store, unless blocks have been removed from the
+    /// wrapped store since the read started at `generation`.
+    fn insert_unless_removed(&mut self, block: Block, generation: u64) {
+        if self.generation == generation {
+            self.insert(block);
+        }
+    }
+
+    /// Removes the block, which is being removed from the wrapped store.
+    fn invalidate(&mut self, cid: &Cid) {
+        self.remove(cid);
+        self.generation += 1;
+    }
+
+    fn remove(&mut self, cid: &Cid) {
+        if let Some((block, last_used)) = self.entries.remove(&RepoCid(cid.to_owned())) {
+            self.by_age.remove(&last_used);
+            self.size -= block.data().len() as u64;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.by_age.clear();
+        self.size = 0;
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::mem::MemBlockStore;
+    use crate::test_support::raw;
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(6);
+        let (a, b, c) = (raw(b"aa"), raw(b"bb"), raw(b"cc"));
+
+        lru.insert(a.clone());
+        lru.insert(b.clone());
+        lru.insert(c.clone());
+        assert_eq!(lru.get(a.cid()), Some(a.clone()));
+
+        // b is the least recently used one
+        lru.insert(raw(b"dd"));
+        assert!(lru.contains(a.cid()));
+        assert!(!lru.contains(b.cid()));
+        assert!(lru.contains(c.cid()));
+        assert_eq!(lru.size, 6);
+
+        // too large to be cached at all
+        lru.insert(raw(b"1234567"));
+        assert_eq!(lru.entries.len(), 3);
+
+        lru.remove(a.cid());
+        assert_eq!(lru.size, 4);
+        assert_eq!(lru.by_age.len(), 2);
+    }
+
+    #[test]
+    fn reads_racing_with_removals_are_not_cached() {
+        let mut lru = Lru::new(6);
+        let (a, b) = (raw(b"aa"), raw(b"bb"));
+
+        // a read of a started, and then a was removed before the read completed
+        let generation = lru.generation;
+        lru.invalidate(a.cid());
+        lru.insert_unless_removed(a.clone(), generation);
+        assert!(!lru.contains(a.cid()));
+
+        let generation = lru.generation;
+        lru.insert_unless_removed(b.clone(), generation);
+        assert!(lru.contains(b.cid()));
+    }
+
+    #[test]
+    fn lru_is_keyed_by_multihash() {
+        let mut lru = Lru::new(6);
+        let v1 = raw(b"aa");
+        let v0 = Cid::new_v0(v1.cid().hash().to_owned()).unwrap();
+
+        lru.insert(v1.clone());
+        let block = lru.get(&v0).unwrap();
+        assert_eq!(block.cid(), &v0);
+        assert_eq!(block.data(), v1.data());
+
+        lru.invalidate(&v0);
+        assert!(!lru.contains(v1.cid()));
+        assert_eq!(lru.size, 0);
+    }
+
+    #[test]
+    fn bloom_grows_while_building() {
+        let mut bloom = Bloom::new(0);
+        let blocks = (0..5000u32)
+            .map(|i| raw(&i.to_be_bytes()))
+            .collect::<Vec<_>>();
+
+        for block in &blocks {
+            bloom.insert(block.cid().hash().as_bytes());
+        }
+
+        assert!(bloom.layers.len() > 1);
+        assert!(blocks
+            .iter()
+            .all(|block| bloom.may_contain(block.cid().hash().as_bytes())));
+    }
+
+    #[test]
+    fn bloom_has_no_false_negatives() {
+        let mut bloom = Bloom::new(0);
+        let blocks = (0..2000u32)
+            .map(|i| raw(&i.to_be_bytes()))
+            .collect::<Vec<_>>();
+
+        for block in &blocks[..1000] {
+            bloom.insert(block.cid().hash().as_bytes());
+        }
+
+        assert!(blocks[..1000]
+            .iter()
+            .all(|block| bloom.may_contain(block.cid().hash().as_bytes())));
+
+        let false_positives = blocks[1000..]
+            .iter()
+            .filter(|block| bloom.may_contain(block.cid().hash().as_bytes()))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+
+        assert!(!bloom.is_full());
+        for block in &blocks[1000..] {
+            bloom.insert(block.cid().hash().as_bytes());
+        }
+        assert!(bloom.is_full());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn caches_blocks_and_filters_missing_ones() {
+        let inner = MemBlockStore::new(PathBuf::new());
+        let existing = raw(b"existing");
+        inner.put(existing.clone()).await.unwrap();
+
+        let store = CachedBlockStore::with_capacity(inner, 1024);
+        store.init().await.unwrap();
+
+        // built from the listing of the wrapped store
+        assert!(store.contains(existing.cid()).await.unwrap());
+        assert_eq!(
+            store.get(existing.cid()).await.unwrap(),
+            Some(existing.clone())
+        );
+        assert_eq!(
+            store.get(existing.cid()).await.unwrap(),
+            Some(existing.clone())
+        );
+
+        let missing = raw(b"missing");
+        assert!(!store.contains(missing.cid()).await.unwrap());
+        assert_eq!(store.get(missing.cid()).await.unwrap(), None);
+
+        let added = raw(b"added");
+        store.put(added.clone()).await.unwrap();
+        assert!(store.contains(added.cid()).await.unwrap());
+
+        let stats = store.cache_stats();
+        assert_eq!(
+            stats,
+            BlockCacheStats {
+                hits: 2,
+                misses: 2,
+                filtered: 2,
+                cached_bytes: 13,
+                cached_blocks: 2,
+                capacity: 1024,
+            }
+        );
+
+        store.remove(added.cid()).await.unwrap().unwrap();
+        assert!(!store.contains(added.cid()).await.unwrap());
+        assert_eq!(store.cache_stats().cached_blocks, 1);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn removal_through_other_cid_version() {
+        let store = CachedBlockStore::with_capacity(MemBlockStore::new(PathBuf::new()), 1024);
+        store.init().await.unwrap();
+
+        let data = b"versioned".to_vec().into_boxed_slice();
+        let v0 = Cid::new_v0(multihash::Sha2_256::digest(&data)).unwrap();
+        let v1 = Cid::new_v1(cid::Codec::DagProtobuf, v0.hash().to_owned());
+        let block = Block::new(data, v1.clone());
+
+        store.put(block.clone()).await.unwrap();
+        assert_eq!(store.get(&v1).await.unwrap(), Some(block));
+        assert_eq!(store.get(&v0).await.unwrap().unwrap().cid(), &v0);
+
+        store.remove(&v0).await.unwrap().unwrap();
+        assert_eq!(store.get(&v1).await.unwrap(), None);
+        assert!(!store.contains(&v1).await.unwrap());
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn filter_is_rebuilt_when_full() {
+        let store = CachedBlockStore::with_capacity(MemBlockStore::new(PathBuf::new()), 0);
+        store.open().await.unwrap();
+
+        let blocks = (0..(MIN_FILTER_ITEMS as u32 * 2 + 1))
+            .map(|i| raw(&i.to_be_bytes()))
+            .collect::<Vec<_>>();
+
+        for block in &blocks {
+            store.put(block.clone()).await.unwrap();
+        }
+
+        let capacity = store
+            .filter
+            .lock()
+            .unwrap()
+            .bloom
+            .as_ref()
+            .unwrap()
+            .capacity;
+        assert!(capacity > MIN_FILTER_ITEMS * 2);
+
+        for block in &blocks {
+            assert!(store.contains(block.cid()).await.unwrap());
+        }
+    }
+}
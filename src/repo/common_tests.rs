@@ -1,5 +1,5 @@
-///! "Interface" tests for pin store, maybe more later
-use crate::repo::DataStore;
+///! "Interface" tests for pin store and block store, maybe more later
+use crate::repo::{BlockStore, DataStore};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tempfile::TempDir;
@@ -39,6 +39,41 @@ impl<T: DataStore> std::ops::Deref for DSTestContext<T> {
     }
 }
 
+pub struct BSTestContext<T> {
+    #[allow(dead_code)]
+    tempdir: TempDir,
+    blockstore: Arc<T>,
+}
+
+impl<T: BlockStore> BSTestContext<T> {
+    /// Create the test context which holds the BlockStore inside an Arc and deletes the temporary
+    /// directory on drop.
+    pub async fn with<F>(factory: F) -> Self
+    where
+        F: FnOnce(PathBuf) -> T,
+    {
+        let tempdir = TempDir::new().expect("tempdir creation failed");
+        let p = tempdir.path().to_owned();
+        let bs = factory(p);
+
+        bs.init().await.unwrap();
+        bs.open().await.unwrap();
+
+        BSTestContext {
+            tempdir,
+            blockstore: Arc::new(bs),
+        }
+    }
+}
+
+impl<T: BlockStore> std::ops::Deref for BSTestContext<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.blockstore
+    }
+}
+
 /// Generates the "common interface" tests for PinStore implementations as a given module using a
 /// types factory method. When adding tests, it might be easier to write them against the one
 /// implementation and only then move them here; the compiler errors seem to point at the
@@ -314,3 +349,212 @@ macro_rules! pinstore_interface_tests {
         }
     };
 }
+
+/// Generates the "common interface" tests for BlockStore implementations as a given module using
+/// a factory method, see [`pinstore_interface_tests`].
+#[macro_export]
+macro_rules! blockstore_interface_tests {
+    ($module_name:ident, $factory:expr) => {
+        #[cfg(test)]
+        mod $module_name {
+
+            use $crate::repo::common_tests::BSTestContext;
+            use $crate::repo::{BlockPut, BlockRm, BlockRmError, BlockStore};
+            use $crate::test_support::raw;
+            use $crate::Block;
+            use cid::Cid;
+            use futures::TryStreamExt;
+            use hex_literal::hex;
+            use std::convert::TryFrom;
+
+            #[tokio::test(max_threads = 1)]
+            async fn put_get_remove() {
+                let store = BSTestContext::with($factory).await;
+
+                let block = raw(b"foobar");
+
+                assert!(!store.contains(block.cid()).await.unwrap());
+                assert_eq!(store.get(block.cid()).await.unwrap(), None);
+
+                let (cid, put) = store.put(block.clone()).await.unwrap();
+                assert_eq!(&cid, block.cid());
+                assert_eq!(put, BlockPut::NewBlock);
+
+                let (_, put) = store.put(block.clone()).await.unwrap();
+                assert_eq!(put, BlockPut::Existed);
+
+                assert!(store.contains(block.cid()).await.unwrap());
+                assert_eq!(store.get(block.cid()).await.unwrap(), Some(block.clone()));
+
+                match store.remove(block.cid()).await.unwrap() {
+                    Ok(BlockRm::Removed(cid)) => assert_eq!(&cid, block.cid()),
+                    other => panic!("unexpected removal: {:?}", other),
+                }
+
+                assert!(!store.contains(block.cid()).await.unwrap());
+                assert_eq!(store.get(block.cid()).await.unwrap(), None);
+
+                match store.remove(block.cid()).await.unwrap() {
+                    Err(BlockRmError::NotFound(cid)) => assert_eq!(&cid, block.cid()),
+                    other => panic!("unexpected removal: {:?}", other),
+                }
+            }
+
+            #[tokio::test(max_threads = 1)]
+            async fn found_with_both_cid_versions() {
+                let store = BSTestContext::with($factory).await;
+
+                let v0 = Cid::try_from("QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL").unwrap();
+                let v1 = Cid::new_v1(v0.codec(), v0.hash().to_owned());
+                let data = hex!("0a0d08021207666f6f6261720a1807");
+
+                store
+                    .put(Block::new(data[..].into(), v0.clone()))
+                    .await
+                    .unwrap();
+
+                assert!(store.contains(&v1).await.unwrap());
+                let block = store.get(&v1).await.unwrap().unwrap();
+                assert_eq!(block.cid(), &v1);
+                assert_eq!(block.data(), &data[..]);
+            }
+
+            #[tokio::test(max_threads = 1)]
+            async fn list_and_list_after() {
+                let store = BSTestContext::with($factory).await;
+
+                let mut cids = Vec::new();
+                for data in &[&b"a"[..], b"b", b"c"] {
+                    cids.push(store.put(raw(data)).await.unwrap().0);
+                }
+
+                let listed = store.list().await.try_collect::<Vec<_>>().await.unwrap();
+                let mut sorted = listed.clone();
+                sorted.sort_by_key(|cid| cid.to_string());
+                cids.sort_by_key(|cid| cid.to_string());
+                assert_eq!(sorted, cids);
+
+                let rest = store
+                    .list_after(listed[0].clone())
+                    .await
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap();
+                assert_eq!(rest, &listed[1..]);
+            }
+
+            #[tokio::test(max_threads = 1)]
+            async fn put_batch_in_order() {
+                let store = BSTestContext::with($factory).await;
+
+                let (a, b) = (raw(b"a"), raw(b"b"));
+                store.put(b.clone()).await.unwrap();
+
+                let results = store
+                    .put_batch(vec![a.clone(), b.clone(), a.clone()])
+                    .await
+                    .unwrap();
+
+                assert_eq!(
+                    results,
+                    vec![
+                        (a.cid().to_owned(), BlockPut::NewBlock),
+                        (b.cid().to_owned(), BlockPut::Existed),
+                        (a.cid().to_owned(), BlockPut::Existed),
+                    ]
+                );
+
+                assert_eq!(store.get(a.cid()).await.unwrap(), Some(a));
+            }
+        }
+    };
+}
+
+/// Generates the "common interface" tests for the [`crate::repo::Column`] operations of DataStore
+/// implementations as a given module using a factory method, see [`pinstore_interface_tests`].
+#[macro_export]
+macro_rules! column_interface_tests {
+    ($module_name:ident, $factory:expr) => {
+        #[cfg(test)]
+        mod $module_name {
+
+            use cid::Cid;
+            use std::convert::TryFrom;
+            use $crate::repo::common_tests::DSTestContext;
+            use $crate::repo::{Column, DataStore, PinStore};
+
+            #[tokio::test(max_threads = 1)]
+            async fn put_get_remove() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+                let key = b"/ipns/foo";
+
+                assert!(!store.contains(col, key).await.unwrap());
+                assert_eq!(store.get(col, key).await.unwrap(), None);
+                // removing a missing key is not an error
+                store.remove(col, key).await.unwrap();
+
+                store.put(col, key, b"first").await.unwrap();
+                assert!(store.contains(col, key).await.unwrap());
+                assert_eq!(store.get(col, key).await.unwrap(), Some(b"first".to_vec()));
+
+                store.put(col, key, b"second").await.unwrap();
+                assert_eq!(store.get(col, key).await.unwrap(), Some(b"second".to_vec()));
+
+                store.remove(col, key).await.unwrap();
+                assert!(!store.contains(col, key).await.unwrap());
+                assert_eq!(store.get(col, key).await.unwrap(), None);
+            }
+
+            #[tokio::test(max_threads = 1)]
+            async fn any_bytes_as_keys_and_values() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+
+                let keys: &[&[u8]] = &[
+                    b"",
+                    b"a",
+                    b"A",
+                    &[0, 255, b'/', b'.'],
+                    &[b'x'; 100],
+                    &[b'x'; 1000],
+                ];
+
+                for (i, key) in keys.iter().enumerate() {
+                    store.put(col, key, &[i as u8]).await.unwrap();
+                }
+                store.put(col, b"empty", b"").await.unwrap();
+
+                for (i, key) in keys.iter().enumerate() {
+                    assert_eq!(store.get(col, key).await.unwrap(), Some(vec![i as u8]));
+                }
+                assert_eq!(store.get(col, b"empty").await.unwrap(), Some(Vec::new()));
+                assert!(store.contains(col, b"empty").await.unwrap());
+            }
+
+            #[tokio::test(max_threads = 1)]
+            async fn wipe_removes_values_and_pins() {
+                let store = DSTestContext::with($factory).await;
+                let col = Column::Ipns;
+                let cid = Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+                store.put(col, b"key", b"value").await.unwrap();
+                store.insert_direct_pin(&cid).await.unwrap();
+
+                store.wipe().await;
+
+                assert!(!store.contains(col, b"key").await.unwrap());
+                assert!(!store.is_pinned(&cid).await.unwrap());
+
+                // still usable after the wipe
+                store.put(col, b"key", b"again").await.unwrap();
+                assert_eq!(
+                    store.get(col, b"key").await.unwrap(),
+                    Some(b"again".to_vec())
+                );
+                store.insert_direct_pin(&cid).await.unwrap();
+                assert!(store.is_pinned(&cid).await.unwrap());
+            }
+        }
+    };
+}
@@ -0,0 +1,734 @@
+//! Encryption of the block contents at rest in front of any [`BlockStore`].
+use super::{BlockFiles, BlockPut, BlockRm, BlockRmError, BlockStore, BlockStoreExtensions};
+use crate::error::Error;
+use crate::Block;
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use cid::{Cid, Codec};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The file under the repository directory recording how the blocks are encrypted.
+const METADATA_FILE: &str = "encryption.json";
+
+/// The version of the encrypted block format, the first byte of the stored blocks.
+const FORMAT_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+/// Known plaintext encrypted into the metadata to detect a wrong passphrase or key file when
+/// opening.
+const CHECK_PLAINTEXT: &[u8] = b"rust-ipfs encrypted block store";
+
+/// Where the key of the [`EncryptedBlockStore`] comes from.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// The key is derived from the passphrase with Argon2id, using a random salt stored in the
+    /// repository.
+    Passphrase(String),
+    /// The key is read from the file, which is created with a random key if it doesn't exist. A
+    /// relative path is relative to the repository directory.
+    KeyFile(PathBuf),
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionKey::Passphrase(_) => write!(fmt, "Passphrase(..)"),
+            EncryptionKey::KeyFile(path) => fmt.debug_tuple("KeyFile").field(path).finish(),
+        }
+    }
+}
+
+/// Configuration of the [`EncryptedBlockStore`].
+#[derive(Debug, Clone)]
+pub struct BlockEncryption {
+    pub key: EncryptionKey,
+    /// When true, the blocks are stored under a keyed hash of the Cid instead of the Cid, so that
+    /// the stored Cids cannot be seen without the key. Listing the blocks then requires reading
+    /// and decrypting all of them.
+    pub hash_paths: bool,
+}
+
+/// Contents of the [`METADATA_FILE`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    version: u8,
+    /// The base64 encoded salt of the passphrase, if the key is derived from one.
+    salt: Option<String>,
+    /// The base64 encoded [`CHECK_PLAINTEXT`] encrypted with the content key.
+    check: String,
+    hash_paths: bool,
+}
+
+/// The keys derived from the master key.
+struct Keys {
+    content: XChaCha20Poly1305,
+    /// The key of the keyed hash of the Cids, when the paths are hashed.
+    paths: Option<[u8; KEY_LEN]>,
+}
+
+/// Wraps a [`BlockStore`], encrypting the block contents with XChaCha20-Poly1305 before they
+/// are stored. The stored content is authenticated against the multihash it's stored under, so
+/// that blocks cannot be swapped around without being noticed.
+///
+/// The keys are loaded when the store is initialized or opened; until then all operations on the
+/// blocks fail. The wrapped store sees the blocks as opaque `raw` blocks, and with
+/// [`BlockEncryption::hash_paths`] even the Cids are replaced by keyed hashes.
+pub struct EncryptedBlockStore<S> {
+    inner: Arc<S>,
+    /// The directory of the metadata file and relative key files.
+    path: PathBuf,
+    encryption: BlockEncryption,
+    keys: RwLock<Option<Arc<Keys>>>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for EncryptedBlockStore<S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("EncryptedBlockStore")
+            .field("inner", &self.inner)
+            .field("path", &self.path)
+            .field("encryption", &self.encryption)
+            .finish()
+    }
+}
+
+impl<S: BlockStore> EncryptedBlockStore<S> {
+    /// Wraps the `inner` store, keeping the metadata of the encryption under the repository
+    /// directory `path`.
+    pub fn with_encryption(inner: S, path: PathBuf, encryption: BlockEncryption) -> Self {
+        EncryptedBlockStore {
+            inner: Arc::new(inner),
+            path,
+            encryption,
+            keys: Default::default(),
+        }
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The block files of the wrapped store, only reported when it has them.
+    fn inner_files(&self) -> &dyn BlockFiles {
+        self.inner
+            .extensions()
+            .files
+            .expect("reported only when the wrapped store has block files")
+    }
+
+    async fn load_keys(&self, create: bool) -> Result<(), Error> {
+        let path = self.path.clone();
+        let encryption = self.encryption.clone();
+        let keys =
+            tokio::task::spawn_blocking(move || load_keys(&path, &encryption, create)).await??;
+        *self.keys.write().unwrap() = Some(Arc::new(keys));
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Arc<Keys>, Error> {
+        self.keys
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("the encrypted block store has not been opened"))
+    }
+}
+
+impl Keys {
+    /// The Cid the block `cid` is stored under in the wrapped store.
+    fn stored_cid(&self, cid: &Cid) -> Cid {
+        match self.paths.as_ref() {
+            Some(key) => {
+                // the same for all Cid versions like the lookups in the other stores
+                let normalized = Cid::new_v1(cid.codec(), cid.hash().to_owned()).to_bytes();
+                let hash = blake2b_simd::Params::new()
+                    .hash_length(KEY_LEN)
+                    .key(key)
+                    .hash(&normalized);
+                let mh = multihash::wrap(multihash::Code::Blake2b256, hash.as_bytes());
+                Cid::new_v1(Codec::Raw, mh)
+            }
+            None => cid.to_owned(),
+        }
+    }
+
+    /// Encrypts the Cid and the content of the block to be stored under `stored`.
+    fn encrypt(&self, block: &Block, stored: &Cid) -> Result<Box<[u8]>, Error> {
+        let cid = block.cid().to_bytes();
+        let mut plaintext = Vec::with_capacity(2 + cid.len() + block.data().len());
+        plaintext.extend_from_slice(&(cid.len() as u16).to_be_bytes());
+        plaintext.extend_from_slice(&cid);
+        plaintext.extend_from_slice(block.data());
+
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let aad = stored.hash();
+        let ciphertext = self
+            .content
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("encrypting block {} failed", block.cid()))?;
+
+        let mut data = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        data.push(FORMAT_VERSION);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data.into_boxed_slice())
+    }
+
+    /// Decrypts the block stored under `stored`, returning the original Cid and content.
+    fn decrypt(&self, data: &[u8], stored: &Cid) -> Result<(Cid, Vec<u8>), Error> {
+        let invalid = || anyhow::anyhow!("stored block {} could not be decrypted", stored);
+
+        if data.len() < 1 + NONCE_LEN || data[0] != FORMAT_VERSION {
+            return Err(invalid());
+        }
+
+        let (nonce, ciphertext) = data[1..].split_at(NONCE_LEN);
+        let aad = stored.hash();
+        let mut plaintext = self
+            .content
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: aad.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid())?;
+
+        if plaintext.len() < 2 {
+            return Err(invalid());
+        }
+        let cid_len = u16::from_be_bytes([plaintext[0], plaintext[1]]) as usize;
+        if plaintext.len() < 2 + cid_len {
+            return Err(invalid());
+        }
+        let cid = Cid::try_from(&plaintext[2..2 + cid_len]).map_err(|_| invalid())?;
+        let content = plaintext.split_off(2 + cid_len);
+
+        Ok((cid, content))
+    }
+}
+
+/// Reads the metadata under `path`, or creates it if `create` is true and there is none, and
+/// derives the keys.
+fn load_keys(path: &Path, encryption: &BlockEncryption, create: bool) -> Result<Keys, Error> {
+    let metadata_path = path.join(METADATA_FILE);
+
+    let metadata = match std::fs::read(&metadata_path) {
+        Ok(json) => Some(serde_json::from_slice::<Metadata>(&json)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow::anyhow!(
+                "the blocks in {:?} are not encrypted, {:?} is missing",
+                path,
+                metadata_path
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if let Some(metadata) = metadata.as_ref() {
+        if metadata.version != FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "unsupported block encryption version {}",
+                metadata.version
+            ));
+        }
+        if metadata.hash_paths != encryption.hash_paths {
+            return Err(anyhow::anyhow!(
+                "the blocks in {:?} are stored with hash_paths = {}",
+                path,
+                metadata.hash_paths
+            ));
+        }
+    }
+
+    let master = match &encryption.key {
+        EncryptionKey::Passphrase(passphrase) => {
+            let salt = match metadata.as_ref().and_then(|m| m.salt.as_ref()) {
+                Some(salt) => base64::decode(salt)?,
+                None if metadata.is_none() => {
+                    let mut salt = vec![0u8; 16];
+                    OsRng.fill_bytes(&mut salt);
+                    salt
+                }
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "the blocks are not encrypted by passphrase"
+                    ))
+                }
+            };
+            derive_from_passphrase(passphrase, &salt)
+                .map(|key| (key, Some(base64::encode(&salt))))?
+        }
+        EncryptionKey::KeyFile(key_file) => (read_key_file(&path.join(key_file), create)?, None),
+    };
+
+    let (master, salt) = master;
+    let keys = Keys {
+        content: XChaCha20Poly1305::new(Key::from_slice(&subkey(&master, b"content"))),
+        paths: if encryption.hash_paths {
+            Some(subkey(&master, b"paths"))
+        } else {
+            None
+        },
+    };
+
+    let check_cid = Cid::new_v1(Codec::Raw, multihash::Sha2_256::digest(CHECK_PLAINTEXT));
+
+    match metadata {
+        Some(metadata) => {
+            let check = base64::decode(&metadata.check)?;
+            match keys.decrypt(&check, &check_cid) {
+                Ok((_, plaintext)) if plaintext == CHECK_PLAINTEXT => {}
+                _ => return Err(anyhow::anyhow!("wrong passphrase or key file")),
+            }
+        }
+        None => {
+            let check = Block::new(CHECK_PLAINTEXT.into(), check_cid.clone());
+            let metadata = Metadata {
+                version: FORMAT_VERSION,
+                salt,
+                check: base64::encode(&keys.encrypt(&check, &check_cid)?),
+                hash_paths: encryption.hash_paths,
+            };
+            std::fs::create_dir_all(path)?;
+            let temp = metadata_path.with_extension("json.tmp");
+            std::fs::write(&temp, serde_json::to_vec(&metadata)?)?;
+            std::fs::rename(temp, metadata_path)?;
+        }
+    }
+
+    Ok(keys)
+}
+
+fn derive_from_passphrase(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], Error> {
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        hash_length: KEY_LEN as u32,
+        ..Default::default()
+    };
+    let hash = argon2::hash_raw(passphrase.as_bytes(), salt, &config)?;
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&hash);
+    Ok(key)
+}
+
+/// Reads the key from the file, creating the file with a random key if allowed.
+fn read_key_file(path: &Path, create: bool) -> Result<[u8; KEY_LEN], Error> {
+    let mut key = [0u8; KEY_LEN];
+
+    match std::fs::read(path) {
+        Ok(bytes) if bytes.len() == KEY_LEN => {
+            key.copy_from_slice(&bytes);
+            return Ok(key);
+        }
+        Ok(bytes) => {
+            return Err(anyhow::anyhow!(
+                "key file {:?} has {} bytes instead of {}",
+                path,
+                bytes.len(),
+                KEY_LEN
+            ))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    use std::io::Write;
+
+    OsRng.fill_bytes(&mut key);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(&key)?;
+    file.sync_all()?;
+
+    Ok(key)
+}
+
+fn subkey(master: &[u8; KEY_LEN], purpose: &[u8]) -> [u8; KEY_LEN] {
+    let hash = blake2b_simd::Params::new()
+        .hash_length(KEY_LEN)
+        .key(master)
+        .personal(purpose)
+        .hash(&[]);
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(hash.as_bytes());
+    key
+}
+
+fn map_rm(
+    res: Result<BlockRm, BlockRmError>,
+    cid: &Cid,
+) -> Result<Result<BlockRm, BlockRmError>, Error> {
+    Ok(match res {
+        Ok(BlockRm::Removed(_)) => Ok(BlockRm::Removed(cid.to_owned())),
+        Err(BlockRmError::NotFound(_)) => Err(BlockRmError::NotFound(cid.to_owned())),
+    })
+}
+
+/// Maps the listing of the wrapped store back to the original Cids.
+fn original_cids<S: BlockStore>(
+    inner: Arc<S>,
+    keys: Arc<Keys>,
+    listing: BoxStream<'static, Result<Cid, Error>>,
+) -> BoxStream<'static, Result<Cid, Error>> {
+    if keys.paths.is_none() {
+        return listing;
+    }
+
+    listing
+        .try_filter_map(move |stored| {
+            let inner = Arc::clone(&inner);
+            let keys = Arc::clone(&keys);
+            async move {
+                match inner.get(&stored).await? {
+                    Some(block) => Ok(Some(keys.decrypt(block.data(), &stored)?.0)),
+                    // removed after listing
+                    None => Ok(None),
+                }
+            }
+        })
+        .boxed()
+}
+
+#[async_trait]
+impl<S: BlockStore> BlockStore for EncryptedBlockStore<S> {
+    /// Wraps the store created at `path`, with a key file `encryption.key` created in the same
+    /// directory.
+    fn new(path: PathBuf) -> Self {
+        let encryption = BlockEncryption {
+            key: EncryptionKey::KeyFile(PathBuf::from("encryption.key")),
+            hash_paths: false,
+        };
+        Self::with_encryption(S::new(path.clone()), path, encryption)
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.inner.is_persistent()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        self.inner.init().await?;
+        self.load_keys(true).await
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        self.inner.open().await?;
+        self.load_keys(false).await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        let stored = self.keys()?.stored_cid(cid);
+        self.inner.contains(&stored).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        let keys = self.keys()?;
+        let stored = keys.stored_cid(cid);
+
+        let block = match self.inner.get(&stored).await? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
+        let (original, data) = keys.decrypt(block.data(), &stored)?;
+        if original.hash() != cid.hash() {
+            return Err(anyhow::anyhow!(
+                "stored block {} contains {} instead",
+                cid,
+                original
+            ));
+        }
+
+        Ok(Some(Block::new(data.into_boxed_slice(), cid.to_owned())))
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        let keys = self.keys()?;
+        let stored = keys.stored_cid(block.cid());
+        let data = keys.encrypt(&block, &stored)?;
+
+        let (_, res) = self.inner.put(Block::new(data, stored)).await?;
+        Ok((block.cid, res))
+    }
+
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let keys = self.keys()?;
+        let mut cids = Vec::with_capacity(blocks.len());
+        let mut encrypted = Vec::with_capacity(blocks.len());
+
+        for block in blocks {
+            let stored = keys.stored_cid(block.cid());
+            encrypted.push(Block::new(keys.encrypt(&block, &stored)?, stored));
+            cids.push(block.cid);
+        }
+
+        let results = self.inner.put_batch(encrypted).await?;
+        Ok(cids
+            .into_iter()
+            .zip(results)
+            .map(|(cid, (_, res))| (cid, res))
+            .collect())
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        let stored = self.keys()?.stored_cid(cid);
+        map_rm(self.inner.remove(&stored).await?, cid)
+    }
+
+    /// Lists the original Cids; with hashed paths each block is read and decrypted to find out
+    /// its Cid.
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        let keys = match self.keys() {
+            Ok(keys) => keys,
+            Err(e) => return futures::stream::once(async move { Err(e) }).boxed(),
+        };
+        let listing = self.inner.list().await;
+        original_cids(Arc::clone(&self.inner), keys, listing)
+    }
+
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        let keys = match self.keys() {
+            Ok(keys) => keys,
+            Err(e) => return futures::stream::once(async move { Err(e) }).boxed(),
+        };
+        let listing = self.inner.list_after(keys.stored_cid(&cursor)).await;
+        original_cids(Arc::clone(&self.inner), keys, listing)
+    }
+
+    async fn size(&self) -> Result<u64, Error> {
+        self.inner.size().await
+    }
+
+    /// The file references are not supported, as the referenced files are not encrypted.
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        let inner = self.inner.extensions();
+        BlockStoreExtensions {
+            references: None,
+            files: inner.files.map(|_| self as &dyn BlockFiles),
+            compression: inner.compression,
+            cache: inner.cache,
+        }
+    }
+
+    async fn wipe(&self) {
+        self.inner.wipe().await
+    }
+}
+
+#[async_trait]
+impl<S: BlockStore> BlockFiles for EncryptedBlockStore<S> {
+    async fn quarantine(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        let stored = self.keys()?.stored_cid(cid);
+        map_rm(self.inner_files().quarantine(&stored).await?, cid)
+    }
+
+    async fn list_orphans(&self) -> Result<Vec<PathBuf>, Error> {
+        self.inner_files().list_orphans().await
+    }
+
+    async fn remove_orphan(&self, path: &Path, quarantine: bool) -> Result<(), Error> {
+        self.inner_files().remove_orphan(path, quarantine).await
+    }
+}
+
+#[cfg(test)]
+crate::blockstore_interface_tests!(
+    common_tests,
+    crate::repo::encrypted::EncryptedBlockStore::<crate::repo::mem::MemBlockStore>::new
+);
+
+#[cfg(test)]
+crate::blockstore_interface_tests!(hashed_common_tests, |path: std::path::PathBuf| {
+    crate::repo::encrypted::EncryptedBlockStore::with_encryption(
+        crate::repo::fs::FsBlockStore::new(path.clone()),
+        path,
+        crate::repo::encrypted::BlockEncryption {
+            key: crate::repo::encrypted::EncryptionKey::KeyFile("keys/block.key".into()),
+            hash_paths: true,
+        },
+    )
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::FsBlockStore;
+    use crate::repo::mem::MemBlockStore;
+    use crate::test_support::raw;
+    use futures::stream::TryStreamExt;
+    use tempfile::TempDir;
+
+    fn passphrase(path: &Path, passphrase: &str) -> EncryptedBlockStore<FsBlockStore> {
+        let encryption = BlockEncryption {
+            key: EncryptionKey::Passphrase(passphrase.to_owned()),
+            hash_paths: true,
+        };
+        EncryptedBlockStore::with_encryption(
+            FsBlockStore::new(path.join("blocks")),
+            path.to_owned(),
+            encryption,
+        )
+    }
+
+    /// Returns the names and contents of all of the files under `path`.
+    fn read_all(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(read_all(&path));
+            } else {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                files.push((name, std::fs::read(&path).unwrap()));
+            }
+        }
+        files
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn nothing_is_stored_in_plain() {
+        let tmp = TempDir::new().unwrap();
+        let store = passphrase(tmp.path(), "correct horse");
+        store.init().await.unwrap();
+
+        let block = raw(b"a very secret block");
+        store.put(block.clone()).await.unwrap();
+
+        let files = read_all(tmp.path());
+        let cid = block.cid().to_string();
+        assert!(files.len() >= 2);
+        for (name, content) in &files {
+            assert!(!name.contains(&cid), "{} leaks the cid", name);
+            assert!(
+                !content
+                    .windows(block.data().len())
+                    .any(|w| w == block.data()),
+                "{} leaks the content",
+                name
+            );
+        }
+
+        let listed = store.list().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(listed, vec![block.cid().to_owned()]);
+        assert_eq!(store.get(block.cid()).await.unwrap(), Some(block));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn wrong_passphrase_is_refused() {
+        let tmp = TempDir::new().unwrap();
+        let block = raw(b"foobar");
+
+        let store = passphrase(tmp.path(), "correct horse");
+        store.init().await.unwrap();
+        store.put(block.clone()).await.unwrap();
+        drop(store);
+
+        let store = passphrase(tmp.path(), "battery staple");
+        let e = store.open().await.unwrap_err();
+        assert_eq!(e.to_string(), "wrong passphrase or key file");
+        store.get(block.cid()).await.unwrap_err();
+
+        let store = passphrase(tmp.path(), "correct horse");
+        store.open().await.unwrap();
+        assert_eq!(store.get(block.cid()).await.unwrap(), Some(block));
+
+        // the paths must be hashed the same way as before
+        let store = EncryptedBlockStore::with_encryption(
+            FsBlockStore::new(tmp.path().join("blocks")),
+            tmp.path().to_owned(),
+            BlockEncryption {
+                key: EncryptionKey::Passphrase("correct horse".into()),
+                hash_paths: false,
+            },
+        );
+        store.open().await.unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn swapped_blocks_are_detected() {
+        let tmp = TempDir::new().unwrap();
+        let store = EncryptedBlockStore::<MemBlockStore>::new(tmp.path().to_owned());
+        store.init().await.unwrap();
+
+        let (a, b) = (raw(b"a"), raw(b"b"));
+        store.put(a.clone()).await.unwrap();
+        store.put(b.clone()).await.unwrap();
+
+        // replace the stored a with the encrypted b
+        let encrypted_b = store.inner().get(b.cid()).await.unwrap().unwrap();
+        store.inner().remove(a.cid()).await.unwrap().unwrap();
+        store
+            .inner()
+            .put(Block::new(encrypted_b.data().into(), a.cid().to_owned()))
+            .await
+            .unwrap();
+
+        store.get(a.cid()).await.unwrap_err();
+        assert_eq!(store.get(b.cid()).await.unwrap(), Some(b));
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn selected_through_repo_options() {
+        use crate::repo::{Repo, RepoOptions};
+
+        let tmp = TempDir::new().unwrap();
+        let options = RepoOptions {
+            path: tmp.path().to_owned(),
+            offline: true,
+            storage_max: None,
+            storage_high_water_mark: 100,
+            migrations: Default::default(),
+            block_encryption: Some(BlockEncryption {
+                key: EncryptionKey::KeyFile("block.key".into()),
+                hash_paths: false,
+            }),
+            block_cache: None,
+            block_compression: Default::default(),
+        };
+
+        let (repo, _) = Repo::<crate::TestTypes>::new(options);
+        repo.init().await.unwrap();
+
+        let block = raw(b"foobar");
+        repo.put_block(block.clone()).await.unwrap();
+        assert_eq!(repo.get_block_now(block.cid()).await.unwrap(), Some(block));
+
+        assert_eq!(
+            std::fs::metadata(tmp.path().join("block.key"))
+                .unwrap()
+                .len(),
+            KEY_LEN as u64
+        );
+        assert!(tmp.path().join(METADATA_FILE).is_file());
+    }
+}
@@ -0,0 +1,132 @@
+//! Filestore allowing the `raw` leaf blocks of added files to be stored as references to the
+//! original files instead of copies of the content ("nocopy" adds).
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Location of the content of a `raw` block within a file on the local filesystem.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileReference {
+    /// Absolute path to the file.
+    pub path: PathBuf,
+    /// Offset of the block content in bytes from the start of the file.
+    pub offset: u64,
+    /// Length of the block content in bytes.
+    pub length: u64,
+}
+
+/// The outcome of reading the block content referenced by a [`FileReference`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilestoreStatus {
+    /// The content is available and matches the block.
+    Ok,
+    /// The referenced file does not exist anymore.
+    FileNotFound,
+    /// The referenced file exists but the content has been modified or truncated.
+    FileChanged,
+    /// The referenced file could not be read.
+    FileError(String),
+}
+
+impl FilestoreStatus {
+    /// The status code as used by go-ipfs in the `filestore/ls` and `filestore/verify` responses.
+    pub fn code(&self) -> u8 {
+        match self {
+            FilestoreStatus::Ok => 0,
+            FilestoreStatus::FileError(_) => 10,
+            FilestoreStatus::FileNotFound => 11,
+            FilestoreStatus::FileChanged => 12,
+        }
+    }
+}
+
+impl fmt::Display for FilestoreStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilestoreStatus::Ok => write!(fmt, "ok"),
+            FilestoreStatus::FileNotFound => write!(fmt, "no such file"),
+            FilestoreStatus::FileChanged => write!(fmt, "file content has changed"),
+            FilestoreStatus::FileError(e) => write!(fmt, "reading the file failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FilestoreStatus {}
+
+/// Reads the referenced content and verifies it against the multihash of the `cid`. This blocks
+/// the current thread on file system operations.
+pub(crate) fn read_reference(
+    cid: &Cid,
+    reference: &FileReference,
+) -> Result<Vec<u8>, FilestoreStatus> {
+    let mut file = match std::fs::File::open(&reference.path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(FilestoreStatus::FileNotFound)
+        }
+        Err(e) => return Err(FilestoreStatus::FileError(e.to_string())),
+    };
+
+    let mut data = Vec::with_capacity(reference.length as usize);
+    file.seek(SeekFrom::Start(reference.offset))
+        .and_then(|_| file.take(reference.length).read_to_end(&mut data))
+        .map_err(|e| FilestoreStatus::FileError(e.to_string()))?;
+
+    if data.len() as u64 != reference.length || !super::digest_matches(cid, &data) {
+        return Err(FilestoreStatus::FileChanged);
+    }
+
+    Ok(data)
+}
+
+/// Checks whether the referenced content still matches the block.
+pub(crate) async fn verify_reference(cid: Cid, reference: FileReference) -> FilestoreStatus {
+    let res = tokio::task::spawn_blocking(move || read_reference(&cid, &reference)).await;
+    match res {
+        Ok(Ok(_)) => FilestoreStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(e) => FilestoreStatus::FileError(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use multihash::Sha2_256;
+
+    #[test]
+    fn reports_missing_and_changed_files() {
+        let path = std::env::temp_dir().join("filestore_reference.txt");
+        std::fs::write(&path, b"foobar\n").unwrap();
+
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(b"bar"));
+        let reference = FileReference {
+            path: path.clone(),
+            offset: 3,
+            length: 3,
+        };
+
+        assert_eq!(read_reference(&cid, &reference).unwrap(), b"bar");
+
+        std::fs::write(&path, b"foobaz\n").unwrap();
+        assert_eq!(
+            read_reference(&cid, &reference),
+            Err(FilestoreStatus::FileChanged)
+        );
+
+        std::fs::write(&path, b"foo").unwrap();
+        assert_eq!(
+            read_reference(&cid, &reference),
+            Err(FilestoreStatus::FileChanged)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            read_reference(&cid, &reference),
+            Err(FilestoreStatus::FileNotFound)
+        );
+    }
+}
@@ -5,38 +5,65 @@
 use crate::error::Error;
 use async_trait::async_trait;
 use std::path::PathBuf;
-use std::sync::{atomic::AtomicU64, Arc};
+use std::sync::{atomic::AtomicU64, Arc, Mutex, RwLock};
 use tokio::sync::Semaphore;
 
 use super::{BlockRm, BlockRmError, Column, DataStore, RepoCid};
+use pin_index::{IndexFiles, PinIndex};
 
 /// The PinStore implementation for FsDataStore
 mod pinstore;
 
+/// The index of the pins of FsDataStore
+mod pin_index;
+pub(crate) use pin_index::{convert_json_index, index_pin_files};
+
 /// The FsBlockStore implementation
 mod blocks;
 pub use blocks::FsBlockStore;
 
+/// The optional compression of the FsBlockStore files
+mod compression;
+pub use compression::Compression;
+
+/// The FlatfsBlockStore implementation
+mod flatfs;
+pub use flatfs::FlatfsBlockStore;
+
 /// Path mangling done for pins and blocks
 mod paths;
-use paths::{block_path, filestem_to_block_cid, filestem_to_pin_cid, pin_path};
-
-/// FsDataStore which uses the filesystem as a lockable key-value store. Maintains a similar to
-/// [`FsBlockStore`] sharded two level storage. Direct have empty files, recursive pins record all of
-/// their indirect descendants. Pin files are separated by their file extensions.
+use paths::{
+    block_path, column_path, filestem_to_block_cid, filestem_to_flatfs_cid, filestem_to_pin_cid,
+    flatfs_block_path,
+};
+
+/// FsDataStore which uses the filesystem as a lockable key-value store. The pins are kept in an
+/// index with the reference counts of the indirectly pinned blocks, which is loaded into memory
+/// on open and journaled to the disk on each change, see `fs/pin_index.rs`.
+///
+/// The [`Column`] values are stored as files in a directory per column next to the pins, written
+/// through a tempfile which is renamed into place.
 ///
 /// When modifying, single lock is used.
 ///
 /// For the [`crate::repo::PinStore`] implementation see `fs/pinstore.rs`.
 #[derive(Debug)]
 pub struct FsDataStore {
-    /// The base directory under which we have a sharded directory structure, and the individual
-    /// blocks are stored under the shard. See unixfs/examples/cat.rs for read example.
+    /// The directory of the pin index.
     path: PathBuf,
 
+    /// The pins, loaded on init or open.
+    pins: Arc<RwLock<PinIndex>>,
+
+    /// The files of the pin index, only modified while holding the `lock`.
+    pin_files: Arc<Mutex<Option<IndexFiles>>>,
+
+    /// The base directory of the column directories.
+    columns: PathBuf,
+
     /// Start with simple, conservative solution, allows concurrent queries but single writer.
-    /// It is assumed the reads do not require permit as non-empty writes are done through
-    /// tempfiles and the consistency regarding reads is not a concern right now. For garbage
+    /// The reads do not require permit as the pins are changed in memory only after the change
+    /// has been journaled, and the column values are written through tempfiles. For garbage
     /// collection implementation, it might be needed to hold this permit for the duration of
     /// garbage collection, or something similar.
     lock: Arc<Semaphore>,
@@ -45,48 +72,171 @@ pub struct FsDataStore {
     written_bytes: AtomicU64,
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
 #[async_trait]
 impl DataStore for FsDataStore {
-    fn new(mut root: PathBuf) -> Self {
-        root.push("pins");
+    fn new(root: PathBuf) -> Self {
         FsDataStore {
-            path: root,
+            path: root.join("pins"),
+            columns: root.join("columns"),
+            pins: Default::default(),
+            pin_files: Default::default(),
             lock: Arc::new(Semaphore::new(1)),
             written_bytes: Default::default(),
         }
     }
 
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
     async fn init(&self) -> Result<(), Error> {
-        tokio::fs::create_dir_all(&self.path).await?;
-        Ok(())
+        tokio::fs::create_dir_all(&self.columns).await?;
+        self.load_pins().await
     }
 
     async fn open(&self) -> Result<(), Error> {
-        Ok(())
+        self.load_pins().await
     }
 
-    async fn contains(&self, _col: Column, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::metadata(path).await {
+            Ok(m) => Ok(m.is_file()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn get(&self, _col: Column, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::read(path).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn put(&self, _col: Column, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await;
+
+        let path = column_path(self.columns.clone(), col, key);
+        let value = value.to_owned();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _entered = span.enter();
+
+            std::fs::create_dir_all(path.parent().expect("column directory has to exist"))?;
+
+            let temp = path.with_extension("tmp");
+            match sync_write_value(&temp, &value) {
+                Ok(()) => {
+                    std::fs::rename(&temp, &path)?;
+                    // the rename is only durable once the directory has been synced; directories
+                    // cannot be opened for syncing on all platforms
+                    #[cfg(unix)]
+                    std::fs::File::open(path.parent().expect("column directory has to exist"))?
+                        .sync_all()?;
+                }
+                Err(e) => {
+                    if let Err(e) = std::fs::remove_file(&temp) {
+                        warn!("failed to cleanup temporary file {:?}: {}", temp, e);
+                    }
+                    return Err(e);
+                }
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await??;
+
+        Ok(())
     }
 
-    async fn remove(&self, _col: Column, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
+        let _permit = self.lock.acquire().await;
+
+        let path = column_path(self.columns.clone(), col, key);
+
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn wipe(&self) {
-        todo!()
+        let _permit = self.lock.acquire().await;
+
+        *self.pins.write().unwrap() = Default::default();
+
+        if let Some(files) = self.pin_files.lock().unwrap().as_mut() {
+            if let Err(e) = files.wipe() {
+                warn!("failed to wipe the pin index: {}", e);
+            }
+        }
+
+        match tokio::fs::remove_dir_all(&self.columns).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to wipe {:?}: {}", self.columns, e),
+        }
+
+        if let Err(e) = tokio::fs::create_dir_all(&self.columns).await {
+            warn!("failed to recreate {:?} after wipe: {}", self.columns, e);
+        }
     }
 }
 
+/// Writes the column value to the temporary file, syncing it before it is renamed into place.
+fn sync_write_value(path: &std::path::Path, value: &[u8]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(value)?;
+    file.sync_all()?;
+    Ok(())
+}
+
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::fs::FsDataStore::new);
+
+#[cfg(test)]
+crate::column_interface_tests!(column_common_tests, crate::repo::fs::FsDataStore::new);
+
+#[cfg(test)]
+crate::blockstore_interface_tests!(block_common_tests, crate::repo::fs::FsBlockStore::new);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test(max_threads = 1)]
+    async fn column_values_are_persistent() {
+        let tmp = TempDir::new().unwrap();
+
+        let store = FsDataStore::new(tmp.path().to_owned());
+        store.init().await.unwrap();
+        store.put(Column::Ipns, b"key", b"value").await.unwrap();
+        drop(store);
+
+        let store = FsDataStore::new(tmp.path().to_owned());
+        store.open().await.unwrap();
+        assert_eq!(
+            store.get(Column::Ipns, b"key").await.unwrap(),
+            Some(b"value".to_vec())
+        );
+
+        // only the value is left behind, no temporary files
+        let files = std::fs::read_dir(tmp.path().join("columns").join("ipns"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec![std::ffi::OsString::from("bnnsxs")]);
+    }
+}
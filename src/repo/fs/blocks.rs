@@ -1,11 +1,18 @@
-use super::{block_path, filestem_to_block_cid};
+use super::compression::{self, Compression};
+use super::{block_path, filestem_to_block_cid, filestem_to_flatfs_cid, flatfs_block_path};
 use super::{BlockRm, BlockRmError, RepoCid};
 use crate::error::Error;
-use crate::repo::{BlockPut, BlockStore};
+use crate::repo::filestore::read_reference;
+use crate::repo::{
+    BlockCompression, BlockFiles, BlockPut, BlockStore, BlockStoreExtensions, FileReference,
+    FileReferences,
+};
 use crate::Block;
 use async_trait::async_trait;
 use cid::Cid;
-use std::collections::HashMap;
+use futures::stream::{BoxStream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::hash::Hash;
 use std::io::Read;
 use std::path::PathBuf;
@@ -32,9 +39,42 @@ pub struct FsBlockStore {
     /// Since this is a broadcast channel, the late arriving receiver might not get any messages.
     writes: ArcMutexMap<RepoCid, broadcast::Sender<Result<(), ()>>>,
 
-    /// Initially used to demonstrate a bug, not really needed anymore. Could be used as a basis
-    /// for periodic synching to disk to know much space we have used.
+    /// The total size of the stored blocks. Initialized by scanning the directory in
+    /// `FsBlockStore::init`, and kept up to date on writes and removals.
     written_bytes: AtomicU64,
+
+    /// The compression of the written block files. Files are read regardless of how they were
+    /// written.
+    compression: Mutex<Compression>,
+
+    /// How the block files are named and sharded under `path`.
+    layout: BlockLayout,
+}
+
+/// The naming and sharding of the block files under [`FsBlockStore::path`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BlockLayout {
+    /// Files are named by the base32 CIDv1, see `block_path`.
+    Cidv1,
+    /// go-ipfs flatfs `next-to-last/2` layout where files are named by the base32 multihash, see
+    /// `flatfs_block_path`. The codec is not stored, making the blocks codec-agnostic.
+    Multihash,
+}
+
+impl BlockLayout {
+    fn block_path(self, base: PathBuf, cid: &Cid) -> PathBuf {
+        match self {
+            BlockLayout::Cidv1 => block_path(base, cid),
+            BlockLayout::Multihash => flatfs_block_path(base, cid),
+        }
+    }
+
+    fn filestem_to_cid(self, file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
+        match self {
+            BlockLayout::Cidv1 => filestem_to_block_cid(file_stem),
+            BlockLayout::Multihash => filestem_to_flatfs_cid(file_stem),
+        }
+    }
 }
 
 /// A helper used to remove our key from `FsBlockStore::writes`. It is quite inefficient, some
@@ -73,6 +113,93 @@ enum WriteCompletion {
 }
 
 impl FsBlockStore {
+    /// Creates a block store keyed only by the multihash of the blocks, in contrast to the default
+    /// which keys the blocks by the CIDv1 including the codec. Blocks with the same multihash are
+    /// stored only once and can be found with any Cid version and codec. Listing reports the blocks
+    /// as `raw` CIDv1.
+    ///
+    /// The files are named and sharded like in the go-ipfs flatfs `next-to-last/2` layout, see
+    /// [`super::FlatfsBlockStore`] for full compatibility.
+    pub fn with_multihash_keys(path: PathBuf) -> Self {
+        Self::with_layout(path, BlockLayout::Multihash)
+    }
+
+    pub(super) fn with_layout(path: PathBuf, layout: BlockLayout) -> Self {
+        FsBlockStore {
+            path,
+            writes: Arc::new(Mutex::new(HashMap::with_capacity(8))),
+            written_bytes: Default::default(),
+            compression: Mutex::new(Compression::None),
+            layout,
+        }
+    }
+
+    /// Compresses the block files written from now on, see [`Compression`]. Files written
+    /// without compression remain readable, as do the compressed files if the compression is
+    /// later turned off.
+    pub fn compressed(self, compression: Compression) -> Self {
+        self.set_compression(compression);
+        self
+    }
+
+    pub(super) fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Blocks stored with [`FileReferences::put_reference`] are stored as `.ref` files next to
+    /// where the `.data` file would be.
+    fn reference_path(&self, cid: &Cid) -> PathBuf {
+        self.layout
+            .block_path(self.path.clone(), cid)
+            .with_extension("ref")
+    }
+
+    /// Lists the blocks in the shard directories in order, skipping everything up to and including
+    /// the `cursor` if given. The position of the `cursor` is known from its path, so it doesn't
+    /// need to exist anymore.
+    fn list_from(&self, cursor: Option<Cid>) -> BoxStream<'static, Result<Cid, Error>> {
+        let path = self.path.clone();
+        let layout = self.layout;
+        let span = tracing::trace_span!("listing blocks");
+
+        let cursor = cursor.map(|cid| {
+            let path = layout.block_path(self.path.clone(), &cid);
+            let stem = path.file_stem().unwrap_or_default().to_owned();
+            let shard = path
+                .parent()
+                .and_then(|shard| shard.file_name())
+                .unwrap_or_default()
+                .to_owned();
+            (shard, stem)
+        });
+
+        let stream = async_stream::try_stream! {
+            let shards = tokio::task::spawn_blocking(move || list_shards(&path)).await??;
+
+            for shard in shards {
+                let name = shard.file_name().unwrap_or_default().to_owned();
+
+                let skip_to = match cursor.as_ref() {
+                    Some((cursor_shard, _)) if &name < cursor_shard => continue,
+                    Some((cursor_shard, stem)) if &name == cursor_shard => Some(stem.to_owned()),
+                    _ => None,
+                };
+
+                let blocks =
+                    tokio::task::spawn_blocking(move || list_shard(&shard, layout)).await??;
+
+                for (stem, cid) in blocks {
+                    if skip_to.as_ref().map(|skip_to| &stem <= skip_to).unwrap_or(false) {
+                        continue;
+                    }
+                    yield cid;
+                }
+            }
+        };
+
+        stream.instrument(span).boxed()
+    }
+
     /// Returns the same Cid in either case. Ok variant is returned in case it is suspected the
     /// write completed successfully or there was never any write ongoing. Err variant is returned
     /// if it's known that the write failed.
@@ -105,33 +232,40 @@ impl FsBlockStore {
 #[async_trait]
 impl BlockStore for FsBlockStore {
     fn new(path: PathBuf) -> Self {
-        FsBlockStore {
-            path,
-            //cids: Default::default(),
-            writes: Arc::new(Mutex::new(HashMap::with_capacity(8))),
-            written_bytes: Default::default(),
-        }
+        Self::with_layout(path, BlockLayout::Cidv1)
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
     }
 
     async fn init(&self) -> Result<(), Error> {
         fs::create_dir_all(self.path.clone()).await?;
-        Ok(())
+        self.open().await
     }
 
     async fn open(&self) -> Result<(), Error> {
-        // TODO: we probably want to cache the space usage?
+        let path = self.path.clone();
+        let size = tokio::task::spawn_blocking(move || disk_usage(&path)).await??;
+        self.written_bytes.store(size, Ordering::SeqCst);
         Ok(())
     }
 
     async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.layout.block_path(self.path.clone(), cid);
 
         // why doesn't this synchronize with the rest? Not sure if there is any use for this method
         // actually. When does it matter if a block exists, except for testing.
 
         let metadata = match fs::metadata(path).await {
             Ok(m) => m,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                match fs::metadata(self.reference_path(cid)).await {
+                    Ok(m) => m,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                }
+            }
             Err(e) => return Err(e.into()),
         };
 
@@ -146,7 +280,8 @@ impl BlockStore for FsBlockStore {
                 return Ok(None);
             }
 
-            let path = block_path(self.path.clone(), cid);
+            let path = self.layout.block_path(self.path.clone(), cid);
+            let reference_path = self.reference_path(cid);
 
             let cid = cid.to_owned();
 
@@ -155,7 +290,9 @@ impl BlockStore for FsBlockStore {
             tokio::task::spawn_blocking(move || {
                 let mut file = match std::fs::File::open(path) {
                     Ok(file) => file,
-                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return get_referenced(cid, reference_path);
+                    }
                     Err(e) => {
                         return Err(e.into());
                     }
@@ -165,6 +302,9 @@ impl BlockStore for FsBlockStore {
 
                 let mut data = Vec::with_capacity(len as usize);
                 file.read_to_end(&mut data)?;
+                let data = compression::decode(data, &cid).map_err(|e| {
+                    anyhow::anyhow!("failed to decode the block file of {}: {}", cid, e)
+                })?;
                 let block = Block::new(data.into_boxed_slice(), cid);
                 Ok(Some(block))
             })
@@ -179,9 +319,10 @@ impl BlockStore for FsBlockStore {
 
         let span = tracing::trace_span!("put block", cid = %block.cid());
 
-        let target_path = block_path(self.path.clone(), &block.cid());
+        let target_path = self.layout.block_path(self.path.clone(), &block.cid());
         let cid = block.cid;
         let data = block.data;
+        let compression = *self.compression.lock().unwrap();
 
         let inner_span = debug_span!(parent: &span, "blocking");
 
@@ -235,10 +376,15 @@ impl BlockStore for FsBlockStore {
 
                 let temp_path = target_path.with_extension("tmp");
 
-                match write_through_tempfile(target, &target_path, temp_path, &data) {
-                    Ok(()) => {
+                let written = compression::encode(&data, compression).and_then(|file| {
+                    write_through_tempfile(target, &target_path, temp_path, &file)
+                        .map(|_| file.len())
+                });
+
+                match written {
+                    Ok(written) => {
                         trace!("successfully wrote the block");
-                        Ok::<_, std::io::Error>(Ok(data.len()))
+                        Ok::<_, std::io::Error>(Ok(written))
                     }
                     Err(e) => {
                         match std::fs::remove_file(&target_path) {
@@ -333,8 +479,103 @@ impl BlockStore for FsBlockStore {
         .await
     }
 
+    /// Writes all of the blocks not being written already by others in one blocking task, syncing
+    /// them and their shard directories once after all of them have been written. On failure
+    /// none of those blocks are left behind.
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        use std::collections::hash_map::Entry;
+
+        let span = tracing::trace_span!("put batch", blocks = blocks.len());
+        let inner_span = debug_span!(parent: &span, "blocking");
+        let compression = *self.compression.lock().unwrap();
+
+        let mut results = blocks.iter().map(|_| None).collect::<Vec<_>>();
+        let mut ours = Vec::new();
+        let mut contended = Vec::new();
+        let mut cleanups = Vec::new();
+        let mut files = Vec::new();
+
+        {
+            let mut g = self.writes.lock().expect("cant support poisoned");
+
+            for (i, block) in blocks.into_iter().enumerate() {
+                match g.entry(RepoCid(block.cid.clone())) {
+                    Entry::Occupied(_) => {
+                        // synchronize with the other writer, or an earlier copy in this batch
+                        contended.push((i, block));
+                    }
+                    Entry::Vacant(ve) => {
+                        let (tx, _) = broadcast::channel(1);
+                        ve.insert(tx.clone());
+                        cleanups.push(RemoveOnDrop(
+                            self.writes.clone(),
+                            Some(RepoCid(block.cid.clone())),
+                        ));
+                        files.push((
+                            self.layout.block_path(self.path.clone(), &block.cid),
+                            block.data,
+                        ));
+                        ours.push((i, block.cid, tx));
+                    }
+                }
+            }
+        }
+
+        async move {
+            let written = tokio::task::spawn_blocking(move || {
+                let _entered = inner_span.enter();
+                write_batch(files, compression)
+            })
+            .await;
+
+            drop(cleanups);
+
+            let written = match written {
+                Ok(Ok(written)) => written,
+                Ok(Err(e)) => {
+                    for (_, _, tx) in &ours {
+                        let _ = tx.send(Err(()));
+                    }
+                    return Err(e.into());
+                }
+                Err(e) => {
+                    for (_, _, tx) in &ours {
+                        let _ = tx.send(Err(()));
+                    }
+                    return Err(e.into());
+                }
+            };
+
+            trace!(blocks = ours.len(), "batch writing succeeded");
+
+            for ((i, cid, tx), written) in ours.into_iter().zip(written) {
+                // there might not be anyone listening
+                let _ = tx.send(Ok(()));
+
+                results[i] = Some(match written {
+                    Some(len) => {
+                        self.written_bytes.fetch_add(len, Ordering::SeqCst);
+                        (cid, BlockPut::NewBlock)
+                    }
+                    None => (cid, BlockPut::Existed),
+                });
+            }
+
+            for (i, block) in contended {
+                results[i] = Some(self.put(block).await?);
+            }
+
+            Ok(results
+                .into_iter()
+                .map(|res| res.expect("all blocks were handled"))
+                .collect())
+        }
+        .instrument(span)
+        .await
+    }
+
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
-        let path = block_path(self.path.clone(), cid);
+        let path = self.layout.block_path(self.path.clone(), cid);
 
         let span = trace_span!("remove block", cid = %cid);
 
@@ -342,58 +583,55 @@ impl BlockStore for FsBlockStore {
             WriteCompletion::KnownBad => Ok(Err(BlockRmError::NotFound(cid.to_owned()))),
             completion => {
                 trace!(cid = %cid, completion = ?completion, "removing block after synchronizing");
-                match fs::remove_file(path).await {
-                    // FIXME: not sure if theres any point in taking cid ownership here?
-                    Ok(()) => Ok(Ok(BlockRm::Removed(cid.to_owned()))),
-                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                        Ok(Err(BlockRmError::NotFound(cid.to_owned())))
+                let len = file_size(&path).await;
+                let removed_data = match fs::remove_file(path).await {
+                    Ok(()) => {
+                        self.written_bytes.fetch_sub(len, Ordering::SeqCst);
+                        true
                     }
-                    Err(e) => Err(e.into()),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                    Err(e) => return Err(e.into()),
+                };
+
+                // the content of a block stored as a reference may have been put later on, in
+                // which case both files exist
+                let removed_reference = match fs::remove_file(self.reference_path(cid)).await {
+                    Ok(()) => true,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+                    Err(e) => return Err(e.into()),
+                };
+
+                // FIXME: not sure if theres any point in taking cid ownership here?
+                if removed_data || removed_reference {
+                    Ok(Ok(BlockRm::Removed(cid.to_owned())))
+                } else {
+                    Ok(Err(BlockRmError::NotFound(cid.to_owned())))
                 }
             }
         }
     }
 
-    async fn list(&self) -> Result<Vec<Cid>, Error> {
-        use futures::future::{ready, Either};
-        use futures::stream::{empty, TryStreamExt};
+    /// Lists the blocks lazily one shard directory at a time, ordered by the shard and then the
+    /// file name.
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        self.list_from(None)
+    }
 
-        let span = tracing::trace_span!("listing blocks");
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        self.list_from(Some(cursor))
+    }
 
-        async move {
-            let stream = fs::read_dir(self.path.clone()).await?;
-
-            // FIXME: written as a stream to make the Vec be BoxStream<'static, Cid>
-            let vec = stream
-                .and_then(|d| async move {
-                    // map over the shard directories
-                    Ok(if d.file_type().await?.is_dir() {
-                        Either::Left(fs::read_dir(d.path()).await?)
-                    } else {
-                        Either::Right(empty())
-                    })
-                })
-                // flatten each
-                .try_flatten()
-                // convert the paths ending in ".data" into cid
-                .try_filter_map(|d| {
-                    let name = d.file_name();
-                    let path: &std::path::Path = name.as_ref();
-
-                    ready(if path.extension() != Some("data".as_ref()) {
-                        Ok(None)
-                    } else {
-                        let maybe_cid = filestem_to_block_cid(path.file_stem());
-                        Ok(maybe_cid)
-                    })
-                })
-                .try_collect::<Vec<_>>()
-                .await?;
+    async fn size(&self) -> Result<u64, Error> {
+        Ok(self.written_bytes.load(Ordering::SeqCst))
+    }
 
-            Ok(vec)
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        BlockStoreExtensions {
+            references: Some(self),
+            files: Some(self),
+            compression: Some(self),
+            cache: None,
         }
-        .instrument(span)
-        .await
     }
 
     async fn wipe(&self) {
@@ -401,6 +639,313 @@ impl BlockStore for FsBlockStore {
     }
 }
 
+#[async_trait]
+impl FileReferences for FsBlockStore {
+    async fn put_reference(
+        &self,
+        cid: Cid,
+        reference: FileReference,
+    ) -> Result<(Cid, BlockPut), Error> {
+        let data_path = self.layout.block_path(self.path.clone(), &cid);
+        let target_path = self.reference_path(&cid);
+        let span = tracing::trace_span!("put reference", cid = %cid);
+        let json = serde_json::to_vec(&reference)?;
+
+        let put = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+
+            if data_path.is_file() {
+                trace!("the block content is stored already");
+                return Ok(BlockPut::Existed);
+            }
+
+            let sharded = target_path
+                .parent()
+                .expect("we already have at least the shard parent");
+            std::fs::create_dir_all(sharded)?;
+
+            // references are immutable like the blocks; the first one wins
+            let target = match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&target_path)
+            {
+                Ok(target) => target,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return Ok(BlockPut::Existed)
+                }
+                Err(e) => return Err(e),
+            };
+
+            let temp_path = target_path.with_extension("ref.tmp");
+
+            match write_through_tempfile(target, &target_path, temp_path, &json) {
+                Ok(()) => Ok(BlockPut::NewBlock),
+                Err(e) => {
+                    std::fs::remove_file(&target_path).ok();
+                    Err(e)
+                }
+            }
+        })
+        .await??;
+
+        Ok((cid, put))
+    }
+
+    async fn list_references(&self) -> Result<Vec<(Cid, FileReference)>, Error> {
+        let path = self.path.clone();
+        let layout = self.layout;
+        tokio::task::spawn_blocking(move || list_references(&path, layout)).await?
+    }
+}
+
+#[async_trait]
+impl BlockFiles for FsBlockStore {
+    /// Renames the `.data` or `.ref` file by appending `.quarantined`, which hides it from the
+    /// listing.
+    async fn quarantine(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        let data_path = self.layout.block_path(self.path.clone(), cid);
+
+        for path in &[data_path, self.reference_path(cid)] {
+            let len = file_size(path).await;
+            match fs::rename(path, quarantined_path(path)).await {
+                Ok(()) => {
+                    if path.extension() == Some("data".as_ref()) {
+                        self.written_bytes.fetch_sub(len, Ordering::SeqCst);
+                    }
+                    return Ok(Ok(BlockRm::Removed(cid.to_owned())));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Err(BlockRmError::NotFound(cid.to_owned())))
+    }
+
+    async fn list_orphans(&self) -> Result<Vec<PathBuf>, Error> {
+        let path = self.path.clone();
+        let layout = self.layout;
+        let tmp_files = tokio::task::spawn_blocking(move || list_tmp_files(&path)).await??;
+
+        // the temporary files of the ongoing writes are not orphans
+        let writes = self.writes.lock().unwrap();
+        Ok(tmp_files
+            .into_iter()
+            .filter(|path| {
+                let stem = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.split('.').next())
+                    .map(std::ffi::OsStr::new);
+
+                match layout.filestem_to_cid(stem) {
+                    Some(cid) => !writes.contains_key(&RepoCid(cid)),
+                    None => true,
+                }
+            })
+            .collect())
+    }
+
+    async fn remove_orphan(&self, path: &std::path::Path, quarantine: bool) -> Result<(), Error> {
+        if !path.starts_with(&self.path) || path.extension() != Some("tmp".as_ref()) {
+            return Err(anyhow::anyhow!(
+                "not a temporary file of the block store: {:?}",
+                path
+            ));
+        }
+
+        if quarantine {
+            fs::rename(path, quarantined_path(path)).await?;
+        } else {
+            fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlockCompression for FsBlockStore {
+    fn set_compression(&self, compression: Compression) {
+        *self.compression.lock().unwrap() = compression;
+    }
+
+    /// Reads the headers of all of the block files, as the size before compression is only needed
+    /// for the statistics.
+    async fn logical_size(&self) -> Result<u64, Error> {
+        let path = self.path.clone();
+        Ok(tokio::task::spawn_blocking(move || logical_usage(&path)).await??)
+    }
+}
+
+/// Reads the block stored as a reference to a file, see [`FileReferences::put_reference`]. Fails if
+/// the referenced content is no longer available as it was.
+fn get_referenced(cid: Cid, reference_path: PathBuf) -> Result<Option<Block>, Error> {
+    let json = match std::fs::read(reference_path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let reference: FileReference = serde_json::from_slice(&json)?;
+    let data = read_reference(&cid, &reference)?;
+    Ok(Some(Block::new(data.into_boxed_slice(), cid)))
+}
+
+/// Lists the shard directories under `path` ordered by name.
+fn list_shards(path: &std::path::Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut shards = Vec::new();
+    for shard in std::fs::read_dir(path)? {
+        let shard = shard?;
+        if shard.file_type()?.is_dir() {
+            shards.push(shard.path());
+        }
+    }
+    shards.sort();
+    Ok(shards)
+}
+
+/// Lists the blocks stored as `.data` or `.ref` files in the `shard` directory ordered by the file
+/// stem. A block can be stored as both the content and a reference, but it's listed once. A shard
+/// removed after it was listed is empty.
+fn list_shard(
+    shard: &std::path::Path,
+    layout: BlockLayout,
+) -> Result<BTreeMap<OsString, Cid>, std::io::Error> {
+    let entries = match std::fs::read_dir(shard) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut blocks = BTreeMap::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        let path: &std::path::Path = name.as_ref();
+
+        if path.extension() != Some("data".as_ref()) && path.extension() != Some("ref".as_ref()) {
+            continue;
+        }
+
+        if let Some(cid) = layout.filestem_to_cid(path.file_stem()) {
+            let stem = path.file_stem().unwrap_or_default().to_owned();
+            blocks.insert(stem, cid);
+        }
+    }
+    Ok(blocks)
+}
+
+/// Reads all of the `.ref` files in the shard directories under `path`.
+fn list_references(
+    path: &std::path::Path,
+    layout: BlockLayout,
+) -> Result<Vec<(Cid, FileReference)>, Error> {
+    let mut references = Vec::new();
+    for shard in std::fs::read_dir(path)? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(shard.path())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let path: &std::path::Path = name.as_ref();
+            if path.extension() != Some("ref".as_ref()) {
+                continue;
+            }
+            if let Some(cid) = layout.filestem_to_cid(path.file_stem()) {
+                let reference = serde_json::from_slice(&std::fs::read(entry.path())?)?;
+                references.push((cid, reference));
+            }
+        }
+    }
+    Ok(references)
+}
+
+/// Lists the `.tmp` files in the shard directories under `path`.
+fn list_tmp_files(path: &std::path::Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut tmp_files = Vec::new();
+    for shard in std::fs::read_dir(path)? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(shard.path())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let path: &std::path::Path = name.as_ref();
+            if path.extension() == Some("tmp".as_ref()) {
+                tmp_files.push(entry.path());
+            }
+        }
+    }
+    Ok(tmp_files)
+}
+
+/// The path of a quarantined file is the original path with an additional `.quarantined`
+/// extension.
+fn quarantined_path(path: &std::path::Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".quarantined");
+    path.with_file_name(name)
+}
+
+/// Sums up the sizes of the block files in the shard directories under `path`, as stored on disk.
+fn disk_usage(path: &std::path::Path) -> Result<u64, std::io::Error> {
+    let mut size = 0;
+    for_each_block_file(path, |file| {
+        size += file.metadata()?.len();
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Sums up the sizes of the blocks in the shard directories under `path` before compression,
+/// which requires reading the header of each block file.
+fn logical_usage(path: &std::path::Path) -> Result<u64, std::io::Error> {
+    let mut logical = 0;
+    for_each_block_file(path, |file| {
+        let file = std::fs::File::open(file.path())?;
+        let len = file.metadata()?.len();
+
+        let mut start = Vec::with_capacity(compression::HEADER_LEN);
+        file.take(compression::HEADER_LEN as u64)
+            .read_to_end(&mut start)?;
+
+        logical += compression::logical_len(&start, len);
+        Ok(())
+    })?;
+    Ok(logical)
+}
+
+fn for_each_block_file<F>(path: &std::path::Path, mut f: F) -> Result<(), std::io::Error>
+where
+    F: FnMut(&std::fs::DirEntry) -> Result<(), std::io::Error>,
+{
+    for shard in std::fs::read_dir(path)? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(shard.path())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let path: &std::path::Path = name.as_ref();
+            if path.extension() == Some("data".as_ref()) {
+                f(&entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the size of the file on disk, or zero if it cannot be read.
+async fn file_size(path: &std::path::Path) -> u64 {
+    fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
 fn write_through_tempfile(
     target: std::fs::File,
     target_path: impl AsRef<std::path::Path>,
@@ -431,11 +976,108 @@ fn write_through_tempfile(
     Ok(())
 }
 
+/// Writes the new files through temporary files without syncing each of them, then syncs all of
+/// the temporary files, renames them in place and finally syncs the directories. Returns the
+/// number of bytes written for each file, or `None` if the file existed already. On failure the
+/// files created by this call are removed.
+fn write_batch(
+    files: Vec<(PathBuf, Box<[u8]>)>,
+    compression: Compression,
+) -> Result<Vec<Option<u64>>, std::io::Error> {
+    let mut written = Vec::with_capacity(files.len());
+    let mut created = Vec::new();
+
+    match write_batch_files(&files, compression, &mut written, &mut created) {
+        Ok(()) => Ok(written),
+        Err(e) => {
+            for (target_path, temp_path) in created {
+                std::fs::remove_file(&temp_path).ok();
+                match std::fs::remove_file(&target_path) {
+                    Ok(_) => debug!("removed partially written {:?}", target_path),
+                    Err(removal) => warn!(
+                        "failed to remove partially written {:?}: {}",
+                        target_path, removal
+                    ),
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+fn write_batch_files(
+    files: &[(PathBuf, Box<[u8]>)],
+    compression: Compression,
+    written: &mut Vec<Option<u64>>,
+    created: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), std::io::Error> {
+    use std::io::Write;
+
+    let mut temps = Vec::new();
+
+    for (target_path, data) in files {
+        let sharded = target_path
+            .parent()
+            .expect("we already have at least the shard parent");
+
+        std::fs::create_dir_all(sharded)?;
+
+        // the winning writer is picked with create_new like in `put`
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&target_path)
+        {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                written.push(None);
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let temp_path = target_path.with_extension("tmp");
+        created.push((target_path.to_owned(), temp_path.clone()));
+
+        let mut temp = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)?;
+
+        let file = compression::encode(data, compression)?;
+        temp.write_all(&file)?;
+        temp.flush()?;
+        temps.push(temp);
+
+        written.push(Some(file.len() as u64));
+    }
+
+    for temp in temps {
+        temp.sync_all()?;
+    }
+
+    let mut dirs = std::collections::BTreeSet::new();
+    for (target_path, temp_path) in created.iter() {
+        std::fs::rename(temp_path, target_path)?;
+        dirs.insert(target_path.parent().expect("shard parent").to_owned());
+    }
+
+    // directories cannot be opened for syncing on all platforms
+    #[cfg(unix)]
+    for dir in dirs {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Block;
     use cid::{Cid, Codec};
+    use futures::stream::TryStreamExt;
     use hex_literal::hex;
     use multihash::Sha2_256;
     use std::convert::TryFrom;
@@ -505,6 +1147,108 @@ mod tests {
         std::fs::remove_dir_all(&tmp).ok();
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn multihash_keyed_blocks_are_codec_agnostic() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_multihash_keys");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let block_store = FsBlockStore::with_multihash_keys(tmp.clone());
+        block_store.init().await.unwrap();
+        block_store.open().await.unwrap();
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let raw = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let dag_pb = Cid::new_v1(Codec::DagProtobuf, raw.hash().to_owned());
+
+        let (_, put) = block_store
+            .put(Block::new(data.clone(), raw.clone()))
+            .await
+            .unwrap();
+        assert_eq!(put, BlockPut::NewBlock);
+
+        let (_, put) = block_store
+            .put(Block::new(data, dag_pb.clone()))
+            .await
+            .unwrap();
+        assert_eq!(put, BlockPut::Existed);
+
+        assert!(block_store.contains(&raw).await.unwrap());
+        assert!(block_store.contains(&dag_pb).await.unwrap());
+
+        let block = block_store.get(&dag_pb).await.unwrap().unwrap();
+        assert_eq!(block.cid(), &dag_pb);
+
+        assert_eq!(
+            block_store.list_multihashes().await.unwrap(),
+            vec![raw.hash().to_owned()]
+        );
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn file_references() {
+        let mut tmp = temp_dir();
+        tmp.push("blockstore_references");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let block_store = FsBlockStore::new(tmp.join("blocks"));
+        block_store.init().await.unwrap();
+
+        let file = tmp.join("referenced.txt");
+        std::fs::write(&file, b"foobar\n").unwrap();
+
+        let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(b"bar"));
+        let reference = FileReference {
+            path: file.clone(),
+            offset: 3,
+            length: 3,
+        };
+
+        let (_, put) = block_store
+            .put_reference(cid.clone(), reference.clone())
+            .await
+            .unwrap();
+        assert_eq!(put, BlockPut::NewBlock);
+
+        assert!(block_store.contains(&cid).await.unwrap());
+        assert_eq!(
+            block_store
+                .list()
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+            vec![cid.clone()]
+        );
+        assert_eq!(
+            block_store.list_references().await.unwrap(),
+            vec![(cid.clone(), reference)]
+        );
+        // the referenced content is not counted as stored
+        assert_eq!(block_store.size().await.unwrap(), 0);
+
+        let block = block_store.get(&cid).await.unwrap().unwrap();
+        assert_eq!(block.data(), b"bar");
+
+        std::fs::write(&file, b"foobaz\n").unwrap();
+        block_store.get(&cid).await.unwrap_err();
+
+        // the content stored later on is read instead of the reference
+        let block = Block::new(b"bar".to_vec().into_boxed_slice(), cid.clone());
+        block_store.put(block.clone()).await.unwrap();
+        assert_eq!(block_store.get(&cid).await.unwrap(), Some(block));
+
+        // removing the block removes both the content and the reference
+        block_store.remove(&cid).await.unwrap().unwrap();
+        assert!(!block_store.contains(&cid).await.unwrap());
+        assert!(block_store.list_references().await.unwrap().is_empty());
+        assert_eq!(block_store.get(&cid).await.unwrap(), None);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_fs_blockstore_list() {
         let mut tmp = temp_dir();
@@ -522,11 +1266,37 @@ mod tests {
             block_store.put(block.clone()).await.unwrap();
         }
 
-        let cids = block_store.list().await.unwrap();
+        let cids = block_store
+            .list()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
         assert_eq!(cids.len(), 3);
         for cid in cids.iter() {
             assert!(block_store.contains(cid).await.unwrap());
         }
+
+        // resuming after each of the listed blocks lists the rest of them
+        for (i, cursor) in cids.iter().enumerate() {
+            let rest = block_store
+                .list_after(cursor.to_owned())
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(rest, &cids[i + 1..]);
+        }
+
+        // the cursor doesn't need to exist anymore
+        block_store.remove(&cids[0]).await.unwrap().unwrap();
+        let rest = block_store
+            .list_after(cids[0].to_owned())
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(rest, &cids[1..]);
     }
 
     #[tokio::test(max_threads = 1)]
@@ -595,6 +1365,107 @@ mod tests {
         assert_eq!(existing, count);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn put_batch() {
+        let tmp = temp_dir().join("blockstore_put_batch");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let store = FsBlockStore::new(tmp.clone());
+        store.init().await.unwrap();
+
+        let blocks = [&b"a"[..], b"bb", b"ccc"]
+            .iter()
+            .map(|data| {
+                Block::new(
+                    (*data).into(),
+                    Cid::new_v1(Codec::Raw, Sha2_256::digest(data)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        store.put(blocks[1].clone()).await.unwrap();
+
+        let batch = vec![
+            blocks[0].clone(),
+            blocks[1].clone(),
+            blocks[2].clone(),
+            blocks[0].clone(),
+        ];
+        let results = store
+            .put_batch(batch)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(cid, res)| (cid, matches!(res, BlockPut::NewBlock)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results,
+            vec![
+                (blocks[0].cid().to_owned(), true),
+                (blocks[1].cid().to_owned(), false),
+                (blocks[2].cid().to_owned(), true),
+                (blocks[0].cid().to_owned(), false),
+            ]
+        );
+
+        for block in &blocks {
+            assert_eq!(store.get(block.cid()).await.unwrap().as_ref(), Some(block));
+        }
+        assert_eq!(store.written_bytes.load(Ordering::SeqCst), 6);
+        assert!(store.list_orphans().await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn compressed_blocks() {
+        let tmp = temp_dir().join("blockstore_compressed");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let block = |data: Vec<u8>| {
+            let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+            Block::new(data.into_boxed_slice(), cid)
+        };
+
+        let legacy = block(b"legacy".repeat(100));
+        let text = block(b"compressible".repeat(100));
+        let batched = block(b"batched".repeat(100));
+        let short = block(b"short".to_vec());
+
+        let store = FsBlockStore::new(tmp.clone());
+        store.init().await.unwrap();
+        store.put(legacy.clone()).await.unwrap();
+        drop(store);
+
+        let store = FsBlockStore::new(tmp.clone()).compressed(Compression::Zstd(3));
+        store.open().await.unwrap();
+        store.put(text.clone()).await.unwrap();
+        store.put(short.clone()).await.unwrap();
+        store.put_batch(vec![batched.clone()]).await.unwrap();
+
+        let logical = 600 + 1200 + 700 + 5;
+        assert_eq!(store.logical_size().await.unwrap(), logical);
+        assert!(store.size().await.unwrap() < logical);
+
+        for block in &[&legacy, &text, &batched, &short] {
+            assert_eq!(store.get(block.cid()).await.unwrap().as_ref(), Some(*block));
+        }
+
+        // the sizes are the same when the compressed files are read without compression
+        let size = store.size().await.unwrap();
+        let store = FsBlockStore::new(tmp.clone());
+        store.open().await.unwrap();
+        assert_eq!(store.size().await.unwrap(), size);
+        assert_eq!(store.logical_size().await.unwrap(), logical);
+        assert_eq!(store.get(text.cid()).await.unwrap(), Some(text.clone()));
+
+        store.remove(text.cid()).await.unwrap().unwrap();
+        assert_eq!(store.logical_size().await.unwrap(), logical - 1200);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
     async fn race_to_insert_scenario(
         count: usize,
         block: Block,
@@ -652,14 +1523,35 @@ mod tests {
             data: data.into(),
         };
 
-        assert_eq!(single.list().await.unwrap().len(), 0);
+        assert_eq!(
+            single
+                .list()
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
 
         single.put(block).await.unwrap();
 
         // compare the multihash since we store the block named as cidv1
-        assert_eq!(single.list().await.unwrap()[0].hash(), cid.hash());
+        assert_eq!(
+            single.list().await.try_collect::<Vec<_>>().await.unwrap()[0].hash(),
+            cid.hash()
+        );
 
         single.remove(&cid).await.unwrap().unwrap();
-        assert_eq!(single.list().await.unwrap().len(), 0);
+        assert_eq!(
+            single
+                .list()
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
     }
 }
@@ -0,0 +1,280 @@
+//! The optional compression of the block files of [`super::FsBlockStore`].
+//!
+//! Compressed files start with a small header: an 8 byte magic, a byte for the algorithm and the
+//! length of the uncompressed content as a little endian `u64`. Files without the header are the
+//! block content as is, which keeps the files written without compression readable, and the
+//! other way around. The Cid is always computed over the uncompressed content.
+//!
+//! The header is only ever written with the compression enabled. The files written without it,
+//! for example by go-ipfs, may still start with the magic, so a file with the header is only
+//! decoded if the result matches the Cid of the block.
+use crate::repo::digest_matches;
+use cid::Cid;
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+const MAGIC: [u8; 8] = *b"\x89BLKZ\r\n\x1a";
+
+/// The number of bytes needed from the start of a block file for [`logical_len`].
+pub(super) const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// The content follows the header as is.
+const STORED: u8 = 0;
+/// The content follows the header as a zstd frame.
+const ZSTD: u8 = 1;
+
+/// Compression of the block files written by [`super::FsBlockStore`]. Blocks which don't get any
+/// smaller are written uncompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The block files are the block content as is, readable by other implementations.
+    None,
+    /// The block files are compressed with zstd at the given level, 1 to 21.
+    Zstd(i32),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+/// The default zstd level, the same as the one of the zstd command line tool.
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+impl FromStr for Compression {
+    type Err = InvalidCompression;
+
+    /// Parses `none`, `zstd` or `zstd:<level>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("none"), None) => Ok(Compression::None),
+            (Some("zstd"), None) => Ok(Compression::Zstd(DEFAULT_ZSTD_LEVEL)),
+            (Some("zstd"), Some(level)) => match level.parse() {
+                Ok(level) if (1..=21).contains(&level) => Ok(Compression::Zstd(level)),
+                _ => Err(InvalidCompression(s.to_owned())),
+            },
+            _ => Err(InvalidCompression(s.to_owned())),
+        }
+    }
+}
+
+/// The string was not one of `none`, `zstd` or `zstd:<level>` with a level from 1 to 21.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid compression {0:?}, expected one of none, zstd or zstd:<level>")]
+pub struct InvalidCompression(String);
+
+/// Returns the content of the block file to write for the block `data`.
+pub(super) fn encode(data: &[u8], compression: Compression) -> io::Result<Cow<'_, [u8]>> {
+    let level = match compression {
+        Compression::None => return Ok(Cow::Borrowed(data)),
+        Compression::Zstd(level) => level,
+    };
+
+    let mut file = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+    write_header(&mut file, ZSTD, data.len());
+
+    let mut encoder = zstd::stream::write::Encoder::new(file, level)?;
+    encoder.write_all(data)?;
+    let file = encoder.finish()?;
+
+    if file.len() < data.len() {
+        Ok(Cow::Owned(file))
+    } else {
+        Ok(stored(data))
+    }
+}
+
+/// Returns the block content as is, unless it starts with the magic in which case it is preceded
+/// by a header so that it's not mistaken for a compressed file.
+fn stored(data: &[u8]) -> Cow<'_, [u8]> {
+    if data.starts_with(&MAGIC) {
+        let mut file = Vec::with_capacity(HEADER_LEN + data.len());
+        write_header(&mut file, STORED, data.len());
+        file.extend_from_slice(data);
+        Cow::Owned(file)
+    } else {
+        Cow::Borrowed(data)
+    }
+}
+
+/// Returns the content of the block `cid` from the content of its block `file`. A file with the
+/// header is the content as is if decoding it does not produce the content of the block. Fails if
+/// neither does.
+pub(super) fn decode(file: Vec<u8>, cid: &Cid) -> io::Result<Vec<u8>> {
+    let header = match read_header(&file) {
+        Some(header) => header,
+        None => return Ok(file),
+    };
+
+    match decode_content(&file[HEADER_LEN..], header) {
+        Ok(content) if digest_matches(cid, &content) => Ok(content),
+        _ if digest_matches(cid, &file) => Ok(file),
+        Ok(_) => Err(invalid_data(format!(
+            "the decoded content does not match {}",
+            cid
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+fn decode_content(content: &[u8], (algorithm, len): (u8, u64)) -> io::Result<Vec<u8>> {
+    match algorithm {
+        STORED if content.len() as u64 == len => Ok(content.to_vec()),
+        STORED => Err(invalid_data(format!(
+            "expected {} bytes of stored content, found {}",
+            len,
+            content.len()
+        ))),
+        ZSTD => decompress(content, len),
+        other => Err(invalid_data(format!(
+            "unsupported compression algorithm {}",
+            other
+        ))),
+    }
+}
+
+/// Returns the length of the block content from the start of the block file, given the length of
+/// the whole file.
+pub(super) fn logical_len(start: &[u8], file_len: u64) -> u64 {
+    match read_header(start) {
+        Some((_, len)) => len,
+        None => file_len,
+    }
+}
+
+fn write_header(file: &mut Vec<u8>, algorithm: u8, len: usize) {
+    file.extend_from_slice(&MAGIC);
+    file.push(algorithm);
+    file.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn read_header(file: &[u8]) -> Option<(u8, u64)> {
+    if file.len() < HEADER_LEN || !file.starts_with(&MAGIC) {
+        return None;
+    }
+
+    let algorithm = file[MAGIC.len()];
+
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&file[MAGIC.len() + 1..HEADER_LEN]);
+    Some((algorithm, u64::from_le_bytes(len)))
+}
+
+/// Decompresses the zstd frame, failing if the content is not exactly `len` bytes.
+fn decompress(frame: &[u8], len: u64) -> io::Result<Vec<u8>> {
+    let decoder = zstd::stream::read::Decoder::new(frame)?;
+
+    // the length limits the allocation in case the frame is not what it seems to be
+    let mut content = Vec::with_capacity(len.min(1024 * 1024) as usize);
+    decoder.take(len + 1).read_to_end(&mut content)?;
+
+    if content.len() as u64 == len {
+        Ok(content)
+    } else {
+        Err(invalid_data(format!(
+            "expected {} bytes of decompressed content, found {}",
+            len,
+            content.len()
+        )))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use multihash::Sha2_256;
+
+    fn cid(data: &[u8]) -> Cid {
+        Cid::new_v1(Codec::Raw, Sha2_256::digest(data))
+    }
+
+    fn roundtrip(data: &[u8], compression: Compression) -> Vec<u8> {
+        let file = encode(data, compression).unwrap().into_owned();
+        assert_eq!(decode(file.to_vec(), &cid(data)).unwrap(), data);
+        assert_eq!(
+            logical_len(&file[..HEADER_LEN.min(file.len())], file.len() as u64),
+            data.len() as u64
+        );
+        file
+    }
+
+    #[test]
+    fn compresses_only_when_smaller() {
+        let text = b"foobar".repeat(100);
+        let file = roundtrip(&text, Compression::Zstd(3));
+        assert!(file.len() < text.len());
+        assert!(file.starts_with(&MAGIC));
+
+        let file = roundtrip(&text, Compression::None);
+        assert_eq!(&*file, &text[..]);
+
+        let short = b"foo";
+        let file = roundtrip(short, Compression::Zstd(3));
+        assert_eq!(&*file, &short[..]);
+    }
+
+    #[test]
+    fn content_starting_with_magic() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(b"not really a header");
+
+        // without compression the files are always the content as is
+        let file = roundtrip(&data, Compression::None);
+        assert_eq!(file, data);
+
+        // written with the header when compressing so that it's not mistaken for a compressed file
+        let file = roundtrip(&data, Compression::Zstd(3));
+        assert_eq!(file.len(), HEADER_LEN + data.len());
+    }
+
+    #[test]
+    fn compressed_file_as_content() {
+        let text = b"foobar".repeat(100);
+        let compressed = encode(&text, Compression::Zstd(3)).unwrap().into_owned();
+
+        // a block which happens to be a compressed file, written without compression
+        let file = encode(&compressed, Compression::None).unwrap().into_owned();
+        assert_eq!(file, compressed);
+        assert_eq!(decode(file, &cid(&compressed)).unwrap(), compressed);
+    }
+
+    #[test]
+    fn corrupted_files_are_errors() {
+        let text = b"foobar".repeat(100);
+        let mut file = encode(&text, Compression::Zstd(3)).unwrap().into_owned();
+
+        let last = file.len() - 1;
+        file[last] ^= 0xff;
+        let e = decode(file.clone(), &cid(&text)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        file.truncate(HEADER_LEN);
+        assert!(decode(file.clone(), &cid(&text)).is_err());
+
+        // written by some later version with an algorithm unknown to this one
+        file[MAGIC.len()] = 42;
+        let e = decode(file, &cid(&text)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parse_compression() {
+        assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+        assert_eq!("zstd".parse::<Compression>().unwrap(), Compression::Zstd(3));
+        assert_eq!(
+            "zstd:19".parse::<Compression>().unwrap(),
+            Compression::Zstd(19)
+        );
+        assert!("zstd:0".parse::<Compression>().is_err());
+        assert!("gzip".parse::<Compression>().is_err());
+        assert!("none:1".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use super::{BlockRm, BlockRmError, FsBlockStore};
+use crate::error::Error;
+use crate::repo::{BlockPut, BlockStore, BlockStoreExtensions};
+use crate::Block;
+use async_trait::async_trait;
+use cid::Cid;
+use futures::stream::BoxStream;
+use multihash::Multihash;
+use std::path::PathBuf;
+use tokio::fs;
+
+/// The contents of the `SHARDING` file written by go-ipfs flatfs for the `next-to-last/2` sharding
+/// function.
+const SHARDING: &str = "/repo/flatfs/shard/v1/next-to-last/2\n";
+
+/// File system backed block store using the go-ipfs flatfs layout.
+///
+/// Blocks are stored as `<shard>/<base32 multihash>.data` files with the `next-to-last/2` sharding,
+/// along with a `SHARDING` file at the root describing the sharding function. This allows opening
+/// the `blocks/` directory of a go-ipfs repository directly and the other way around.
+///
+/// As the blocks are keyed by multihash alone, the same bytes are stored only once regardless of
+/// the Cid version or codec, and the blocks listed are reported as `raw` CIDv1.
+#[derive(Debug)]
+pub struct FlatfsBlockStore(FsBlockStore);
+
+impl FlatfsBlockStore {
+    /// Checks that the `SHARDING` file describes the only supported sharding function. Returns
+    /// `Ok(false)` if the file does not exist.
+    async fn check_sharding(&self) -> Result<bool, Error> {
+        let path = self.0.path().join("SHARDING");
+        match fs::read_to_string(&path).await {
+            Ok(s) if s.trim_end() == SHARDING.trim_end() => Ok(true),
+            Ok(s) => Err(anyhow::anyhow!(
+                "unsupported flatfs sharding function {:?} in {:?}",
+                s.trim_end(),
+                path
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockStore for FlatfsBlockStore {
+    fn new(path: PathBuf) -> Self {
+        FlatfsBlockStore(FsBlockStore::with_multihash_keys(path))
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.0.is_persistent()
+    }
+
+    async fn init(&self) -> Result<(), Error> {
+        self.0.init().await?;
+
+        if !self.check_sharding().await? {
+            fs::write(self.0.path().join("SHARDING"), SHARDING).await?;
+        }
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<(), Error> {
+        if !self.check_sharding().await? {
+            return Err(anyhow::anyhow!(
+                "missing SHARDING file in {:?}",
+                self.0.path()
+            ));
+        }
+        self.0.open().await
+    }
+
+    async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        self.0.contains(cid).await
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.0.get(cid).await
+    }
+
+    async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        self.0.put(block).await
+    }
+
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        self.0.put_batch(blocks).await
+    }
+
+    async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
+        self.0.remove(cid).await
+    }
+
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        self.0.list().await
+    }
+
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        self.0.list_after(cursor).await
+    }
+
+    async fn size(&self) -> Result<u64, Error> {
+        self.0.size().await
+    }
+
+    async fn list_multihashes(&self) -> Result<Vec<Multihash>, Error> {
+        self.0.list_multihashes().await
+    }
+
+    /// The block files are never compressed, as go-ipfs would not be able to read them.
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        BlockStoreExtensions {
+            compression: None,
+            ..self.0.extensions()
+        }
+    }
+
+    async fn wipe(&self) {
+        self.0.wipe().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Codec;
+    use futures::stream::TryStreamExt;
+    use multihash::Sha2_256;
+    use std::env::temp_dir;
+
+    #[tokio::test(max_threads = 1)]
+    async fn writes_go_ipfs_layout() {
+        let mut tmp = temp_dir();
+        tmp.push("flatfs_layout");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let store = FlatfsBlockStore::new(tmp.clone());
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let sharding = std::fs::read_to_string(tmp.join("SHARDING")).unwrap();
+        assert_eq!(sharding, "/repo/flatfs/shard/v1/next-to-last/2\n");
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let cid = Cid::new_v0(Sha2_256::digest(&data)).unwrap();
+        let block = Block::new(data, cid.clone());
+
+        store.put(block.clone()).await.unwrap();
+
+        // the same multihash with a different codec is the same block
+        let other = Cid::new_v1(Codec::Raw, cid.hash().to_owned());
+        assert!(store.contains(&other).await.unwrap());
+
+        let key = multibase::Base::Base32Upper.encode(cid.hash().as_bytes());
+        let shard = &key[key.len() - 3..key.len() - 1];
+        let expected = tmp.join(shard).join(format!("{}.data", key));
+        assert_eq!(std::fs::read(expected).unwrap(), b"1");
+
+        assert_eq!(store.get(&cid).await.unwrap(), Some(block));
+        let listed = store.list().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(listed, vec![other]);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn refuses_unknown_sharding() {
+        let mut tmp = temp_dir();
+        tmp.push("flatfs_unknown_sharding");
+        std::fs::remove_dir_all(&tmp).ok();
+        std::fs::create_dir_all(&tmp).unwrap();
+        std::fs::write(tmp.join("SHARDING"), "/repo/flatfs/shard/v1/prefix/2\n").unwrap();
+
+        let store = FlatfsBlockStore::new(tmp.clone());
+        store.init().await.unwrap_err();
+        store.open().await.unwrap_err();
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -1,3 +1,4 @@
+use crate::repo::Column;
 use cid::Cid;
 use core::convert::TryFrom;
 use std::path::PathBuf;
@@ -36,8 +37,34 @@ pub fn filestem_to_block_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid>
     })
 }
 
+/// Path of the block in the go-ipfs flatfs `next-to-last/2` layout: the key is the uppercase
+/// base32 encoded multihash without padding, as used by go-ipfs since it moved to multihash keys.
+/// The produced filename can be converted back to `Cid` using [`filestem_to_flatfs_cid`].
+pub fn flatfs_block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
+    let key = multibase::Base::Base32Upper.encode(cid.hash().as_bytes());
+
+    shard(&mut base, &key);
+
+    base.set_extension("data");
+    base
+}
+
+/// Decodes the file stem produced by [`flatfs_block_path`], ignoring errors. As the codec is not
+/// stored in the flatfs layout, the block is assumed to be `raw` like go-ipfs does when listing.
+pub fn filestem_to_flatfs_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
+    file_stem.and_then(|stem| stem.to_str()).and_then(|s| {
+        let bytes = multibase::Base::Base32Upper.decode(s).ok()?;
+        let multihash = multihash::Multihash::from_bytes(bytes).ok()?;
+
+        // See filestem_to_block_cid for discussion on why the error is ignored
+        Some(Cid::new_v1(cid::Codec::Raw, multihash))
+    })
+}
+
 /// Same as `block_path` except it doesn't canonicalize the cid to later version. The produced
-/// filename must be converted back to `Cid` using [`filestem_to_pin_cid`].
+/// filename must be converted back to `Cid` using [`filestem_to_pin_cid`]. The pin files are only
+/// read when indexing the pins of older repositories.
+#[cfg(test)]
 pub fn pin_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
     // it might be illegal to to render cidv0 as base32
     let key: String = multibase::Base::Base32Lower.encode(cid.to_bytes());
@@ -57,6 +84,34 @@ pub fn filestem_to_pin_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
     })
 }
 
+/// The longest file name used for a column key, leaving room for the `.tmp` extension of the
+/// temporary files within the usual limit of 255 bytes.
+const MAX_COLUMN_FILE_NAME: usize = 200;
+
+/// Path of the value of `key` in the column `col`. The columns are few and small so the keys are
+/// not sharded; the key is multibase encoded as base32 to allow any bytes, including none. Keys
+/// too long to be encoded as a file name are named by the base32 encoded sha2-256 digest of the
+/// key instead, with a `.sha256` extension which the encoded keys never have.
+pub fn column_path(mut base: PathBuf, col: Column, key: &[u8]) -> PathBuf {
+    let col = match col {
+        Column::Ipns => "ipns",
+        Column::PinMetadata => "pin_metadata",
+        Column::RemotePinServices => "remote_pin_services",
+    };
+    base.push(col);
+
+    let name = multibase::encode(multibase::Base::Base32Lower, key);
+    if name.len() <= MAX_COLUMN_FILE_NAME {
+        base.push(name);
+    } else {
+        let digest = multihash::Sha2_256::digest(key);
+        let mut name = multibase::encode(multibase::Base::Base32Lower, digest.digest());
+        name.push_str(".sha256");
+        base.push(name);
+    }
+    base
+}
+
 /// second-to-last/2 sharding, just by taking the two characters from suffix ignoring the last
 /// character from an ASCII encoded key string to be prepended as the directory or "shard".
 ///
@@ -152,6 +207,29 @@ mod tests {
         assert_eq!(parsed, Some(cid_v1));
     }
 
+    #[test]
+    fn cid_to_flatfs_block_path_and_back() {
+        let cid_v0 = "QmTEn8ypAkbJXZUXCRHBorwF2jM8uTUW9yRLzrcQouSoD4";
+        let cid_v0 = Cid::try_from(cid_v0).unwrap();
+        let cid_v1 = "bafybeicizfmyaovkw4pnrwpa4kcirzaveabyw4vsixt45mrrhr2xm2d5lm";
+        let cid_v1 = Cid::try_from(cid_v1).unwrap();
+
+        let base = PathBuf::from("blocks");
+
+        let cid_v0_path = super::flatfs_block_path(base.clone(), &cid_v0);
+        let cid_v1_path = super::flatfs_block_path(base, &cid_v1);
+
+        assert_eq!(cid_v0_path, cid_v1_path);
+
+        // this is the path go-ipfs would use for the block
+        let expected = "blocks/2W/CIQERSKZQA5KVNY63DM6BYUERDSBKIADRNZLERPHZ2ZDCPDVOZUH2WY.data";
+        assert_eq!(cid_v1_path, Path::new(expected));
+
+        let parsed = super::filestem_to_flatfs_cid(cid_v1_path.file_stem()).unwrap();
+        assert_eq!(parsed.codec(), cid::Codec::Raw);
+        assert_eq!(parsed.hash(), cid_v1.hash());
+    }
+
     #[test]
     fn invalid_block_path_is_silently_ignored() {
         let block_path = Path::new("another_root/ba/foobar.data");
@@ -174,4 +252,29 @@ mod tests {
         let expected = Path::new("some_root/EF/ABCDEFG");
         assert_eq!(path, expected);
     }
+
+    #[test]
+    fn column_paths() {
+        use crate::repo::Column;
+
+        let base = PathBuf::from("columns");
+
+        assert_eq!(
+            super::column_path(base.clone(), Column::Ipns, b"/ipns/foo"),
+            Path::new("columns/ipns/bf5uxa3ttf5tg63y")
+        );
+
+        // the empty key is still a file in the column directory
+        assert_eq!(
+            super::column_path(base.clone(), Column::Ipns, b""),
+            Path::new("columns/ipns/b")
+        );
+
+        // long keys are named by their digest to stay within the limits of the file names
+        let long = super::column_path(base.clone(), Column::Ipns, &[b'x'; 1000]);
+        let name = long.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with(".sha256"), "{}", name);
+        assert!(name.len() < 100, "{}", name);
+        assert_ne!(long, super::column_path(base, Column::Ipns, &[b'x'; 1001]));
+    }
 }
@@ -0,0 +1,1157 @@
+//! The index of the pins of [`super::FsDataStore`].
+//!
+//! The index is kept in memory. It consists of the direct pins, the recursive pins with the
+//! references of each, and the recursive pins referencing every indirectly pinned block. The
+//! recursive pins and the indirectly pinned blocks are numbered, so that the references are a
+//! number each, and the blocks shared by overlapping recursive pins are stored only once. The
+//! recursive pins are recorded as intended before their references are walked, so that the
+//! interrupted ones can be found and repaired by [`crate::Ipfs::verify_pins`].
+//!
+//! On disk the index is a snapshot, written as a whole through a tempfile, and a journal of the
+//! changes made after the snapshot, both protobuf encoded with the Cids in their binary form. Each
+//! change is appended and synced to the journal before it is applied in memory. The journal is
+//! folded into a new snapshot once it grows larger than the snapshot.
+use super::filestem_to_pin_cid;
+use crate::error::Error;
+use crate::repo::{PinKind, PinMode};
+use cid::Cid;
+use prost::Message;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+mod pb {
+    include!(concat!(env!("OUT_DIR"), "/pin_index_pb.rs"));
+}
+
+const SNAPSHOT: &str = "snapshot";
+const JOURNAL: &str = "journal";
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// The JSON snapshot and journal of the repo version 2, see [`convert_json_index`].
+const JSON_SNAPSHOT: &str = "index";
+const JSON_JOURNAL: &str = "index.journal";
+
+/// The journal is not folded into a snapshot before it is at least this large.
+const MIN_JOURNAL_LEN: u64 = 64 * 1024;
+
+/// A change to the pins, as written to the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum PinOp {
+    InsertDirect(#[serde(with = "cid_str")] Cid),
+    InsertRecursive {
+        #[serde(with = "cid_str")]
+        cid: Cid,
+        #[serde(with = "cid_strs")]
+        refs: Vec<Cid>,
+    },
+    RemoveDirect(#[serde(with = "cid_str")] Cid),
+    RemoveRecursive(#[serde(with = "cid_str")] Cid),
+    /// Recorded before the references of the recursive pin are walked.
+    #[serde(skip)]
+    IntendRecursive(Cid),
+}
+
+impl PinOp {
+    fn to_entry(&self, seq: u64) -> pb::JournalEntry {
+        use pb::journal_entry::Op;
+
+        let op = match self {
+            PinOp::InsertDirect(cid) => Op::InsertDirect(cid.to_bytes()),
+            PinOp::InsertRecursive { cid, refs } => Op::InsertRecursive(pb::InsertRecursive {
+                cid: cid.to_bytes(),
+                refs: refs.iter().map(Cid::to_bytes).collect(),
+            }),
+            PinOp::RemoveDirect(cid) => Op::RemoveDirect(cid.to_bytes()),
+            PinOp::RemoveRecursive(cid) => Op::RemoveRecursive(cid.to_bytes()),
+            PinOp::IntendRecursive(cid) => Op::IntendRecursive(cid.to_bytes()),
+        };
+
+        pb::JournalEntry { seq, op: Some(op) }
+    }
+
+    fn from_entry(entry: pb::JournalEntry) -> Result<(u64, PinOp), Error> {
+        use pb::journal_entry::Op;
+
+        let op = match entry.op {
+            Some(Op::InsertDirect(cid)) => PinOp::InsertDirect(Cid::try_from(cid)?),
+            Some(Op::InsertRecursive(pb::InsertRecursive { cid, refs })) => {
+                PinOp::InsertRecursive {
+                    cid: Cid::try_from(cid)?,
+                    refs: cids_from_bytes(refs)?,
+                }
+            }
+            Some(Op::RemoveDirect(cid)) => PinOp::RemoveDirect(Cid::try_from(cid)?),
+            Some(Op::RemoveRecursive(cid)) => PinOp::RemoveRecursive(Cid::try_from(cid)?),
+            Some(Op::IntendRecursive(cid)) => PinOp::IntendRecursive(Cid::try_from(cid)?),
+            None => return Err(anyhow::anyhow!("missing change")),
+        };
+
+        Ok((entry.seq, op))
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonJournalEntry {
+    seq: u64,
+    op: PinOp,
+}
+
+/// The snapshot of the [`PinIndex`], decoded from either of the on-disk formats.
+#[derive(Debug)]
+struct Snapshot {
+    /// The sequence number of the last journaled change included in the snapshot.
+    seq: u64,
+    direct: Vec<Cid>,
+    /// The recursive pins and the indices of their references to `blocks`.
+    recursive: Vec<(Cid, Vec<u64>)>,
+    /// The indirectly pinned blocks.
+    blocks: Vec<Cid>,
+    intended: Vec<Cid>,
+}
+
+impl Snapshot {
+    fn encode(&self) -> Vec<u8> {
+        let snapshot = pb::Snapshot {
+            version: SNAPSHOT_VERSION,
+            seq: self.seq,
+            direct: self.direct.iter().map(Cid::to_bytes).collect(),
+            recursive: self
+                .recursive
+                .iter()
+                .map(|(cid, refs)| pb::Recursive {
+                    cid: cid.to_bytes(),
+                    refs: refs.clone(),
+                })
+                .collect(),
+            blocks: self.blocks.iter().map(Cid::to_bytes).collect(),
+            intended: self.intended.iter().map(Cid::to_bytes).collect(),
+        };
+
+        let mut bytes = Vec::with_capacity(snapshot.encoded_len());
+        snapshot
+            .encode(&mut bytes)
+            .expect("Vec<u8> provides the needed capacity");
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let snapshot = pb::Snapshot::decode(bytes)?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(anyhow::anyhow!(
+                "unsupported pin index version {}",
+                snapshot.version
+            ));
+        }
+
+        Ok(Snapshot {
+            seq: snapshot.seq,
+            direct: cids_from_bytes(snapshot.direct)?,
+            recursive: snapshot
+                .recursive
+                .into_iter()
+                .map(|pb::Recursive { cid, refs }| Ok((Cid::try_from(cid)?, refs)))
+                .collect::<Result<_, Error>>()?,
+            blocks: cids_from_bytes(snapshot.blocks)?,
+            intended: cids_from_bytes(snapshot.intended)?,
+        })
+    }
+}
+
+fn cids_from_bytes(cids: Vec<Vec<u8>>) -> Result<Vec<Cid>, Error> {
+    cids.into_iter()
+        .map(|cid| Cid::try_from(cid).map_err(Error::from))
+        .collect()
+}
+
+/// The JSON snapshot of the repo version 2.
+#[derive(Debug, Deserialize)]
+struct JsonSnapshot {
+    version: u8,
+    seq: u64,
+    #[serde(with = "cid_strs")]
+    direct: Vec<Cid>,
+    recursive: Vec<JsonSnapshotRecursive>,
+    #[serde(with = "cid_strs")]
+    blocks: Vec<Cid>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonSnapshotRecursive {
+    #[serde(with = "cid_str")]
+    cid: Cid,
+    refs: Vec<u64>,
+}
+
+impl TryFrom<JsonSnapshot> for Snapshot {
+    type Error = Error;
+
+    fn try_from(snapshot: JsonSnapshot) -> Result<Self, Self::Error> {
+        if snapshot.version != 1 {
+            return Err(anyhow::anyhow!(
+                "unsupported JSON pin index version {}",
+                snapshot.version
+            ));
+        }
+
+        Ok(Snapshot {
+            seq: snapshot.seq,
+            direct: snapshot.direct,
+            recursive: snapshot
+                .recursive
+                .into_iter()
+                .map(|JsonSnapshotRecursive { cid, refs }| (cid, refs))
+                .collect(),
+            blocks: snapshot.blocks,
+            intended: Vec::new(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Recursive {
+    /// The number of the recursive pin, referring to it from the indirectly pinned blocks.
+    id: u64,
+    /// The numbers of the references.
+    refs: Vec<u64>,
+}
+
+#[derive(Debug)]
+struct Indirect {
+    cid: Cid,
+    /// The numbers of the recursive pins referencing the block, once for each reference.
+    referrers: Vec<u64>,
+}
+
+/// The pins in memory. The pins are keyed by the Cid as pinned, so the pins of CIDv0 and CIDv1
+/// versions of the same block are separate.
+#[derive(Debug, Default)]
+pub(super) struct PinIndex {
+    direct: HashSet<Cid>,
+    recursive: HashMap<Cid, Recursive>,
+    /// The recursive pins whose references have not been recorded, as the insertion was
+    /// interrupted or is still walking them.
+    intended: HashSet<Cid>,
+    /// The recursive pins by their numbers.
+    roots: HashMap<u64, Cid>,
+    /// The numbers of the indirectly pinned blocks.
+    ids: HashMap<Cid, u64>,
+    blocks: HashMap<u64, Indirect>,
+    next_id: u64,
+    next_root_id: u64,
+}
+
+impl PinIndex {
+    pub fn is_pinned(&self, cid: &Cid) -> bool {
+        self.ids.contains_key(cid)
+            || self.recursive.contains_key(cid)
+            || self.direct.contains(cid)
+            || self.intended.contains(cid)
+    }
+
+    /// Fails if the change cannot be applied, leaving the index as is.
+    pub fn check(&self, op: &PinOp) -> Result<(), Error> {
+        match op {
+            PinOp::InsertDirect(cid) if self.recursive.contains_key(cid) => {
+                Err(anyhow::anyhow!("already pinned recursively"))
+            }
+            PinOp::RemoveDirect(cid) if self.recursive.contains_key(cid) => {
+                Err(anyhow::anyhow!("is pinned recursively"))
+            }
+            PinOp::RemoveDirect(cid) if !self.direct.contains(cid) => {
+                Err(anyhow::anyhow!("not pinned or pinned indirectly"))
+            }
+            PinOp::RemoveRecursive(cid)
+                if !self.recursive.contains_key(cid)
+                    && !self.direct.contains(cid)
+                    && !self.intended.contains(cid) =>
+            {
+                Err(anyhow::anyhow!("not pinned or pinned indirectly"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the change, which must have been checked with [`PinIndex::check`].
+    pub fn apply(&mut self, op: PinOp) {
+        match op {
+            PinOp::InsertDirect(cid) => {
+                self.direct.insert(cid);
+            }
+            PinOp::IntendRecursive(cid) => {
+                // pinning again walks the references again, the complete pin remains meanwhile
+                if !self.recursive.contains_key(&cid) {
+                    self.intended.insert(cid);
+                }
+            }
+            PinOp::InsertRecursive { cid, refs } => {
+                // the previously direct has now been upgraded to recursive
+                self.direct.remove(&cid);
+                self.intended.remove(&cid);
+
+                let id = match self.recursive.get(&cid) {
+                    Some(previous) => previous.id,
+                    None => {
+                        self.next_root_id += 1;
+                        self.roots.insert(self.next_root_id, cid.clone());
+                        self.next_root_id
+                    }
+                };
+
+                // pinning again replaces the references, which should have been the same
+                let refs = self.acquire(id, refs);
+                if let Some(previous) = self.recursive.insert(cid, Recursive { id, refs }) {
+                    self.release(id, previous.refs);
+                }
+            }
+            PinOp::RemoveDirect(cid) => {
+                self.direct.remove(&cid);
+            }
+            PinOp::RemoveRecursive(cid) => {
+                // removing the recursive pin removes the direct pin as well
+                self.direct.remove(&cid);
+                self.intended.remove(&cid);
+
+                if let Some(Recursive { id, refs }) = self.recursive.remove(&cid) {
+                    self.release(id, refs);
+                    self.roots.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// Adds a reference from the recursive pin `root` to each of the unique `refs`, returning
+    /// their numbers.
+    fn acquire(&mut self, root: u64, refs: Vec<Cid>) -> Vec<u64> {
+        let mut ids = Vec::with_capacity(refs.len());
+        let mut seen = HashSet::with_capacity(refs.len());
+
+        for cid in refs {
+            let next_id = &mut self.next_id;
+            let id = *self.ids.entry(cid.clone()).or_insert_with(|| {
+                *next_id += 1;
+                *next_id
+            });
+
+            if !seen.insert(id) {
+                continue;
+            }
+
+            self.blocks
+                .entry(id)
+                .or_insert(Indirect {
+                    cid,
+                    referrers: Vec::new(),
+                })
+                .referrers
+                .push(root);
+            ids.push(id);
+        }
+
+        ids
+    }
+
+    /// Removes the reference from the recursive pin `root` from each of the blocks, forgetting the
+    /// unreferenced.
+    fn release(&mut self, root: u64, ids: Vec<u64>) {
+        for id in ids {
+            let indirect = self
+                .blocks
+                .get_mut(&id)
+                .expect("referenced blocks are always known");
+
+            if let Some(pos) = indirect.referrers.iter().position(|r| *r == root) {
+                indirect.referrers.swap_remove(pos);
+            }
+
+            if indirect.referrers.is_empty() {
+                let Indirect { cid, .. } = self.blocks.remove(&id).unwrap();
+                self.ids.remove(&cid);
+            }
+        }
+    }
+
+    /// Lists the pins in the priority order of recursive, direct and indirect, returning each
+    /// block only once in the first of the modes.
+    pub fn list(&self, requirement: Option<PinMode>) -> Vec<(Cid, PinMode)> {
+        let wanted = |mode: PinMode| requirement.as_ref().map(|r| *r == mode).unwrap_or(true);
+        let mut pins = Vec::new();
+
+        if wanted(PinMode::Recursive) {
+            pins.extend(
+                self.recursive
+                    .keys()
+                    .chain(self.intended.iter())
+                    .map(|cid| (cid.to_owned(), PinMode::Recursive)),
+            );
+        }
+
+        if wanted(PinMode::Direct) {
+            pins.extend(
+                self.direct
+                    .iter()
+                    .filter(|cid| !self.recursive.contains_key(cid) && !self.intended.contains(cid))
+                    .map(|cid| (cid.to_owned(), PinMode::Direct)),
+            );
+        }
+
+        if wanted(PinMode::Indirect) {
+            // the roots are returned as recursive or direct when listing all of the pins
+            let is_listed = |cid: &Cid| {
+                requirement.is_none()
+                    && (self.recursive.contains_key(cid)
+                        || self.intended.contains(cid)
+                        || self.direct.contains(cid))
+            };
+
+            pins.extend(
+                self.blocks
+                    .values()
+                    .filter(|indirect| !is_listed(&indirect.cid))
+                    .map(|indirect| (indirect.cid.to_owned(), PinMode::Indirect)),
+            );
+        }
+
+        pins
+    }
+
+    /// Returns the pins of the `ids` in the order given, once for each unique Cid. Fails if any of
+    /// the `ids` is not pinned as required.
+    pub fn query(
+        &self,
+        ids: Vec<Cid>,
+        requirement: Option<PinMode>,
+    ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
+        let check_roots = requirement != Some(PinMode::Indirect);
+        let check_indirect = requirement.is_none() || requirement == Some(PinMode::Indirect);
+
+        let mut seen = HashSet::with_capacity(ids.len());
+        let mut response = Vec::with_capacity(ids.len());
+
+        for cid in ids {
+            if !seen.insert(cid.clone()) {
+                continue;
+            }
+
+            let kind = match self.recursive.get(&cid) {
+                Some(recursive) if check_roots && requirement != Some(PinMode::Direct) => {
+                    Some(PinKind::Recursive(recursive.refs.len() as u64))
+                }
+                None if check_roots
+                    && requirement != Some(PinMode::Direct)
+                    && self.intended.contains(&cid) =>
+                {
+                    Some(PinKind::RecursiveIntention)
+                }
+                _ if check_roots
+                    && requirement != Some(PinMode::Recursive)
+                    && self.direct.contains(&cid) =>
+                {
+                    Some(PinKind::Direct)
+                }
+                _ if check_indirect => self.referrer(&cid).map(PinKind::IndirectFrom),
+                _ => None,
+            };
+
+            match kind {
+                Some(kind) => response.push((cid, kind)),
+                None => return Err(anyhow::anyhow!("{} is not pinned", cid)),
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Returns one of the recursive pins referencing the block.
+    fn referrer(&self, cid: &Cid) -> Option<Cid> {
+        let id = self.ids.get(cid)?;
+        let root = self.blocks.get(id)?.referrers.first()?;
+        self.roots.get(root).cloned()
+    }
+
+    fn to_snapshot(&self, seq: u64) -> Snapshot {
+        let mut blocks = Vec::with_capacity(self.blocks.len());
+        let mut dense = HashMap::with_capacity(self.blocks.len());
+
+        for (id, indirect) in &self.blocks {
+            dense.insert(*id, blocks.len() as u64);
+            blocks.push(indirect.cid.to_owned());
+        }
+
+        let recursive = self
+            .recursive
+            .iter()
+            .map(|(cid, recursive)| {
+                let refs = recursive.refs.iter().map(|id| dense[id]).collect();
+                (cid.to_owned(), refs)
+            })
+            .collect();
+
+        Snapshot {
+            seq,
+            direct: self.direct.iter().cloned().collect(),
+            recursive,
+            blocks,
+            intended: self.intended.iter().cloned().collect(),
+        }
+    }
+
+    fn from_snapshot(snapshot: Snapshot) -> Result<Self, Error> {
+        let mut index = PinIndex {
+            direct: snapshot.direct.into_iter().collect(),
+            intended: snapshot.intended.into_iter().collect(),
+            ..Default::default()
+        };
+
+        for (id, cid) in snapshot.blocks.into_iter().enumerate() {
+            let id = id as u64 + 1;
+            index.ids.insert(cid.clone(), id);
+            index.blocks.insert(
+                id,
+                Indirect {
+                    cid,
+                    referrers: Vec::new(),
+                },
+            );
+            index.next_id = id;
+        }
+
+        for (cid, refs) in snapshot.recursive {
+            index.next_root_id += 1;
+            let root = index.next_root_id;
+
+            let refs = refs.into_iter().map(|i| i + 1).collect::<Vec<_>>();
+            for id in &refs {
+                index
+                    .blocks
+                    .get_mut(id)
+                    .ok_or_else(|| anyhow::anyhow!("invalid reference in pin index: {}", id))?
+                    .referrers
+                    .push(root);
+            }
+
+            index.roots.insert(root, cid.clone());
+            index.recursive.insert(cid, Recursive { id: root, refs });
+        }
+
+        // blocks without references would be reported as pinned
+        let unreferenced = index
+            .blocks
+            .iter()
+            .filter(|(_, indirect)| indirect.referrers.is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in unreferenced {
+            let Indirect { cid, .. } = index.blocks.remove(&id).unwrap();
+            index.ids.remove(&cid);
+        }
+
+        Ok(index)
+    }
+
+    /// Applies the change read from a journal unless it was already included in the snapshot,
+    /// skipping the changes which cannot be applied.
+    fn replay(&mut self, last_seq: &mut u64, seq: u64, op: PinOp) {
+        if seq <= *last_seq {
+            // already included in the snapshot
+            return;
+        }
+
+        *last_seq = seq;
+
+        match self.check(&op) {
+            Ok(()) => self.apply(op),
+            Err(e) => warn!(seq, "skipping invalid change in the pin journal: {}", e),
+        }
+    }
+}
+
+/// The snapshot and the journal of the [`PinIndex`] in a directory.
+#[derive(Debug)]
+pub(super) struct IndexFiles {
+    dir: PathBuf,
+    journal: File,
+    journal_len: u64,
+    snapshot_len: u64,
+    /// The sequence number of the last journaled change.
+    seq: u64,
+}
+
+impl IndexFiles {
+    /// Loads the index from the snapshot and the journal in `dir`, creating the directory and the
+    /// journal if needed. A partially written change at the end of the journal is discarded.
+    pub fn load(dir: &Path) -> Result<(PinIndex, IndexFiles), Error> {
+        std::fs::create_dir_all(dir)?;
+
+        let (mut index, mut seq, snapshot_len) = match std::fs::read(dir.join(SNAPSHOT)) {
+            Ok(bytes) => {
+                let snapshot = Snapshot::decode(&bytes)?;
+                let seq = snapshot.seq;
+                (PinIndex::from_snapshot(snapshot)?, seq, bytes.len() as u64)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (PinIndex::default(), 0, 0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(JOURNAL))?;
+
+        let mut contents = Vec::new();
+        journal.read_to_end(&mut contents)?;
+
+        let mut remaining = &contents[..];
+        let mut valid_len = 0u64;
+
+        while !remaining.is_empty() {
+            let before = remaining.len();
+
+            let entry = pb::JournalEntry::decode_length_delimited(&mut remaining)
+                .map_err(Error::from)
+                .and_then(PinOp::from_entry);
+
+            let (entry_seq, op) = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(
+                        offset = valid_len,
+                        "discarding a partially written change at the end of the pin journal: {}",
+                        e
+                    );
+                    break;
+                }
+            };
+
+            valid_len += (before - remaining.len()) as u64;
+            index.replay(&mut seq, entry_seq, op);
+        }
+
+        if journal.metadata()?.len() != valid_len {
+            journal.set_len(valid_len)?;
+            journal.sync_all()?;
+        }
+
+        let files = IndexFiles {
+            dir: dir.to_owned(),
+            journal,
+            journal_len: valid_len,
+            snapshot_len,
+            seq,
+        };
+
+        Ok((index, files))
+    }
+
+    /// Appends the change to the journal, syncing it to the disk.
+    pub fn append(&mut self, op: &PinOp) -> Result<(), Error> {
+        let entry = op.to_entry(self.seq + 1);
+
+        let mut bytes = Vec::with_capacity(entry.encoded_len() + 10);
+        entry
+            .encode_length_delimited(&mut bytes)
+            .expect("Vec<u8> provides the needed capacity");
+
+        let written = self
+            .journal
+            .write_all(&bytes)
+            .and_then(|_| self.journal.sync_data());
+
+        if let Err(e) = written {
+            // don't leave a partial entry for the next change to follow
+            if let Err(e) = self.journal.set_len(self.journal_len) {
+                warn!(
+                    "failed to truncate the pin journal after a failed write: {}",
+                    e
+                );
+            }
+            return Err(e.into());
+        }
+
+        self.seq += 1;
+        self.journal_len += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Returns true when the journal has grown large enough to be folded into a new snapshot.
+    pub fn should_compact(&self) -> bool {
+        self.journal_len > MIN_JOURNAL_LEN.max(self.snapshot_len)
+    }
+
+    /// Writes a new snapshot of the `index`, which must include all of the journaled changes, and
+    /// empties the journal.
+    pub fn compact(&mut self, index: &PinIndex) -> Result<(), Error> {
+        let snapshot = index.to_snapshot(self.seq).encode();
+
+        let temp = self.dir.join("snapshot.tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(&snapshot)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&temp, self.dir.join(SNAPSHOT))?;
+
+        // the journaled changes are skipped by their sequence numbers if this fails
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+
+        self.journal_len = 0;
+        self.snapshot_len = snapshot.len() as u64;
+        Ok(())
+    }
+
+    /// Removes the snapshot and empties the journal.
+    pub fn wipe(&mut self) -> Result<(), Error> {
+        match std::fs::remove_file(self.dir.join(SNAPSHOT)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        self.journal.set_len(0)?;
+        self.journal.sync_all()?;
+
+        self.journal_len = 0;
+        self.snapshot_len = 0;
+        Ok(())
+    }
+}
+
+/// Converts the JSON snapshot and journal of the pin index under `dir`, written by the repo
+/// version 2, to the binary format, removing the JSON files. With `dry_run` nothing is modified.
+/// Returns the descriptions of the changes made, or to be made on a dry run.
+pub(crate) fn convert_json_index(dir: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+    let snapshot = match std::fs::read(dir.join(JSON_SNAPSHOT)) {
+        Ok(bytes) => {
+            let snapshot: JsonSnapshot = serde_json::from_slice(&bytes)?;
+            Some(Snapshot::try_from(snapshot)?)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let journal = match File::open(dir.join(JSON_JOURNAL)) {
+        Ok(journal) => Some(journal),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    if snapshot.is_none() && journal.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let (mut index, mut seq) = match snapshot {
+        Some(snapshot) => {
+            let seq = snapshot.seq;
+            (PinIndex::from_snapshot(snapshot)?, seq)
+        }
+        None => (PinIndex::default(), 0),
+    };
+
+    if let Some(journal) = journal {
+        let mut reader = BufReader::new(journal);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+
+            // a partially written change can only be at the end
+            let entry = match serde_json::from_slice::<JsonJournalEntry>(&line) {
+                Ok(entry) if line.ends_with(b"\n") => entry,
+                _ => break,
+            };
+
+            index.replay(&mut seq, entry.seq, entry.op);
+        }
+    }
+
+    let changes = vec![format!(
+        "convert the index of {} direct and {} recursive pins to the binary format",
+        index.list(Some(PinMode::Direct)).len(),
+        index.recursive.len()
+    )];
+
+    if dry_run {
+        return Ok(changes);
+    }
+
+    let (_, mut files) = IndexFiles::load(dir)?;
+    files.seq = seq;
+    files.compact(&index)?;
+
+    for name in &[JSON_SNAPSHOT, JSON_JOURNAL, "index.tmp"] {
+        match std::fs::remove_file(dir.join(name)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Indexes the pin files written by the earlier versions of [`super::FsDataStore`] under `dir`,
+/// where a direct pin was an empty file and a recursive pin a file listing all of the references,
+/// removing the pin files. With `dry_run` nothing is modified. Returns the descriptions of the
+/// changes made, or to be made on a dry run.
+pub(crate) fn index_pin_files(dir: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+    let shards = match std::fs::read_dir(dir) {
+        Ok(shards) => shards,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut direct = Vec::new();
+    let mut recursive = Vec::new();
+    let mut files = Vec::new();
+
+    for shard in shards {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() {
+            continue;
+        }
+
+        for entry in std::fs::read_dir(shard.path())? {
+            let path = entry?.path();
+            let cid = filestem_to_pin_cid(path.file_stem());
+
+            match (path.extension().and_then(|ext| ext.to_str()), cid) {
+                (Some("direct"), Some(cid)) => direct.push(cid),
+                (Some("recursive"), Some(cid)) => {
+                    let refs: Vec<String> = serde_json::from_slice(&std::fs::read(&path)?)?;
+                    let refs = refs
+                        .iter()
+                        .map(|s| Cid::try_from(s.as_str()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    recursive.push((cid, refs));
+                }
+                // interrupted writes of recursive pins
+                (Some("recursive_temp"), _) => {}
+                _ => continue,
+            }
+
+            files.push(path);
+        }
+    }
+
+    if files.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let changes = vec![format!(
+        "index {} direct and {} recursive pins",
+        direct.len(),
+        recursive.len()
+    )];
+
+    if dry_run {
+        return Ok(changes);
+    }
+
+    let (mut index, mut index_files) = IndexFiles::load(dir)?;
+
+    // the direct pins first, as the recursive pins of the same blocks take their place
+    let ops = direct.into_iter().map(PinOp::InsertDirect).chain(
+        recursive
+            .into_iter()
+            .map(|(cid, refs)| PinOp::InsertRecursive { cid, refs }),
+    );
+
+    for op in ops {
+        index.check(&op)?;
+        index.apply(op);
+    }
+
+    index_files.compact(&index)?;
+
+    for path in files {
+        std::fs::remove_file(&path)?;
+        if let Some(shard) = path.parent() {
+            // only succeeds once the shard is empty
+            std::fs::remove_dir(shard).ok();
+        }
+    }
+
+    Ok(changes)
+}
+
+mod cid_str {
+    use cid::Cid;
+    use serde::{de::Error, Deserialize, Deserializer};
+    use std::convert::TryFrom;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cid, D::Error> {
+        let s = <&str>::deserialize(deserializer)?;
+        Cid::try_from(s).map_err(D::Error::custom)
+    }
+}
+
+mod cid_strs {
+    use cid::Cid;
+    use serde::{de::Error, Deserialize, Deserializer};
+    use std::convert::TryFrom;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Cid>, D::Error> {
+        Vec::<&str>::deserialize(deserializer)?
+            .into_iter()
+            .map(|s| Cid::try_from(s).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::paths::pin_path;
+    use multihash::Sha2_256;
+    use tempfile::TempDir;
+
+    fn cid(data: &[u8]) -> Cid {
+        Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(data))
+    }
+
+    fn change(index: &mut PinIndex, files: &mut IndexFiles, op: PinOp) {
+        index.check(&op).unwrap();
+        files.append(&op).unwrap();
+        index.apply(op);
+    }
+
+    fn sorted(mut pins: Vec<(Cid, PinMode)>) -> Vec<(Cid, PinMode)> {
+        pins.sort_by_key(|(cid, _)| cid.to_string());
+        pins
+    }
+
+    #[test]
+    fn overlapping_recursive_pins_are_counted() {
+        let (a, b, shared) = (cid(b"a"), cid(b"b"), cid(b"shared"));
+        let mut index = PinIndex::default();
+
+        for (root, only) in &[(&a, cid(b"only a")), (&b, cid(b"only b"))] {
+            index.apply(PinOp::InsertRecursive {
+                cid: (*root).to_owned(),
+                refs: vec![shared.clone(), only.clone(), shared.clone()],
+            });
+        }
+
+        assert_eq!(index.blocks.len(), 3);
+        assert_eq!(index.blocks[&index.ids[&shared]].referrers.len(), 2);
+
+        index.apply(PinOp::RemoveRecursive(a.clone()));
+
+        assert!(index.is_pinned(&shared));
+        assert!(!index.is_pinned(&cid(b"only a")));
+        assert!(!index.is_pinned(&a));
+
+        let pins = index.query(vec![shared.clone()], None).unwrap();
+        assert_eq!(pins, vec![(shared, PinKind::IndirectFrom(b.clone()))]);
+
+        index.apply(PinOp::RemoveRecursive(b));
+        assert!(index.blocks.is_empty());
+        assert!(index.ids.is_empty());
+        assert!(index.roots.is_empty());
+    }
+
+    #[test]
+    fn journal_and_snapshot_are_loaded() {
+        let tmp = TempDir::new().unwrap();
+        let (root, leaf, direct) = (cid(b"root"), cid(b"leaf"), cid(b"direct"));
+
+        let (mut index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        change(&mut index, &mut files, PinOp::InsertDirect(direct.clone()));
+        change(
+            &mut index,
+            &mut files,
+            PinOp::InsertRecursive {
+                cid: root.clone(),
+                refs: vec![leaf],
+            },
+        );
+
+        let expected = sorted(index.list(None));
+        assert_eq!(expected.len(), 3);
+
+        // from the journal, ignoring the partially written change at the end
+        drop(files);
+        let journal = tmp.path().join(JOURNAL);
+        let mut contents = std::fs::read(&journal).unwrap();
+        let valid = contents.len();
+        let mut partial = Vec::new();
+        PinOp::RemoveDirect(direct.clone())
+            .to_entry(3)
+            .encode_length_delimited(&mut partial)
+            .unwrap();
+        contents.extend_from_slice(&partial[..partial.len() - 1]);
+        std::fs::write(&journal, &contents).unwrap();
+
+        let (index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(sorted(index.list(None)), expected);
+
+        // from the snapshot; the journaled changes included in it are skipped
+        files.compact(&index).unwrap();
+        std::fs::write(&journal, &contents[..valid]).unwrap();
+
+        let (mut index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(sorted(index.list(None)), expected);
+
+        // both
+        change(&mut index, &mut files, PinOp::RemoveRecursive(root));
+        drop(files);
+
+        let (index, _) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(index.list(None), vec![(direct, PinMode::Direct)]);
+    }
+
+    #[test]
+    fn legacy_pin_files_are_indexed() {
+        let tmp = TempDir::new().unwrap();
+        let (root, leaf, direct) = (cid(b"root"), cid(b"leaf"), cid(b"direct"));
+
+        write_pin_file(tmp.path(), &direct, None);
+        write_pin_file(tmp.path(), &root, Some(&[leaf.clone()]));
+
+        let changes = index_pin_files(tmp.path(), true).unwrap();
+        assert_eq!(
+            changes,
+            vec!["index 1 direct and 1 recursive pins".to_owned()]
+        );
+        assert_eq!(IndexFiles::load(tmp.path()).unwrap().0.list(None), vec![]);
+
+        index_pin_files(tmp.path(), false).unwrap();
+
+        let (index, _) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(
+            sorted(index.list(None)),
+            sorted(vec![
+                (root, PinMode::Recursive),
+                (direct, PinMode::Direct),
+                (leaf, PinMode::Indirect),
+            ])
+        );
+
+        // only the index is left
+        let mut left = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, vec![JOURNAL, SNAPSHOT]);
+
+        assert!(index_pin_files(tmp.path(), false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn intended_recursive_pins_are_loaded() {
+        let tmp = TempDir::new().unwrap();
+        let (root, leaf) = (cid(b"root"), cid(b"leaf"));
+
+        let (mut index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        change(&mut index, &mut files, PinOp::IntendRecursive(root.clone()));
+        drop(files);
+
+        let (index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        files.compact(&index).unwrap();
+        drop(files);
+
+        let (mut index, mut files) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(index.list(None), vec![(root.clone(), PinMode::Recursive)]);
+        assert_eq!(
+            index.query(vec![root.clone()], None).unwrap(),
+            vec![(root.clone(), PinKind::RecursiveIntention)]
+        );
+
+        change(
+            &mut index,
+            &mut files,
+            PinOp::InsertRecursive {
+                cid: root.clone(),
+                refs: vec![leaf],
+            },
+        );
+        assert_eq!(
+            index.query(vec![root.clone()], None).unwrap(),
+            vec![(root, PinKind::Recursive(1))]
+        );
+        assert!(index.intended.is_empty());
+    }
+
+    #[test]
+    fn json_index_is_converted() {
+        let tmp = TempDir::new().unwrap();
+        let (root, leaf, direct) = (cid(b"root"), cid(b"leaf"), cid(b"direct"));
+
+        let snapshot = serde_json::json!({
+            "version": 1,
+            "seq": 2,
+            "direct": [direct.to_string()],
+            "recursive": [{ "cid": root.to_string(), "refs": [0] }],
+            "blocks": [leaf.to_string()],
+        });
+        std::fs::write(
+            tmp.path().join(JSON_SNAPSHOT),
+            serde_json::to_vec(&snapshot).unwrap(),
+        )
+        .unwrap();
+
+        // the first change is already in the snapshot, the last one partially written
+        let journal = format!(
+            "{}\n{}\n{}",
+            serde_json::json!({ "seq": 2, "op": { "insert_direct": root.to_string() } }),
+            serde_json::json!({ "seq": 3, "op": { "remove_direct": direct.to_string() } }),
+            r#"{"seq":4,"op":{"remove_recursive":"#,
+        );
+        std::fs::write(tmp.path().join(JSON_JOURNAL), journal).unwrap();
+
+        let changes = convert_json_index(tmp.path(), true).unwrap();
+        assert_eq!(
+            changes,
+            vec!["convert the index of 0 direct and 1 recursive pins to the binary format"]
+        );
+        assert!(tmp.path().join(JSON_SNAPSHOT).exists());
+
+        convert_json_index(tmp.path(), false).unwrap();
+
+        let (index, files) = IndexFiles::load(tmp.path()).unwrap();
+        assert_eq!(files.seq, 3);
+        assert_eq!(
+            sorted(index.list(None)),
+            sorted(vec![(root, PinMode::Recursive), (leaf, PinMode::Indirect)])
+        );
+
+        let mut left = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, vec![JOURNAL, SNAPSHOT]);
+
+        assert!(convert_json_index(tmp.path(), false).unwrap().is_empty());
+    }
+
+    /// Writes the pin file of the earlier versions of [`super::FsDataStore`] for testing the
+    /// [`index_pin_files`].
+    fn write_pin_file(dir: &Path, cid: &Cid, refs: Option<&[Cid]>) {
+        let mut path = pin_path(dir.to_owned(), cid);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        match refs {
+            Some(refs) => {
+                path.set_extension("recursive");
+                let refs = refs.iter().map(|cid| cid.to_string()).collect::<Vec<_>>();
+                std::fs::write(path, serde_json::to_vec(&refs).unwrap()).unwrap();
+            }
+            None => {
+                path.set_extension("direct");
+                std::fs::write(path, b"").unwrap();
+            }
+        }
+    }
+}
@@ -1,9 +1,15 @@
 //! Volatile memory backed repo
 use crate::error::Error;
-use crate::repo::{BlockPut, BlockStore, Column, DataStore, PinKind, PinMode, PinStore};
+use crate::repo::filestore::read_reference;
+use crate::repo::{
+    BlockPut, BlockStore, BlockStoreExtensions, Column, DataStore, FileReference, FileReferences,
+    PinKind, PinMode, PinStore,
+};
 use crate::Block;
 use async_trait::async_trait;
 use cid::Cid;
+use futures::future::ready;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use std::convert::TryFrom;
 use std::path::PathBuf;
 use tokio::sync::{Mutex, OwnedMutexGuard};
@@ -19,6 +25,7 @@ use std::sync::Arc;
 #[derive(Debug, Default)]
 pub struct MemBlockStore {
     blocks: Mutex<HashMap<RepoCid, Block>>,
+    references: Mutex<HashMap<RepoCid, FileReference>>,
 }
 
 #[async_trait]
@@ -36,11 +43,9 @@ impl BlockStore for MemBlockStore {
     }
 
     async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
-        let contains = self
-            .blocks
-            .lock()
-            .await
-            .contains_key(&RepoCid(cid.to_owned()));
+        let key = RepoCid(cid.to_owned());
+        let contains = self.blocks.lock().await.contains_key(&key)
+            || self.references.lock().await.contains_key(&key);
         Ok(contains)
     }
 
@@ -50,8 +55,31 @@ impl BlockStore for MemBlockStore {
             .lock()
             .await
             .get(&RepoCid(cid.to_owned()))
-            .map(|block| block.to_owned());
-        Ok(block)
+            // the blocks are keyed by the multihash; answer with the Cid which was asked for
+            .map(|block| Block::new(block.data.clone(), cid.to_owned()));
+
+        if block.is_some() {
+            return Ok(block);
+        }
+
+        let reference = self
+            .references
+            .lock()
+            .await
+            .get(&RepoCid(cid.to_owned()))
+            .cloned();
+
+        match reference {
+            Some(reference) => {
+                let cid = cid.to_owned();
+                tokio::task::spawn_blocking(move || {
+                    let data = read_reference(&cid, &reference)?;
+                    Ok(Some(Block::new(data.into_boxed_slice(), cid)))
+                })
+                .await?
+            }
+            None => Ok(None),
+        }
     }
 
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
@@ -72,31 +100,101 @@ impl BlockStore for MemBlockStore {
     }
 
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error> {
-        match self.blocks.lock().await.remove(&RepoCid(cid.to_owned())) {
-            Some(_block) => Ok(Ok(BlockRm::Removed(cid.clone()))),
-            None => Ok(Err(BlockRmError::NotFound(cid.clone()))),
+        let key = RepoCid(cid.to_owned());
+        let block = self.blocks.lock().await.remove(&key);
+        let reference = self.references.lock().await.remove(&key);
+        match (block, reference) {
+            (None, None) => Ok(Err(BlockRmError::NotFound(cid.clone()))),
+            _ => Ok(Ok(BlockRm::Removed(cid.clone()))),
         }
     }
 
-    async fn list(&self) -> Result<Vec<Cid>, Error> {
+    /// Lists a snapshot of the blocks ordered by the multihash.
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>> {
+        let guard = self.blocks.lock().await;
+        let references = self.references.lock().await;
+        let mut cids = guard
+            .keys()
+            .chain(references.keys().filter(|cid| !guard.contains_key(cid)))
+            .map(|cid| cid.0.clone())
+            .collect::<Vec<_>>();
+        cids.sort_by(|a, b| a.hash().as_bytes().cmp(b.hash().as_bytes()));
+        stream::iter(cids.into_iter().map(Ok)).boxed()
+    }
+
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        self.list()
+            .await
+            .try_filter(move |cid| ready(cid.hash().as_bytes() > cursor.hash().as_bytes()))
+            .boxed()
+    }
+
+    async fn size(&self) -> Result<u64, Error> {
         let guard = self.blocks.lock().await;
-        Ok(guard.iter().map(|(cid, _block)| cid.0.clone()).collect())
+        Ok(guard.values().map(|block| block.data().len() as u64).sum())
+    }
+
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        BlockStoreExtensions {
+            references: Some(self),
+            ..Default::default()
+        }
     }
 
     async fn wipe(&self) {
         self.blocks.lock().await.clear();
+        self.references.lock().await.clear();
+    }
+}
+
+#[async_trait]
+impl FileReferences for MemBlockStore {
+    async fn put_reference(
+        &self,
+        cid: Cid,
+        reference: FileReference,
+    ) -> Result<(Cid, BlockPut), Error> {
+        let key = RepoCid(cid.to_owned());
+        if self.blocks.lock().await.contains_key(&key) {
+            return Ok((cid, BlockPut::Existed));
+        }
+        match self.references.lock().await.entry(key) {
+            Entry::Occupied(_) => Ok((cid, BlockPut::Existed)),
+            Entry::Vacant(ve) => {
+                ve.insert(reference);
+                Ok((cid, BlockPut::NewBlock))
+            }
+        }
+    }
+
+    async fn list_references(&self) -> Result<Vec<(Cid, FileReference)>, Error> {
+        let guard = self.references.lock().await;
+        Ok(guard
+            .iter()
+            .map(|(cid, reference)| (cid.0.clone(), reference.clone()))
+            .collect())
     }
 }
 
 #[derive(Debug, Default)]
 pub struct MemDataStore {
     ipns: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    pin_metadata: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    remote_pin_services: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
     // this could also be PinDocument however doing any serialization allows to see the required
     // error types easier
     pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
 }
 
 impl MemDataStore {
+    fn column(&self, col: Column) -> &Mutex<HashMap<Vec<u8>, Vec<u8>>> {
+        match col {
+            Column::Ipns => &self.ipns,
+            Column::PinMetadata => &self.pin_metadata,
+            Column::RemotePinServices => &self.remote_pin_services,
+        }
+    }
+
     /// Returns true if the pin document was changed, false otherwise.
     fn insert_pin<'a>(
         g: &mut OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
@@ -276,6 +374,9 @@ impl PinStore for MemDataStore {
         // this must fail if it is already fully pinned
         Self::remove_pin(&mut g, target, &kind.as_ref())?;
 
+        // an interrupted recursive pin has recorded only some of the references
+        let partial = kind == PinKind::RecursiveIntention;
+
         let target_v1 = if target.version() == cid::Version::V1 {
             target.to_owned()
         } else {
@@ -286,7 +387,12 @@ impl PinStore for MemDataStore {
         let kind = PinKind::IndirectFrom(&target_v1);
         while let Some(next) = refs.try_next().await? {
             // no rollback, nothing
-            Self::remove_pin(&mut g, &next, &kind)?;
+            match Self::remove_pin(&mut g, &next, &kind) {
+                Err(_) if partial => {}
+                res => {
+                    res?;
+                }
+            }
         }
 
         Ok(())
@@ -397,39 +503,33 @@ impl DataStore for MemDataStore {
     }
 
     async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error> {
-        let map = match col {
-            Column::Ipns => &self.ipns,
-        };
+        let map = self.column(col);
         let contains = map.lock().await.contains_key(key);
         Ok(contains)
     }
 
     async fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        let map = match col {
-            Column::Ipns => &self.ipns,
-        };
+        let map = self.column(col);
         let value = map.lock().await.get(key).map(|value| value.to_owned());
         Ok(value)
     }
 
     async fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), Error> {
-        let map = match col {
-            Column::Ipns => &self.ipns,
-        };
+        let map = self.column(col);
         map.lock().await.insert(key.to_owned(), value.to_owned());
         Ok(())
     }
 
     async fn remove(&self, col: Column, key: &[u8]) -> Result<(), Error> {
-        let map = match col {
-            Column::Ipns => &self.ipns,
-        };
+        let map = self.column(col);
         map.lock().await.remove(key);
         Ok(())
     }
 
     async fn wipe(&self) {
         self.ipns.lock().await.clear();
+        self.pin_metadata.lock().await.clear();
+        self.remote_pin_services.lock().await.clear();
         self.pin.lock().await.clear();
     }
 }
@@ -651,6 +751,12 @@ pub enum PinUpdateError {
 #[cfg(test)]
 crate::pinstore_interface_tests!(common_tests, crate::repo::mem::MemDataStore::new);
 
+#[cfg(test)]
+crate::column_interface_tests!(column_common_tests, crate::repo::mem::MemDataStore::new);
+
+#[cfg(test)]
+crate::blockstore_interface_tests!(block_common_tests, crate::repo::mem::MemBlockStore::new);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -692,6 +798,25 @@ mod tests {
         assert_eq!(get.await.unwrap(), None);
     }
 
+    #[tokio::test(max_threads = 1)]
+    async fn mem_blockstore_is_codec_agnostic() {
+        let store = MemBlockStore::new(temp_dir());
+
+        let data = b"1".to_vec().into_boxed_slice();
+        let raw = Cid::new_v1(Codec::Raw, Sha2_256::digest(&data));
+        let dag_pb = Cid::new_v1(Codec::DagProtobuf, raw.hash().to_owned());
+
+        store.put(Block::new(data, raw.clone())).await.unwrap();
+
+        assert!(store.contains(&dag_pb).await.unwrap());
+        let block = store.get(&dag_pb).await.unwrap().unwrap();
+        assert_eq!(block.cid(), &dag_pb);
+        assert_eq!(
+            store.list_multihashes().await.unwrap(),
+            vec![raw.hash().to_owned()]
+        );
+    }
+
     #[tokio::test(max_threads = 1)]
     async fn test_mem_blockstore_list() {
         let tmp = temp_dir();
@@ -708,11 +833,27 @@ mod tests {
             assert!(mem_store.contains(block.cid()).await.unwrap());
         }
 
-        let cids = mem_store.list().await.unwrap();
+        let cids = mem_store
+            .list()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
         assert_eq!(cids.len(), 3);
         for cid in cids.iter() {
             assert!(mem_store.contains(cid).await.unwrap());
         }
+
+        // resuming after each of the listed blocks lists the rest of them
+        for (i, cursor) in cids.iter().enumerate() {
+            let rest = mem_store
+                .list_after(cursor.to_owned())
+                .await
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(rest, &cids[i + 1..]);
+        }
     }
 
     #[tokio::test(max_threads = 1)]
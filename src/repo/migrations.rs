@@ -0,0 +1,379 @@
+//! Locking of the repository directory and the versioning of the on-disk format.
+//!
+//! The repository directory contains a `repo.lock` file, which is exclusively locked by the
+//! process using the repository, and a `version` file with the version of the on-disk format. Older
+//! versions are upgraded with the registered [`Migration`]s when the repository is opened.
+use crate::error::Error;
+use async_trait::async_trait;
+use fs2::FileExt;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The current version of the on-disk format.
+pub const REPO_VERSION: u32 = 3;
+
+const LOCK_FILE: &str = "repo.lock";
+const VERSION_FILE: &str = "version";
+
+/// Upgrades the on-disk format of a repository from one version to the next.
+#[async_trait]
+pub trait Migration: Debug + Send + Sync {
+    /// The version this migration upgrades from, to the next version.
+    fn upgrades_from(&self) -> u32;
+
+    /// Human readable description of the migration.
+    fn description(&self) -> String;
+
+    /// Applies the migration to the repository at `path`. When `dry_run` is true, nothing is
+    /// modified. Returns the descriptions of the changes made, or to be made on a dry run.
+    async fn run(&self, path: &Path, dry_run: bool) -> Result<Vec<String>, Error>;
+}
+
+/// Registry of the migrations, which upgrade the repositories up to the target version.
+#[derive(Clone, Debug)]
+pub struct Migrations {
+    target: u32,
+    by_version: BTreeMap<u32, Arc<dyn Migration>>,
+}
+
+impl Default for Migrations {
+    /// The built-in migrations up to [`REPO_VERSION`].
+    fn default() -> Self {
+        let mut migrations = Migrations::new(REPO_VERSION);
+        migrations.register(Arc::new(Unversioned));
+        migrations.register(Arc::new(IndexPins));
+        migrations.register(Arc::new(BinaryPinIndex));
+        migrations
+    }
+}
+
+impl Migrations {
+    /// Creates an empty registry upgrading the repositories up to `target` version.
+    pub fn new(target: u32) -> Self {
+        Migrations {
+            target,
+            by_version: Default::default(),
+        }
+    }
+
+    /// Registers a migration, replacing any previous migration from the same version.
+    pub fn register(&mut self, migration: Arc<dyn Migration>) {
+        self.by_version.insert(migration.upgrades_from(), migration);
+    }
+
+    /// The version the repositories are upgraded to.
+    pub fn target(&self) -> u32 {
+        self.target
+    }
+
+    /// Upgrades the repository at `path` to the target version, writing the new version after
+    /// each migration. Refuses to touch repositories of unknown versions. With `dry_run` nothing is
+    /// modified. Returns the descriptions of the changes made, or to be made on a dry run.
+    pub async fn run(&self, path: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+        let mut version = read_version(path).await?;
+        let mut changes = Vec::new();
+
+        if version > self.target {
+            return Err(anyhow::anyhow!(
+                "unsupported repo version {} in {:?}, the latest supported is {}",
+                version,
+                path,
+                self.target
+            ));
+        }
+
+        if let Some(missing) = (version..self.target).find(|v| !self.by_version.contains_key(v)) {
+            return Err(anyhow::anyhow!(
+                "unsupported repo version {} in {:?}, no migration from version {}",
+                version,
+                path,
+                missing
+            ));
+        }
+
+        while version < self.target {
+            let migration = &self.by_version[&version];
+
+            trace!(from = version, "{}", migration.description());
+            changes.extend(migration.run(path, dry_run).await?);
+            version += 1;
+
+            if !dry_run {
+                write_version(path, version).await?;
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Repositories created before the version file are of version 0, which differ from version 1
+/// only by the lack of the version file.
+#[derive(Debug)]
+struct Unversioned;
+
+#[async_trait]
+impl Migration for Unversioned {
+    fn upgrades_from(&self) -> u32 {
+        0
+    }
+
+    fn description(&self) -> String {
+        "add the version file".into()
+    }
+
+    async fn run(&self, _path: &Path, _dry_run: bool) -> Result<Vec<String>, Error> {
+        Ok(vec![self.description()])
+    }
+}
+
+/// Version 1 kept each pin of the filesystem datastore in its own file, with the recursive pin
+/// files listing all of the descendants. Version 2 keeps them in a single reference counted index.
+#[derive(Debug)]
+struct IndexPins;
+
+#[async_trait]
+impl Migration for IndexPins {
+    fn upgrades_from(&self) -> u32 {
+        1
+    }
+
+    fn description(&self) -> String {
+        "index the pins".into()
+    }
+
+    async fn run(&self, path: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+        let pins = path.join("datastore").join("pins");
+        tokio::task::spawn_blocking(move || super::fs::index_pin_files(&pins, dry_run)).await?
+    }
+}
+
+/// Version 2 wrote the pin index of the filesystem datastore as JSON, with the Cids as strings.
+/// Version 3 writes it in a binary format.
+#[derive(Debug)]
+struct BinaryPinIndex;
+
+#[async_trait]
+impl Migration for BinaryPinIndex {
+    fn upgrades_from(&self) -> u32 {
+        2
+    }
+
+    fn description(&self) -> String {
+        "convert the pin index to the binary format".into()
+    }
+
+    async fn run(&self, path: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+        let pins = path.join("datastore").join("pins");
+        tokio::task::spawn_blocking(move || super::fs::convert_json_index(&pins, dry_run)).await?
+    }
+}
+
+/// Reads the version of the repository at `path`, which is 0 if there is no version file.
+pub async fn read_version(path: &Path) -> Result<u32, Error> {
+    let path = path.join(VERSION_FILE);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(s) => s
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid version file {:?}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write_version(path: &Path, version: u32) -> Result<(), Error> {
+    let target = path.join(VERSION_FILE);
+    let temp = path.join("version.tmp");
+    tokio::fs::write(&temp, format!("{}\n", version)).await?;
+    tokio::fs::rename(temp, target).await?;
+    Ok(())
+}
+
+/// Creates the repository directory if needed and exclusively locks it. The lock is held until
+/// the returned file is dropped.
+pub(super) async fn lock(path: &Path) -> Result<std::fs::File, Error> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&path)?;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(path.join(LOCK_FILE))?;
+        file.try_lock_exclusive().map_err(|e| {
+            anyhow::anyhow!("repo at {:?} is locked by another process: {}", path, e)
+        })?;
+        Ok(file)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::path::PathBuf;
+
+    /// Renames the files under `path/data` by appending `.v2`.
+    #[derive(Debug)]
+    struct RenameFiles;
+
+    #[async_trait]
+    impl Migration for RenameFiles {
+        fn upgrades_from(&self) -> u32 {
+            REPO_VERSION
+        }
+
+        fn description(&self) -> String {
+            "rename the data files".into()
+        }
+
+        async fn run(&self, path: &Path, dry_run: bool) -> Result<Vec<String>, Error> {
+            let mut changes = Vec::new();
+            for entry in std::fs::read_dir(path.join("data"))? {
+                let from = entry?.path();
+                let mut to = from.clone().into_os_string();
+                to.push(".v2");
+                let to = PathBuf::from(to);
+                changes.push(format!("{:?} -> {:?}", from, to));
+                if !dry_run {
+                    std::fs::rename(from, to)?;
+                }
+            }
+            Ok(changes)
+        }
+    }
+
+    fn tmp_repo(name: &str) -> PathBuf {
+        let path = temp_dir().join(name);
+        std::fs::remove_dir_all(&path).ok();
+        std::fs::create_dir_all(path.join("data")).unwrap();
+        path
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn unversioned_is_upgraded() {
+        let path = tmp_repo("migrations_unversioned");
+
+        let changes = Migrations::default().run(&path, false).await.unwrap();
+        assert_eq!(changes, vec!["add the version file".to_owned()]);
+        assert_eq!(
+            std::fs::read_to_string(path.join("version")).unwrap(),
+            format!("{}\n", REPO_VERSION)
+        );
+
+        // nothing to do the second time
+        let changes = Migrations::default().run(&path, false).await.unwrap();
+        assert!(changes.is_empty());
+
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn registered_migrations_and_dry_run() {
+        let path = tmp_repo("migrations_registered");
+        std::fs::write(path.join("data").join("block"), b"foo").unwrap();
+
+        let mut migrations = Migrations {
+            target: REPO_VERSION + 1,
+            ..Default::default()
+        };
+
+        // the next version cannot be reached without the registered migration
+        migrations.run(&path, false).await.unwrap_err();
+        assert_eq!(read_version(&path).await.unwrap(), 0);
+
+        migrations.register(Arc::new(RenameFiles));
+
+        let changes = migrations.run(&path, true).await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(path.join("data").join("block").exists());
+        assert_eq!(read_version(&path).await.unwrap(), 0);
+
+        let changes = migrations.run(&path, false).await.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(path.join("data").join("block.v2").exists());
+        assert_eq!(read_version(&path).await.unwrap(), REPO_VERSION + 1);
+
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn refuses_unknown_versions() {
+        let path = tmp_repo("migrations_unknown");
+        std::fs::write(path.join("version"), format!("{}\n", REPO_VERSION + 1)).unwrap();
+
+        Migrations::default().run(&path, false).await.unwrap_err();
+        Migrations::default().run(&path, true).await.unwrap_err();
+
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn persistent_repo_is_locked_and_versioned() {
+        use crate::repo::{Repo, RepoOptions};
+        use crate::DynTypes;
+
+        let path = tmp_repo("migrations_repo");
+        let options = RepoOptions {
+            path: path.clone(),
+            offline: true,
+            storage_max: None,
+            storage_high_water_mark: 100,
+            migrations: Default::default(),
+            block_encryption: None,
+            block_cache: None,
+            block_compression: Default::default(),
+        };
+
+        let (first, _) = Repo::<DynTypes>::new(options.clone());
+        first.init().await.unwrap();
+        assert_eq!(read_version(&path).await.unwrap(), REPO_VERSION);
+
+        let (second, _) = Repo::<DynTypes>::new(options);
+        second.init().await.unwrap_err();
+
+        drop(first);
+        second.open().await.unwrap();
+
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn migrations_of_the_options_are_run() {
+        use crate::repo::{Repo, RepoOptions};
+        use crate::{DynTypes, IpfsOptions};
+
+        let path = tmp_repo("migrations_options");
+        std::fs::write(path.join("data").join("block"), b"foo").unwrap();
+
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.ipfs_path = path.clone();
+        opts.offline = true;
+        opts.migrations.target = REPO_VERSION + 1;
+        opts.migrations.register(Arc::new(RenameFiles));
+
+        let (repo, _) = Repo::<DynTypes>::new(RepoOptions::from(&opts));
+        repo.init().await.unwrap();
+
+        assert_eq!(read_version(&path).await.unwrap(), REPO_VERSION + 1);
+        assert!(path.join("data").join("block.v2").exists());
+
+        drop(repo);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn lock_is_exclusive() {
+        let path = tmp_repo("migrations_lock");
+
+        let first = lock(&path).await.unwrap();
+        lock(&path).await.unwrap_err();
+        drop(first);
+        lock(&path).await.unwrap();
+
+        std::fs::remove_dir_all(&path).ok();
+    }
+}
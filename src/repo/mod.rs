@@ -1,9 +1,9 @@
 //! Storage implementation(s) backing the [`crate::Ipfs`].
-use crate::error::Error;
+use crate::error::{BlockNotFound, Error};
 use crate::p2p::KadResult;
 use crate::path::IpfsPath;
 use crate::subscription::{RequestKind, SubscriptionFuture, SubscriptionRegistry};
-use crate::{Block, IpfsOptions};
+use crate::{Block, IpfsOptions, RemotePinService};
 use async_trait::async_trait;
 use cid::{self, Cid};
 use core::convert::TryFrom;
@@ -13,17 +13,38 @@ use futures::channel::{
     oneshot,
 };
 use futures::sink::SinkExt;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use libp2p::core::PeerId;
+use migrations::Migrations;
+use multihash::Multihash;
 use std::borrow::Borrow;
 use std::hash::{Hash, Hasher};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tracing_futures::Instrument;
 
 #[macro_use]
 #[cfg(test)]
 mod common_tests;
 
+mod batch;
+mod boxed;
+mod cache;
+mod encrypted;
+mod filestore;
 pub mod fs;
 pub mod mem;
+pub mod migrations;
+mod quota;
+mod verify;
+
+pub use batch::Batch;
+pub use cache::{BlockCacheStats, CachedBlockStore, DEFAULT_CACHE_CAPACITY};
+pub use encrypted::{BlockEncryption, EncryptedBlockStore, EncryptionKey};
+pub use filestore::{FileReference, FilestoreStatus};
+pub use fs::Compression;
+use quota::Quota;
+pub(crate) use verify::{digest_matches, hash_matches};
+pub use verify::{RepoRepair, RepoVerifyEntry, RepoVerifyStatus};
 
 pub trait RepoTypes: Send + Sync + 'static {
     type TBlockStore: BlockStore;
@@ -33,12 +54,33 @@ pub trait RepoTypes: Send + Sync + 'static {
 #[derive(Clone, Debug)]
 pub struct RepoOptions {
     path: PathBuf,
+    /// When true, missing blocks are not requested from the network.
+    offline: bool,
+    /// The maximum size of the block store in bytes, if any.
+    storage_max: Option<u64>,
+    /// The percentage of `storage_max` after which unpinned blocks are evicted.
+    storage_high_water_mark: u8,
+    /// Migrations run on the repository directory when opening persistent stores.
+    migrations: Migrations,
+    /// When configured, the block store is wrapped in an [`EncryptedBlockStore`].
+    block_encryption: Option<BlockEncryption>,
+    /// When configured, the block store is wrapped in a [`CachedBlockStore`] of this capacity.
+    block_cache: Option<u64>,
+    /// The compression of the blocks written, if supported by the block store.
+    block_compression: Compression,
 }
 
 impl From<&IpfsOptions> for RepoOptions {
     fn from(options: &IpfsOptions) -> Self {
         RepoOptions {
             path: options.ipfs_path.clone(),
+            offline: options.offline,
+            storage_max: options.storage_max,
+            storage_high_water_mark: options.storage_high_water_mark,
+            migrations: options.migrations.clone(),
+            block_encryption: options.block_encryption.clone(),
+            block_cache: options.block_cache,
+            block_compression: options.block_compression,
         }
     }
 }
@@ -49,6 +91,21 @@ pub fn create_repo<TRepoTypes: RepoTypes>(
     Repo::new(options)
 }
 
+/// Returns true if the Cid uses the identity multihash, and so contains the block data inline.
+pub(crate) fn is_inline(cid: &Cid) -> bool {
+    cid.hash().algorithm() == multihash::Code::Identity
+}
+
+/// Returns the block contained in the Cid, if it uses the identity multihash.
+pub(crate) fn inline_block(cid: &Cid) -> Option<Block> {
+    if is_inline(cid) {
+        let data = cid.hash().digest().to_vec().into_boxed_slice();
+        Some(Block::new(data, cid.to_owned()))
+    } else {
+        None
+    }
+}
+
 /// A wrapper for `Cid` that has a `Multihash`-based equality check
 #[derive(Debug)]
 pub struct RepoCid(Cid);
@@ -93,20 +150,146 @@ pub enum BlockRmError {
 // FIXME: why is this unpin? doesn't probably need to be since all of the futures are Box::pin'd.
 #[async_trait]
 pub trait BlockStore: Debug + Send + Sync + Unpin + 'static {
-    fn new(path: PathBuf) -> Self;
+    fn new(path: PathBuf) -> Self
+    where
+        Self: Sized;
+    /// Returns true if the blocks are kept on disk under the path given to `new`, in which case
+    /// the repository directory is locked and versioned.
+    fn is_persistent(&self) -> bool {
+        false
+    }
     async fn init(&self) -> Result<(), Error>;
     async fn open(&self) -> Result<(), Error>;
     async fn contains(&self, cid: &Cid) -> Result<bool, Error>;
     async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error>;
     async fn put(&self, block: Block) -> Result<(Cid, BlockPut), Error>;
+    /// Puts all of the blocks, returning the outcomes in the same order. Implementations should
+    /// make the blocks durable with a single pass instead of one block at a time, and not leave
+    /// any of the blocks stored on failure. The default implementation puts the blocks one at a
+    /// time.
+    async fn put_batch(&self, blocks: Vec<Block>) -> Result<Vec<(Cid, BlockPut)>, Error> {
+        let mut results = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            results.push(self.put(block).await?);
+        }
+        Ok(results)
+    }
     async fn remove(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error>;
-    async fn list(&self) -> Result<Vec<Cid>, Error>;
+    /// Lists the stored blocks lazily in an order which is stable between calls, so that the
+    /// listing can be resumed with [`BlockStore::list_after`].
+    async fn list(&self) -> BoxStream<'static, Result<Cid, Error>>;
+    /// Lists the stored blocks which come after the `cursor` in the order of
+    /// [`BlockStore::list`]. The default implementation skips the blocks until the `cursor` is
+    /// found, listing nothing if it has been removed in the meantime.
+    async fn list_after(&self, cursor: Cid) -> BoxStream<'static, Result<Cid, Error>> {
+        let mut found = false;
+        self.list()
+            .await
+            .try_filter(move |cid| {
+                let after = found;
+                found = found || cid == &cursor;
+                futures::future::ready(after)
+            })
+            .boxed()
+    }
+    /// Returns the total size of the stored blocks in bytes, as stored after any compression. The
+    /// default implementation reads all of the blocks.
+    async fn size(&self) -> Result<u64, Error> {
+        let mut size = 0;
+        let mut blocks = self.list().await;
+        while let Some(cid) = blocks.try_next().await? {
+            if let Some(block) = self.get(&cid).await? {
+                size += block.data().len() as u64;
+            }
+        }
+        Ok(size)
+    }
+    /// Lists the multihashes of the stored blocks, each reported once even if the block store keeps
+    /// the same multihash under multiple codecs.
+    async fn list_multihashes(&self) -> Result<Vec<Multihash>, Error> {
+        let mut seen = std::collections::HashSet::new();
+        self.list()
+            .await
+            .map_ok(|cid| cid.hash().to_owned())
+            .try_filter(|mh| futures::future::ready(seen.insert(mh.clone())))
+            .try_collect()
+            .await
+    }
+    /// Returns the optional capabilities of the block store, see [`BlockStoreExtensions`]. None by
+    /// default.
+    fn extensions(&self) -> BlockStoreExtensions<'_> {
+        BlockStoreExtensions::default()
+    }
     async fn wipe(&self);
 }
 
+/// The optional capabilities of a [`BlockStore`], returned by [`BlockStore::extensions`]. The
+/// block stores wrapping another block store pass on the capabilities of the wrapped one.
+#[derive(Clone, Copy, Default)]
+pub struct BlockStoreExtensions<'a> {
+    /// Storing the `raw` blocks as references to files.
+    pub references: Option<&'a dyn FileReferences>,
+    /// Moving the damaged blocks aside and cleaning up after the interrupted writes.
+    pub files: Option<&'a dyn BlockFiles>,
+    /// Compressing the stored blocks.
+    pub compression: Option<&'a dyn BlockCompression>,
+    /// Caching the blocks in memory, see [`CachedBlockStore`].
+    pub cache: Option<&'a dyn BlockCache>,
+}
+
+/// Storing the `raw` blocks as references to the files containing their content, see
+/// [`BlockStoreExtensions::references`].
+#[async_trait]
+pub trait FileReferences: Send + Sync {
+    /// Stores the `raw` block `cid` as a reference to the file containing its content, instead of
+    /// the content itself. Reading the block later on verifies the referenced content.
+    async fn put_reference(
+        &self,
+        cid: Cid,
+        reference: FileReference,
+    ) -> Result<(Cid, BlockPut), Error>;
+    /// Lists the blocks stored as references to files with [`FileReferences::put_reference`].
+    async fn list_references(&self) -> Result<Vec<(Cid, FileReference)>, Error>;
+}
+
+/// Handling of the block files for the repair of the repository, see
+/// [`BlockStoreExtensions::files`].
+#[async_trait]
+pub trait BlockFiles: Send + Sync {
+    /// Moves the block aside so that it's no longer found, but keeps the content around for later
+    /// inspection.
+    async fn quarantine(&self, cid: &Cid) -> Result<Result<BlockRm, BlockRmError>, Error>;
+    /// Lists the temporary files left over from interrupted writes.
+    async fn list_orphans(&self) -> Result<Vec<PathBuf>, Error>;
+    /// Removes or quarantines a temporary file listed by [`BlockFiles::list_orphans`].
+    async fn remove_orphan(&self, path: &Path, quarantine: bool) -> Result<(), Error>;
+}
+
+/// Compression of the stored blocks, see [`BlockStoreExtensions::compression`].
+#[async_trait]
+pub trait BlockCompression: Send + Sync {
+    /// Compresses the blocks written from now on.
+    fn set_compression(&self, compression: Compression);
+    /// Returns the total size of the stored blocks before any compression in bytes.
+    async fn logical_size(&self) -> Result<u64, Error>;
+}
+
+/// The in-memory block cache, see [`BlockStoreExtensions::cache`].
+pub trait BlockCache: Send + Sync {
+    /// Returns the counters of the block cache.
+    fn cache_stats(&self) -> BlockCacheStats;
+}
+
 #[async_trait]
 pub trait DataStore: PinStore + Debug + Send + Sync + Unpin + 'static {
-    fn new(path: PathBuf) -> Self;
+    fn new(path: PathBuf) -> Self
+    where
+        Self: Sized;
+    /// Returns true if the data is kept on disk under the path given to `new`, in which case the
+    /// repository directory is locked and versioned.
+    fn is_persistent(&self) -> bool {
+        false
+    }
     async fn init(&self) -> Result<(), Error>;
     async fn open(&self) -> Result<(), Error>;
     async fn contains(&self, col: Column, key: &[u8]) -> Result<bool, Error>;
@@ -157,9 +340,40 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error>;
 }
 
+/// The key of the single value in [`Column::RemotePinServices`].
+const REMOTE_PIN_SERVICES_KEY: &[u8] = b"services";
+
 #[derive(Clone, Copy, Debug)]
 pub enum Column {
     Ipns,
+    /// The [`PinMetadata`] of the direct and recursive pins, keyed by the pinned Cid.
+    PinMetadata,
+    /// The registered [`crate::RemotePinService`]s, all stored under a single key.
+    RemotePinServices,
+}
+
+/// The optional name and key/value metadata of a direct or recursive pin, see
+/// [`crate::Ipfs::insert_pin_with`]. Indirect pins have no metadata of their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PinMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub metadata: std::collections::BTreeMap<String, String>,
+}
+
+impl PinMetadata {
+    /// Metadata with only the given name.
+    pub fn named<S: Into<String>>(name: S) -> Self {
+        PinMetadata {
+            name: Some(name.into()),
+            metadata: Default::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.metadata.is_empty()
+    }
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -205,10 +419,38 @@ impl<C: Borrow<Cid>> PinKind<C> {
 
 #[derive(Debug)]
 pub struct Repo<TRepoTypes: RepoTypes> {
-    block_store: TRepoTypes::TBlockStore,
+    /// Boxed so that it can be wrapped according to the [`RepoOptions`].
+    block_store: Box<dyn BlockStore>,
     data_store: TRepoTypes::TDataStore,
     events: Sender<RepoEvent>,
     pub(crate) subscriptions: SubscriptionRegistry<Block, String>,
+    /// Offline repo does not send any [`RepoEvent`]s and will not wait for blocks to arrive from
+    /// the network.
+    offline: bool,
+    /// When configured, least recently used unpinned blocks are evicted to keep the size of the
+    /// block store under the limit.
+    quota: Option<Quota>,
+    /// The repository directory.
+    path: PathBuf,
+    migrations: Migrations,
+    /// The exclusively locked `repo.lock` file, held while the repository is in use.
+    lock: std::sync::Mutex<Option<std::fs::File>>,
+    /// Held while inserting pins and while removing blocks, so that a block cannot be pinned
+    /// between checking that it is unpinned and removing it.
+    pinning: tokio::sync::Mutex<()>,
+}
+
+/// Statistics on the block store, returned by [`Repo::stat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoStat {
+    /// The total size of the stored blocks in bytes, as stored after any compression.
+    pub size: u64,
+    /// The total size of the stored blocks before compression in bytes.
+    pub logical_size: u64,
+    /// The number of stored blocks.
+    pub blocks: u64,
+    /// The configured maximum size of the block store, if any.
+    pub storage_max: Option<u64>,
 }
 
 /// Events used to communicate to the swarm on repo changes.
@@ -238,23 +480,70 @@ impl TryFrom<RequestKind> for RepoEvent {
 impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     pub fn new(options: RepoOptions) -> (Self, Receiver<RepoEvent>) {
         let mut blockstore_path = options.path.clone();
-        let mut datastore_path = options.path;
+        let mut datastore_path = options.path.clone();
         blockstore_path.push("blockstore");
         datastore_path.push("datastore");
         let block_store = TRepoTypes::TBlockStore::new(blockstore_path);
         let data_store = TRepoTypes::TDataStore::new(datastore_path);
+        Self::with_stores(options, block_store, data_store)
+    }
+
+    /// Creates a repo on top of already created stores. Useful with [`crate::DynTypes`] when the
+    /// stores are selected at runtime. The path in the `options` is only used for the repository
+    /// lock, the migrations and the metadata of the block encryption.
+    pub fn with_stores(
+        options: RepoOptions,
+        block_store: TRepoTypes::TBlockStore,
+        data_store: TRepoTypes::TDataStore,
+    ) -> (Self, Receiver<RepoEvent>) {
         let (sender, receiver) = channel(1);
+        if options.block_compression != Compression::None {
+            match block_store.extensions().compression {
+                Some(compression) => compression.set_compression(options.block_compression),
+                None => warn!(
+                    "the block store does not support compression, ignoring {:?}",
+                    options.block_compression
+                ),
+            }
+        }
+        let high_water_mark = options.storage_high_water_mark;
+        let block_store: Box<dyn BlockStore> = match options.block_encryption {
+            Some(encryption) => Box::new(EncryptedBlockStore::with_encryption(
+                block_store,
+                options.path.clone(),
+                encryption,
+            )),
+            None => Box::new(block_store),
+        };
+        // the cache is outermost so that the cached blocks need not be decrypted again
+        let block_store: Box<dyn BlockStore> = match options.block_cache {
+            Some(capacity) => Box::new(CachedBlockStore::with_capacity(block_store, capacity)),
+            None => block_store,
+        };
         (
             Repo {
                 block_store,
                 data_store,
                 events: sender,
                 subscriptions: Default::default(),
+                offline: options.offline,
+                quota: options
+                    .storage_max
+                    .map(|max| Quota::new(max, high_water_mark)),
+                path: options.path,
+                migrations: options.migrations,
+                lock: Default::default(),
+                pinning: Default::default(),
             },
             receiver,
         )
     }
 
+    /// Returns true if the repo was created for a node without networking.
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
     /// Shutdowns the repo, cancelling any pending subscriptions; Likely going away after some
     /// refactoring, see notes on [`crate::Ipfs::exit_daemon`].
     pub fn shutdown(&self) {
@@ -262,31 +551,118 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     }
 
     pub async fn init(&self) -> Result<(), Error> {
+        self.lock_and_migrate().await?;
+
         let f1 = self.block_store.init();
         let f2 = self.data_store.init();
         let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
-        }
+        if r1.is_err() { r1 } else { r2 }?;
+
+        self.seed_quota().await
     }
 
     pub async fn open(&self) -> Result<(), Error> {
+        self.lock_and_migrate().await?;
+
         let f1 = self.block_store.open();
         let f2 = self.data_store.open();
         let (r1, r2) = futures::future::join(f1, f2).await;
-        if r1.is_err() {
-            r1
-        } else {
-            r2
+        if r1.is_err() { r1 } else { r2 }?;
+
+        self.seed_quota().await
+    }
+
+    /// Seeds the running total of the quota with the size of the block store, evicting some of the
+    /// already stored blocks if the block store is already over the quota.
+    async fn seed_quota(&self) -> Result<(), Error> {
+        let quota = match self.quota.as_ref() {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        // the existing blocks are listed only once they need to be evicted
+        quota.set_size(self.block_store.size().await?);
+
+        self.evict_if_needed(None).await
+    }
+
+    /// Exclusively locks the repository directory and runs the migrations on it, when either of
+    /// the stores is persistent. Fails if the repository is already in use by another process or
+    /// if the on-disk version is unknown.
+    async fn lock_and_migrate(&self) -> Result<(), Error> {
+        if !self.block_store.is_persistent() && !self.data_store.is_persistent() {
+            return Ok(());
+        }
+
+        if self.lock.lock().unwrap().is_some() {
+            // already locked and migrated by an earlier init or open
+            return Ok(());
+        }
+
+        let file = migrations::lock(&self.path).await?;
+
+        for change in self.migrations.run(&self.path, false).await? {
+            info!("migrated repo at {:?}: {}", self.path, change);
         }
+
+        *self.lock.lock().unwrap() = Some(file);
+        Ok(())
     }
 
-    /// Puts a block into the block store.
+    /// Puts a block into the block store. The blocks of the Cids using the identity multihash are
+    /// not stored, as the content is in the Cid.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
-        let cid = block.cid.clone();
+        if is_inline(&block.cid) {
+            return Ok((block.cid, BlockPut::Existed));
+        }
+
         let (_cid, res) = self.block_store.put(block.clone()).await?;
+        self.block_stored(block, res).await
+    }
+
+    /// Puts a `raw` block into the block store as a reference to the file containing the block
+    /// content, see [`FileReferences::put_reference`].
+    pub async fn put_block_reference(
+        &self,
+        block: Block,
+        reference: FileReference,
+    ) -> Result<(Cid, BlockPut), Error> {
+        if is_inline(&block.cid) {
+            return Ok((block.cid, BlockPut::Existed));
+        }
+
+        let references = self.block_store.extensions().references.ok_or_else(|| {
+            anyhow::anyhow!("storing file references is not supported by this block store")
+        })?;
+        let (_cid, res) = references
+            .put_reference(block.cid.clone(), reference)
+            .await?;
+        self.block_stored(block, res).await
+    }
+
+    /// Completes the subscriptions and announces the new block after it has been stored, and
+    /// evicts other blocks if the quota is exceeded.
+    async fn block_stored(&self, block: Block, res: BlockPut) -> Result<(Cid, BlockPut), Error> {
+        let (cid, res) = self.announce_stored(block, res).await?;
+
+        if let BlockPut::NewBlock = res {
+            self.evict_if_needed(Some(&cid)).await?;
+        }
+
+        Ok((cid, res))
+    }
+
+    /// Completes the subscriptions and announces the new block after it has been stored.
+    async fn announce_stored(&self, block: Block, res: BlockPut) -> Result<(Cid, BlockPut), Error> {
+        let cid = block.cid.clone();
+
+        if let Some(quota) = self.quota.as_ref() {
+            let size = block.data().len() as u64;
+            match res {
+                BlockPut::NewBlock => quota.stored(&cid, size),
+                BlockPut::Existed => quota.touch(&cid, Some(size)),
+            }
+        }
 
         // FIXME: this doesn't cause actual DHT providing yet, only some
         // bitswap housekeeping; we might want to not ignore the channel
@@ -295,6 +671,11 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
             self.subscriptions
                 .finish_subscription(cid.clone().into(), Ok(block));
 
+            if self.offline {
+                // there is no one to receive the event
+                return Ok((cid, res));
+            }
+
             // sending only fails if no one is listening anymore
             // and that is okay with us.
             let (tx, rx) = oneshot::channel();
@@ -321,6 +702,8 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         // cancel it?
         if let Some(block) = self.get_block_now(&cid).await? {
             Ok(block)
+        } else if self.offline {
+            Err(BlockNotFound(cid.to_owned()).into())
         } else {
             let subscription = self
                 .subscriptions
@@ -336,34 +719,248 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         }
     }
 
-    /// Retrives a block from the block store if it's available locally.
+    /// Retrives a block from the block store if it's available locally. The blocks of the Cids
+    /// using the identity multihash are always available.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
-        self.block_store.get(&cid).await
+        if let Some(block) = inline_block(cid) {
+            return Ok(Some(block));
+        }
+
+        let block = self.block_store.get(&cid).await?;
+        if let (Some(quota), Some(block)) = (self.quota.as_ref(), block.as_ref()) {
+            quota.touch(cid, Some(block.data().len() as u64));
+        }
+        Ok(block)
     }
 
-    pub async fn list_blocks(&self) -> Result<Vec<Cid>, Error> {
-        self.block_store.list().await
+    /// Returns true if the block is available locally, which the blocks of the Cids using the
+    /// identity multihash always are.
+    pub async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        if is_inline(cid) {
+            return Ok(true);
+        }
+        self.block_store.contains(cid).await
+    }
+
+    /// Lists the stored blocks lazily, optionally only the ones with the given `codec` and only
+    /// the ones after the `cursor` in the order of [`BlockStore::list`], allowing an interrupted
+    /// listing to be resumed from the last listed block.
+    pub async fn list_blocks(
+        &self,
+        codec: Option<cid::Codec>,
+        cursor: Option<Cid>,
+    ) -> BoxStream<'static, Result<Cid, Error>> {
+        let blocks = match cursor {
+            Some(cursor) => self.block_store.list_after(cursor).await,
+            None => self.block_store.list().await,
+        };
+
+        match codec {
+            Some(codec) => blocks
+                .try_filter(move |cid| futures::future::ready(cid.codec() == codec))
+                .boxed(),
+            None => blocks,
+        }
+    }
+
+    /// Lists the multihashes of the stored blocks, regardless of the codecs they were stored with.
+    pub async fn list_multihashes(&self) -> Result<Vec<Multihash>, Error> {
+        self.block_store.list_multihashes().await
+    }
+
+    /// Lists the blocks stored as references to files.
+    pub async fn list_references(&self) -> Result<Vec<(Cid, FileReference)>, Error> {
+        match self.block_store.extensions().references {
+            Some(references) => references.list_references().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Lists the blocks stored as references to files along with the status of the referenced
+    /// content.
+    pub async fn verify_references(
+        &self,
+    ) -> Result<Vec<(Cid, FileReference, FilestoreStatus)>, Error> {
+        let mut ret = Vec::new();
+        for (cid, reference) in self.list_references().await? {
+            let status = filestore::verify_reference(cid.clone(), reference.clone()).await;
+            ret.push((cid, reference, status));
+        }
+        Ok(ret)
+    }
+
+    /// Returns the size and the number of the stored blocks along with the configured limit.
+    pub async fn stat(&self) -> Result<RepoStat, Error> {
+        let size = self.block_store.size().await?;
+        let logical_size = match self.block_store.extensions().compression {
+            Some(compression) => compression.logical_size().await?,
+            None => size,
+        };
+        let blocks = self
+            .block_store
+            .list()
+            .await
+            .try_fold(0, |count, _| futures::future::ready(Ok(count + 1)))
+            .await?;
+        Ok(RepoStat {
+            size,
+            logical_size,
+            blocks,
+            storage_max: self.quota.as_ref().map(|quota| quota.storage_max),
+        })
+    }
+
+    /// Returns the counters of the block cache, if the block store is a [`CachedBlockStore`].
+    pub fn block_cache_stats(&self) -> Option<BlockCacheStats> {
+        self.block_store
+            .extensions()
+            .cache
+            .map(|cache| cache.cache_stats())
+    }
+
+    /// Evicts the least recently used unpinned blocks, if the size of the block store is over the
+    /// high-water mark of the configured quota. The blocks stored before the repo was opened are
+    /// evicted first. The `keep` block and the blocks held by the unfinished batches are never
+    /// evicted.
+    async fn evict_if_needed(&self, keep: Option<&Cid>) -> Result<(), Error> {
+        let quota = match self.quota.as_ref() {
+            Some(quota) => quota,
+            None => return Ok(()),
+        };
+
+        if quota.size() <= quota.high_water {
+            return Ok(());
+        }
+
+        // someone is already evicting, they will get the size under the limit
+        let _guard = match quota.evicting.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return Ok(()),
+        };
+
+        // the pin being inserted may be walking the blocks, and put them while doing so; the next
+        // put after it has been recorded will evict
+        let _pinning = match self.pinning.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                trace!("pins are being inserted, not evicting");
+                return Ok(());
+            }
+        };
+
+        // the running total only approximates the size, for example with the compressed blocks
+        let mut size = self.block_store.size().await?;
+        quota.set_size(size);
+        if size <= quota.high_water {
+            return Ok(());
+        }
+
+        let span = debug_span!("evicting", size, high_water = quota.high_water);
+        let keep = keep.map(|cid| RepoCid(cid.to_owned()));
+
+        async move {
+            if quota.has_unlisted() {
+                let mut blocks = self.block_store.list().await;
+                loop {
+                    if size <= quota.high_water {
+                        break;
+                    }
+
+                    let cid = match blocks.try_next().await? {
+                        Some(cid) => cid,
+                        None => {
+                            quota.listed();
+                            break;
+                        }
+                    };
+
+                    if quota.is_tracked(&cid) {
+                        continue;
+                    }
+
+                    let freed = self.evict(quota, &cid, keep.as_ref()).await?;
+                    size = size.saturating_sub(freed);
+                }
+            }
+
+            for cid in quota.least_recently_used() {
+                if size <= quota.high_water {
+                    break;
+                }
+
+                let freed = self.evict(quota, &cid, keep.as_ref()).await?;
+                size = size.saturating_sub(freed);
+            }
+
+            let size = self.block_store.size().await?;
+            quota.set_size(size);
+
+            if size > quota.high_water {
+                warn!(
+                    size,
+                    high_water = quota.high_water,
+                    "no unpinned blocks left to evict"
+                );
+            }
+
+            Ok(())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Evicts a single block unless it is kept, held or pinned. Returns the number of bytes freed.
+    /// Must be called with the `pinning` lock held.
+    async fn evict(&self, quota: &Quota, cid: &Cid, keep: Option<&RepoCid>) -> Result<u64, Error> {
+        if keep == Some(&RepoCid(cid.clone())) || quota.is_held(cid) {
+            return Ok(0);
+        }
+
+        // the sizes of the blocks stored before the repo was opened are not known
+        let block_size = match quota.size_of(cid) {
+            Some(block_size) => block_size,
+            None => match self.block_store.get(cid).await? {
+                Some(block) => block.data().len() as u64,
+                None => 0,
+            },
+        };
+
+        match self.remove_unpinned_block(cid).await {
+            Ok(_) => {
+                trace!(cid = %cid, "evicted");
+                Ok(block_size)
+            }
+            // pinned blocks cannot be removed
+            Err(e) => {
+                trace!(cid = %cid, "not evicted: {}", e);
+                Ok(0)
+            }
+        }
     }
 
     /// Remove block from the block store.
     pub async fn remove_block(&self, cid: &Cid) -> Result<Cid, Error> {
-        if self.is_pinned(&cid).await? {
+        let _pinning = self.pinning.lock().await;
+        self.remove_unpinned_block(cid).await
+    }
+
+    /// Removes the block unless it is pinned. The `pinning` lock must be held so that the block
+    /// cannot be pinned between the check and the removal.
+    async fn remove_unpinned_block(&self, cid: &Cid) -> Result<Cid, Error> {
+        if self.is_pinned_by_multihash(&cid).await? {
             return Err(anyhow::anyhow!("block to remove is pinned"));
         }
 
+        let removed = self.block_store.remove(&cid).await?;
+
         // FIXME: Need to change location of pinning logic.
         // I like this pattern of the repo abstraction being some sort of
         // "clearing house" for the underlying result enums, but this
         // could potentially be pushed out out of here up to Ipfs, idk
-        match self.block_store.remove(&cid).await? {
+        match removed {
             Ok(success) => match success {
                 BlockRm::Removed(_cid) => {
-                    // sending only fails if the background task has exited
-                    self.events
-                        .clone()
-                        .send(RepoEvent::RemovedBlock(cid.clone()))
-                        .await
-                        .ok();
+                    self.block_removed(cid).await;
                     Ok(cid.clone())
                 }
             },
@@ -373,6 +970,39 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         }
     }
 
+    /// Returns true if the block is pinned through any Cid with the same multihash. The block
+    /// stores keyed by multihash keep a single copy of the content for all of those Cids, so
+    /// removing an unpinned alias would remove the pinned block as well.
+    async fn is_pinned_by_multihash(&self, cid: &Cid) -> Result<bool, Error> {
+        if self.is_pinned(cid).await? {
+            return Ok(true);
+        }
+
+        let mut pins = self.data_store.list(None).await;
+        while let Some((pinned, _)) = pins.try_next().await? {
+            if pinned.hash() == cid.hash() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Stops tracking and providing the block after it has been removed from the block store.
+    async fn block_removed(&self, cid: &Cid) {
+        if let Some(quota) = self.quota.as_ref() {
+            quota.forget(cid);
+        }
+
+        if !self.offline {
+            // sending only fails if the background task has exited
+            self.events
+                .clone()
+                .send(RepoEvent::RemovedBlock(cid.clone()))
+                .await
+                .ok();
+        }
+    }
+
     /// Get an ipld path from the datastore.
     pub async fn get_ipns(&self, ipns: &PeerId) -> Result<Option<IpfsPath>, Error> {
         use std::str::FromStr;
@@ -404,11 +1034,80 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
         self.data_store.remove(Column::Ipns, ipns.as_bytes()).await
     }
 
+    /// Get the last ipns record published by the peer from the datastore.
+    pub async fn get_ipns_record(&self, ipns: &PeerId) -> Result<Option<Vec<u8>>, Error> {
+        self.data_store
+            .get(Column::Ipns, &crate::ipns::entry::key(ipns))
+            .await
+    }
+
+    /// Put the last ipns record published by the peer into the datastore.
+    pub async fn put_ipns_record(&self, ipns: &PeerId, record: &[u8]) -> Result<(), Error> {
+        self.data_store
+            .put(Column::Ipns, &crate::ipns::entry::key(ipns), record)
+            .await
+    }
+
+    /// Returns the metadata of the direct or recursive pin, if it has any.
+    pub async fn get_pin_metadata(&self, cid: &Cid) -> Result<Option<PinMetadata>, Error> {
+        match self
+            .data_store
+            .get(Column::PinMetadata, &cid.to_bytes())
+            .await?
+        {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the metadata of the pin, removing it if the `metadata` is empty.
+    pub async fn put_pin_metadata(&self, cid: &Cid, metadata: &PinMetadata) -> Result<(), Error> {
+        if metadata.is_empty() {
+            return self.remove_pin_metadata(cid).await;
+        }
+
+        let value = serde_json::to_vec(metadata)?;
+        self.data_store
+            .put(Column::PinMetadata, &cid.to_bytes(), &value)
+            .await
+    }
+
+    pub async fn remove_pin_metadata(&self, cid: &Cid) -> Result<(), Error> {
+        self.data_store
+            .remove(Column::PinMetadata, &cid.to_bytes())
+            .await
+    }
+
+    /// Returns the registered remote pinning services in the order of registration.
+    pub async fn get_remote_pin_services(&self) -> Result<Vec<RemotePinService>, Error> {
+        match self
+            .data_store
+            .get(Column::RemotePinServices, REMOTE_PIN_SERVICES_KEY)
+            .await?
+        {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Replaces the registered remote pinning services.
+    pub async fn put_remote_pin_services(
+        &self,
+        services: &[RemotePinService],
+    ) -> Result<(), Error> {
+        let value = serde_json::to_vec(services)?;
+        self.data_store
+            .put(Column::RemotePinServices, REMOTE_PIN_SERVICES_KEY, &value)
+            .await
+    }
+
     pub async fn insert_direct_pin(&self, cid: &Cid) -> Result<(), Error> {
+        let _pinning = self.pinning.lock().await;
         self.data_store.insert_direct_pin(cid).await
     }
 
     pub async fn insert_recursive_pin(&self, cid: &Cid, refs: References<'_>) -> Result<(), Error> {
+        let _pinning = self.pinning.lock().await;
         self.data_store.insert_recursive_pin(cid, refs).await
     }
 
@@ -0,0 +1,291 @@
+//! Storage quota for the [`super::BlockStore`], evicting the least recently used blocks.
+
+use super::RepoCid;
+use cid::Cid;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Limits on the size of the block store, along with the bookkeeping on the least recently used
+/// blocks.
+#[derive(Debug)]
+pub(super) struct Quota {
+    /// The configured maximum size of the block store in bytes.
+    pub(super) storage_max: u64,
+    /// Once the size of the block store goes over this, blocks will be evicted until it's below
+    /// this again.
+    pub(super) high_water: u64,
+    recency: Mutex<Recency>,
+    /// The blocks of the unfinished batches, which must not be evicted before they are pinned.
+    held: Mutex<HashMap<RepoCid, usize>>,
+    /// Held while evicting so that concurrent writers don't all start evicting at the same time.
+    pub(super) evicting: tokio::sync::Mutex<()>,
+    /// True until the blocks stored before the repo was opened have all been listed while
+    /// evicting. They are not tracked otherwise, and are older than any block used since.
+    unlisted: AtomicBool,
+}
+
+impl Quota {
+    /// `watermark` is the percentage of `storage_max` after which the eviction starts.
+    pub(super) fn new(storage_max: u64, watermark: u8) -> Self {
+        let watermark = u64::from(watermark.min(100));
+        Quota {
+            storage_max,
+            high_water: storage_max / 100 * watermark + storage_max % 100 * watermark / 100,
+            recency: Default::default(),
+            held: Default::default(),
+            evicting: Default::default(),
+            unlisted: AtomicBool::new(true),
+        }
+    }
+
+    /// The running total of the size of the block store.
+    pub(super) fn size(&self) -> u64 {
+        self.recency.lock().unwrap().size
+    }
+
+    /// Replaces the running total with the size read from the block store.
+    pub(super) fn set_size(&self, size: u64) {
+        self.recency.lock().unwrap().size = size;
+    }
+
+    /// Marks the block as the most recently used one. The size is recorded if known.
+    pub(super) fn touch(&self, cid: &Cid, size: Option<u64>) {
+        self.recency.lock().unwrap().touch(cid, size);
+    }
+
+    /// Marks the newly stored block as the most recently used one, adding it to the running total.
+    pub(super) fn stored(&self, cid: &Cid, size: u64) {
+        let mut recency = self.recency.lock().unwrap();
+        recency.touch(cid, Some(size));
+        recency.size += size;
+    }
+
+    /// Forgets the block after it has been removed, subtracting it from the running total if its
+    /// size is known.
+    pub(super) fn forget(&self, cid: &Cid) {
+        let mut recency = self.recency.lock().unwrap();
+        if let Some(size) = recency.forget(cid) {
+            recency.size = recency.size.saturating_sub(size);
+        }
+    }
+
+    /// Returns the recorded size of the block, if any.
+    pub(super) fn size_of(&self, cid: &Cid) -> Option<u64> {
+        self.recency.lock().unwrap().size_of(cid)
+    }
+
+    /// Returns the known blocks starting from the least recently used one.
+    pub(super) fn least_recently_used(&self) -> Vec<Cid> {
+        self.recency
+            .lock()
+            .unwrap()
+            .by_age
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if the block has been used since the repo was opened.
+    pub(super) fn is_tracked(&self, cid: &Cid) -> bool {
+        self.recency
+            .lock()
+            .unwrap()
+            .last_used
+            .contains_key(&RepoCid(cid.to_owned()))
+    }
+
+    /// Returns true if the blocks stored before the repo was opened may still need listing.
+    pub(super) fn has_unlisted(&self) -> bool {
+        self.unlisted.load(Ordering::Acquire)
+    }
+
+    /// Records that the whole block store has been listed once.
+    pub(super) fn listed(&self) {
+        self.unlisted.store(false, Ordering::Release);
+    }
+
+    /// Protects the block from eviction until it is released as many times as it was held.
+    pub(super) fn hold(&self, cid: &Cid) {
+        *self
+            .held
+            .lock()
+            .unwrap()
+            .entry(RepoCid(cid.to_owned()))
+            .or_default() += 1;
+    }
+
+    /// Releases a block held with [`Quota::hold`].
+    pub(super) fn release(&self, cid: &Cid) {
+        let mut held = self.held.lock().unwrap();
+        let key = RepoCid(cid.to_owned());
+        if let Some(count) = held.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                held.remove(&key);
+            }
+        }
+    }
+
+    /// Returns true if the block is protected from eviction.
+    pub(super) fn is_held(&self, cid: &Cid) -> bool {
+        self.held
+            .lock()
+            .unwrap()
+            .contains_key(&RepoCid(cid.to_owned()))
+    }
+}
+
+/// Orders the blocks by the "time" of their last use. The time is a counter incremented on every
+/// use.
+#[derive(Debug, Default)]
+struct Recency {
+    /// The running total of the size of the block store, so that the size does not need to be
+    /// read from the block store on every put. Synchronized with the block store when evicting.
+    size: u64,
+    clock: u64,
+    /// The time of the last use and the size of each block, if known.
+    last_used: HashMap<RepoCid, (u64, Option<u64>)>,
+    by_age: BTreeMap<u64, Cid>,
+}
+
+impl Recency {
+    fn touch(&mut self, cid: &Cid, size: Option<u64>) {
+        self.clock += 1;
+        let now = self.clock;
+        let entry = self
+            .last_used
+            .entry(RepoCid(cid.to_owned()))
+            .or_insert((now, None));
+        if entry.0 != now {
+            self.by_age.remove(&entry.0);
+            entry.0 = now;
+        }
+        if size.is_some() {
+            entry.1 = size;
+        }
+        self.by_age.insert(now, cid.to_owned());
+    }
+
+    fn forget(&mut self, cid: &Cid) -> Option<u64> {
+        let (previous, size) = self.last_used.remove(&RepoCid(cid.to_owned()))?;
+        self.by_age.remove(&previous);
+        size
+    }
+
+    fn size_of(&self, cid: &Cid) -> Option<u64> {
+        self.last_used
+            .get(&RepoCid(cid.to_owned()))
+            .and_then(|(_, size)| *size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quota;
+    use cid::{Cid, Codec};
+    use multihash::Sha2_256;
+
+    fn cid(data: &[u8]) -> Cid {
+        Cid::new_v1(Codec::Raw, Sha2_256::digest(data))
+    }
+
+    #[test]
+    fn high_water_mark() {
+        assert_eq!(Quota::new(1000, 90).high_water, 900);
+        assert_eq!(Quota::new(1000, 200).high_water, 1000);
+        assert_eq!(Quota::new(u64::MAX, 100).high_water, u64::MAX);
+    }
+
+    #[test]
+    fn least_recently_used_first() {
+        let quota = Quota::new(1000, 90);
+        let (a, b, c) = (cid(b"a"), cid(b"b"), cid(b"c"));
+
+        quota.touch(&a, None);
+        quota.touch(&b, None);
+        quota.touch(&c, None);
+        quota.touch(&a, None);
+
+        assert_eq!(quota.least_recently_used(), vec![b.clone(), c.clone(), a]);
+
+        quota.forget(&c);
+        assert_eq!(quota.least_recently_used()[0], b);
+        assert_eq!(quota.least_recently_used().len(), 2);
+    }
+
+    #[test]
+    fn running_total() {
+        let quota = Quota::new(1000, 90);
+        let (a, b, c) = (cid(b"a"), cid(b"b"), cid(b"c"));
+
+        // an existing block of an unknown size
+        quota.set_size(100);
+        quota.touch(&a, None);
+
+        quota.stored(&b, 10);
+        quota.stored(&c, 20);
+        assert_eq!(quota.size(), 130);
+
+        // the size is kept when the block is used without knowing it
+        quota.touch(&b, None);
+        assert_eq!(quota.size_of(&b), Some(10));
+
+        quota.forget(&b);
+        assert_eq!(quota.size(), 120);
+
+        // nothing is known to subtract
+        quota.forget(&a);
+        assert_eq!(quota.size(), 120);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn existing_blocks_are_evicted_first() {
+        use crate::test_support::{offline_repo_with_quota, raw};
+
+        let tmp = std::env::temp_dir().join("repo_quota_open");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let blocks = vec![raw(&[1; 40]), raw(&[2; 40]), raw(&[3; 40])];
+
+        {
+            let repo = offline_repo_with_quota(tmp.clone(), None);
+            repo.init().await.unwrap();
+            for block in &blocks {
+                repo.put_block(block.clone()).await.unwrap();
+            }
+        }
+
+        // the existing blocks are evicted to get under the quota of the reopened repo
+        let repo = offline_repo_with_quota(tmp.clone(), Some(100));
+        repo.open().await.unwrap();
+
+        let quota = repo.quota.as_ref().unwrap();
+        assert!(quota.least_recently_used().is_empty());
+        assert_eq!(quota.size(), 80);
+        assert_eq!(repo.stat().await.unwrap().blocks, 2);
+
+        // the blocks stored before opening are older than the new one
+        let new = raw(&[4; 40]);
+        repo.put_block(new.clone()).await.unwrap();
+
+        assert_eq!(quota.least_recently_used(), vec![new.cid().to_owned()]);
+        assert_eq!(repo.stat().await.unwrap().blocks, 2);
+        assert!(repo.get_block_now(new.cid()).await.unwrap().is_some());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[test]
+    fn held_blocks() {
+        let quota = Quota::new(1000, 90);
+        let a = cid(b"a");
+
+        quota.hold(&a);
+        quota.hold(&a);
+        quota.release(&a);
+        assert!(quota.is_held(&a));
+        quota.release(&a);
+        assert!(!quota.is_held(&a));
+    }
+}
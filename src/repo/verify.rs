@@ -0,0 +1,297 @@
+//! Integrity verification of the stored blocks and pins.
+use super::{BlockFiles, BlockRm, BlockStore, PinMode, PinStore, Repo, RepoTypes};
+use crate::error::Error;
+use crate::Block;
+use cid::Cid;
+use futures::stream::{Stream, TryStreamExt};
+use std::fmt;
+use std::path::PathBuf;
+
+/// The status of a single item checked by [`Repo::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoVerifyStatus {
+    /// The block could be read and the content matches the Cid.
+    Ok(Cid),
+    /// The block could be read but the content does not match the Cid.
+    HashMismatch(Cid),
+    /// The block could not be read.
+    Unreadable(Cid, String),
+    /// A temporary file left over from an interrupted write.
+    OrphanTempFile(PathBuf),
+    /// The pin points to a block which does not exist in the block store.
+    MissingPinnedBlock(Cid, PinMode),
+}
+
+impl RepoVerifyStatus {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, RepoVerifyStatus::Ok(_))
+    }
+}
+
+impl fmt::Display for RepoVerifyStatus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RepoVerifyStatus::*;
+        match self {
+            Ok(cid) => write!(fmt, "block {} ok", cid),
+            HashMismatch(cid) => write!(fmt, "block {} was corrupt: hash mismatch", cid),
+            Unreadable(cid, e) => write!(fmt, "block {} was unreadable: {}", cid, e),
+            OrphanTempFile(path) => write!(fmt, "orphaned temporary file {:?}", path),
+            MissingPinnedBlock(cid, mode) => {
+                write!(fmt, "{:?} pinned block {} is missing", mode, cid)
+            }
+        }
+    }
+}
+
+/// How the bad blocks and orphaned files found by [`Repo::verify`] are repaired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoRepair {
+    /// Moves the bad blocks and files aside, see [`BlockFiles::quarantine`].
+    Quarantine,
+    /// Removes the bad blocks and files.
+    Delete,
+}
+
+/// A single item checked by [`Repo::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoVerifyEntry {
+    pub status: RepoVerifyStatus,
+    /// True if the item was quarantined or deleted.
+    pub repaired: bool,
+}
+
+impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
+    /// Reads through all of the stored blocks checking their content against the Cid, lists the
+    /// orphaned temporary files, and checks that all pinned blocks exist. Bad blocks and orphaned
+    /// files are quarantined or deleted as they are found, if `repair` is given.
+    pub fn verify(
+        &self,
+        repair: Option<RepoRepair>,
+    ) -> impl Stream<Item = Result<RepoVerifyEntry, Error>> + Send + '_ {
+        async_stream::try_stream! {
+            let mut blocks = self.block_store.list().await;
+
+            while let Some(cid) = blocks.try_next().await? {
+                let status = match self.block_store.get(&cid).await {
+                    Ok(Some(block)) if hash_matches(&block) => RepoVerifyStatus::Ok(cid),
+                    Ok(Some(_)) => RepoVerifyStatus::HashMismatch(cid),
+                    // removed after listing
+                    Ok(None) => continue,
+                    Err(e) => RepoVerifyStatus::Unreadable(cid, e.to_string()),
+                };
+
+                let repaired = match repair {
+                    Some(repair) => self.repair(&status, repair).await?,
+                    None => false,
+                };
+
+                yield RepoVerifyEntry { status, repaired };
+            }
+
+            let orphans = match self.block_store.extensions().files {
+                Some(files) => files.list_orphans().await?,
+                None => Vec::new(),
+            };
+
+            for path in orphans {
+                let status = RepoVerifyStatus::OrphanTempFile(path);
+
+                let repaired = match repair {
+                    Some(repair) => self.repair(&status, repair).await?,
+                    None => false,
+                };
+
+                yield RepoVerifyEntry { status, repaired };
+            }
+
+            let mut pins = self.data_store.list(None).await;
+
+            while let Some((cid, mode)) = pins.try_next().await? {
+                if !self.contains(&cid).await? {
+                    let status = RepoVerifyStatus::MissingPinnedBlock(cid, mode);
+                    yield RepoVerifyEntry { status, repaired: false };
+                }
+            }
+        }
+    }
+
+    /// Returns true if the bad block or the orphaned file was quarantined or deleted.
+    async fn repair(&self, status: &RepoVerifyStatus, repair: RepoRepair) -> Result<bool, Error> {
+        match status {
+            RepoVerifyStatus::HashMismatch(cid) | RepoVerifyStatus::Unreadable(cid, _) => {
+                let removed = match (repair, self.block_store.extensions().files) {
+                    (RepoRepair::Quarantine, Some(files)) => files.quarantine(cid).await?,
+                    _ => self.block_store.remove(cid).await?,
+                };
+
+                match removed {
+                    Ok(BlockRm::Removed(_)) => {
+                        self.block_removed(cid).await;
+                        Ok(true)
+                    }
+                    Err(_) => Ok(false),
+                }
+            }
+            RepoVerifyStatus::OrphanTempFile(path) => match self.block_store.extensions().files {
+                Some(files) => {
+                    files
+                        .remove_orphan(path, repair == RepoRepair::Quarantine)
+                        .await?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+            RepoVerifyStatus::Ok(_) | RepoVerifyStatus::MissingPinnedBlock(..) => Ok(false),
+        }
+    }
+}
+
+/// Returns true if the content of the block matches the multihash of its Cid.
+pub(crate) fn hash_matches(block: &Block) -> bool {
+    digest_matches(block.cid(), block.data())
+}
+
+/// Checks that the multihash of the Cid is the digest of the data. The digest may have been
+/// truncated when the Cid was created.
+pub(crate) fn digest_matches(cid: &Cid, data: &[u8]) -> bool {
+    let hash = cid.hash();
+    let expected = hash.digest();
+    let actual = hash.algorithm().digest(data);
+    let actual = actual.digest();
+
+    if expected.len() == actual.len() {
+        expected == actual
+    } else {
+        !expected.is_empty() && actual.starts_with(expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{offline_repo, raw};
+    use crate::DynTypes;
+    use cid::Codec;
+    use futures::stream::StreamExt;
+    use multihash::Sha2_256;
+    use std::env::temp_dir;
+
+    async fn put(repo: &Repo<DynTypes>, data: &[u8]) -> Cid {
+        repo.put_block(raw(data)).await.unwrap()
+    }
+
+    /// Finds the `.data` file of the CIDv1 block from the shard directories.
+    fn stored_path(dir: &std::path::Path, cid: &Cid) -> PathBuf {
+        let name = format!("{}.data", cid);
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|shard| shard.unwrap().path().join(&name))
+            .find(|path| path.is_file())
+            .unwrap()
+    }
+
+    async fn verify(repo: &Repo<DynTypes>, repair: Option<RepoRepair>) -> Vec<RepoVerifyEntry> {
+        repo.verify(repair)
+            .map(|entry| entry.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn finds_and_quarantines_corrupt_blocks() {
+        let tmp = temp_dir().join("repo_verify_corrupt");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let good = put(&repo, b"good").await;
+        let bad = put(&repo, b"bad").await;
+
+        let bad_path = stored_path(&tmp, &bad);
+        std::fs::write(&bad_path, b"rotten").unwrap();
+        let orphan = bad_path.with_extension("tmp");
+        std::fs::write(&orphan, b"partial").unwrap();
+
+        let mut entries = verify(&repo, None).await;
+        entries.sort_by_key(|entry| entry.status.to_string());
+
+        assert_eq!(
+            entries,
+            vec![
+                RepoVerifyEntry {
+                    status: RepoVerifyStatus::HashMismatch(bad.clone()),
+                    repaired: false,
+                },
+                RepoVerifyEntry {
+                    status: RepoVerifyStatus::Ok(good.clone()),
+                    repaired: false,
+                },
+                RepoVerifyEntry {
+                    status: RepoVerifyStatus::OrphanTempFile(orphan.clone()),
+                    repaired: false,
+                },
+            ]
+        );
+
+        let entries = verify(&repo, Some(RepoRepair::Quarantine)).await;
+        assert_eq!(entries.iter().filter(|entry| entry.repaired).count(), 2);
+
+        assert!(!bad_path.exists());
+        assert!(!orphan.exists());
+        assert_eq!(
+            std::fs::read(bad_path.with_extension("data.quarantined")).unwrap(),
+            b"rotten"
+        );
+
+        let entries = verify(&repo, None).await;
+        assert_eq!(
+            entries,
+            vec![RepoVerifyEntry {
+                status: RepoVerifyStatus::Ok(good),
+                repaired: false,
+            }]
+        );
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn finds_missing_pinned_blocks() {
+        let tmp = temp_dir().join("repo_verify_pins");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let repo = offline_repo(tmp.clone());
+        repo.init().await.unwrap();
+
+        let cid = put(&repo, b"pinned").await;
+        repo.insert_direct_pin(&cid).await.unwrap();
+
+        std::fs::remove_file(stored_path(&tmp, &cid)).unwrap();
+
+        let entries = verify(&repo, Some(RepoRepair::Delete)).await;
+        assert_eq!(
+            entries,
+            vec![RepoVerifyEntry {
+                status: RepoVerifyStatus::MissingPinnedBlock(cid, PinMode::Direct),
+                repaired: false,
+            }]
+        );
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[test]
+    fn truncated_digests_match() {
+        let data = b"truncated";
+        let full = Sha2_256::digest(data);
+        let truncated = multihash::wrap(multihash::Code::Sha2_256, &full.digest()[..20]);
+
+        let cid = Cid::new_v1(Codec::Raw, truncated);
+        assert!(digest_matches(&cid, data));
+        assert!(!digest_matches(&cid, b"something else"));
+
+        let empty = multihash::wrap(multihash::Code::Sha2_256, &[]);
+        assert!(!digest_matches(&Cid::new_v1(Codec::Raw, empty), data));
+    }
+}
@@ -0,0 +1,220 @@
+//! Client of the [Delegated Routing HTTP API], which lets nodes without a DHT of their own find
+//! the providers of content and the addresses of peers.
+//!
+//! [Delegated Routing HTTP API]: https://specs.ipfs.tech/routing/http-routing-v1/
+use super::{ContentRouting, PeerInfo, PeerRouting};
+use crate::error::Error;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cid::Cid;
+use hyper::client::HttpConnector;
+use hyper::header::ACCEPT;
+use hyper::{Body, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use libp2p::{Multiaddr, PeerId};
+use serde::Deserialize;
+use std::fmt;
+
+/// Routes through a delegated routing server, such as `https://delegated-ipfs.dev`. Providing is
+/// not supported, as the API for it has been deprecated.
+pub struct DelegatedRouter {
+    endpoint: String,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+}
+
+impl fmt::Debug for DelegatedRouter {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("DelegatedRouter")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+/// The records in the responses; the older `bitswap` schema has the same fields as the `peer`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PeerRecord {
+    #[serde(rename = "ID")]
+    id: Option<String>,
+    addrs: Option<Vec<String>>,
+}
+
+impl PeerRecord {
+    fn into_peer_info(self) -> Option<PeerInfo> {
+        let peer_id = self.id?.parse::<PeerId>().ok()?;
+        let addrs = self
+            .addrs
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|addr| addr.parse::<Multiaddr>().ok())
+            .collect();
+
+        Some(PeerInfo { peer_id, addrs })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProvidersResponse {
+    providers: Option<Vec<PeerRecord>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PeersResponse {
+    peers: Option<Vec<PeerRecord>>,
+}
+
+impl DelegatedRouter {
+    /// Fails if the endpoint is not an http or https url. The endpoint is the part before
+    /// `/routing/v1`.
+    pub fn new<E: Into<String>>(endpoint: E) -> Result<Self, Error> {
+        let endpoint = endpoint.into();
+
+        let uri = endpoint
+            .parse::<Uri>()
+            .map_err(|e| anyhow!("invalid endpoint {:?}: {}", endpoint, e))?;
+
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => return Err(anyhow!("endpoint {:?} is not an http(s) url", endpoint)),
+        }
+
+        let client = hyper::Client::builder().build(HttpsConnector::with_webpki_roots());
+
+        Ok(DelegatedRouter {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            client,
+        })
+    }
+
+    /// Returns the body of a successful response, or `None` if nothing was found.
+    async fn get(&self, path: &str) -> Result<Option<hyper::body::Bytes>, Error> {
+        let req = Request::get(format!("{}/routing/v1{}", self.endpoint, path))
+            .header(ACCEPT, "application/json")
+            .body(Body::empty())?;
+
+        let resp = self.client.request(req).await?;
+        let status = resp.status();
+        let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(bytes)),
+            status => Err(anyhow!("{} responded with {}", self.endpoint, status)),
+        }
+    }
+}
+
+#[async_trait]
+impl ContentRouting for DelegatedRouter {
+    async fn find_providers(&self, cid: &Cid) -> Result<Vec<PeerInfo>, Error> {
+        let bytes = match self.get(&format!("/providers/{}", cid)).await? {
+            Some(bytes) => bytes,
+            None => return Ok(Vec::new()),
+        };
+
+        let ProvidersResponse { providers } = serde_json::from_slice(&bytes)?;
+
+        Ok(providers
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(PeerRecord::into_peer_info)
+            .collect())
+    }
+
+    async fn provide(&self, _cid: &Cid) -> Result<(), Error> {
+        Err(anyhow!(
+            "providing is not supported by the delegated router {}",
+            self.endpoint
+        ))
+    }
+}
+
+#[async_trait]
+impl PeerRouting for DelegatedRouter {
+    async fn find_peer(&self, peer_id: &PeerId) -> Result<Vec<Multiaddr>, Error> {
+        let not_found = || anyhow!("couldn't find peer {}", peer_id);
+
+        let bytes = self
+            .get(&format!("/peers/{}", peer_id))
+            .await?
+            .ok_or_else(not_found)?;
+
+        let PeersResponse { peers } = serde_json::from_slice(&bytes)?;
+
+        peers
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(PeerRecord::into_peer_info)
+            .find(|info| &info.peer_id == peer_id && !info.addrs.is_empty())
+            .map(|info| info.addrs)
+            .ok_or_else(not_found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::http_server;
+    use hyper::Response;
+    use multihash::Sha2_256;
+
+    /// Serves a single provider and peer, returning the endpoint.
+    fn stub_server(peer_id: PeerId, cid: Cid) -> String {
+        http_server(move |req: Request<Body>| {
+            let providers = format!("/routing/v1/providers/{}", cid);
+            let peers = format!("/routing/v1/peers/{}", peer_id);
+            let record = serde_json::json!({
+                "Schema": "peer",
+                "ID": peer_id.to_string(),
+                "Addrs": ["/ip4/127.0.0.1/tcp/4001", "not an address"],
+                "Protocols": ["transport-bitswap"],
+            });
+
+            let resp = if req.uri().path() == providers {
+                Response::new(Body::from(
+                    serde_json::json!({ "Providers": [record] }).to_string(),
+                ))
+            } else if req.uri().path() == peers {
+                Response::new(Body::from(
+                    serde_json::json!({ "Peers": [record] }).to_string(),
+                ))
+            } else {
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())
+                    .unwrap()
+            };
+
+            async move { resp }
+        })
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn providers_and_peers() {
+        let peer_id = PeerId::random();
+        let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"foobar"));
+        let router = DelegatedRouter::new(stub_server(peer_id.clone(), cid.clone())).unwrap();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+
+        let providers = router.find_providers(&cid).await.unwrap();
+        assert_eq!(
+            providers,
+            vec![PeerInfo {
+                peer_id: peer_id.clone(),
+                addrs: vec![addr.clone()],
+            }]
+        );
+
+        assert_eq!(router.find_peer(&peer_id).await.unwrap(), vec![addr]);
+
+        // the stub knows nothing else
+        let other = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"foo"));
+        assert!(router.find_providers(&other).await.unwrap().is_empty());
+        router.find_peer(&PeerId::random()).await.unwrap_err();
+        router.provide(&cid).await.unwrap_err();
+
+        DelegatedRouter::new("ftp://127.0.0.1:1").unwrap_err();
+    }
+}
@@ -0,0 +1,116 @@
+//! Routing through the Kademlia DHT of the swarm running in the background task.
+use super::{ContentRouting, PeerInfo, PeerRouting};
+use crate::error::Error;
+use crate::p2p::KadResult;
+use crate::IpfsEvent;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cid::Cid;
+use either::Either;
+use futures::channel::{mpsc::Sender, oneshot::channel as oneshot_channel};
+use futures::sink::SinkExt;
+use libp2p::{Multiaddr, PeerId};
+use tracing::Span;
+use tracing_futures::Instrument;
+
+/// Sends the routing requests to the swarm, see [`crate::Ipfs::find_peer`] for the peer lookups.
+#[derive(Debug)]
+pub(crate) struct KademliaRouter {
+    span: Span,
+    to_task: Sender<IpfsEvent>,
+}
+
+impl KademliaRouter {
+    pub(crate) fn new(span: Span, to_task: Sender<IpfsEvent>) -> Self {
+        KademliaRouter { span, to_task }
+    }
+}
+
+#[async_trait]
+impl ContentRouting for KademliaRouter {
+    async fn find_providers(&self, cid: &Cid) -> Result<Vec<PeerInfo>, Error> {
+        let kad_result = async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::GetProviders(cid.to_owned(), tx))
+                .await?;
+
+            Ok(rx.await?).map_err(|e: String| anyhow!(e))
+        }
+        .instrument(self.span.clone())
+        .await?
+        .await;
+
+        match kad_result {
+            Ok(KadResult::Peers(providers)) => {
+                Ok(providers.into_iter().map(PeerInfo::from).collect())
+            }
+            Ok(_) => unreachable!(),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+
+    async fn provide(&self, cid: &Cid) -> Result<(), Error> {
+        let kad_result = async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::Provide(cid.to_owned(), tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await?
+        .await;
+
+        match kad_result {
+            Ok(KadResult::Complete) => Ok(()),
+            Ok(_) => unreachable!(),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl PeerRouting for KademliaRouter {
+    /// The addresses are first searched for locally and the DHT is used as a fallback: a
+    /// `Kademlia::get_closest_peers(peer_id)` query is run and when it's finished, the newly
+    /// added DHT records are checked for the existence of the desired `peer_id` and if it's
+    /// there, the list of its known addresses is returned.
+    async fn find_peer(&self, peer_id: &PeerId) -> Result<Vec<Multiaddr>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::FindPeer(peer_id.clone(), false, tx))
+                .await?;
+
+            match rx.await? {
+                Either::Left(addrs) if !addrs.is_empty() => Ok(addrs),
+                Either::Left(_) => unreachable!(),
+                Either::Right(future) => {
+                    future.await?;
+
+                    let (tx, rx) = oneshot_channel();
+
+                    self.to_task
+                        .clone()
+                        .send(IpfsEvent::FindPeer(peer_id.clone(), true, tx))
+                        .await?;
+
+                    match rx.await? {
+                        Either::Left(addrs) if !addrs.is_empty() => Ok(addrs),
+                        _ => Err(anyhow!("couldn't find peer {}", peer_id)),
+                    }
+                }
+            }
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+}
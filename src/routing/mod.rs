@@ -0,0 +1,329 @@
+//! Content and peer routing: finding the providers of content and the addresses of peers.
+//!
+//! The [`Ipfs`](crate::Ipfs) facade queries the routers through a [`CompositeRouter`], which
+//! always contains the DHT of an online node and the routers given in
+//! [`IpfsOptions::content_routers`](crate::IpfsOptions::content_routers) and
+//! [`IpfsOptions::peer_routers`](crate::IpfsOptions::peer_routers), such as the
+//! [`DelegatedRouter`].
+use crate::error::Error;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use cid::Cid;
+use futures::future::{join_all, select_ok, FutureExt};
+use futures::stream::{BoxStream, FuturesUnordered, StreamExt};
+use libp2p::{Multiaddr, PeerId};
+use std::fmt;
+use std::sync::Arc;
+
+mod delegated;
+mod kad;
+
+pub use delegated::DelegatedRouter;
+pub(crate) use kad::KademliaRouter;
+
+/// A peer found by the routers, with the addresses the router knows for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    pub addrs: Vec<Multiaddr>,
+}
+
+impl From<PeerId> for PeerInfo {
+    fn from(peer_id: PeerId) -> Self {
+        PeerInfo {
+            peer_id,
+            addrs: Vec::new(),
+        }
+    }
+}
+
+/// Finds the providers of content and announces the local node as one.
+#[async_trait]
+pub trait ContentRouting: fmt::Debug + Send + Sync {
+    /// Returns the peers providing the content. Not finding any providers is not an error.
+    async fn find_providers(&self, cid: &Cid) -> Result<Vec<PeerInfo>, Error>;
+
+    /// Announces the local node as a provider of the content.
+    async fn provide(&self, cid: &Cid) -> Result<(), Error>;
+}
+
+/// Finds the addresses of peers.
+#[async_trait]
+pub trait PeerRouting: fmt::Debug + Send + Sync {
+    /// Returns the addresses of the peer, failing if the peer could not be found.
+    async fn find_peer(&self, peer_id: &PeerId) -> Result<Vec<Multiaddr>, Error>;
+}
+
+/// Queries all of the routers in parallel.
+#[derive(Debug, Clone, Default)]
+pub struct CompositeRouter {
+    content: Vec<Arc<dyn ContentRouting>>,
+    peer: Vec<Arc<dyn PeerRouting>>,
+}
+
+impl CompositeRouter {
+    /// Adds a router to be queried for the providers of content.
+    pub fn add_content_router(&mut self, router: Arc<dyn ContentRouting>) {
+        self.content.push(router);
+    }
+
+    /// Adds a router to be queried for the addresses of peers.
+    pub fn add_peer_router(&mut self, router: Arc<dyn PeerRouting>) {
+        self.peer.push(router);
+    }
+
+    /// Streams the providers found by the routers as soon as each of the routers answers, so
+    /// that a fast router does not wait for the slower ones. The same provider is yielded once
+    /// for every router which found it. Fails at the end only if all of the routers fail.
+    pub fn providers(&self, cid: &Cid) -> BoxStream<'static, Result<PeerInfo, Error>> {
+        let routers = self.content.clone();
+        let cid = cid.to_owned();
+
+        async_stream::stream! {
+            if routers.is_empty() {
+                yield Err(anyhow!("no content routers"));
+                return;
+            }
+
+            let mut lookups = routers
+                .iter()
+                .map(|router| router.find_providers(&cid))
+                .collect::<FuturesUnordered<_>>();
+
+            let mut succeeded = false;
+            let mut first_error = None;
+
+            while let Some(result) = lookups.next().await {
+                match result {
+                    Ok(found) => {
+                        succeeded = true;
+                        for info in found {
+                            yield Ok(info);
+                        }
+                    }
+                    Err(e) => {
+                        debug!("content router failed to find providers for {}: {}", cid, e);
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+
+            if let Some(e) = first_error {
+                if !succeeded {
+                    yield Err(e);
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl ContentRouting for CompositeRouter {
+    /// Returns the providers found by any of the routers, merging the addresses of the providers
+    /// found by many. Fails only if all of the routers fail.
+    async fn find_providers(&self, cid: &Cid) -> Result<Vec<PeerInfo>, Error> {
+        let mut found = self.providers(cid);
+        let mut providers: Vec<PeerInfo> = Vec::new();
+
+        while let Some(info) = found.next().await {
+            let info = info?;
+            match providers.iter_mut().find(|p| p.peer_id == info.peer_id) {
+                Some(existing) => {
+                    for addr in info.addrs {
+                        if !existing.addrs.contains(&addr) {
+                            existing.addrs.push(addr);
+                        }
+                    }
+                }
+                None => providers.push(info),
+            }
+        }
+
+        Ok(providers)
+    }
+
+    /// Succeeds if any of the routers succeeds.
+    async fn provide(&self, cid: &Cid) -> Result<(), Error> {
+        if self.content.is_empty() {
+            return Err(anyhow!("no content routers"));
+        }
+
+        let results = join_all(self.content.iter().map(|router| router.provide(cid))).await;
+
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    debug!("content router failed to provide {}: {}", cid, e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(first_error.expect("there was at least one router"))
+    }
+}
+
+#[async_trait]
+impl PeerRouting for CompositeRouter {
+    /// Returns the addresses from the first router to find the peer.
+    async fn find_peer(&self, peer_id: &PeerId) -> Result<Vec<Multiaddr>, Error> {
+        if self.peer.is_empty() {
+            return Err(anyhow!("no peer routers"));
+        }
+
+        let lookups = self
+            .peer
+            .iter()
+            .map(|router| router.find_peer(peer_id).boxed());
+
+        select_ok(lookups).await.map(|(addrs, _)| addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multihash::Sha2_256;
+    use std::time::Duration;
+
+    /// Router which knows a single provider or peer address after a delay, or fails.
+    #[derive(Debug)]
+    struct Mock {
+        found: Option<PeerInfo>,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl ContentRouting for Mock {
+        async fn find_providers(&self, _cid: &Cid) -> Result<Vec<PeerInfo>, Error> {
+            tokio::time::delay_for(self.delay).await;
+            self.found
+                .clone()
+                .map(|info| vec![info])
+                .ok_or_else(|| anyhow!("failed"))
+        }
+
+        async fn provide(&self, _cid: &Cid) -> Result<(), Error> {
+            self.found
+                .as_ref()
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("failed"))
+        }
+    }
+
+    #[async_trait]
+    impl PeerRouting for Mock {
+        async fn find_peer(&self, _peer_id: &PeerId) -> Result<Vec<Multiaddr>, Error> {
+            tokio::time::delay_for(self.delay).await;
+            self.found
+                .clone()
+                .map(|info| info.addrs)
+                .ok_or_else(|| anyhow!("failed"))
+        }
+    }
+
+    fn peer(peer_id: &PeerId, addr: &str) -> Option<PeerInfo> {
+        Some(PeerInfo {
+            peer_id: peer_id.clone(),
+            addrs: vec![addr.parse().unwrap()],
+        })
+    }
+
+    fn composite(mocks: Vec<Mock>) -> CompositeRouter {
+        let mut router = CompositeRouter::default();
+        for mock in mocks {
+            let mock = Arc::new(mock);
+            router.add_content_router(mock.clone());
+            router.add_peer_router(mock);
+        }
+        router
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn providers_are_merged() {
+        let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"foobar"));
+        let peer_id = PeerId::random();
+
+        let router = composite(vec![
+            Mock {
+                found: peer(&peer_id, "/ip4/127.0.0.1/tcp/1"),
+                delay: Duration::from_millis(10),
+            },
+            Mock {
+                found: peer(&peer_id, "/ip4/127.0.0.1/tcp/2"),
+                delay: Duration::from_millis(0),
+            },
+            Mock {
+                found: None,
+                delay: Duration::from_millis(0),
+            },
+        ]);
+
+        let providers = router.find_providers(&cid).await.unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].addrs.len(), 2);
+        router.provide(&cid).await.unwrap();
+
+        let failing = composite(vec![Mock {
+            found: None,
+            delay: Duration::from_millis(0),
+        }]);
+        failing.find_providers(&cid).await.unwrap_err();
+        failing.provide(&cid).await.unwrap_err();
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn providers_are_streamed_as_found() {
+        let cid = Cid::new_v1(cid::Codec::Raw, Sha2_256::digest(b"foobar"));
+        let (slow, fast) = (PeerId::random(), PeerId::random());
+
+        let router = composite(vec![
+            Mock {
+                found: peer(&slow, "/ip4/127.0.0.1/tcp/1"),
+                delay: Duration::from_secs(60),
+            },
+            Mock {
+                found: peer(&fast, "/ip4/127.0.0.1/tcp/2"),
+                delay: Duration::from_millis(10),
+            },
+        ]);
+
+        let first = tokio::time::timeout(Duration::from_secs(5), router.providers(&cid).next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.peer_id, fast);
+    }
+
+    #[tokio::test(max_threads = 1)]
+    async fn first_found_peer_wins() {
+        let peer_id = PeerId::random();
+
+        let router = composite(vec![
+            Mock {
+                found: None,
+                delay: Duration::from_millis(0),
+            },
+            Mock {
+                found: peer(&peer_id, "/ip4/127.0.0.1/tcp/1"),
+                delay: Duration::from_secs(60),
+            },
+            Mock {
+                found: peer(&peer_id, "/ip4/127.0.0.1/tcp/2"),
+                delay: Duration::from_millis(10),
+            },
+        ]);
+
+        let addrs = router.find_peer(&peer_id).await.unwrap();
+        assert_eq!(addrs, vec!["/ip4/127.0.0.1/tcp/2".parse().unwrap()]);
+
+        CompositeRouter::default()
+            .find_peer(&peer_id)
+            .await
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,63 @@
+//! Helpers shared by the tests of the crate.
+use crate::repo::fs::FsBlockStore;
+use crate::repo::mem::MemDataStore;
+use crate::repo::{BlockStore, DataStore, Repo, RepoOptions};
+use crate::{Block, DynTypes, IpfsOptions};
+use cid::{Cid, Codec};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use multihash::Sha2_256;
+use std::convert::Infallible;
+use std::future::Future;
+use std::path::PathBuf;
+
+/// Creates a raw block of the data with a CIDv1.
+pub(crate) fn raw(data: &[u8]) -> Block {
+    let cid = Cid::new_v1(Codec::Raw, Sha2_256::digest(data));
+    Block::new(data.into(), cid)
+}
+
+/// Creates an offline repo storing the blocks in the directory.
+pub(crate) fn offline_repo(path: PathBuf) -> Repo<DynTypes> {
+    offline_repo_with_quota(path, None)
+}
+
+/// Creates an offline repo storing the blocks in the directory, limited to `storage_max` bytes.
+pub(crate) fn offline_repo_with_quota(path: PathBuf, storage_max: Option<u64>) -> Repo<DynTypes> {
+    let mut options = IpfsOptions::inmemory_with_generated_keys();
+    options.ipfs_path = path.clone();
+    options.offline = true;
+    options.storage_max = storage_max;
+    options.storage_high_water_mark = 100;
+
+    let (repo, _) = Repo::with_stores(
+        RepoOptions::from(&options),
+        Box::new(FsBlockStore::new(path)) as Box<dyn BlockStore>,
+        Box::new(MemDataStore::new(PathBuf::new())) as Box<dyn DataStore>,
+    );
+    repo
+}
+
+/// Serves the requests on a local port with the handler, returning the `http://` endpoint.
+pub(crate) fn http_server<F, Fut>(handler: F) -> String
+where
+    F: Fn(Request<Body>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let resp = handler(req);
+                async move { Ok::<_, Infallible>(resp.await) }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let endpoint = format!("http://{}/", server.local_addr());
+    tokio::spawn(async move {
+        server.await.unwrap();
+    });
+    endpoint
+}
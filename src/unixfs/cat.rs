@@ -49,7 +49,13 @@ where
     let mut cache = None;
     // Start the visit from the root block. We need to move the both components as Options into the
     // stream as we can't yet return them from this Future context.
-    let (visit, bytes) = match visit.start(&data) {
+    let started = if cid.codec() == cid::Codec::Raw {
+        Ok(visit.start_raw(&data))
+    } else {
+        visit.start(&data)
+    };
+
+    let (visit, bytes) = match started {
         Ok((bytes, _, _, visit)) => {
             let bytes = if !bytes.is_empty() {
                 Some(bytes.to_vec())
@@ -189,10 +189,14 @@ async fn dht_get_put() {
     let (nodes, foreign_node) = spawn_bootstrapped_nodes(CHAIN_LEN).await;
     let last_index = CHAIN_LEN - if foreign_node.is_none() { 1 } else { 2 };
 
-    let (key, value) = (b"key".to_vec(), b"value".to_vec());
+    // only the records of the known namespaces are accepted
+    let mut key = b"/pk/".to_vec();
+    key.extend_from_slice(nodes[last_index].id.as_bytes());
+    let (public_key, _) = nodes[last_index].identity().await.unwrap();
+    let value = public_key.into_protobuf_encoding();
     let quorum = Quorum::One;
 
-    // the last node puts a key+value record
+    // the last node puts its public key record
     nodes[last_index]
         .dht_put(key.clone(), value.clone(), quorum)
         .await
@@ -201,3 +205,21 @@ async fn dht_get_put() {
     // and the first node should be able to get it
     assert_eq!(nodes[0].dht_get(key, quorum).await.unwrap(), vec![value]);
 }
+
+/// Check that the records of the known namespaces are validated before they are put.
+#[tokio::test(max_threads = 1)]
+async fn dht_put_validates_records() {
+    let nodes = spawn_nodes(2, Topology::None).await;
+
+    let mut key = b"/pk/".to_vec();
+    key.extend_from_slice(nodes[1].id.as_bytes());
+
+    // the public key of another peer
+    let (public_key, _) = nodes[0].identity().await.unwrap();
+    let err = nodes[0]
+        .dht_put(key, public_key.into_protobuf_encoding(), Quorum::One)
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("invalid record"), "{}", err);
+}
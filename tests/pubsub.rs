@@ -139,6 +139,65 @@ async fn publish_between_two_nodes() {
     assert!(disappeared, "timed out before a saw b's unsubscription");
 }
 
+#[tokio::test(max_threads = 1)]
+async fn ipns_over_pubsub() {
+    use ipfs::{IpfsOptions, IpfsPath};
+
+    // separate dhts, so that the names can only be resolved over pubsub
+    let mut nodes = Vec::with_capacity(2);
+    for i in 0..2 {
+        let mut opts = IpfsOptions::inmemory_with_generated_keys();
+        opts.kad_protocol = Some(format!("/ipfs/test{}/kad/1.0.0", i));
+        opts.ipns_pubsub = true;
+        nodes.push(Node::with_options(opts).await);
+    }
+    nodes[0].connect(nodes[1].addrs[0].clone()).await.unwrap();
+
+    let first: IpfsPath = "/ipfs/QmPZ9gcCEpqKTo6aq61g2nXGUhM4iCL3ewB6LDXZCtioEB"
+        .parse()
+        .unwrap();
+    let second: IpfsPath = "/ipfs/QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
+        .parse()
+        .unwrap();
+
+    // published before the other node subscribes, which gets the record once the publisher sees
+    // it join the topic
+    let name = nodes[0]
+        .publish_ipns(&first, Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert_eq!(name, IpfsPath::from(nodes[0].id.clone()));
+
+    assert_eq!(nodes[1].resolve_ipns(&name, false).await.unwrap(), first);
+    assert_eq!(
+        nodes[1].ipns_pubsub_subscriptions().unwrap(),
+        vec![nodes[0].id.clone()]
+    );
+
+    // the subscriber keeps receiving the newer records
+    nodes[0]
+        .publish_ipns(&second, Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    let mut updated = false;
+    for _ in 0..100usize {
+        if nodes[1].resolve_ipns(&name, false).await.unwrap() == second {
+            updated = true;
+            break;
+        }
+        timeout(Duration::from_millis(100), pending::<()>())
+            .await
+            .unwrap_err();
+    }
+
+    assert!(updated, "timed out before the newer record was received");
+
+    assert!(nodes[1].ipns_pubsub_cancel(&nodes[0].id).unwrap());
+    assert!(!nodes[1].ipns_pubsub_cancel(&nodes[0].id).unwrap());
+    assert!(nodes[1].ipns_pubsub_subscriptions().unwrap().is_empty());
+}
+
 #[cfg(any(feature = "test_go_interop", feature = "test_js_interop"))]
 #[tokio::test(max_threads = 1)]
 #[ignore = "doesn't work yet"]
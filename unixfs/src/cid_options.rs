@@ -0,0 +1,244 @@
+//! Options for creating the Cids of the blocks: the hash function, the Cid version and the length
+//! of the multihash digest.
+
+use cid::{Cid, Codec, Version};
+use core::fmt;
+use multihash::Code;
+
+/// The way the Cids are created for the blocks. Defaults to the full length sha2-256 multihash
+/// and Cid version 0, which is what go-ipfs uses by default.
+///
+/// Version 0 can only be used with `dag-pb` blocks, so the blocks of other codecs always get
+/// version 1 Cids, like the raw leaves of the files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidOptions {
+    hash: Code,
+    version: Version,
+    mhlen: Option<usize>,
+    inline_limit: Option<usize>,
+}
+
+impl Default for CidOptions {
+    fn default() -> Self {
+        CidOptions {
+            hash: Code::Sha2_256,
+            version: Version::V0,
+            mhlen: None,
+            inline_limit: None,
+        }
+    }
+}
+
+impl CidOptions {
+    /// Creates options for hashing with the given hash function and creating Cids of the given
+    /// version, with the digests optionally truncated to `mhlen` bytes. Version 0 requires the
+    /// full length sha2-256.
+    pub fn new(
+        hash: Code,
+        version: Version,
+        mhlen: Option<usize>,
+    ) -> Result<Self, InvalidCidOptions> {
+        let max = hash.digest(&[]).digest().len();
+
+        let mhlen = match mhlen {
+            Some(len) if hash == Code::Identity || len == 0 || len > max => {
+                return Err(InvalidCidOptions::InvalidLength { len, max })
+            }
+            Some(len) if len == max => None,
+            other => other,
+        };
+
+        if version == Version::V0 && (hash != Code::Sha2_256 || mhlen.is_some()) {
+            return Err(InvalidCidOptions::UnsupportedVersion0);
+        }
+
+        Ok(CidOptions {
+            hash,
+            version,
+            mhlen,
+            inline_limit: None,
+        })
+    }
+
+    /// The hash function used for the blocks.
+    pub fn hash(&self) -> Code {
+        self.hash
+    }
+
+    /// The version of the Cids for the `dag-pb` blocks.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The length of the truncated multihash digests, if they are truncated.
+    pub fn mhlen(&self) -> Option<usize> {
+        self.mhlen
+    }
+
+    /// The largest block size to inline into the Cids, if the blocks are inlined.
+    pub fn inline_limit(&self) -> Option<usize> {
+        self.inline_limit
+    }
+
+    /// Sets the largest block size to inline into the Cids with the identity multihash.
+    pub fn with_inline_limit(mut self, inline_limit: Option<usize>) -> Self {
+        self.inline_limit = inline_limit;
+        self
+    }
+
+    /// Checks that a block of `len` bytes can be hashed with these options. The identity hash
+    /// copies the whole block into the Cid, so like in go-ipfs it is only allowed for blocks of at
+    /// most the inline limit, or [`crate::DEFAULT_INLINE_LIMIT`] when not inlining.
+    pub fn check_len(&self, len: usize) -> Result<(), InvalidCidOptions> {
+        let max = self.inline_limit.unwrap_or(crate::DEFAULT_INLINE_LIMIT);
+        if self.hash == Code::Identity && len > max {
+            return Err(InvalidCidOptions::IdentityTooLong { len, max });
+        }
+        Ok(())
+    }
+
+    /// Like [`CidOptions::cid`] but fails for the blocks [`CidOptions::check_len`] rejects.
+    pub fn try_cid(&self, codec: Codec, block: &[u8]) -> Result<Cid, InvalidCidOptions> {
+        self.check_len(block.len())?;
+        Ok(self.cid(codec, block))
+    }
+
+    /// Returns the Cid for the block of the given codec. Blocks of at most the inline limit
+    /// bytes get the version 1 Cid with the identity multihash containing the block.
+    pub fn cid(&self, codec: Codec, block: &[u8]) -> Cid {
+        match self.inline_limit {
+            Some(limit) if block.len() <= limit => {
+                return Cid::new_v1(codec, multihash::Identity::digest(block));
+            }
+            _ => {}
+        }
+
+        let mh = self.hash.digest(block);
+        let mh = match self.mhlen {
+            Some(len) => multihash::wrap(self.hash, &mh.digest()[..len]),
+            None => mh,
+        };
+
+        if self.version == Version::V0 && codec == Codec::DagProtobuf {
+            Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+        } else {
+            Cid::new_v1(codec, mh)
+        }
+    }
+}
+
+/// The combination of the hash function, Cid version and multihash length is not supported.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidCidOptions {
+    /// Cid version 0 can only be used with the full length sha2-256.
+    UnsupportedVersion0,
+    /// The multihash length was zero, longer than the digest of the hash function or given for
+    /// the identity hash.
+    InvalidLength {
+        /// The requested length
+        len: usize,
+        /// The length of the full digest
+        max: usize,
+    },
+    /// The block is too large to be hashed with the identity hash.
+    IdentityTooLong {
+        /// The length of the block
+        len: usize,
+        /// The largest allowed length
+        max: usize,
+    },
+}
+
+impl fmt::Display for InvalidCidOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use InvalidCidOptions::*;
+        match self {
+            UnsupportedVersion0 => write!(fmt, "cidv0 only supports the full length sha2-256"),
+            InvalidLength { len, max } => write!(
+                fmt,
+                "invalid multihash length {}, the digest is {} bytes",
+                len, max
+            ),
+            IdentityTooLong { len, max } => write!(
+                fmt,
+                "block of {} bytes is too large for the identity hash, the limit is {} bytes",
+                len, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidCidOptions {}
+
+#[cfg(test)]
+mod tests {
+    use super::{CidOptions, InvalidCidOptions};
+    use cid::{Codec, Version};
+    use multihash::Code;
+
+    #[test]
+    fn default_is_cidv0_for_dag_pb() {
+        let opts = CidOptions::default();
+
+        let cid = opts.cid(Codec::DagProtobuf, b"foobar\n");
+        assert_eq!(cid.version(), Version::V0);
+
+        let cid = opts.cid(Codec::Raw, b"foobar\n");
+        assert_eq!(cid.version(), Version::V1);
+        assert_eq!(cid.hash().algorithm(), Code::Sha2_256);
+    }
+
+    #[test]
+    fn truncated_digests() {
+        let opts = CidOptions::new(Code::Sha2_512, Version::V1, Some(20)).unwrap();
+        let cid = opts.cid(Codec::DagProtobuf, b"foobar\n");
+
+        assert_eq!(cid.version(), Version::V1);
+        assert_eq!(cid.hash().algorithm(), Code::Sha2_512);
+        assert_eq!(
+            cid.hash().digest(),
+            &Code::Sha2_512.digest(b"foobar\n").digest()[..20]
+        );
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert_eq!(
+            CidOptions::new(Code::Blake2b256, Version::V0, None).unwrap_err(),
+            InvalidCidOptions::UnsupportedVersion0
+        );
+        assert_eq!(
+            CidOptions::new(Code::Sha2_256, Version::V0, Some(20)).unwrap_err(),
+            InvalidCidOptions::UnsupportedVersion0
+        );
+        assert_eq!(
+            CidOptions::new(Code::Sha2_256, Version::V1, Some(33)).unwrap_err(),
+            InvalidCidOptions::InvalidLength { len: 33, max: 32 }
+        );
+        // the full length is the same as not truncating
+        assert_eq!(
+            CidOptions::new(Code::Sha2_256, Version::V0, Some(32)).unwrap(),
+            CidOptions::default()
+        );
+    }
+
+    #[test]
+    fn identity_is_limited_to_the_inline_limit() {
+        let opts = CidOptions::new(Code::Identity, Version::V1, None).unwrap();
+
+        let cid = opts.try_cid(Codec::Raw, b"foobar\n").unwrap();
+        assert_eq!(cid.hash().algorithm(), Code::Identity);
+
+        let block = vec![0u8; crate::DEFAULT_INLINE_LIMIT + 1];
+        assert_eq!(
+            opts.try_cid(Codec::Raw, &block).unwrap_err(),
+            InvalidCidOptions::IdentityTooLong {
+                len: crate::DEFAULT_INLINE_LIMIT + 1,
+                max: crate::DEFAULT_INLINE_LIMIT
+            }
+        );
+
+        let opts = opts.with_inline_limit(Some(64));
+        assert!(opts.try_cid(Codec::Raw, &block).is_ok());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::CidOptions;
 use cid::Cid;
 use core::fmt;
 
@@ -55,6 +56,7 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    cid_options: CidOptions,
 }
 
 impl Default for TreeOptions {
@@ -63,6 +65,7 @@ impl Default for TreeOptions {
             // this is just a guess; our bitswap message limit is a bit more
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            cid_options: CidOptions::default(),
         }
     }
 }
@@ -79,6 +82,19 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// When set, the directory blocks of at most `limit` bytes are inlined into their Cids with
+    /// the identity multihash, like `ipfs add --inline` does with the limit of
+    /// [`crate::DEFAULT_INLINE_LIMIT`]. Defaults to `None`.
+    pub fn inline_limit(&mut self, limit: Option<usize>) {
+        self.cid_options = self.cid_options.with_inline_limit(limit);
+    }
+
+    /// Configures the hash function and the Cid version of the directory blocks, defaults to
+    /// sha2-256 and Cid version 0.
+    pub fn cid_options(&mut self, cid_options: CidOptions) {
+        self.cid_options = cid_options.with_inline_limit(self.cid_options.inline_limit());
+    }
 }
 
 /// Tree building failure cases.
@@ -396,6 +396,35 @@ mod tests {
         verify_results(expected, actual);
     }
 
+    #[test]
+    fn small_directories_are_inlined() {
+        let mut opts = TreeOptions::default();
+        opts.inline_limit(Some(8));
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.set_metadata("a/b", Metadata::default()).unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| res.map(|n| (n.path, n.cid, n.block)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let paths = actual
+            .iter()
+            .map(|(path, ..)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, &["a/b", "a"]);
+
+        for (path, cid, block) in actual {
+            let inlined = cid.hash().algorithm() == multihash::Code::Identity;
+            assert_eq!(inlined, block.len() <= 8, "{}: {}", path, cid);
+            if inlined {
+                assert_eq!(cid.codec(), cid::Codec::DagProtobuf);
+                assert_eq!(cid.hash().digest(), &block[..]);
+            }
+        }
+    }
+
     fn verify_results(
         mut expected: Vec<(
             impl AsRef<str> + core::fmt::Debug,
@@ -1,6 +1,7 @@
 use super::{
     CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
 };
+use crate::CidOptions;
 use cid::Cid;
 use core::fmt;
 use std::collections::HashMap;
@@ -81,10 +82,10 @@ impl PostOrderIterator {
         links: &[Option<NamedLeaf>],
         buffer: &mut Vec<u8>,
         block_size_limit: &Option<u64>,
+        cid_options: &CidOptions,
     ) -> Result<Leaf, TreeConstructionFailed> {
         use crate::pb::{UnixFs, UnixFsType};
         use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
 
         // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
         // need to introduce states in to the "iterator":
@@ -141,8 +142,7 @@ impl PostOrderIterator {
 
         buffer.truncate(size);
 
-        let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&buffer));
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+        let cid = cid_options.cid(cid::Codec::DagProtobuf, buffer);
 
         let combined_from_links = links
             .iter()
@@ -231,6 +231,7 @@ impl PostOrderIterator {
                         &leaves,
                         buffer,
                         &self.opts.block_size_limit,
+                        &self.opts.cid_options,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -278,6 +279,7 @@ impl PostOrderIterator {
                         &leaves,
                         buffer,
                         &self.opts.block_size_limit,
+                        &self.opts.cid_options,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
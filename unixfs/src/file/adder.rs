@@ -1,24 +1,27 @@
 use cid::Cid;
 
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::CidOptions;
 use alloc::borrow::Cow;
 use core::fmt;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
-
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
 /// chunker and collector.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
+/// Current implementation maintains an internal buffer for the block creation and by default
+/// produces sha2-256 Cid version 0 links, or Cid version 1 `raw` leaves when configured with
+/// [`FileAdderBuilder::with_raw_leaves`]. The hash function and the Cid version can be configured
+/// with [`FileAdderBuilder::with_cid_options`], and the small blocks can be inlined into their
+/// Cids with [`FileAdderBuilder::with_inline_limit`].
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    raw_leaves: bool,
+    cid_options: CidOptions,
     block_buffer: Vec<u8>,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
@@ -32,8 +35,10 @@ impl fmt::Debug for FileAdder {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "FileAdder {{ chunker: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
+            "FileAdder {{ chunker: {:?}, raw_leaves: {}, cid_options: {:?}, block_buffer: {}/{}, unflushed_links: {} }}",
             self.chunker,
+            self.raw_leaves,
+            self.cid_options,
             self.block_buffer.len(),
             self.block_buffer.capacity(),
             LinkFormatter(&self.unflushed_links),
@@ -114,6 +119,8 @@ impl fmt::Debug for Link {
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    raw_leaves: bool,
+    cid_options: CidOptions,
 }
 
 impl FileAdderBuilder {
@@ -130,13 +137,46 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to produce the leaf blocks as `raw` blocks containing only the file
+    /// bytes, instead of wrapping the bytes in UnixFs `File` blocks. A single block file will have
+    /// the `raw` leaf as the root.
+    pub fn with_raw_leaves(self, raw_leaves: bool) -> Self {
+        FileAdderBuilder { raw_leaves, ..self }
+    }
+
+    /// Configures the builder to inline the blocks of at most `limit` bytes into their Cids, using
+    /// the identity multihash, like `ipfs add --inline` does with the limit of
+    /// [`crate::DEFAULT_INLINE_LIMIT`]. The inlined blocks are still returned.
+    pub fn with_inline_limit(self, inline_limit: Option<usize>) -> Self {
+        FileAdderBuilder {
+            cid_options: self.cid_options.with_inline_limit(inline_limit),
+            ..self
+        }
+    }
+
+    /// Configures the builder to create the Cids with the given hash function and Cid version
+    /// instead of the default sha2-256 and Cid version 0.
+    pub fn with_cid_options(self, cid_options: CidOptions) -> Self {
+        FileAdderBuilder {
+            cid_options: cid_options.with_inline_limit(self.cid_options.inline_limit()),
+            ..self
+        }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            raw_leaves,
+            cid_options,
+        } = self;
 
         FileAdder {
             chunker,
             collector,
+            raw_leaves,
+            cid_options,
             ..Default::default()
         }
     }
@@ -171,7 +211,13 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = Self::flush_buffered_leaf(
+                accepted,
+                &mut self.unflushed_links,
+                false,
+                self.raw_leaves,
+                &self.cid_options,
+            );
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
@@ -197,6 +243,8 @@ impl FileAdder {
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
                     false,
+                    self.raw_leaves,
+                    &self.cid_options,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
                 self.block_buffer.clear();
@@ -219,6 +267,8 @@ impl FileAdder {
             &self.block_buffer.as_slice(),
             &mut self.unflushed_links,
             true,
+            self.raw_leaves,
+            &self.cid_options,
         );
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
@@ -231,11 +281,26 @@ impl FileAdder {
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
         finishing: bool,
+        raw_leaves: bool,
+        cid_options: &CidOptions,
     ) -> Option<(Cid, Vec<u8>)> {
         if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
             return None;
         }
 
+        if raw_leaves {
+            let cid = cid_options.cid(cid::Codec::Raw, input);
+
+            unflushed_links.push(Link {
+                depth: 0,
+                target: cid.clone(),
+                total_size: input.len() as u64,
+                file_size: input.len() as u64,
+            });
+
+            return Some((cid, input.to_vec()));
+        }
+
         // for empty unixfs file the bytes is missing but filesize is present.
 
         let data = if !input.is_empty() {
@@ -257,7 +322,7 @@ impl FileAdder {
             },
         };
 
-        let (cid, vec) = render_and_hash(&inner);
+        let (cid, vec) = render_and_hash(&inner, cid_options);
 
         let total_size = vec.len();
 
@@ -275,7 +340,7 @@ impl FileAdder {
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, finishing, &self.cid_options)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -283,7 +348,11 @@ impl FileAdder {
     /// chunker, otherwise `all_content` is pushed at `amt` sized slices with the idea of catching
     /// bugs in chunkers.
     #[cfg(test)]
-    fn collect_blocks(mut self, all_content: &[u8], mut amt: usize) -> Vec<(Cid, Vec<u8>)> {
+    pub(crate) fn collect_blocks(
+        mut self,
+        all_content: &[u8],
+        mut amt: usize,
+    ) -> Vec<(Cid, Vec<u8>)> {
         let mut written = 0;
         let mut blocks_received = Vec::new();
 
@@ -307,7 +376,7 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+fn render_and_hash(flat: &FlatUnixFs<'_>, cid_options: &CidOptions) -> (Cid, Vec<u8>) {
     // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
     // either just render a fixed header and continue with the body OR links, though the links are
     // a bit more complicated.
@@ -315,8 +384,7 @@ fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
     let mut writer = Writer::new(&mut out);
     flat.write_message(&mut writer)
         .expect("unsure how this could fail");
-    let mh = multihash::wrap(multihash::Code::Sha2_256, &Sha256::digest(&out));
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+    let cid = cid_options.cid(cid::Codec::DagProtobuf, &out);
     (cid, out)
 }
 
@@ -374,11 +442,16 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        cid_options: &CidOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, finishing, cid_options),
         }
     }
 }
@@ -437,8 +510,13 @@ impl BalancedCollector {
 
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
-    /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    /// block. The Cids of the link blocks are created with the given `cid_options`.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        finishing: bool,
+        cid_options: &CidOptions,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -578,7 +656,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = render_and_hash(&inner, cid_options);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -771,6 +849,122 @@ mod tests {
         }
     }
 
+    #[test]
+    fn raw_leaves_single_block_file() {
+        let content = b"foobar\n";
+        let adder = FileAdder::builder().with_raw_leaves(true).build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+
+        // the single raw leaf is the root
+        assert_eq!(blocks_received.len(), 1);
+        let (cid, block) = &blocks_received[0];
+        assert_eq!(cid.codec(), cid::Codec::Raw);
+        assert_eq!(block.as_slice(), content);
+    }
+
+    #[test]
+    fn raw_leaves_multi_block_file() {
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+
+        // "fo", "ob", "ar", "\n", root block
+        assert_eq!(blocks_received.len(), 5);
+
+        let leaves = &blocks_received[..4];
+        assert!(leaves.iter().all(|(cid, _)| cid.codec() == cid::Codec::Raw));
+        let concatenated = leaves
+            .iter()
+            .flat_map(|(_, block)| block.iter().copied())
+            .collect::<Vec<u8>>();
+        assert_eq!(concatenated.as_slice(), content);
+
+        let (root, _) = blocks_received.last().unwrap();
+        assert_eq!(root.codec(), cid::Codec::DagProtobuf);
+    }
+
+    #[test]
+    fn raw_leaves_empty_file() {
+        let adder = FileAdder::builder().with_raw_leaves(true).build();
+        let blocks = adder.collect_blocks(b"", 0);
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].1.is_empty());
+        // same as with go-ipfs add --raw-leaves
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+    }
+
+    #[test]
+    fn inlined_single_block_file() {
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_inline_limit(Some(crate::DEFAULT_INLINE_LIMIT))
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+
+        assert_eq!(blocks_received.len(), 1);
+        let (cid, block) = &blocks_received[0];
+        assert_eq!(cid.version(), cid::Version::V1);
+        assert_eq!(cid.codec(), cid::Codec::DagProtobuf);
+        assert_eq!(cid.hash().algorithm(), multihash::Code::Identity);
+        assert_eq!(cid.hash().digest(), block.as_slice());
+    }
+
+    #[test]
+    fn only_small_blocks_are_inlined() {
+        let content = b"foobar\n";
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .with_inline_limit(Some(1))
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+        assert_eq!(blocks_received.len(), 5);
+
+        let inlined = blocks_received
+            .iter()
+            .map(|(cid, _)| cid.hash().algorithm() == multihash::Code::Identity)
+            .collect::<Vec<_>>();
+
+        // only the "\n" leaf fits
+        assert_eq!(inlined, vec![false, false, false, true, false]);
+
+        let (cid, block) = &blocks_received[3];
+        assert_eq!(cid.codec(), cid::Codec::Raw);
+        assert_eq!(cid.hash().digest(), block.as_slice());
+    }
+
+    #[test]
+    fn custom_hash_and_cid_version() {
+        use crate::CidOptions;
+        use multihash::Code;
+
+        let content = b"foobar\n";
+        let opts = CidOptions::new(Code::Blake2b256, cid::Version::V1, None).unwrap();
+        let adder = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_cid_options(opts)
+            .build();
+
+        let blocks_received = adder.collect_blocks(content, 0);
+        assert_eq!(blocks_received.len(), 5);
+
+        for (cid, block) in blocks_received {
+            assert_eq!(cid.version(), cid::Version::V1);
+            assert_eq!(cid.codec(), cid::Codec::DagProtobuf);
+            assert_eq!(cid.hash(), Code::Blake2b256.digest(&block));
+        }
+    }
+
     #[test]
     fn empty_file() {
         let blocks = FileAdder::default().collect_blocks(b"", 0);
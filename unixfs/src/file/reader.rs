@@ -212,6 +212,27 @@ impl Traversal {
         FileReader::from_continued(self, tree_range.start, next_block)
     }
 
+    /// Continues the walk on the merkle tree with the contents of a `raw` leaf block. As opposed
+    /// to [`Traversal::continue_walk`], the whole block is the content of the file at the given
+    /// range.
+    pub fn continue_walk_raw<'a>(
+        self,
+        next_block: &'a [u8],
+        tree_range: &Range<u64>,
+    ) -> Result<FileReader<'a>, FileReadFailed> {
+        self.last_ending
+            .check_is_suitable_next(self.last_offset, tree_range)?;
+        Ok(FileReader {
+            offset: tree_range.start,
+            end: Ending::Chunk(tree_range.start + next_block.len() as u64),
+            links: Vec::new(),
+            data: next_block,
+            blocksizes: Vec::new(),
+            metadata: self.metadata,
+            file_size: self.file_size,
+        })
+    }
+
     /// Returns the total size of the file.
     pub fn file_size(&self) -> u64 {
         self.file_size
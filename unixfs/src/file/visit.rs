@@ -34,6 +34,13 @@ impl IdleFileVisit {
         self.start_from_reader(fr, &mut None)
     }
 
+    /// Begins the visitation of a file consisting of a single `raw` block, which is the content of
+    /// the file. Such files have no metadata and there is never anything more to visit.
+    pub fn start_raw(self, block: &'_ [u8]) -> FileVisitResult<'_> {
+        let content = maybe_target_slice(block, &(0..block.len() as u64), self.range.as_ref());
+        (content, block.len() as u64, Metadata::default(), None)
+    }
+
     pub(crate) fn start_from_parsed<'a>(
         self,
         block: FlatUnixFs<'a>,
@@ -155,13 +162,17 @@ impl FileVisit {
         cache: &mut Option<Cache>,
     ) -> Result<(&'a [u8], Option<Self>), FileReadFailed> {
         let traversal = self.state;
-        let (_, range) = self
+        let (cid, range) = self
             .pending
             .pop()
             .expect("User called continue_walk there must have been a next link");
 
         // interesting, validation doesn't trigger if the range is the same?
-        let fr = traversal.continue_walk(next, &range)?;
+        let fr = if cid.codec() == cid::Codec::Raw {
+            traversal.continue_walk_raw(next, &range)?
+        } else {
+            traversal.continue_walk(next, &range)?
+        };
         let (content, traversal) = fr.content();
         match content {
             FileContent::Bytes(content) => {
@@ -32,6 +32,9 @@ pub mod dagpb;
 /// Support for walking over all UnixFs trees
 pub mod walk;
 
+mod cid_options;
+pub use cid_options::{CidOptions, InvalidCidOptions};
+
 #[cfg(test)]
 pub(crate) mod test_support;
 
@@ -176,3 +179,8 @@ impl<'a> From<&'a UnixFs<'_>> for Metadata {
         Metadata { mode, mtime }
     }
 }
+
+/// The default size limit of go-ipfs for inlining blocks into their Cids with `ipfs add --inline`.
+/// See [`file::adder::FileAdderBuilder::with_inline_limit`] and
+/// [`dir::builder::TreeOptions::inline_limit`].
+pub const DEFAULT_INLINE_LIMIT: usize = 32;
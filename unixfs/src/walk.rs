@@ -145,6 +145,19 @@ impl Walker {
             return Ok(ContinuedWalk::File(segment, cid, path, metadata, *sz));
         }
 
+        if next.as_ref().map(|(cid, ..)| cid.codec()) == Some(cid::Codec::Raw) {
+            // raw leaves are files of a single block, which can be found as the root or as a
+            // directory entry.
+            let visited = IdleFileVisit::default().start_raw(bytes);
+            return Ok(Self::started_file(
+                current,
+                next,
+                pending,
+                should_continue,
+                visited,
+            ));
+        }
+
         let flat = FlatUnixFs::try_from(bytes)?;
         let metadata = Metadata::from(&flat.data);
 
@@ -231,37 +244,13 @@ impl Walker {
                 })
             }
             UnixFsType::Raw | UnixFsType::File => {
-                let (bytes, file_size, metadata, step) =
-                    IdleFileVisit::default().start_from_parsed(flat, cache)?;
-                let (cid, name, depth) = next.take().expect("validated at new and earlier");
-                let file_continues = step.is_some();
-
-                match current {
-                    None => {
-                        let ie =
-                            InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
-                        *current = Some(ie);
-                    }
-                    Some(ie) => {
-                        ie.as_file(cid, &name, depth, metadata, step, file_size);
-                    }
-                };
-
-                let next_local = pending.pop();
-                if file_continues || next_local.is_some() {
-                    *next = next_local;
-                    *should_continue = true;
-                }
-
-                let segment = FileSegment::first(bytes, !file_continues);
-
-                let ie = current.as_ref().unwrap();
-                Ok(ContinuedWalk::File(
-                    segment,
-                    &ie.cid,
-                    &ie.path,
-                    &ie.metadata,
-                    file_size,
+                let visited = IdleFileVisit::default().start_from_parsed(flat, cache)?;
+                Ok(Self::started_file(
+                    current,
+                    next,
+                    pending,
+                    should_continue,
+                    visited,
                 ))
             }
             UnixFsType::Metadata => Err(Error::UnsupportedType(flat.data.Type.into())),
@@ -299,6 +288,39 @@ impl Walker {
         }
     }
 
+    /// Records the start of the file walk as the current entry.
+    fn started_file<'a: 'c, 'b: 'c, 'c>(
+        current: &'a mut Option<InnerEntry>,
+        next: &mut Option<(Cid, String, usize)>,
+        pending: &mut Vec<(Cid, String, usize)>,
+        should_continue: &mut bool,
+        (bytes, file_size, metadata, step): (&'b [u8], u64, Metadata, Option<FileVisit>),
+    ) -> ContinuedWalk<'c> {
+        let (cid, name, depth) = next.take().expect("validated at new and earlier");
+        let file_continues = step.is_some();
+
+        match current {
+            None => {
+                let ie = InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
+                *current = Some(ie);
+            }
+            Some(ie) => {
+                ie.as_file(cid, &name, depth, metadata, step, file_size);
+            }
+        };
+
+        let next_local = pending.pop();
+        if file_continues || next_local.is_some() {
+            *next = next_local;
+            *should_continue = true;
+        }
+
+        let segment = FileSegment::first(bytes, !file_continues);
+
+        let ie = current.as_ref().unwrap();
+        ContinuedWalk::File(segment, &ie.cid, &ie.path, &ie.metadata, file_size)
+    }
+
     /// `true` if the walk of `inspect` should continue
     pub fn should_continue(&self) -> bool {
         self.should_continue
@@ -897,6 +919,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn walk_raw_leaves_file() {
+        use crate::file::adder::{Chunker, FileAdder};
+
+        let content = b"foobar\n";
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(content, 0)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(walk_to_bytes(&blocks), content);
+    }
+
+    #[test]
+    fn walk_raw_block_as_root() {
+        use crate::file::adder::FileAdder;
+
+        let content = b"foobar\n";
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(content, 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0.codec(), cid::Codec::Raw);
+
+        let blocks = blocks.into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(walk_to_bytes(&blocks), content);
+    }
+
+    /// Walks the single file made out of `blocks`, which must all be reachable from the last one.
+    fn walk_to_bytes(blocks: &HashMap<Cid, Vec<u8>>) -> Vec<u8> {
+        // the root is the only one not linked to from any other block
+        let root = blocks
+            .keys()
+            .find(|cid| cid.codec() == cid::Codec::DagProtobuf || blocks.len() == 1)
+            .unwrap();
+
+        let mut walker = Walker::new(root.to_owned(), String::new());
+        let mut ret = Vec::new();
+
+        while walker.should_continue() {
+            let (next, _) = walker.pending_links();
+            let block = &blocks[next];
+            match walker.next(block, &mut None).unwrap() {
+                ContinuedWalk::File(segment, ..) => ret.extend_from_slice(segment.as_ref()),
+                x => unreachable!("{:?}", x),
+            }
+        }
+
+        ret
+    }
+
     trait CountsExt {
         fn checked_removal(&mut self, key: &PathBuf, expected: usize);
     }